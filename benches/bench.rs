@@ -11,7 +11,9 @@ use std::{ops, ptr};
 use test::Bencher;
 
 use miniz_oxide::deflate::compress_to_vec;
-use miniz_oxide::deflate::core::{create_comp_flags_from_zip_params, CompressorOxide};
+use miniz_oxide::deflate::core::{
+    compress_to_output, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush,
+};
 
 use miniz_oxide_c_api::miniz_def_free_func;
 
@@ -82,6 +84,29 @@ macro_rules! compress_bench {
     };
 }
 
+/// Compress with a given number of hash bits. The compressed sizes for the same data are
+/// compared in the `hash_bits_ratio` test in `deflate::core`.
+macro_rules! hash_bits_bench {
+    ($bench_name:ident, $hash_bits:expr, $level:expr, $path_to_data:expr) => {
+        #[bench]
+        fn $bench_name(b: &mut ::Bencher) {
+            let input = ::get_test_file_data($path_to_data);
+            let flags = ::create_comp_flags_from_zip_params($level, -15, 0);
+            b.bytes = input.len() as u64;
+            b.iter(|| {
+                let mut d = ::CompressorOxide::new(flags);
+                d.set_hash_bits($hash_bits);
+                let mut out_len = 0;
+                ::compress_to_output(&mut d, &input, ::TDEFLFlush::Finish, |out: &[u8]| {
+                    out_len += out.len();
+                    true
+                });
+                out_len
+            });
+        }
+    };
+}
+
 mod oxide {
     use miniz_oxide_c_api::{tdefl_compress_mem_to_heap, tinfl_decompress_mem_to_heap};
 
@@ -383,3 +408,35 @@ fn create_compressor(b: &mut Bencher) {
     let flags = create_comp_flags_from_zip_params(6, true as i32, 0);
     b.iter(|| CompressorOxide::new(flags));
 }
+
+mod hash_bits {
+    hash_bits_bench!(compress_code_lvl_1_hash_8, 8, 1, "benches/data/code");
+    hash_bits_bench!(compress_code_lvl_1_hash_12, 12, 1, "benches/data/code");
+    hash_bits_bench!(compress_code_lvl_1_hash_15, 15, 1, "benches/data/code");
+    hash_bits_bench!(compress_code_lvl_1_hash_16, 16, 1, "benches/data/code");
+
+    hash_bits_bench!(compress_code_lvl_6_hash_8, 8, 6, "benches/data/code");
+    hash_bits_bench!(compress_code_lvl_6_hash_12, 12, 6, "benches/data/code");
+    hash_bits_bench!(compress_code_lvl_6_hash_15, 15, 6, "benches/data/code");
+    hash_bits_bench!(compress_code_lvl_6_hash_16, 16, 6, "benches/data/code");
+
+    hash_bits_bench!(compress_code_lvl_9_hash_8, 8, 9, "benches/data/code");
+    hash_bits_bench!(compress_code_lvl_9_hash_12, 12, 9, "benches/data/code");
+    hash_bits_bench!(compress_code_lvl_9_hash_15, 15, 9, "benches/data/code");
+    hash_bits_bench!(compress_code_lvl_9_hash_16, 16, 9, "benches/data/code");
+
+    hash_bits_bench!(compress_bin_lvl_1_hash_8, 8, 1, "benches/data/bin");
+    hash_bits_bench!(compress_bin_lvl_1_hash_12, 12, 1, "benches/data/bin");
+    hash_bits_bench!(compress_bin_lvl_1_hash_15, 15, 1, "benches/data/bin");
+    hash_bits_bench!(compress_bin_lvl_1_hash_16, 16, 1, "benches/data/bin");
+
+    hash_bits_bench!(compress_bin_lvl_6_hash_8, 8, 6, "benches/data/bin");
+    hash_bits_bench!(compress_bin_lvl_6_hash_12, 12, 6, "benches/data/bin");
+    hash_bits_bench!(compress_bin_lvl_6_hash_15, 15, 6, "benches/data/bin");
+    hash_bits_bench!(compress_bin_lvl_6_hash_16, 16, 6, "benches/data/bin");
+
+    hash_bits_bench!(compress_bin_lvl_9_hash_8, 8, 9, "benches/data/bin");
+    hash_bits_bench!(compress_bin_lvl_9_hash_12, 12, 9, "benches/data/bin");
+    hash_bits_bench!(compress_bin_lvl_9_hash_15, 15, 9, "benches/data/bin");
+    hash_bits_bench!(compress_bin_lvl_9_hash_16, 16, 9, "benches/data/bin");
+}
@@ -272,6 +272,9 @@ enum
 /* Window bits */
 #define MZ_DEFAULT_WINDOW_BITS 15
 
+/* Memory level, the hash table uses mem_level + 7 bits */
+#define MZ_DEFAULT_MEM_LEVEL 8
+
 struct mz_internal_state;
 
 /* Compression/decompression stream struct. */
@@ -319,12 +322,16 @@ int mz_deflateInit(mz_streamp pStream, int level);
 /* Additional parameters: */
 /*   method must be MZ_DEFLATED */
 /*   window_bits must be MZ_DEFAULT_WINDOW_BITS (to wrap the deflate stream with zlib header/adler-32 footer) or -MZ_DEFAULT_WINDOW_BITS (raw deflate/no header or footer) */
-/*   mem_level must be between [1, 9] (it's checked but ignored by miniz.c) */
+/*   mem_level must be between [1, 9], the hash table uses mem_level + 7 bits like zlib */
 int mz_deflateInit2(mz_streamp pStream, int level, int method, int window_bits, int mem_level, int strategy);
 
 /* Quickly resets a compressor without having to reallocate anything. Same as calling mz_deflateEnd() followed by mz_deflateInit()/mz_deflateInit2(). */
 int mz_deflateReset(mz_streamp pStream);
 
+/* Fine-tunes the compressor's match finder, with the same parameters as zlib's deflateTune(). */
+/* Only affects levels above 1, and is reset when the level changes. */
+int mz_deflateTune(mz_streamp pStream, int good_length, int max_lazy, int nice_length, int max_chain);
+
 /* mz_deflate() compresses the input to output, consuming as much of the input and producing as much output as possible. */
 /* Parameters: */
 /*   pStream is the stream to read from and write to. You must initialize/update the next_in, avail_in, next_out, and avail_out members. */
@@ -436,6 +443,7 @@ typedef void *const voidpc;
 #define deflateInit mz_deflateInit
 #define deflateInit2 mz_deflateInit2
 #define deflateReset mz_deflateReset
+#define deflateTune mz_deflateTune
 #define deflate mz_deflate
 #define deflateEnd mz_deflateEnd
 #define deflateBound mz_deflateBound
@@ -0,0 +1,138 @@
+//! Adler-32 checksum as used by the zlib format.
+//!
+//! The portable implementation defers the modulo operations over chunks of up to `NMAX` bytes,
+//! and keeps interleaved sums within each chunk that the compiler can vectorize. With the
+//! `simd` feature, hand-written SSE2/AVX2 versions are used on x86 where available.
+
+#[cfg(feature = "simd")]
+#[allow(unsafe_code)]
+mod simd;
+
+/// Largest prime smaller than 65536.
+const MOD_ADLER: u32 = 65_521;
+
+/// Largest n such that 255n(n+1)/2 + (n+1)(MOD_ADLER-1) fits in 32 bits, i.e. the number of
+/// bytes that can be processed before the sums have to be reduced.
+const NMAX: usize = 5552;
+
+/// Update the Adler-32 checksum `adler` with the bytes in `data`.
+pub fn update(adler: u32, data: &[u8]) -> u32 {
+    #[cfg(feature = "simd")]
+    {
+        if let Some(adler) = simd::update(adler, data) {
+            return adler;
+        }
+    }
+
+    update_portable(adler, data)
+}
+
+fn update_portable(adler: u32, data: &[u8]) -> u32 {
+    const LANES: usize = 4;
+    const CHUNK_SIZE: usize = NMAX / LANES * LANES;
+
+    let mut a = adler & 0xFFFF;
+    let mut b = adler >> 16;
+
+    for chunk in data.chunks(CHUNK_SIZE) {
+        // Keep separate sums for every fourth byte, which the compiler can vectorize.
+        let mut a_vec = [0u32; LANES];
+        let mut b_vec = [0u32; LANES];
+        let mut blocks = chunk.chunks_exact(LANES);
+        for block in &mut blocks {
+            for i in 0..LANES {
+                a_vec[i] += u32::from(block[i]);
+                b_vec[i] += a_vec[i];
+            }
+        }
+
+        // Byte i of the k-th block from the end is counted k times in b_vec[i], but would have
+        // been added to b LANES * k - i times.
+        let num_bytes = (chunk.len() - blocks.remainder().len()) as u32;
+        b += a * num_bytes;
+        for i in 0..LANES {
+            a += a_vec[i];
+            b += LANES as u32 * b_vec[i] - i as u32 * a_vec[i];
+        }
+        update_tail(&mut a, &mut b, blocks.remainder());
+    }
+
+    (b << 16) | a
+}
+
+/// Add the bytes in `data` one at a time and reduce the sums.
+///
+/// `data` plus whatever was added to the sums since they were last reduced should be at most
+/// `NMAX` bytes.
+#[inline]
+fn update_tail(a: &mut u32, b: &mut u32, data: &[u8]) {
+    for &byte in data {
+        *a += u32::from(byte);
+        *b += *a;
+    }
+    *a %= MOD_ADLER;
+    *b %= MOD_ADLER;
+}
+
+#[cfg(test)]
+mod test {
+    use super::{update, update_portable, MOD_ADLER, NMAX};
+    use std::prelude::v1::*;
+
+    /// Straightforward implementation to compare against.
+    fn update_naive(adler: u32, data: &[u8]) -> u32 {
+        let mut a = adler & 0xFFFF;
+        let mut b = adler >> 16;
+        for &byte in data {
+            a = (a + u32::from(byte)) % MOD_ADLER;
+            b = (b + a) % MOD_ADLER;
+        }
+        (b << 16) | a
+    }
+
+    #[test]
+    fn known_values() {
+        assert_eq!(update(1, b""), 1);
+        assert_eq!(update(1, b"a"), 0x0062_0062);
+        assert_eq!(update(1, b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn matches_naive() {
+        let mut seed = 1u32;
+        let data: Vec<u8> = (0..3 * NMAX + 100)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        // All 0xFF bytes, which is the worst case for the deferred modulo.
+        let ones = vec![0xFF; 3 * NMAX + 100];
+
+        for input in &[&data, &ones] {
+            for &len in &[
+                0,
+                1,
+                15,
+                16,
+                17,
+                31,
+                32,
+                33,
+                100,
+                NMAX - 1,
+                NMAX,
+                NMAX + 1,
+                input.len(),
+            ] {
+                for &adler in &[1, 0xFFF0_FFF0, 0x1234_5678] {
+                    let expected = update_naive(adler, &input[..len]);
+                    assert_eq!(update(adler, &input[..len]), expected);
+                    assert_eq!(update_portable(adler, &input[..len]), expected);
+                }
+            }
+            // Unaligned start.
+            assert_eq!(update(1, &input[3..]), update_naive(1, &input[3..]));
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! SIMD versions of the Adler-32 update.
+//!
+//! Each block of bytes is summed into `a`, and weighted by its distance from the end of the block
+//! into `b`. The sum of `a` at the start of every block is tracked separately and scaled by the
+//! block size at the end of each chunk, so the modulo is still only needed once per chunk.
+//!
+//! This is the only place in the crate where `unsafe` is allowed, and it's only compiled with the
+//! `simd` feature.
+//!
+//! There is only an x86 version for now. Other targets, including aarch64, use the portable
+//! version.
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use self::x86::{update_avx2, update_sse2};
+
+/// Update the checksum with the best available instruction set, or return `None` if there is no
+/// SIMD version for this CPU.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn update(adler: u32, data: &[u8]) -> Option<u32> {
+    if has_avx2() {
+        // Safe as we just checked that AVX2 is supported.
+        Some(unsafe { update_avx2(adler, data) })
+    } else if has_sse2() {
+        // Safe as we just checked that SSE2 is supported.
+        Some(unsafe { update_sse2(adler, data) })
+    } else {
+        None
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn update(_adler: u32, _data: &[u8]) -> Option<u32> {
+    None
+}
+
+// Runtime detection needs the real std, so inside SGX we only use what the
+// target was compiled for.
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(all(feature = "mesalock_sgx", not(target_env = "sgx")))
+))]
+fn has_avx2() -> bool {
+    is_x86_feature_detected!("avx2")
+}
+
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(all(feature = "mesalock_sgx", not(target_env = "sgx")))
+))]
+fn has_sse2() -> bool {
+    is_x86_feature_detected!("sse2")
+}
+
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    all(feature = "mesalock_sgx", not(target_env = "sgx"))
+))]
+fn has_avx2() -> bool {
+    cfg!(target_feature = "avx2")
+}
+
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    all(feature = "mesalock_sgx", not(target_env = "sgx"))
+))]
+fn has_sse2() -> bool {
+    cfg!(target_feature = "sse2")
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    use super::super::{update_tail, NMAX};
+
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::*;
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn update_sse2(adler: u32, data: &[u8]) -> u32 {
+        const BLOCK_SIZE: usize = 16;
+        const CHUNK_SIZE: usize = NMAX / BLOCK_SIZE * BLOCK_SIZE;
+
+        let mut a = adler & 0xFFFF;
+        let mut b = adler >> 16;
+
+        let zero = _mm_setzero_si128();
+        let weights_lo = _mm_setr_epi16(16, 15, 14, 13, 12, 11, 10, 9);
+        let weights_hi = _mm_setr_epi16(8, 7, 6, 5, 4, 3, 2, 1);
+
+        for chunk in data.chunks(CHUNK_SIZE) {
+            let mut v_a = zero;
+            let mut v_prev_a = zero;
+            let mut v_b = zero;
+
+            let mut blocks = chunk.chunks_exact(BLOCK_SIZE);
+            for block in &mut blocks {
+                let bytes = _mm_loadu_si128(block.as_ptr() as *const __m128i);
+                v_prev_a = _mm_add_epi32(v_prev_a, v_a);
+                v_a = _mm_add_epi32(v_a, _mm_sad_epu8(bytes, zero));
+                let lo = _mm_madd_epi16(_mm_unpacklo_epi8(bytes, zero), weights_lo);
+                let hi = _mm_madd_epi16(_mm_unpackhi_epi8(bytes, zero), weights_hi);
+                v_b = _mm_add_epi32(v_b, _mm_add_epi32(lo, hi));
+            }
+
+            let num_bytes = (chunk.len() - blocks.remainder().len()) as u32;
+            v_b = _mm_add_epi32(v_b, _mm_slli_epi32(v_prev_a, 4));
+            b += a * num_bytes + hsum_sse2(v_b);
+            a += hsum_sse2(v_a);
+            update_tail(&mut a, &mut b, blocks.remainder());
+        }
+
+        (b << 16) | a
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn update_avx2(adler: u32, data: &[u8]) -> u32 {
+        const BLOCK_SIZE: usize = 32;
+        const CHUNK_SIZE: usize = NMAX / BLOCK_SIZE * BLOCK_SIZE;
+
+        let mut a = adler & 0xFFFF;
+        let mut b = adler >> 16;
+
+        let zero = _mm256_setzero_si256();
+        let ones = _mm256_set1_epi16(1);
+        let weights = _mm256_setr_epi8(
+            32, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17, 16, 15, 14, 13, 12, 11,
+            10, 9, 8, 7, 6, 5, 4, 3, 2, 1,
+        );
+
+        for chunk in data.chunks(CHUNK_SIZE) {
+            let mut v_a = zero;
+            let mut v_prev_a = zero;
+            let mut v_b = zero;
+
+            let mut blocks = chunk.chunks_exact(BLOCK_SIZE);
+            for block in &mut blocks {
+                let bytes = _mm256_loadu_si256(block.as_ptr() as *const __m256i);
+                v_prev_a = _mm256_add_epi32(v_prev_a, v_a);
+                v_a = _mm256_add_epi32(v_a, _mm256_sad_epu8(bytes, zero));
+                // The pairwise sums of bytes times weights are at most 255 * 63, so
+                // maddubs can't saturate.
+                let weighted = _mm256_madd_epi16(_mm256_maddubs_epi16(bytes, weights), ones);
+                v_b = _mm256_add_epi32(v_b, weighted);
+            }
+
+            let num_bytes = (chunk.len() - blocks.remainder().len()) as u32;
+            v_b = _mm256_add_epi32(v_b, _mm256_slli_epi32(v_prev_a, 5));
+            b += a * num_bytes + hsum_avx2(v_b);
+            a += hsum_avx2(v_a);
+            update_tail(&mut a, &mut b, blocks.remainder());
+        }
+
+        (b << 16) | a
+    }
+
+    #[inline]
+    #[target_feature(enable = "sse2")]
+    unsafe fn hsum_sse2(v: __m128i) -> u32 {
+        let sum = _mm_add_epi32(v, _mm_shuffle_epi32(v, 0b01_00_11_10));
+        let sum = _mm_add_epi32(sum, _mm_shuffle_epi32(sum, 0b10_11_00_01));
+        _mm_cvtsi128_si32(sum) as u32
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn hsum_avx2(v: __m256i) -> u32 {
+        hsum_sse2(_mm_add_epi32(
+            _mm256_castsi256_si128(v),
+            _mm256_extracti128_si256(v, 1),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{update_portable, NMAX};
+    use std::prelude::v1::*;
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[test]
+    fn x86_matches_portable() {
+        let data: Vec<u8> = (0..2 * NMAX + 77)
+            .map(|i| (i * 7 + i / 255) as u8)
+            .collect();
+        for &len in &[0, 15, 16, 31, 32, 33, NMAX, data.len()] {
+            let expected = update_portable(0x0102_0304, &data[..len]);
+            if super::has_sse2() {
+                assert_eq!(
+                    unsafe { super::update_sse2(0x0102_0304, &data[..len]) },
+                    expected
+                );
+            }
+            if super::has_avx2() {
+                assert_eq!(
+                    unsafe { super::update_avx2(0x0102_0304, &data[..len]) },
+                    expected
+                );
+            }
+        }
+    }
+}
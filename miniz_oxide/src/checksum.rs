@@ -0,0 +1,235 @@
+//! Adler-32 and CRC-32 checksums.
+//!
+//! Both checksums can be computed incrementally, and the checksums of two consecutive pieces of
+//! data can be joined without access to the data itself, e.g. when chunks have been compressed in
+//! parallel.
+//!
+//! ``` rust
+//! use miniz_oxide::checksum::{crc32_combine, Crc32};
+//!
+//! let (first, second) = b"Hello, world!".split_at(5);
+//!
+//! let mut crc = Crc32::new();
+//! crc.update(first);
+//! crc.update(second);
+//!
+//! let first_crc = Crc32::checksum_of(first);
+//! let second_crc = Crc32::checksum_of(second);
+//! assert_eq!(
+//!     crc32_combine(first_crc, second_crc, second.len() as u64),
+//!     crc.checksum()
+//! );
+//! ```
+
+use crate::shared::MZ_ADLER32_INIT;
+
+/// Largest prime smaller than 65536.
+const MOD_ADLER: u32 = 65_521;
+
+/// Reversed CRC-32 polynomial.
+pub(crate) const CRC32_POLY: u32 = 0xEDB8_8320;
+
+/// Streaming Adler-32 checksum, as used by the zlib format.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Adler32 {
+    value: u32,
+}
+
+impl Adler32 {
+    /// Create a new checksum with the initial value of 1.
+    pub fn new() -> Adler32 {
+        Adler32::from_checksum(MZ_ADLER32_INIT)
+    }
+
+    /// Continue from a previously computed checksum.
+    pub fn from_checksum(value: u32) -> Adler32 {
+        Adler32 { value }
+    }
+
+    /// Compute the checksum of `data` in one go.
+    pub fn checksum_of(data: &[u8]) -> u32 {
+        let mut adler = Adler32::new();
+        adler.update(data);
+        adler.checksum()
+    }
+
+    /// Add `data` to the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        self.value = crate::adler32::update(self.value, data);
+    }
+
+    /// Append the checksum of `len` bytes of data directly following the data checksummed so far.
+    pub fn combine(&mut self, other: u32, len: u64) {
+        self.value = adler32_combine(self.value, other, len);
+    }
+
+    /// Get the checksum of the data added so far.
+    pub fn checksum(&self) -> u32 {
+        self.value
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Adler32 {
+        Adler32::new()
+    }
+}
+
+/// Streaming CRC-32 checksum, as used by the gzip and zip formats.
+#[derive(Debug, Clone, Default)]
+pub struct Crc32 {
+    hasher: crc32fast::Hasher,
+}
+
+impl Crc32 {
+    /// Create a new checksum with the initial value of 0.
+    pub fn new() -> Crc32 {
+        Crc32::default()
+    }
+
+    /// Continue from a previously computed checksum.
+    pub fn from_checksum(value: u32) -> Crc32 {
+        Crc32 {
+            hasher: crc32fast::Hasher::new_with_initial(value),
+        }
+    }
+
+    /// Compute the checksum of `data` in one go.
+    pub fn checksum_of(data: &[u8]) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(data);
+        crc.checksum()
+    }
+
+    /// Add `data` to the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    /// Append the checksum of `len` bytes of data directly following the data checksummed so far.
+    pub fn combine(&mut self, other: u32, len: u64) {
+        *self = Crc32::from_checksum(crc32_combine(self.checksum(), other, len));
+    }
+
+    /// Get the checksum of the data added so far.
+    pub fn checksum(&self) -> u32 {
+        self.hasher.clone().finalize()
+    }
+}
+
+/// Compute the Adler-32 checksum of the concatenation of two pieces of data, given the checksum
+/// of each and the length of the second piece.
+pub fn adler32_combine(adler1: u32, adler2: u32, len2: u64) -> u32 {
+    let rem = (len2 % u64::from(MOD_ADLER)) as u32;
+    let mut a = adler1 & 0xFFFF;
+    // Every byte of the second piece adds the final a of the first piece to b once more.
+    let mut b = (rem * a) % MOD_ADLER;
+    // The initial value of 1 is included in both a sums, so subtract it once.
+    a += (adler2 & 0xFFFF) + MOD_ADLER - 1;
+    b += (adler1 >> 16) + (adler2 >> 16) + MOD_ADLER - rem;
+    if a >= MOD_ADLER {
+        a -= MOD_ADLER;
+    }
+    if a >= MOD_ADLER {
+        a -= MOD_ADLER;
+    }
+    if b >= MOD_ADLER << 1 {
+        b -= MOD_ADLER << 1;
+    }
+    if b >= MOD_ADLER {
+        b -= MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+/// Compute the CRC-32 checksum of the concatenation of two pieces of data, given the checksum
+/// of each and the length of the second piece.
+pub fn crc32_combine(crc1: u32, crc2: u32, len2: u64) -> u32 {
+    // Appending len2 zero bytes to the first piece multiplies its crc by x^(8 * len2), and the
+    // crc of the second piece is linear on top of that.
+    multiply_mod_poly(x_pow_8n_mod_poly(len2), crc1) ^ crc2
+}
+
+/// Multiply two polynomials modulo the CRC-32 polynomial, in the reflected bit order where the
+/// highest bit is x^0.
+fn multiply_mod_poly(a: u32, mut b: u32) -> u32 {
+    let mut product = 0;
+    let mut bit = 1 << 31;
+    while bit != 0 {
+        if a & bit != 0 {
+            product ^= b;
+        }
+        b = if b & 1 != 0 {
+            (b >> 1) ^ CRC32_POLY
+        } else {
+            b >> 1
+        };
+        bit >>= 1;
+    }
+    product
+}
+
+/// Compute x^(8n) modulo the CRC-32 polynomial by repeated squaring.
+fn x_pow_8n_mod_poly(mut n: u64) -> u32 {
+    // x^8
+    let mut square = 1 << (31 - 8);
+    let mut result = 1 << 31;
+    while n != 0 {
+        if n & 1 != 0 {
+            result = multiply_mod_poly(square, result);
+        }
+        square = multiply_mod_poly(square, square);
+        n >>= 1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::{adler32_combine, crc32_combine, Adler32, Crc32};
+    use std::prelude::v1::*;
+
+    #[test]
+    fn known_values() {
+        assert_eq!(Adler32::checksum_of(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(Crc32::checksum_of(b"123456789"), 0xCBF4_3926);
+        assert_eq!(Adler32::new().checksum(), 1);
+        assert_eq!(Crc32::new().checksum(), 0);
+
+        let mut crc = Crc32::from_checksum(Crc32::checksum_of(b"12345"));
+        crc.update(b"6789");
+        assert_eq!(crc.checksum(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn combine() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 31 + i / 7) as u8).collect();
+        let adler = Adler32::checksum_of(&data);
+        let crc = Crc32::checksum_of(&data);
+
+        for &split in &[0, 1, 100, 65_520, 65_521, 65_522, 99_999, data.len()] {
+            let (first, second) = data.split_at(split);
+            let len = second.len() as u64;
+            let adler2 = Adler32::checksum_of(second);
+            let crc2 = Crc32::checksum_of(second);
+            assert_eq!(
+                adler32_combine(Adler32::checksum_of(first), adler2, len),
+                adler
+            );
+            assert_eq!(crc32_combine(Crc32::checksum_of(first), crc2, len), crc);
+
+            let mut streaming_adler = Adler32::new();
+            streaming_adler.update(first);
+            streaming_adler.combine(adler2, len);
+            assert_eq!(streaming_adler.checksum(), adler);
+
+            let mut streaming_crc = Crc32::new();
+            streaming_crc.update(first);
+            streaming_crc.combine(crc2, len);
+            streaming_crc.update(b"tail");
+            let mut expected = Crc32::from_checksum(crc);
+            expected.update(b"tail");
+            assert_eq!(streaming_crc.checksum(), expected.checksum());
+        }
+    }
+}
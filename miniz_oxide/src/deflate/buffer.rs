@@ -1,9 +1,13 @@
 //! Buffer wrappers implementing default so we can allocate the buffers with `Box::default()`
 //! to avoid stack copies. Box::new() doesn't at the moment, and using a vec means we would lose
 //! static length info.
+//!
+//! The hash buffers are the exception, as their size depends on whether the compressor emits
+//! standard deflate or Deflate64 streams and on the configured hash size, so they are allocated
+//! as boxed slices.
 
 use std::prelude::v1::*;
-use crate::deflate::core::{LZ_DICT_SIZE, MAX_MATCH_LEN};
+use crate::deflate::core::{LZ_DICT_SIZE, LZ_HASH_SIZE, MAX_MATCH_LEN};
 
 /// Size of the buffer of lz77 encoded data.
 pub const LZ_CODE_BUF_SIZE: usize = 64 * 1024;
@@ -11,26 +15,57 @@ pub const LZ_CODE_BUF_SIZE: usize = 64 * 1024;
 pub const OUT_BUF_SIZE: usize = (LZ_CODE_BUF_SIZE * 13) / 10;
 pub const LZ_DICT_FULL_SIZE: usize = LZ_DICT_SIZE + MAX_MATCH_LEN - 1 + 1;
 
+#[derive(Clone)]
 pub struct HashBuffers {
-    pub dict: [u8; LZ_DICT_FULL_SIZE],
-    pub next: [u16; LZ_DICT_SIZE],
-    pub hash: [u16; LZ_DICT_SIZE],
+    /// Ring buffer of input data, followed by a copy of the first `max_match_len - 1` bytes
+    /// so matches can be compared without wrapping.
+    pub dict: Box<[u8]>,
+    pub next: Box<[u16]>,
+    pub hash: Box<[u16]>,
 }
 
 impl HashBuffers {
+    /// Allocate buffers for a ring buffer of `dict_size` bytes, matches of up to
+    /// `max_match_len` bytes and a hash table with `hash_size` entries.
+    pub fn new(dict_size: usize, max_match_len: usize, hash_size: usize) -> HashBuffers {
+        HashBuffers {
+            dict: vec![0; dict_size + max_match_len - 1 + 1].into_boxed_slice(),
+            next: vec![0; dict_size].into_boxed_slice(),
+            hash: vec![0; hash_size].into_boxed_slice(),
+        }
+    }
+
     #[inline]
     pub fn reset(&mut self) {
-        *self = HashBuffers::default();
+        fill_zero(&mut self.dict);
+        fill_zero(&mut self.next);
+        fill_zero(&mut self.hash);
+    }
+
+    /// Copy the contents of `other`, only re-allocating if the sizes differ.
+    pub fn copy_from(&mut self, other: &HashBuffers) {
+        if self.dict.len() == other.dict.len()
+            && self.next.len() == other.next.len()
+            && self.hash.len() == other.hash.len()
+        {
+            self.dict.copy_from_slice(&other.dict);
+            self.next.copy_from_slice(&other.next);
+            self.hash.copy_from_slice(&other.hash);
+        } else {
+            *self = other.clone();
+        }
+    }
+}
+
+fn fill_zero<T: Copy + Default>(slice: &mut [T]) {
+    for x in slice {
+        *x = T::default();
     }
 }
 
 impl Default for HashBuffers {
     fn default() -> HashBuffers {
-        HashBuffers {
-            dict: [0; LZ_DICT_FULL_SIZE],
-            next: [0; LZ_DICT_SIZE],
-            hash: [0; LZ_DICT_SIZE],
-        }
+        HashBuffers::new(LZ_DICT_SIZE, MAX_MATCH_LEN, LZ_HASH_SIZE)
     }
 }
 
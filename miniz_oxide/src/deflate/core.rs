@@ -119,8 +119,11 @@ const SMALL_DIST_EXTRA: [u8; 512] = [
 ];
 
 /// Base values to calculate distances above 512.
+///
+/// The entries past index 127 are only used by Deflate64, which adds two distance codes
+/// (30 and 31) for distances above 32768.
 #[rustfmt::skip]
-const LARGE_DIST_SYM: [u8; 128] = [
+const LARGE_DIST_SYM: [u8; 256] = [
      0,  0, 18, 19, 20, 20, 21, 21, 22, 22, 22, 22, 23, 23, 23, 23,
     24, 24, 24, 24, 24, 24, 24, 24, 25, 25, 25, 25, 25, 25, 25, 25,
     26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26,
@@ -128,12 +131,20 @@ const LARGE_DIST_SYM: [u8; 128] = [
     28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
     28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
     29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29,
-    29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29
+    29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29, 29,
+    30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31,
+    31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31,
+    31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31,
+    31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31, 31
 ];
 
 /// Number of extra bits distances above 512.
 #[rustfmt::skip]
-const LARGE_DIST_EXTRA: [u8; 128] = [
+const LARGE_DIST_EXTRA: [u8; 256] = [
      0,  0,  8,  8,  9,  9,  9,  9, 10, 10, 10, 10, 10, 10, 10, 10,
     11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11,
     12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12,
@@ -141,7 +152,29 @@ const LARGE_DIST_EXTRA: [u8; 128] = [
     13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13,
     13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13,
     13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13,
-    13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13
+    13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13,
+    14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14,
+    14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14,
+    14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14,
+    14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14,
+    14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14,
+    14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14,
+    14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14,
+    14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14, 14
+];
+
+/// Number of extra bits for each length symbol from 257 to 284.
+#[rustfmt::skip]
+const LEN_SYM_EXTRA: [u8; 28] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2,
+    2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5
+];
+
+/// Number of extra bits for each distance symbol.
+#[rustfmt::skip]
+const DIST_SYM_EXTRA: [u8; 32] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13, 14, 14
 ];
 
 #[rustfmt::skip]
@@ -152,7 +185,7 @@ const BITMASKS: [u32; 17] = [
 
 /// The maximum number of checks for matches in the hash table the compressor will make for each
 /// compression level.
-const NUM_PROBES: [u32; 11] = [0, 1, 6, 32, 16, 32, 128, 256, 512, 768, 1500];
+pub(crate) const NUM_PROBES: [u32; 11] = [0, 1, 6, 32, 16, 32, 128, 256, 512, 768, 1500];
 
 #[derive(Copy, Clone)]
 struct SymFreq {
@@ -180,6 +213,10 @@ pub mod deflate_flags {
     pub const TDEFL_FORCE_ALL_STATIC_BLOCKS: u32 = 0x0004_0000;
     /// Force the compressor to only output raw/uncompressed blocks.
     pub const TDEFL_FORCE_ALL_RAW_BLOCKS: u32 = 0x0008_0000;
+    /// Emit a Deflate64 (enhanced deflate) stream, which uses a 64 KiB window and match
+    /// lengths of up to 65538 bytes. This is only honoured when creating the compressor, and
+    /// the output is normally used raw, e.g as method 9 in zip archives.
+    pub const TDEFL_DEFLATE64: u32 = 0x0010_0000;
 }
 
 /// Strategy setting for compression.
@@ -251,14 +288,16 @@ pub enum TDEFLStatus {
 }
 
 const MAX_HUFF_SYMBOLS: usize = 288;
-/// Size of hash values in the hash chains.
+/// Default size of hash values in the hash chains.
 const LZ_HASH_BITS: i32 = 15;
+/// Smallest supported size of hash values.
+const MIN_LZ_HASH_BITS: u8 = 8;
+/// Largest supported size of hash values.
+const MAX_LZ_HASH_BITS: u8 = 16;
 /// Size of hash chain for fast compression mode.
 const LEVEL1_HASH_SIZE_MASK: u32 = 4095;
-/// How many bits to shift when updating the current hash value.
-const LZ_HASH_SHIFT: i32 = (LZ_HASH_BITS + 2) / 3;
-/// Size of the chained hash tables.
-const LZ_HASH_SIZE: usize = 1 << LZ_HASH_BITS;
+/// Default size of the chained hash tables.
+pub(crate) const LZ_HASH_SIZE: usize = 1 << LZ_HASH_BITS;
 
 /// The number of huffman tables used by the compressor.
 /// Literal/length, Distances and Length of the huffman codes for the other two tables.
@@ -273,10 +312,29 @@ const MAX_HUFF_SYMBOLS_2: usize = 19;
 pub(crate) const LZ_DICT_SIZE: usize = 32_768;
 /// Mask used when stepping through the hash chains.
 const LZ_DICT_SIZE_MASK: u32 = LZ_DICT_SIZE as u32 - 1;
+/// Smallest supported window size, in bits.
+const MIN_WINDOW_BITS: u8 = 8;
 /// The minimum length of a match.
 const MIN_MATCH_LEN: u32 = 3;
+/// Match length above which the hash chains are searched less thoroughly by default.
+const DEFAULT_GOOD_LENGTH: u32 = 32;
+/// Match length from which matches are used without checking the next position for a longer one
+/// by default.
+const DEFAULT_MAX_LAZY: u32 = 128;
 /// The maximum length of a match.
 pub(crate) const MAX_MATCH_LEN: usize = 258;
+/// Size of the dictionary ring buffer in Deflate64 mode.
+///
+/// This has to hold the 64 KiB window as well as the lookahead, which can be as long as the
+/// longest match.
+const LZ_DICT_SIZE_64: usize = 131_072;
+/// The maximum match distance in Deflate64 mode.
+///
+/// Deflate64 allows distances of up to 65536, but hash chain positions are stored as 16-bit
+/// values, so we stop one byte short of that.
+const MAX_DIST_64: u32 = 65_535;
+/// The maximum length of a match in Deflate64 mode.
+const MAX_MATCH_LEN_64: usize = 65_538;
 
 const DEFAULT_FLAGS: u32 = NUM_PROBES[4] | TDEFL_WRITE_ZLIB_HEADER;
 
@@ -300,6 +358,12 @@ fn read_u16_le(slice: &[u8], pos: usize) -> u16 {
     slice[pos] as u16 | ((slice[pos + 1] as u16) << 8)
 }
 
+/// A saved compressor dictionary, see `CompressorOxide::dict_snapshot`.
+#[derive(Clone)]
+pub struct DictSnapshot {
+    dict: DictOxide,
+}
+
 /// Main compression struct.
 pub struct CompressorOxide {
     lz: LZOxide,
@@ -364,6 +428,56 @@ impl CompressorOxide {
         self.dict.reset();
     }
 
+    /// Use `dictionary` as a preset dictionary, so the data compressed afterwards can refer
+    /// back to it. Only the last 32 KiB (64 KiB in Deflate64 mode) are used.
+    ///
+    /// The decompressor has to be given the same dictionary, e.g. with
+    /// `InflateState::set_dictionary`. Preset dictionaries are only supported for raw deflate
+    /// streams, and have to be set before any data is compressed. Returns
+    /// `TDEFLStatus::BadParam` otherwise.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> TDEFLStatus {
+        let started = self.dict.lookahead_pos != 0
+            || self.dict.lookahead_size != 0
+            || self.params.block_index != 0;
+        if started || self.params.flags & TDEFL_WRITE_ZLIB_HEADER != 0 {
+            return TDEFLStatus::BadParam;
+        }
+        let fast = uses_compress_fast(self.params.flags);
+        self.dict.load(dictionary, fast);
+        TDEFLStatus::Okay
+    }
+
+    /// Save the current dictionary, including the hash chains and match finder settings.
+    ///
+    /// This is meant to be called right after `set_dictionary`, so that many small inputs
+    /// can be compressed against the same dictionary with `reset_to_snapshot`, without
+    /// hashing the dictionary again each time.
+    pub fn dict_snapshot(&self) -> DictSnapshot {
+        DictSnapshot {
+            dict: self.dict.clone(),
+        }
+    }
+
+    /// Reset the state of the compressor like `reset`, and then restore the dictionary saved in
+    /// `snapshot`.
+    ///
+    /// The snapshot may come from a different compressor with the same flags. Returns
+    /// `TDEFLStatus::BadParam` without changing anything if only one of them is in Deflate64
+    /// mode.
+    pub fn reset_to_snapshot(&mut self, snapshot: &DictSnapshot) -> TDEFLStatus {
+        if snapshot.dict.size_mask != self.dict.size_mask {
+            return TDEFLStatus::BadParam;
+        }
+        // Only the positions and counts have to be reset, the buffers are always written before
+        // they are read.
+        self.lz.reset();
+        self.params.reset_positions();
+        memset(&mut self.huff.count[0][..MAX_HUFF_SYMBOLS_0], 0);
+        memset(&mut self.huff.count[1][..MAX_HUFF_SYMBOLS_1], 0);
+        self.dict.restore(&snapshot.dict);
+        TDEFLStatus::Okay
+    }
+
     /// Set the compression level of the compressor.
     ///
     /// Using this to change level after compresson has started is supported.
@@ -384,6 +498,82 @@ impl CompressorOxide {
         self.set_format_and_level(format, level);
     }
 
+    /// Set the maximum number of input bytes to put in a single deflate block.
+    ///
+    /// Smaller blocks let a decompressor start producing output sooner, at some cost in
+    /// compression ratio. Blocks may still be ended earlier if the internal buffers fill up, or
+    /// at levels above 1, if the statistics of the data change enough that a new huffman table
+    /// pays off (see `set_split_blocks`). By default, the block size is only limited by the
+    /// internal buffers.
+    pub fn set_max_block_size(&mut self, size: usize) {
+        self.params.max_block_size = cmp::max(1, cmp::min(size, u32::max_value() as usize)) as u32;
+    }
+
+    /// Set whether to end a dynamic block early when the statistics of the data change enough
+    /// that a new huffman table pays off.
+    ///
+    /// This is on by default. With it off, blocks only end when the internal buffers fill up or
+    /// at the maximum block size, like in miniz.
+    pub fn set_split_blocks(&mut self, split_blocks: bool) {
+        self.params.split_blocks = split_blocks;
+    }
+
+    /// Set the number of bits in the hash values used to find matches, which determines the
+    /// size of the hash table.
+    ///
+    /// Fewer bits use less memory, at the cost of more hash collisions, which leads to longer
+    /// hash chains and missed matches at higher levels. Values are clamped to the range 8-16,
+    /// and the default is 15. This corresponds to zlib's `memLevel` plus 7. Level 1 only uses up
+    /// to 12 bits.
+    ///
+    /// # Notes
+    /// This is intended to be called before compression starts. Calling it later is supported,
+    /// but matches against the data seen so far will be lost.
+    pub fn set_hash_bits(&mut self, bits: u8) {
+        let bits = cmp::max(MIN_LZ_HASH_BITS, cmp::min(bits, MAX_LZ_HASH_BITS));
+        self.dict.set_hash_bits(bits);
+    }
+
+    /// Limit the distance of matches to `2^bits` bytes, so the output can be decompressed with a
+    /// smaller window.
+    ///
+    /// Values are clamped to the range 8-15, or 8-16 in Deflate64 mode, and the default is the
+    /// largest window the format allows. The zlib header always advertises a 32 KiB window, which
+    /// is still valid, as the header only gives an upper bound.
+    pub fn set_window_bits(&mut self, bits: u8) {
+        let bits = cmp::max(MIN_WINDOW_BITS, cmp::min(bits, 16));
+        self.dict.window_size = cmp::min(1 << bits, self.dict.max_dist);
+    }
+
+    /// Fine-tune the match finder, with the same meaning as the parameters of zlib's
+    /// `deflateTune`:
+    ///
+    /// * `good_length`: Search a quarter as far down the hash chains once a match of at least
+    ///   this length has been found.
+    /// * `max_lazy`: Use matches of at least this length right away, rather than checking if
+    ///   there is a longer match at the next position.
+    /// * `nice_length`: Stop searching once a match of at least this length has been found.
+    /// * `max_chain`: The maximum number of hash chain entries to check at each position.
+    ///
+    /// # Notes
+    /// These have no effect on level 1, which doesn't use the hash chains, and are reset to the
+    /// defaults for the level when the compression level is changed.
+    pub fn set_tuning(
+        &mut self,
+        good_length: u32,
+        max_lazy: u32,
+        nice_length: u32,
+        max_chain: u32,
+    ) {
+        self.params.max_lazy = max_lazy;
+        self.dict.good_length = good_length;
+        self.dict.nice_length = cmp::max(
+            MIN_MATCH_LEN,
+            cmp::min(nice_length, self.dict.max_match_len),
+        );
+        self.dict.max_probes = probes_from_chain_len(max_chain);
+    }
+
     /// Update the compression settings of the compressor.
     ///
     /// Changing the `DataFormat` after compression has started will result in
@@ -394,11 +584,13 @@ impl CompressorOxide {
     /// `default` or after calling `CompressorOxide::reset()`, and behaviour may be changed
     /// to disallow calling it after starting compression in the future.
     pub fn set_format_and_level(&mut self, data_format: DataFormat, level: u8) {
+        // Deflate64 mode determines the size of the dictionary buffers,
+        // so it can't be changed here.
         let flags = create_comp_flags_from_zip_params(
             level.into(),
             data_format.to_window_bits(),
             CompressionStrategy::Default as i32,
-        );
+        ) | (self.params.flags & TDEFL_DEFLATE64);
         self.params.update_flags(flags);
         self.dict.update_flags(flags);
     }
@@ -825,27 +1017,61 @@ impl HuffmanOxide {
         }
     }
 
-    fn enforce_max_code_size(num_codes: &mut [i32], code_list_len: usize, max_code_size: usize) {
-        if code_list_len <= 1 {
-            return;
+    /// Calculate optimal code lengths limited to `max_code_size` bits using the package-merge
+    /// algorithm, and store the number of codes of each length in `num_codes`.
+    ///
+    /// `freqs` are the frequencies of the used symbols in ascending order. As with
+    /// `calculate_minimum_redundancy`, the least frequent symbols get the longest codes, so only
+    /// the number of codes of each length is needed.
+    fn package_merge(freqs: &[u16], max_code_size: usize, num_codes: &mut [i32]) {
+        let n = freqs.len();
+        debug_assert!(n >= 2);
+        debug_assert!(n <= 1 << max_code_size);
+
+        // Item weights and whether they are leaves for each level, starting with the
+        // deepest one, which only holds leaves.
+        let mut lists: Vec<Vec<(u32, bool)>> = Vec::with_capacity(max_code_size);
+        lists.push(freqs.iter().map(|&f| (u32::from(f), true)).collect());
+        for _ in 1..max_code_size {
+            let packages: Vec<u32> = {
+                let prev = &lists[lists.len() - 1];
+                prev.chunks_exact(2).map(|p| p[0].0 + p[1].0).collect()
+            };
+            let mut list = Vec::with_capacity(n + packages.len());
+            let mut leaf = 0;
+            let mut package = 0;
+            while leaf < n || package < packages.len() {
+                if package >= packages.len()
+                    || (leaf < n && u32::from(freqs[leaf]) <= packages[package])
+                {
+                    list.push((u32::from(freqs[leaf]), true));
+                    leaf += 1;
+                } else {
+                    list.push((packages[package], false));
+                    package += 1;
+                }
+            }
+            lists.push(list);
         }
 
-        num_codes[max_code_size] += num_codes[max_code_size + 1..].iter().sum::<i32>();
-        let total = num_codes[1..=max_code_size]
-            .iter()
-            .rev()
-            .enumerate()
-            .fold(0u32, |total, (i, &x)| total + ((x as u32) << i));
-
-        for _ in (1 << max_code_size)..total {
-            num_codes[max_code_size] -= 1;
-            for i in (1..max_code_size).rev() {
-                if num_codes[i] != 0 {
-                    num_codes[i] -= 1;
-                    num_codes[i + 1] += 2;
-                    break;
-                }
+        // Select the 2n - 2 cheapest items on the top level, and follow the packages
+        // among them down through the levels. Each leaf selected on a level adds one bit
+        // to the code length of that symbol, and since leaves are taken in ascending order
+        // the selected leaves are always the least frequent ones.
+        let mut code_sizes = [0u8; MAX_HUFF_SYMBOLS];
+        let mut num_selected = 2 * n - 2;
+        for list in lists.iter().rev() {
+            let num_leaves = list[..num_selected].iter().filter(|item| item.1).count();
+            for code_size in &mut code_sizes[..num_leaves] {
+                *code_size += 1;
             }
+            num_selected = 2 * (num_selected - num_leaves);
+        }
+        debug_assert_eq!(num_selected, 0);
+
+        memset(num_codes, 0);
+        for &code_size in &code_sizes[..n] {
+            num_codes[code_size as usize] += 1;
         }
     }
 
@@ -888,13 +1114,21 @@ impl HuffmanOxide {
                 &mut symbols0[..num_used_symbols],
                 &mut symbols1[..num_used_symbols],
             );
+            let mut freqs = [0u16; MAX_HUFF_SYMBOLS];
+            for (freq, symbol) in freqs.iter_mut().zip(symbols.iter()) {
+                *freq = symbol.key;
+            }
             Self::calculate_minimum_redundancy(symbols);
 
             for symbol in symbols.iter() {
                 num_codes[symbol.key as usize] += 1;
             }
 
-            Self::enforce_max_code_size(&mut num_codes, num_used_symbols, code_size_limit);
+            // The unrestricted code lengths are optimal if they fit within the limit,
+            // otherwise fall back to the slower package-merge algorithm.
+            if num_codes[code_size_limit + 1..].iter().any(|&n| n != 0) {
+                Self::package_merge(&freqs[..num_used_symbols], code_size_limit, &mut num_codes);
+            }
 
             memset(&mut self.code_sizes[table_num][..], 0);
             memset(&mut self.codes[table_num][..], 0);
@@ -937,12 +1171,36 @@ impl HuffmanOxide {
         }
     }
 
-    fn start_static_block(&mut self, output: &mut OutputBufferOxide) {
-        memset(&mut self.code_sizes[LITLEN_TABLE][0..144], 8);
-        memset(&mut self.code_sizes[LITLEN_TABLE][144..256], 9);
-        memset(&mut self.code_sizes[LITLEN_TABLE][256..280], 7);
-        memset(&mut self.code_sizes[LITLEN_TABLE][280..288], 8);
+    /// Get the number of bits needed for the symbols counted in the current block, including
+    /// extra bits, when using the given literal/length and distance code sizes.
+    fn block_bits(&self, litlen_sizes: &[u8], dist_sizes: &[u8], deflate64: bool) -> u64 {
+        let mut bits = 0;
+        for (sym, (&count, &size)) in self.count[LITLEN_TABLE]
+            .iter()
+            .zip(litlen_sizes)
+            .enumerate()
+        {
+            let extra = match sym {
+                257..=284 => LEN_SYM_EXTRA[sym - 257],
+                // Deflate64 redefines the last length code to take 16 extra bits.
+                285 if deflate64 => 16,
+                _ => 0,
+            };
+            bits += u64::from(count) * u64::from(size + extra);
+        }
+        for ((&count, &size), &extra) in self.count[DIST_TABLE]
+            .iter()
+            .zip(dist_sizes)
+            .zip(&DIST_SYM_EXTRA)
+        {
+            bits += u64::from(count) * u64::from(size + extra);
+        }
+        bits
+    }
 
+    fn start_static_block(&mut self, output: &mut OutputBufferOxide) {
+        self.code_sizes[LITLEN_TABLE][..MAX_HUFF_SYMBOLS_0]
+            .copy_from_slice(&static_litlen_code_sizes());
         memset(&mut self.code_sizes[DIST_TABLE][..32], 5);
 
         self.optimize_table(LITLEN_TABLE, 288, 15, true);
@@ -965,8 +1223,9 @@ impl HuffmanOxide {
                 .take_while(|&x| *x == 0)
                 .count();
 
-        let num_dist_codes = 30
-            - &self.code_sizes[1][1..30]
+        // Distance codes 30 and 31 are only used in Deflate64 streams.
+        let num_dist_codes = 32
+            - &self.code_sizes[1][1..32]
                 .iter()
                 .rev()
                 .take_while(|&x| *x == 0)
@@ -1067,6 +1326,15 @@ impl HuffmanOxide {
     }
 }
 
+/// Code sizes of the fixed literal/length huffman codes.
+fn static_litlen_code_sizes() -> [u8; MAX_HUFF_SYMBOLS_0] {
+    let mut code_sizes = [8; MAX_HUFF_SYMBOLS_0];
+    memset(&mut code_sizes[144..256], 9);
+    memset(&mut code_sizes[256..280], 7);
+    code_sizes
+}
+
+#[derive(Clone)]
 struct DictOxide {
     /// The maximum number of checks in the hash chain, for the initial,
     /// and the lazy match respectively.
@@ -1079,29 +1347,80 @@ struct DictOxide {
     pub lookahead_size: u32,
     pub lookahead_pos: u32,
     pub size: u32,
+
+    /// Mask used to wrap positions in the dictionary ring buffer.
+    pub size_mask: u32,
+    /// The maximum distance of a match.
+    pub max_dist: u32,
+    /// The maximum distance of a match, as limited by `set_window_bits`.
+    pub window_size: u32,
+    /// The maximum length of a match.
+    pub max_match_len: u32,
+    /// How many bits to shift when updating the current hash value.
+    pub hash_shift: u32,
+    /// Mask used to wrap hash values to the size of the hash table.
+    pub hash_mask: u32,
+    /// Match length from which the second, lower number of probes is used.
+    pub good_length: u32,
+    /// Match length from which we stop looking for longer matches.
+    pub nice_length: u32,
 }
 
 fn probes_from_flags(flags: u32) -> [u32; 2] {
-    [
-        1 + ((flags & 0xFFF) + 2) / 3,
-        1 + (((flags & 0xFFF) >> 2) + 2) / 3,
-    ]
+    probes_from_chain_len(flags & 0xFFF)
+}
+
+/// Each probe checks up to three entries in the hash chain.
+fn probes_from_chain_len(chain_len: u32) -> [u32; 2] {
+    [1 + (chain_len + 2) / 3, 1 + ((chain_len >> 2) + 2) / 3]
+}
+
+/// How far to shift the rolling hash per byte so that the last three bytes all affect the
+/// hash value.
+fn hash_shift_from_bits(bits: u8) -> u32 {
+    (u32::from(bits) + MIN_MATCH_LEN - 1) / MIN_MATCH_LEN
 }
 
 impl DictOxide {
     fn new(flags: u32) -> Self {
+        let (dict_size, max_dist, max_match_len) = if flags & TDEFL_DEFLATE64 != 0 {
+            (LZ_DICT_SIZE_64, MAX_DIST_64, MAX_MATCH_LEN_64)
+        } else {
+            (LZ_DICT_SIZE, LZ_DICT_SIZE as u32, MAX_MATCH_LEN)
+        };
         DictOxide {
             max_probes: probes_from_flags(flags),
-            b: Box::default(),
+            b: Box::new(HashBuffers::new(dict_size, max_match_len, LZ_HASH_SIZE)),
             code_buf_dict_pos: 0,
             lookahead_size: 0,
             lookahead_pos: 0,
             size: 0,
+            size_mask: dict_size as u32 - 1,
+            max_dist,
+            window_size: max_dist,
+            max_match_len: max_match_len as u32,
+            hash_shift: hash_shift_from_bits(LZ_HASH_BITS as u8),
+            hash_mask: LZ_HASH_SIZE as u32 - 1,
+            good_length: DEFAULT_GOOD_LENGTH,
+            nice_length: max_match_len as u32,
         }
     }
 
     fn update_flags(&mut self, flags: u32) {
         self.max_probes = probes_from_flags(flags);
+        self.good_length = DEFAULT_GOOD_LENGTH;
+        self.nice_length = self.max_match_len;
+    }
+
+    fn set_hash_bits(&mut self, bits: u8) {
+        let hash_size = 1 << bits;
+        if self.b.hash.len() != hash_size {
+            // The hash chains are rebuilt as new data is added, and all matches are verified
+            // against the dictionary, so dropping the old table is safe.
+            self.b.hash = vec![0; hash_size].into_boxed_slice();
+            self.hash_shift = hash_shift_from_bits(bits);
+            self.hash_mask = hash_size as u32 - 1;
+        }
     }
 
     fn reset(&mut self) {
@@ -1112,6 +1431,60 @@ impl DictOxide {
         self.size = 0;
     }
 
+    /// Load the end of `dictionary` as if it had just been compressed, inserting every
+    /// position into the hash table used by the fast routine if `fast` is set, or the hash
+    /// chains otherwise.
+    fn load(&mut self, dictionary: &[u8], fast: bool) {
+        let dictionary = &dictionary[dictionary.len().saturating_sub(self.max_dist as usize)..];
+        let dict_size = self.size_mask + 1;
+        let n = dictionary.len() as u32;
+
+        let dictb = &mut self.b;
+        dictb.dict[..n as usize].copy_from_slice(dictionary);
+        let m = cmp::min(n, self.max_match_len - 1) as usize;
+        dictb.dict[dict_size as usize..dict_size as usize + m].copy_from_slice(&dictionary[..m]);
+
+        // The last two positions are hashed once the following bytes are known.
+        for pos in 0..n.saturating_sub(2) {
+            let p = pos as usize;
+            if fast {
+                let trigram = u32::from(dictb.dict[p])
+                    | (u32::from(dictb.dict[p + 1]) << 8)
+                    | (u32::from(dictb.dict[p + 2]) << 16);
+                let hash = (trigram ^ (trigram >> (24 - (LZ_HASH_BITS - 8))))
+                    & cmp::min(LEVEL1_HASH_SIZE_MASK, self.hash_mask);
+                dictb.hash[hash as usize] = pos as u16;
+            } else {
+                let hash = ((u32::from(dictb.dict[p]) << (self.hash_shift * 2))
+                    ^ (u32::from(dictb.dict[p + 1]) << self.hash_shift)
+                    ^ u32::from(dictb.dict[p + 2]))
+                    & self.hash_mask;
+                dictb.next[p] = dictb.hash[hash as usize];
+                dictb.hash[hash as usize] = pos as u16;
+            }
+        }
+
+        self.code_buf_dict_pos = n;
+        self.lookahead_pos = n;
+        self.size = n;
+    }
+
+    /// Restore the state saved in `snapshot`, which has to use the same format, dropping any data
+    /// that was still waiting to be processed when it was taken.
+    fn restore(&mut self, snapshot: &DictOxide) {
+        self.b.copy_from(&snapshot.b);
+        self.max_probes = snapshot.max_probes;
+        self.code_buf_dict_pos = snapshot.lookahead_pos;
+        self.lookahead_size = 0;
+        self.lookahead_pos = snapshot.lookahead_pos;
+        self.size = snapshot.size;
+        self.window_size = snapshot.window_size;
+        self.hash_shift = snapshot.hash_shift;
+        self.hash_mask = snapshot.hash_mask;
+        self.good_length = snapshot.good_length;
+        self.nice_length = snapshot.nice_length;
+    }
+
     /// Do an unaligned read of the data at `pos` in the dictionary and treat it as if it was of
     /// type T.
     #[inline]
@@ -1159,16 +1532,18 @@ impl DictOxide {
         // This should normally end up as at worst conditional moves,
         // so it shouldn't slow us down much.
         // TODO: Statically verify these so we don't need to do this.
-        let max_match_len = cmp::min(MAX_MATCH_LEN as u32, max_match_len);
+        let max_match_len = cmp::min(self.max_match_len, max_match_len);
         match_len = cmp::max(match_len, 1);
 
-        let pos = lookahead_pos & LZ_DICT_SIZE_MASK;
+        let pos = lookahead_pos & self.size_mask;
         let mut probe_pos = pos;
         // Number of probes into the hash chains.
-        let mut num_probes_left = self.max_probes[(match_len >= 32) as usize];
+        let mut num_probes_left = self.max_probes[(match_len >= self.good_length) as usize];
+        // Length at which a match is good enough to stop searching.
+        let nice_len = cmp::min(self.nice_length, max_match_len);
 
-        // If we already have a match of the full length don't bother searching for another one.
-        if max_match_len <= match_len {
+        // If we already have a match that is long enough don't bother searching for another one.
+        if nice_len <= match_len {
             return (match_dist, match_len);
         }
 
@@ -1198,9 +1573,10 @@ impl DictOxide {
                         return (match_dist, match_len);
                     }
 
-                    // Mask the position value to get the position in the hash chain of the next
-                    // position to match against.
-                    probe_pos = next_probe_pos & LZ_DICT_SIZE_MASK;
+                    // Get the position in the hash chain of the next position to match against.
+                    // The stored positions are truncated to 16 bits, so compute it from the
+                    // distance, as the dictionary may be larger than that in Deflate64 mode.
+                    probe_pos = lookahead_pos.wrapping_sub(dist) & self.size_mask;
 
                     if self.read_as_u16((probe_pos + match_len - 1) as usize) == c01 {
                         break 'found;
@@ -1222,7 +1598,7 @@ impl DictOxide {
             let mut p = pos + 2;
             let mut q = probe_pos + 2;
             // The first two bytes matched, so check the full length of the match.
-            for _ in 0..32 {
+            for _ in 0..(self.max_match_len - 2) >> 3 {
                 let p_data: u64 = self.read_unaligned_u64(p);
                 let q_data: u64 = self.read_unaligned_u64(q);
                 // Compare of 8 bytes at a time by using unaligned loads of 64-bit integers.
@@ -1238,8 +1614,8 @@ impl DictOxide {
                     if probe_len > match_len {
                         match_dist = dist;
                         match_len = cmp::min(max_match_len, probe_len);
-                        if match_len == max_match_len {
-                            // We found a match that had the maximum allowed length,
+                        if match_len >= nice_len {
+                            // We found a match that is long enough,
                             // so there is now point searching further.
                             return (match_dist, match_len);
                         }
@@ -1251,7 +1627,7 @@ impl DictOxide {
                 }
             }
 
-            return (dist, cmp::min(max_match_len, MAX_MATCH_LEN as u32));
+            return (dist, max_match_len);
         }
     }
 }
@@ -1281,6 +1657,15 @@ struct ParamsOxide {
     pub saved_bits_in: u32,
 
     pub local_buf: Box<LocalBuf>,
+
+    /// The maximum number of input bytes in a block.
+    pub max_block_size: u32,
+    pub split_stats: SplitStats,
+    /// Whether to end dynamic blocks early when the statistics of the data change.
+    pub split_blocks: bool,
+
+    /// Match length from which lazy matching is skipped.
+    pub max_lazy: u32,
 }
 
 impl ParamsOxide {
@@ -1303,16 +1688,27 @@ impl ParamsOxide {
             saved_bit_buffer: 0,
             saved_bits_in: 0,
             local_buf: Box::default(),
+            max_block_size: u32::max_value(),
+            split_stats: SplitStats::new(),
+            split_blocks: true,
+            max_lazy: DEFAULT_MAX_LAZY,
         }
     }
 
     fn update_flags(&mut self, flags: u32) {
         self.flags = flags;
         self.greedy_parsing = self.flags & TDEFL_GREEDY_PARSING_FLAG != 0;
+        self.max_lazy = DEFAULT_MAX_LAZY;
     }
 
     /// Reset state, saving settings.
     fn reset(&mut self) {
+        self.reset_positions();
+        self.local_buf.b = [0; OUT_BUF_SIZE];
+    }
+
+    /// Reset state like `reset`, but leave the contents of the output buffer.
+    fn reset_positions(&mut self) {
         self.block_index = 0;
         self.saved_match_len = 0;
         self.saved_match_dist = 0;
@@ -1327,7 +1723,7 @@ impl ParamsOxide {
         self.prev_return_status = TDEFLStatus::Okay;
         self.saved_bit_buffer = 0;
         self.saved_bits_in = 0;
-        self.local_buf.b = [0; OUT_BUF_SIZE];
+        self.split_stats.reset();
     }
 }
 
@@ -1351,6 +1747,14 @@ impl LZOxide {
         }
     }
 
+    /// Start a new block, leaving the contents of the code buffer.
+    fn reset(&mut self) {
+        self.code_position = 1;
+        self.flag_position = 0;
+        self.total_bytes = 0;
+        self.num_flags_left = 8;
+    }
+
     fn write_code(&mut self, val: u8) {
         self.codes[self.code_position] = val;
         self.code_position += 1;
@@ -1383,10 +1787,246 @@ impl LZOxide {
     }
 }
 
+/// Number of input bytes between checks for a shift in the symbol statistics.
+const SPLIT_CHECK_INTERVAL: u32 = 4 * 1024;
+/// Rough cost in bits of a dynamic block header, not counting the code lengths.
+const SPLIT_HEADER_BASE_COST: u64 = 5 + 5 + 4 + 19 * 3;
+/// Rough cost in bits per symbol present in a dynamic block header.
+const SPLIT_HEADER_SYMBOL_COST: u64 = 4;
+/// Number of fractional bits in the values returned by `log2_fixed`.
+const LOG2_FRAC_BITS: u32 = 4;
+
+/// `log2(1 + i / 16)` in units of 1/16 bit.
+const LOG2_FRAC: [u32; 16] = [0, 1, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 15];
+
+/// Approximation of `log2(x)` in units of `1 / (1 << LOG2_FRAC_BITS)` bits.
+///
+/// Integer math is used so the output of the compressor doesn't depend on the platform.
+fn log2_fixed(x: u32) -> u64 {
+    debug_assert!(x > 0);
+    let int = 31 - x.leading_zeros();
+    let frac = if int >= 4 {
+        (x >> (int - 4)) & 15
+    } else {
+        (x << (4 - int)) & 15
+    };
+    u64::from((int << LOG2_FRAC_BITS) + LOG2_FRAC[frac as usize])
+}
+
+/// Estimate how many bits would be saved by coding the symbols counted in `new` with their own
+/// huffman table instead of one built from `old`, and the number of symbols used in `new`.
+///
+/// Both costs are approximated by the entropy of the counts. Symbols missing from `old` are
+/// treated as if they had a count of one so they still have a finite cost.
+fn shift_gain(old: &[u16], new: &[u16]) -> (u64, u64) {
+    let old_total = old.iter().map(|&n| u32::from(n)).sum::<u32>();
+    let new_total = new.iter().map(|&n| u32::from(n)).sum::<u32>();
+    if new_total == 0 {
+        return (0, 0);
+    }
+    let old_log_total = log2_fixed(cmp::max(old_total, 1));
+    let new_log_total = log2_fixed(new_total);
+
+    let mut old_cost = 0;
+    let mut new_cost = 0;
+    let mut used = 0;
+    for (&o, &n) in old.iter().zip(new) {
+        if n != 0 {
+            let n = u32::from(n);
+            let o = cmp::max(u32::from(o), 1);
+            old_cost += u64::from(n) * old_log_total.saturating_sub(log2_fixed(o));
+            new_cost += u64::from(n) * (new_log_total - log2_fixed(n));
+            used += 1;
+        }
+    }
+
+    (old_cost.saturating_sub(new_cost) >> LOG2_FRAC_BITS, used)
+}
+
+/// Symbol statistics used to decide when to end a dynamic block early.
+///
+/// Every `SPLIT_CHECK_INTERVAL` bytes, the symbols recorded since the last check are compared
+/// to the ones recorded before it. If giving them their own huffman table would save more than
+/// the cost of a new block header, the data has changed enough that it's worth starting a new
+/// block. The block then ends at the last check, so the data that changed starts the new one.
+struct SplitStats {
+    /// Literal/length counts at the last check.
+    pub litlen_count: [u16; MAX_HUFF_SYMBOLS_0],
+    /// Distance counts at the last check.
+    pub dist_count: [u16; MAX_HUFF_SYMBOLS_1],
+    /// Number of bytes in the current block at the last check.
+    pub checked_bytes: u32,
+    /// Number of bytes in the current block at which to do the next check.
+    pub next_check: u32,
+}
+
+impl SplitStats {
+    fn new() -> Self {
+        SplitStats {
+            litlen_count: [0; MAX_HUFF_SYMBOLS_0],
+            dist_count: [0; MAX_HUFF_SYMBOLS_1],
+            checked_bytes: 0,
+            next_check: SPLIT_CHECK_INTERVAL,
+        }
+    }
+
+    fn reset(&mut self) {
+        memset(&mut self.litlen_count, 0);
+        memset(&mut self.dist_count, 0);
+        self.checked_bytes = 0;
+        self.next_check = SPLIT_CHECK_INTERVAL;
+    }
+
+    /// Check whether the block should be ended at `checked_bytes`, given the counts of the
+    /// current block in `h` and the number of bytes it covers.
+    fn should_split(&mut self, h: &HuffmanOxide, total_bytes: u32) -> bool {
+        if total_bytes < self.next_check {
+            return false;
+        }
+        self.next_check = total_bytes + SPLIT_CHECK_INTERVAL;
+
+        // Don't split off the first part of the block, there is nothing to compare it with.
+        if self.litlen_count.iter().any(|&n| n != 0) {
+            let mut litlen_new = [0; MAX_HUFF_SYMBOLS_0];
+            for ((n, &c), &o) in litlen_new
+                .iter_mut()
+                .zip(&h.count[0][..])
+                .zip(&self.litlen_count[..])
+            {
+                *n = c.wrapping_sub(o);
+            }
+            let mut dist_new = [0; MAX_HUFF_SYMBOLS_1];
+            for ((n, &c), &o) in dist_new
+                .iter_mut()
+                .zip(&h.count[1][..])
+                .zip(&self.dist_count[..])
+            {
+                *n = c.wrapping_sub(o);
+            }
+
+            let (litlen_gain, litlen_used) = shift_gain(&self.litlen_count, &litlen_new);
+            let (dist_gain, dist_used) = shift_gain(&self.dist_count, &dist_new);
+            let header_cost =
+                SPLIT_HEADER_BASE_COST + (litlen_used + dist_used) * SPLIT_HEADER_SYMBOL_COST;
+            if litlen_gain + dist_gain > header_cost {
+                return true;
+            }
+        }
+
+        self.litlen_count
+            .copy_from_slice(&h.count[0][..MAX_HUFF_SYMBOLS_0]);
+        self.dist_count
+            .copy_from_slice(&h.count[1][..MAX_HUFF_SYMBOLS_1]);
+        self.checked_bytes = total_bytes;
+        false
+    }
+}
+
+/// A literal or a match as recorded in the LZ code buffer, with the match length minus
+/// `MIN_MATCH_LEN` and the distance minus one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum LZCode {
+    Literal(u8),
+    Match(u16, u16),
+}
+
+/// Read back the codes recorded in `lz` for the current block.
+fn lz_codes(lz: &LZOxide, deflate64: bool) -> Vec<LZCode> {
+    let mut codes = Vec::new();
+    let mut flags = 1;
+    let mut i = 0;
+    while i < lz.code_position {
+        if flags == 1 {
+            // The flags of the last group are shifted into place as more codes are recorded.
+            let mut flag = lz.codes[i];
+            if i == lz.flag_position {
+                if lz.num_flags_left == 8 {
+                    break;
+                }
+                flag >>= lz.num_flags_left;
+            }
+            flags = u32::from(flag) | 0x100;
+            i += 1;
+        }
+        if flags & 1 == 1 {
+            let (match_len, len_size) = if deflate64 {
+                (read_u16_le(&lz.codes, i), 2)
+            } else {
+                (u16::from(lz.codes[i]), 1)
+            };
+            codes.push(LZCode::Match(
+                match_len,
+                read_u16_le(&lz.codes, i + len_size),
+            ));
+            i += len_size + 2;
+        } else {
+            codes.push(LZCode::Literal(lz.codes[i]));
+            i += 1;
+        }
+        flags >>= 1;
+    }
+    codes
+}
+
+/// Flush the current block up to the last statistics check, and record the codes after it
+/// again for the next block.
+fn flush_split_block(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> io::Result<i32> {
+    let deflate64 = d.params.flags & TDEFL_DEFLATE64 != 0;
+    let codes = lz_codes(&d.lz, deflate64);
+    let split_bytes = d.params.split_stats.checked_bytes;
+
+    memset(&mut d.huff.count[0][..MAX_HUFF_SYMBOLS_0], 0);
+    memset(&mut d.huff.count[1][..MAX_HUFF_SYMBOLS_1], 0);
+    d.lz.reset();
+    let mut codes = codes.into_iter();
+    while d.lz.total_bytes < split_bytes {
+        match codes.next() {
+            Some(code) => record_code(&mut d.huff, &mut d.lz, code, deflate64),
+            None => break,
+        }
+    }
+
+    let n = flush_block(d, callback, TDEFLFlush::None)?;
+    for code in codes {
+        record_code(&mut d.huff, &mut d.lz, code, deflate64);
+    }
+    Ok(n)
+}
+
+fn record_code(h: &mut HuffmanOxide, lz: &mut LZOxide, code: LZCode, deflate64: bool) {
+    match code {
+        LZCode::Literal(lit) => record_literal(h, lz, lit),
+        LZCode::Match(len, dist) => record_match(
+            h,
+            lz,
+            u32::from(len) + MIN_MATCH_LEN,
+            u32::from(dist) + 1,
+            deflate64,
+        ),
+    }
+}
+
+/// Get the length symbol and number of extra bits for a match length (minus `MIN_MATCH_LEN`)
+/// in a Deflate64 stream.
+///
+/// Deflate64 redefines length code 285 to take 16 extra bits covering lengths from 3 to 65538,
+/// so a length of 258 has to use code 284 instead.
+#[inline]
+fn len_sym_64(match_len: usize) -> (usize, u32) {
+    if match_len < 255 {
+        (LEN_SYM[match_len] as usize, u32::from(LEN_EXTRA[match_len]))
+    } else if match_len == 255 {
+        (284, 5)
+    } else {
+        (285, 16)
+    }
+}
+
 fn compress_lz_codes(
     huff: &HuffmanOxide,
     output: &mut OutputBufferOxide,
     lz_code_buf: &[u8],
+    deflate64: bool,
 ) -> io::Result<bool> {
     let mut flags = 1;
     let mut bb = BitBuffer {
@@ -1408,21 +2048,42 @@ fn compress_lz_codes(
             let sym;
             let num_extra_bits;
 
-            let match_len = lz_code_buf[i] as usize;
-
-            let match_dist = read_u16_le(lz_code_buf, i + 1);
+            let match_len;
+            let match_dist;
+            if deflate64 {
+                // Deflate64 match lengths don't fit in one byte.
+                match_len = read_u16_le(lz_code_buf, i) as usize;
+                match_dist = read_u16_le(lz_code_buf, i + 2);
+                i += 4;
 
-            i += 3;
+                let (len_sym, len_extra) = len_sym_64(match_len);
+                debug_assert!(huff.code_sizes[0][len_sym] != 0);
+                bb.put_fast(
+                    u64::from(huff.codes[0][len_sym]),
+                    u32::from(huff.code_sizes[0][len_sym]),
+                );
+                bb.put_fast(
+                    match_len as u64 & u64::from(BITMASKS[len_extra as usize]),
+                    len_extra,
+                );
+                // The bit buffer could overflow with the distance code and up to 16 extra
+                // length bits, so flush it before continuing.
+                bb.flush(output)?;
+            } else {
+                match_len = lz_code_buf[i] as usize;
+                match_dist = read_u16_le(lz_code_buf, i + 1);
+                i += 3;
 
-            debug_assert!(huff.code_sizes[0][LEN_SYM[match_len] as usize] != 0);
-            bb.put_fast(
-                u64::from(huff.codes[0][LEN_SYM[match_len] as usize]),
-                u32::from(huff.code_sizes[0][LEN_SYM[match_len] as usize]),
-            );
-            bb.put_fast(
-                match_len as u64 & u64::from(BITMASKS[LEN_EXTRA[match_len] as usize]),
-                u32::from(LEN_EXTRA[match_len]),
-            );
+                debug_assert!(huff.code_sizes[0][LEN_SYM[match_len] as usize] != 0);
+                bb.put_fast(
+                    u64::from(huff.codes[0][LEN_SYM[match_len] as usize]),
+                    u32::from(huff.code_sizes[0][LEN_SYM[match_len] as usize]),
+                );
+                bb.put_fast(
+                    match_len as u64 & u64::from(BITMASKS[LEN_EXTRA[match_len] as usize]),
+                    u32::from(LEN_EXTRA[match_len]),
+                );
+            }
 
             if match_dist < 512 {
                 sym = SMALL_DIST_SYM[match_dist as usize] as usize;
@@ -1481,21 +2142,6 @@ fn compress_lz_codes(
     Ok(true)
 }
 
-fn compress_block(
-    huff: &mut HuffmanOxide,
-    output: &mut OutputBufferOxide,
-    lz: &LZOxide,
-    static_block: bool,
-) -> io::Result<bool> {
-    if static_block {
-        huff.start_static_block(output);
-    } else {
-        huff.start_dynamic_block(output)?;
-    }
-
-    compress_lz_codes(huff, output, &lz.codes[..lz.code_position])
-}
-
 fn flush_block(
     d: &mut CompressorOxide,
     callback: &mut CallbackOxide,
@@ -1529,28 +2175,56 @@ fn flush_block(
 
         saved_buffer = output.save();
 
-        let comp_success = if !use_raw_block {
-            let use_static =
-                (d.params.flags & TDEFL_FORCE_ALL_STATIC_BLOCKS != 0) || (d.lz.total_bytes < 48);
-            compress_block(&mut d.huff, &mut output, &d.lz, use_static)?
-        } else {
-            false
-        };
+        let deflate64 = d.params.flags & TDEFL_DEFLATE64 != 0;
+        let block_start = saved_buffer.pos * 8 + u64::from(saved_buffer.bits_in);
+        let mut use_raw_block = use_raw_block;
+        let mut use_dynamic = false;
+        let mut expected_bits = None;
+        if !use_raw_block {
+            // There will always be one, and only one end of block code.
+            d.huff.count[0][256] = 1;
+            let static_bits = 2 + d.huff.block_bits(
+                &static_litlen_code_sizes(),
+                &[5; MAX_HUFF_SYMBOLS_1],
+                deflate64,
+            );
+
+            // Write the dynamic block header to find out how large it is, so we can compare
+            // the exact size of the block with each of the three block types.
+            let dynamic_bits = if d.params.flags & TDEFL_FORCE_ALL_STATIC_BLOCKS == 0 {
+                d.huff.start_dynamic_block(&mut output)?;
+                let header_bits =
+                    output.inner.position() * 8 + u64::from(output.bits_in) - block_start;
+                header_bits
+                    + d.huff
+                        .block_bits(&d.huff.code_sizes[0], &d.huff.code_sizes[1], deflate64)
+            } else {
+                u64::max_value()
+            };
+
+            // A stored block needs the input data to still be in the dictionary, and can hold
+            // at most 65535 bytes. The length fields start on a byte boundary.
+            let can_store = d.lz.total_bytes <= 0xFFFF
+                && (d.dict.lookahead_pos - d.dict.code_buf_dict_pos <= d.dict.size);
+            let stored_bits = if can_store {
+                let padding = (8 - (saved_buffer.bits_in + 2) % 8) % 8;
+                2 + u64::from(padding) + 32 + 8 * u64::from(d.lz.total_bytes)
+            } else {
+                u64::max_value()
+            };
+
+            expected_bits = Some(if stored_bits < cmp::min(dynamic_bits, static_bits) {
+                use_raw_block = true;
+                stored_bits
+            } else if dynamic_bits < static_bits {
+                use_dynamic = true;
+                dynamic_bits
+            } else {
+                static_bits
+            });
+        }
 
-        // If we failed to compress anything and the output would take up more space than the output
-        // data, output a stored block instead, which has at most 5 bytes of overhead.
-        // We only use some simple heuristics for now.
-        // A stored block will have an overhead of at least 4 bytes containing the block length
-        // but usually more due to the length parameters having to start at a byte boundary and thus
-        // requiring up to 5 bytes of padding.
-        // As a static block will have an overhead of at most 1 bit per byte
-        // (as literals are either 8 or 9 bytes), a raw block will
-        // never take up less space if the number of input bytes are less than 32.
-        let expanded = (d.lz.total_bytes > 32)
-            && (output.inner.position() - saved_buffer.pos + 1 >= u64::from(d.lz.total_bytes))
-            && (d.dict.lookahead_pos - d.dict.code_buf_dict_pos <= d.dict.size);
-
-        if use_raw_block || expanded {
+        if use_raw_block {
             output.load(saved_buffer);
 
             // Block header.
@@ -1565,12 +2239,27 @@ fn flush_block(
 
             // Write the actual bytes.
             for i in 0..d.lz.total_bytes {
-                let pos = (d.dict.code_buf_dict_pos + i) & LZ_DICT_SIZE_MASK;
+                let pos = (d.dict.code_buf_dict_pos + i) & d.dict.size_mask;
                 output.put_bits(u32::from(d.dict.b.dict[pos as usize]), 8);
             }
-        } else if !comp_success {
-            output.load(saved_buffer);
-            compress_block(&mut d.huff, &mut output, &d.lz, true)?;
+        } else {
+            if !use_dynamic {
+                output.load(saved_buffer);
+                d.huff.start_static_block(&mut output);
+            }
+            compress_lz_codes(
+                &d.huff,
+                &mut output,
+                &d.lz.codes[..d.lz.code_position],
+                deflate64,
+            )?;
+        }
+
+        if let Some(bits) = expected_bits {
+            debug_assert_eq!(
+                output.inner.position() * 8 + u64::from(output.bits_in) - block_start,
+                bits
+            );
         }
 
         if flush != TDEFLFlush::None {
@@ -1595,6 +2284,7 @@ fn flush_block(
 
         memset(&mut d.huff.count[0][..MAX_HUFF_SYMBOLS_0], 0);
         memset(&mut d.huff.count[1][..MAX_HUFF_SYMBOLS_1], 0);
+        d.params.split_stats.reset();
 
         d.lz.code_position = 1;
         d.lz.flag_position = 0;
@@ -1622,15 +2312,29 @@ fn record_literal(h: &mut HuffmanOxide, lz: &mut LZOxide, lit: u8) {
     h.count[0][lit as usize] += 1;
 }
 
-fn record_match(h: &mut HuffmanOxide, lz: &mut LZOxide, mut match_len: u32, mut match_dist: u32) {
+fn record_match(
+    h: &mut HuffmanOxide,
+    lz: &mut LZOxide,
+    mut match_len: u32,
+    mut match_dist: u32,
+    deflate64: bool,
+) {
     assert!(match_len >= MIN_MATCH_LEN);
     assert!(match_dist >= 1);
-    assert!(match_dist as usize <= LZ_DICT_SIZE);
+    let max_dist = if deflate64 {
+        MAX_DIST_64
+    } else {
+        LZ_DICT_SIZE as u32
+    };
+    assert!(match_dist <= max_dist);
 
     lz.total_bytes += match_len;
     match_dist -= 1;
     match_len -= MIN_MATCH_LEN;
     lz.write_code(match_len as u8);
+    if deflate64 {
+        lz.write_code((match_len >> 8) as u8);
+    }
     lz.write_code(match_dist as u8);
     lz.write_code((match_dist >> 8) as u8);
 
@@ -1641,10 +2345,15 @@ fn record_match(h: &mut HuffmanOxide, lz: &mut LZOxide, mut match_len: u32, mut
     let symbol = if match_dist < 512 {
         SMALL_DIST_SYM[match_dist as usize]
     } else {
-        LARGE_DIST_SYM[((match_dist >> 8) & 127) as usize]
+        LARGE_DIST_SYM[((match_dist >> 8) & 255) as usize]
     } as usize;
     h.count[1][symbol] += 1;
-    h.count[0][LEN_SYM[match_len as usize] as usize] += 1;
+    let len_sym = if deflate64 {
+        len_sym_64(match_len as usize).0
+    } else {
+        LEN_SYM[match_len as usize] as usize
+    };
+    h.count[0][len_sym] += 1;
 }
 
 fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool {
@@ -1660,54 +2369,62 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
     let mut saved_match_dist = d.params.saved_match_dist;
     let mut saved_match_len = d.params.saved_match_len;
 
+    let deflate64 = d.params.flags & TDEFL_DEFLATE64 != 0;
+    let dict_size = d.dict.size_mask + 1;
+    let max_match_len = d.dict.max_match_len;
+    let hash_shift = d.dict.hash_shift;
+    let hash_mask = d.dict.hash_mask;
+
     while src_pos < in_buf.len() || (d.params.flush != TDEFLFlush::None && lookahead_size != 0) {
         let src_buf_left = in_buf.len() - src_pos;
-        let num_bytes_to_process = cmp::min(src_buf_left, MAX_MATCH_LEN - lookahead_size as usize);
+        let num_bytes_to_process =
+            cmp::min(src_buf_left, (max_match_len - lookahead_size) as usize);
 
         if lookahead_size + d.dict.size >= MIN_MATCH_LEN - 1 && num_bytes_to_process > 0 {
             let dictb = &mut d.dict.b;
 
-            let mut dst_pos = (lookahead_pos + lookahead_size) & LZ_DICT_SIZE_MASK;
+            let mask = d.dict.size_mask;
+
+            let mut dst_pos = (lookahead_pos + lookahead_size) & mask;
             let mut ins_pos = lookahead_pos + lookahead_size - 2;
-            let mut hash = (u32::from(dictb.dict[(ins_pos & LZ_DICT_SIZE_MASK) as usize])
-                << LZ_HASH_SHIFT)
-                ^ u32::from(dictb.dict[((ins_pos + 1) & LZ_DICT_SIZE_MASK) as usize]);
+            let mut hash = (u32::from(dictb.dict[(ins_pos & mask) as usize]) << hash_shift)
+                ^ u32::from(dictb.dict[((ins_pos + 1) & mask) as usize]);
 
             lookahead_size += num_bytes_to_process as u32;
             for &c in &in_buf[src_pos..src_pos + num_bytes_to_process] {
                 dictb.dict[dst_pos as usize] = c;
-                if (dst_pos as usize) < MAX_MATCH_LEN - 1 {
-                    dictb.dict[LZ_DICT_SIZE + dst_pos as usize] = c;
+                if dst_pos < max_match_len - 1 {
+                    dictb.dict[(dict_size + dst_pos) as usize] = c;
                 }
 
-                hash = ((hash << LZ_HASH_SHIFT) ^ u32::from(c)) & (LZ_HASH_SIZE as u32 - 1);
-                dictb.next[(ins_pos & LZ_DICT_SIZE_MASK) as usize] = dictb.hash[hash as usize];
+                hash = ((hash << hash_shift) ^ u32::from(c)) & hash_mask;
+                dictb.next[(ins_pos & mask) as usize] = dictb.hash[hash as usize];
 
                 dictb.hash[hash as usize] = ins_pos as u16;
-                dst_pos = (dst_pos + 1) & LZ_DICT_SIZE_MASK;
+                dst_pos = (dst_pos + 1) & mask;
                 ins_pos += 1;
             }
             src_pos += num_bytes_to_process;
         } else {
             let dictb = &mut d.dict.b;
+            let mask = d.dict.size_mask;
             for &c in &in_buf[src_pos..src_pos + num_bytes_to_process] {
-                let dst_pos = (lookahead_pos + lookahead_size) & LZ_DICT_SIZE_MASK;
+                let dst_pos = (lookahead_pos + lookahead_size) & mask;
                 dictb.dict[dst_pos as usize] = c;
-                if (dst_pos as usize) < MAX_MATCH_LEN - 1 {
-                    dictb.dict[LZ_DICT_SIZE + dst_pos as usize] = c;
+                if dst_pos < max_match_len - 1 {
+                    dictb.dict[(dict_size + dst_pos) as usize] = c;
                 }
 
                 lookahead_size += 1;
                 if lookahead_size + d.dict.size >= MIN_MATCH_LEN {
                     let ins_pos = lookahead_pos + lookahead_size - 3;
-                    let hash = ((u32::from(dictb.dict[(ins_pos & LZ_DICT_SIZE_MASK) as usize])
-                        << (LZ_HASH_SHIFT * 2))
-                        ^ ((u32::from(dictb.dict[((ins_pos + 1) & LZ_DICT_SIZE_MASK) as usize])
-                            << LZ_HASH_SHIFT)
+                    let hash = ((u32::from(dictb.dict[(ins_pos & mask) as usize])
+                        << (hash_shift * 2))
+                        ^ ((u32::from(dictb.dict[((ins_pos + 1) & mask) as usize]) << hash_shift)
                             ^ u32::from(c)))
-                        & (LZ_HASH_SIZE as u32 - 1);
+                        & hash_mask;
 
-                    dictb.next[(ins_pos & LZ_DICT_SIZE_MASK) as usize] = dictb.hash[hash as usize];
+                    dictb.next[(ins_pos & mask) as usize] = dictb.hash[hash as usize];
                     dictb.hash[hash as usize] = ins_pos as u16;
                 }
             }
@@ -1715,8 +2432,8 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
             src_pos += num_bytes_to_process;
         }
 
-        d.dict.size = cmp::min(LZ_DICT_SIZE as u32 - lookahead_size, d.dict.size);
-        if d.params.flush == TDEFLFlush::None && (lookahead_size as usize) < MAX_MATCH_LEN {
+        d.dict.size = cmp::min(dict_size - lookahead_size, d.dict.size);
+        if d.params.flush == TDEFLFlush::None && lookahead_size < max_match_len {
             break;
         }
 
@@ -1727,10 +2444,10 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
         } else {
             MIN_MATCH_LEN - 1
         };
-        let cur_pos = lookahead_pos & LZ_DICT_SIZE_MASK;
+        let cur_pos = lookahead_pos & d.dict.size_mask;
         if d.params.flags & (TDEFL_RLE_MATCHES | TDEFL_FORCE_ALL_RAW_BLOCKS) != 0 {
             if d.dict.size != 0 && d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS == 0 {
-                let c = d.dict.b.dict[((cur_pos.wrapping_sub(1)) & LZ_DICT_SIZE_MASK) as usize];
+                let c = d.dict.b.dict[((cur_pos.wrapping_sub(1)) & d.dict.size_mask) as usize];
                 cur_match_len = d.dict.b.dict[cur_pos as usize..(cur_pos + lookahead_size) as usize]
                     .iter()
                     .take_while(|&x| *x == c)
@@ -1744,7 +2461,7 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
         } else {
             let dist_len = d.dict.find_match(
                 lookahead_pos,
-                d.dict.size,
+                cmp::min(d.dict.size, d.dict.window_size),
                 lookahead_size,
                 cur_match_dist,
                 cur_match_len,
@@ -1763,8 +2480,14 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
         if saved_match_len != 0 {
             if cur_match_len > saved_match_len {
                 record_literal(&mut d.huff, &mut d.lz, saved_lit);
-                if cur_match_len >= 128 {
-                    record_match(&mut d.huff, &mut d.lz, cur_match_len, cur_match_dist);
+                if cur_match_len >= d.params.max_lazy {
+                    record_match(
+                        &mut d.huff,
+                        &mut d.lz,
+                        cur_match_len,
+                        cur_match_dist,
+                        deflate64,
+                    );
                     saved_match_len = 0;
                     len_to_move = cur_match_len;
                 } else {
@@ -1773,7 +2496,13 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
                     saved_match_len = cur_match_len;
                 }
             } else {
-                record_match(&mut d.huff, &mut d.lz, saved_match_len, saved_match_dist);
+                record_match(
+                    &mut d.huff,
+                    &mut d.lz,
+                    saved_match_len,
+                    saved_match_dist,
+                    deflate64,
+                );
                 len_to_move = saved_match_len - 1;
                 saved_match_len = 0;
             }
@@ -1785,11 +2514,17 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
             );
         } else if d.params.greedy_parsing
             || (d.params.flags & TDEFL_RLE_MATCHES != 0)
-            || cur_match_len >= 128
+            || cur_match_len >= d.params.max_lazy
         {
             // If we are using lazy matching, check for matches at the next byte if the current
-            // match was shorter than 128 bytes.
-            record_match(&mut d.huff, &mut d.lz, cur_match_len, cur_match_dist);
+            // match was shorter than `max_lazy` bytes.
+            record_match(
+                &mut d.huff,
+                &mut d.lz,
+                cur_match_len,
+                cur_match_dist,
+                deflate64,
+            );
             len_to_move = cur_match_len;
         } else {
             saved_lit = d.dict.b.dict[cmp::min(cur_pos as usize, d.dict.b.dict.len() - 1)];
@@ -1800,21 +2535,30 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
         lookahead_pos += len_to_move;
         assert!(lookahead_size >= len_to_move);
         lookahead_size -= len_to_move;
-        d.dict.size = cmp::min(d.dict.size + len_to_move, LZ_DICT_SIZE as u32);
+        d.dict.size = cmp::min(d.dict.size + len_to_move, d.dict.max_dist);
 
         let lz_buf_tight = d.lz.code_position > LZ_CODE_BUF_SIZE - 8;
         let raw = d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS != 0;
         let fat = ((d.lz.code_position * 115) >> 7) >= d.lz.total_bytes as usize;
         let fat_or_raw = (d.lz.total_bytes > 31 * 1024) && (fat || raw);
+        let full = d.lz.total_bytes >= d.params.max_block_size;
+        // Only dynamic blocks have a table that can fit the data better.
+        let split = d.params.split_blocks
+            && d.params.flags & (TDEFL_FORCE_ALL_STATIC_BLOCKS | TDEFL_FORCE_ALL_RAW_BLOCKS) == 0
+            && d.params.split_stats.should_split(&d.huff, d.lz.total_bytes);
 
-        if lz_buf_tight || fat_or_raw {
+        if lz_buf_tight || fat_or_raw || full || split {
             d.params.src_pos = src_pos;
             // These values are used in flush_block, so we need to write them back here.
             d.dict.lookahead_size = lookahead_size;
             d.dict.lookahead_pos = lookahead_pos;
 
-            let n = flush_block(d, callback, TDEFLFlush::None)
-                .unwrap_or(TDEFLStatus::PutBufFailed as i32);
+            let n = if split && !(lz_buf_tight || fat_or_raw || full) {
+                flush_split_block(d, callback)
+            } else {
+                flush_block(d, callback, TDEFLFlush::None)
+            };
+            let n = n.unwrap_or(TDEFLStatus::PutBufFailed as i32);
             if n != 0 {
                 d.params.saved_lit = saved_lit;
                 d.params.saved_match_dist = saved_match_dist;
@@ -1846,6 +2590,8 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
         Some(in_buf) => in_buf,
     };
 
+    let level1_hash_mask = cmp::min(LEVEL1_HASH_SIZE_MASK, d.dict.hash_mask);
+
     debug_assert!(d.lz.code_position < LZ_CODE_BUF_SIZE - 2);
 
     while src_pos < in_buf.len() || (d.params.flush != TDEFLFlush::None && lookahead_size > 0) {
@@ -1881,14 +2627,14 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
 
             let first_trigram = d.dict.read_unaligned_u32(cur_pos) & 0xFF_FFFF;
 
-            let hash = (first_trigram ^ (first_trigram >> (24 - (LZ_HASH_BITS - 8))))
-                & LEVEL1_HASH_SIZE_MASK;
+            let hash =
+                (first_trigram ^ (first_trigram >> (24 - (LZ_HASH_BITS - 8)))) & level1_hash_mask;
 
             let mut probe_pos = u32::from(d.dict.b.hash[hash as usize]);
             d.dict.b.hash[hash as usize] = lookahead_pos as u16;
 
             let mut cur_match_dist = (lookahead_pos - probe_pos) as u16;
-            if u32::from(cur_match_dist) <= d.dict.size {
+            if u32::from(cur_match_dist) <= cmp::min(d.dict.size, d.dict.window_size) {
                 probe_pos &= LZ_DICT_SIZE_MASK;
 
                 let trigram = d.dict.read_unaligned_u32(probe_pos) & 0xFF_FFFF;
@@ -1964,7 +2710,9 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
                 cur_pos = (cur_pos + cur_match_len) & LZ_DICT_SIZE_MASK;
                 lookahead_size -= cur_match_len;
 
-                if d.lz.code_position > LZ_CODE_BUF_SIZE - 8 {
+                if d.lz.code_position > LZ_CODE_BUF_SIZE - 8
+                    || d.lz.total_bytes >= d.params.max_block_size
+                {
                     // These values are used in flush_block, so we need to write them back here.
                     d.dict.lookahead_size = lookahead_size;
                     d.dict.lookahead_pos = lookahead_pos;
@@ -2002,7 +2750,9 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
             cur_pos = (cur_pos + 1) & LZ_DICT_SIZE_MASK;
             lookahead_size -= 1;
 
-            if d.lz.code_position > LZ_CODE_BUF_SIZE - 8 {
+            if d.lz.code_position > LZ_CODE_BUF_SIZE - 8
+                || d.lz.total_bytes >= d.params.max_block_size
+            {
                 // These values are used in flush_block, so we need to write them back here.
                 d.dict.lookahead_size = lookahead_size;
                 d.dict.lookahead_pos = lookahead_pos;
@@ -2103,6 +2853,18 @@ pub fn compress_to_output(
     (res.0, res.1)
 }
 
+/// Whether the given flags select the fast compression routine, which uses a different hash
+/// function than the others.
+fn uses_compress_fast(flags: u32) -> bool {
+    let one_probe = flags & MAX_PROBES_MASK as u32 == 1;
+    let greedy = flags & TDEFL_GREEDY_PARSING_FLAG != 0;
+    // The fast compression routine is tailored for the standard 32 KiB window.
+    let filter_or_rle_or_raw = flags
+        & (TDEFL_FILTER_MATCHES | TDEFL_FORCE_ALL_RAW_BLOCKS | TDEFL_RLE_MATCHES | TDEFL_DEFLATE64)
+        != 0;
+    one_probe && greedy && !filter_or_rle_or_raw
+}
+
 fn compress_inner(
     d: &mut CompressorOxide,
     callback: &mut CallbackOxide,
@@ -2126,13 +2888,7 @@ fn compress_inner(
         return res;
     }
 
-    let one_probe = d.params.flags & MAX_PROBES_MASK as u32 == 1;
-    let greedy = d.params.flags & TDEFL_GREEDY_PARSING_FLAG != 0;
-    let filter_or_rle_or_raw = d.params.flags
-        & (TDEFL_FILTER_MATCHES | TDEFL_FORCE_ALL_RAW_BLOCKS | TDEFL_RLE_MATCHES)
-        != 0;
-
-    let compress_success = if one_probe && greedy && !filter_or_rle_or_raw {
+    let compress_success = if uses_compress_fast(d.params.flags) {
         compress_fast(d, callback)
     } else {
         compress_normal(d, callback)
@@ -2236,12 +2992,24 @@ pub fn create_comp_flags_from_zip_params(level: i32, window_bits: i32, strategy:
 
 #[cfg(test)]
 mod test {
+    use super::deflate_flags::{
+        TDEFL_DEFLATE64, TDEFL_FORCE_ALL_RAW_BLOCKS, TDEFL_FORCE_ALL_STATIC_BLOCKS,
+    };
     use super::{
-        compress_to_output, create_comp_flags_from_zip_params, read_u16_le, write_u16_le,
-        CompressionStrategy, CompressorOxide, TDEFLFlush, TDEFLStatus, DEFAULT_FLAGS,
-        MZ_DEFAULT_WINDOW_BITS,
+        compress, compress_to_output, create_comp_flags_from_zip_params, read_u16_le, write_u16_le,
+        CompressionStrategy, CompressorOxide, HuffmanOxide, SplitStats, SymFreq, TDEFLFlush,
+        TDEFLStatus, DEFAULT_FLAGS, MAX_HUFF_SYMBOLS, MAX_SUPPORTED_HUFF_CODESIZE,
+        MZ_DEFAULT_WINDOW_BITS, SPLIT_CHECK_INTERVAL,
+    };
+    use crate::inflate::core::inflate_flags::{TINFL_FLAG_DEFLATE64, TINFL_FLAG_HAS_MORE_INPUT};
+    use crate::inflate::core::{decompress, DecompressorOxide};
+    use crate::inflate::stream::{inflate, InflateState};
+    use crate::inflate::{
+        decompress_to_vec, decompress_to_vec_inner, decompress_to_vec_zlib, TINFLStatus,
     };
-    use crate::inflate::decompress_to_vec;
+    use crate::{DataFormat, MZFlush, MZStatus};
+    use std::io::Cursor;
+    use std::prelude::v1::*;
 
     #[test]
     fn u16_to_slice() {
@@ -2285,4 +3053,603 @@ mod test {
         let decoded = decompress_to_vec(&encoded[..]).unwrap();
         assert_eq!(&decoded[..], &slice[..]);
     }
+
+    fn compress_with_flags(data: &[u8], flags: u32) -> Vec<u8> {
+        compress_with(data, CompressorOxide::new(flags))
+    }
+
+    fn compress_with(data: &[u8], mut d: CompressorOxide) -> Vec<u8> {
+        let mut encoded = vec![];
+        let (status, in_consumed) =
+            compress_to_output(&mut d, data, TDEFLFlush::Finish, |out: &[u8]| {
+                encoded.extend_from_slice(out);
+                true
+            });
+        assert_eq!(status, TDEFLStatus::Done);
+        assert_eq!(in_consumed, data.len());
+        encoded
+    }
+
+    /// Data with repeats further apart than the standard window and runs longer than the
+    /// standard maximum match length.
+    fn deflate64_test_data() -> Vec<u8> {
+        let mut seed = 1u32;
+        let block: Vec<u8> = (0..40_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        let mut data = block.clone();
+        data.extend_from_slice(&block);
+        data.extend(std::iter::repeat(7).take(100_000));
+        data.extend_from_slice(&block[..20_000]);
+        data.extend_from_slice(b"Deflate64");
+        data
+    }
+
+    #[test]
+    fn deflate64_roundtrip() {
+        let data = deflate64_test_data();
+        for &level in &[1, 6, 9] {
+            let flags = create_comp_flags_from_zip_params(level, 0, 0);
+            let standard = compress_with_flags(&data, flags);
+            let encoded = compress_with_flags(&data, flags | TDEFL_DEFLATE64);
+
+            let decoded = decompress_to_vec_inner(&encoded, TINFL_FLAG_DEFLATE64).unwrap();
+            assert!(decoded == data);
+            // The second copy of the block is out of reach for the standard window.
+            assert!(encoded.len() + 30_000 < standard.len());
+        }
+    }
+
+    #[test]
+    fn deflate64_wrapping_output() {
+        let data = deflate64_test_data();
+        let flags = create_comp_flags_from_zip_params(6, 0, 0) | TDEFL_DEFLATE64;
+        let encoded = compress_with_flags(&data, flags);
+
+        // A wrapping output buffer has to hold the whole 64 KiB window.
+        let mut r = DecompressorOxide::new();
+        let mut small = vec![0; 32_768];
+        let res = decompress(
+            &mut r,
+            &encoded,
+            &mut Cursor::new(&mut small[..]),
+            TINFL_FLAG_DEFLATE64,
+        );
+        assert_eq!(res.0, TINFLStatus::BadParam);
+
+        // Feed the input in small pieces to go through the slow decoding paths as well.
+        let mut r = DecompressorOxide::new();
+        let mut window = vec![0; 65_536];
+        let mut out_pos = 0;
+        let mut in_pos = 0;
+        let mut decoded = Vec::new();
+        loop {
+            let in_end = std::cmp::min(in_pos + 1000, encoded.len());
+            let mut flags = TINFL_FLAG_DEFLATE64;
+            if in_end < encoded.len() {
+                flags |= TINFL_FLAG_HAS_MORE_INPUT;
+            }
+            let mut cursor = Cursor::new(&mut window[..]);
+            cursor.set_position(out_pos as u64);
+            let (status, in_consumed, out_written) =
+                decompress(&mut r, &encoded[in_pos..in_end], &mut cursor, flags);
+            decoded.extend_from_slice(&window[out_pos..out_pos + out_written]);
+            in_pos += in_consumed;
+            out_pos = (out_pos + out_written) & 0xFFFF;
+            match status {
+                TINFLStatus::Done => break,
+                TINFLStatus::NeedsMoreInput | TINFLStatus::HasMoreOutput => (),
+                _ => panic!("Failed to decompress: {:?}", status),
+            }
+        }
+        assert!(decoded == data);
+    }
+
+    #[test]
+    fn split_on_statistics_change() {
+        let mut h = HuffmanOxide::default();
+        let mut stats = SplitStats::new();
+
+        // Text-like data, only letters.
+        for _ in 0..200 {
+            for c in b'a'..=b'z' {
+                h.count[0][c as usize] += 1;
+            }
+        }
+        assert!(!stats.should_split(&h, SPLIT_CHECK_INTERVAL));
+
+        // More of the same.
+        for _ in 0..200 {
+            for c in b'a'..=b'z' {
+                h.count[0][c as usize] += 1;
+            }
+        }
+        assert!(!stats.should_split(&h, SPLIT_CHECK_INTERVAL * 2));
+
+        // Binary-like data using all byte values.
+        for _ in 0..20 {
+            for c in 0..256 {
+                h.count[0][c] += 1;
+            }
+        }
+        assert!(stats.should_split(&h, SPLIT_CHECK_INTERVAL * 3));
+    }
+
+    #[test]
+    fn split_mixed_data() {
+        // Text, then noise starting at a multiple of the check interval, then text again.
+        let shift = 6 * SPLIT_CHECK_INTERVAL as usize;
+        let text: Vec<u8> = b"the quick brown fox jumps over the lazy dog, "
+            .iter()
+            .cycle()
+            .enumerate()
+            .map(|(i, &b)| {
+                if i % 97 == 0 {
+                    b'A' + (i % 26) as u8
+                } else {
+                    b
+                }
+            })
+            .take(shift)
+            .collect();
+        let mut seed = 7u32;
+        let noise: Vec<u8> = (0..shift)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect();
+        let mut data = text.clone();
+        data.extend_from_slice(&noise);
+        data.extend_from_slice(&text);
+
+        // Feed the input in small pieces to see where each block starts.
+        let compress_blocks = |split_blocks: bool| {
+            let mut d = CompressorOxide::new(DEFAULT_FLAGS);
+            d.set_split_blocks(split_blocks);
+            let mut encoded = vec![];
+            let mut block_starts = vec![];
+            for chunk in data.chunks(512) {
+                let (status, _) =
+                    compress_to_output(&mut d, chunk, TDEFLFlush::None, |out: &[u8]| {
+                        encoded.extend_from_slice(out);
+                        true
+                    });
+                assert_eq!(status, TDEFLStatus::Okay);
+                let start = d.dict.code_buf_dict_pos as usize;
+                if block_starts.last() != Some(&start) {
+                    block_starts.push(start);
+                }
+            }
+            let (status, _) =
+                compress_to_output(&mut d, &[], TDEFLFlush::Finish, |out: &[u8]| {
+                    encoded.extend_from_slice(out);
+                    true
+                });
+            assert_eq!(status, TDEFLStatus::Done);
+            assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), data);
+            (encoded, block_starts)
+        };
+
+        let (split, split_starts) = compress_blocks(true);
+        let (unsplit, unsplit_starts) = compress_blocks(false);
+        assert_eq!(unsplit_starts, [0]);
+        assert!(split.len() <= unsplit.len());
+
+        // Each shift should start a new block, give or take the drift of the checks, rather
+        // than leaving the first check interval of the new data in the previous block.
+        assert_eq!(split_starts.len(), 3);
+        for (&start, &shift) in split_starts[1..].iter().zip(&[shift, shift * 2]) {
+            assert!(start >= shift - SPLIT_CHECK_INTERVAL as usize, "{}", start);
+            assert!(
+                start < shift + SPLIT_CHECK_INTERVAL as usize / 4,
+                "{}",
+                start
+            );
+        }
+    }
+
+    #[test]
+    fn max_block_size() {
+        let data: Vec<u8> = (0..100_000u32)
+            .map(|i| (i % 251) as u8 ^ (i / 1000) as u8)
+            .collect();
+        for &level in &[1, 6] {
+            let flags = create_comp_flags_from_zip_params(level, 0, 0);
+            let default = compress_with_flags(&data, flags);
+
+            let mut d = CompressorOxide::new(flags);
+            d.set_max_block_size(1000);
+            let encoded = compress_with(&data, d);
+            assert!(decompress_to_vec(&encoded).unwrap() == data);
+            // Each block needs a new header.
+            assert!(encoded.len() > default.len());
+        }
+    }
+
+    #[test]
+    fn hash_bits() {
+        let data = std::fs::read("../miniz/miniz.c").unwrap();
+        for &level in &[1, 6, 9] {
+            let flags = create_comp_flags_from_zip_params(level, 0, 0);
+            let default = compress_with_flags(&data, flags);
+            let mut sizes = vec![];
+            for &bits in &[0, 8, 12, 15, 16, 20] {
+                let mut d = CompressorOxide::new(flags);
+                d.set_hash_bits(bits);
+                let encoded = compress_with(&data, d);
+                assert!(decompress_to_vec(&encoded).unwrap() == data);
+                if bits == 15 {
+                    assert!(encoded == default);
+                }
+                sizes.push(encoded.len());
+            }
+            // Out of range values are clamped.
+            assert_eq!(sizes[0], sizes[1]);
+            assert_eq!(sizes[4], sizes[5]);
+
+            // Changing the hash size in the middle of the stream.
+            let mut d = CompressorOxide::new(flags);
+            let (half, rest) = data.split_at(data.len() / 2);
+            let mut encoded = vec![0; data.len() * 2];
+            let (_, in_consumed, out_first) =
+                compress(&mut d, half, &mut encoded, TDEFLFlush::None);
+            assert_eq!(in_consumed, half.len());
+            d.set_hash_bits(10);
+            let (status, in_consumed, out_rest) =
+                compress(&mut d, rest, &mut encoded[out_first..], TDEFLFlush::Finish);
+            assert_eq!(status, TDEFLStatus::Done);
+            assert_eq!(in_consumed, rest.len());
+            encoded.truncate(out_first + out_rest);
+            assert!(decompress_to_vec(&encoded).unwrap() == data);
+        }
+    }
+
+    /// The compression side of the trade-off measured by the `hash_bits` benchmarks: fewer hash
+    /// bits mean more collisions and missed matches.
+    #[test]
+    fn hash_bits_ratio() {
+        let data = std::fs::read("../benches/data/code").unwrap();
+        for &level in &[1, 6, 9] {
+            let flags = create_comp_flags_from_zip_params(level, -15, 0);
+            let sizes: Vec<usize> = [8, 12, 15, 16]
+                .iter()
+                .map(|&bits| {
+                    let mut d = CompressorOxide::new(flags);
+                    d.set_hash_bits(bits);
+                    compress_with(&data, d).len()
+                })
+                .collect();
+            assert!(sizes[0] > sizes[2], "level {}: {:?}", level, sizes);
+            if level == 1 {
+                // Level 1 only uses up to 12 bits.
+                assert_eq!(sizes[1], sizes[2]);
+                assert_eq!(sizes[2], sizes[3]);
+            }
+        }
+    }
+
+    /// Decompress using a wrapping output buffer of `window_size` bytes, which only gives the
+    /// right result if no match reaches further back than that.
+    fn decompress_with_window(encoded: &[u8], window_size: usize) -> Vec<u8> {
+        let mut r = DecompressorOxide::new();
+        let mut window = vec![0; window_size];
+        let mut out_pos = 0;
+        let mut in_pos = 0;
+        let mut decoded = Vec::new();
+        loop {
+            let mut cursor = Cursor::new(&mut window[..]);
+            cursor.set_position(out_pos as u64);
+            let (status, in_consumed, out_written) =
+                decompress(&mut r, &encoded[in_pos..], &mut cursor, 0);
+            decoded.extend_from_slice(&window[out_pos..out_pos + out_written]);
+            in_pos += in_consumed;
+            out_pos = (out_pos + out_written) & (window_size - 1);
+            match status {
+                TINFLStatus::Done => break,
+                TINFLStatus::HasMoreOutput => (),
+                _ => panic!("Failed to decompress: {:?}", status),
+            }
+        }
+        decoded
+    }
+
+    #[test]
+    fn window_bits() {
+        let data = std::fs::read("../miniz/miniz.c").unwrap();
+        for &level in &[1, 6, 9] {
+            let flags = create_comp_flags_from_zip_params(level, 0, 0);
+            let default = compress_with_flags(&data, flags);
+            let mut sizes = vec![];
+            for &bits in &[0, 8, 9, 12, 15, 20] {
+                let mut d = CompressorOxide::new(flags);
+                d.set_window_bits(bits);
+                let encoded = compress_with(&data, d);
+                let window_size = 1 << std::cmp::max(8, std::cmp::min(bits, 15));
+                assert!(decompress_with_window(&encoded, window_size) == data);
+                if bits >= 15 {
+                    assert!(encoded == default);
+                }
+                sizes.push(encoded.len());
+            }
+            // Out of range values are clamped.
+            assert_eq!(sizes[0], sizes[1]);
+            assert!(sizes[2] > sizes[3] && sizes[3] > sizes[4]);
+        }
+
+        // Smaller windows don't limit the size of stored blocks.
+        let flags = create_comp_flags_from_zip_params(0, 0, 0);
+        let mut d = CompressorOxide::new(flags);
+        d.set_window_bits(9);
+        assert!(compress_with(&data, d) == compress_with_flags(&data, flags));
+    }
+
+    #[test]
+    fn dictionary_snapshot() {
+        let data = std::fs::read("../miniz/miniz.c").unwrap();
+        let (dictionary, rest) = data.split_at(20_000);
+        let records: Vec<&[u8]> = rest.chunks(700).take(30).collect();
+
+        for &level in &[1, 6, 9] {
+            let flags = create_comp_flags_from_zip_params(level, 0, 0);
+            let mut d = CompressorOxide::new(flags);
+            assert_eq!(d.set_dictionary(dictionary), TDEFLStatus::Okay);
+            let snapshot = d.dict_snapshot();
+
+            let mut inflate_state = InflateState::new_boxed(DataFormat::Raw);
+            inflate_state.set_dictionary(dictionary).unwrap();
+            let window = inflate_state.window_snapshot();
+
+            let mut with_dict_size = 0;
+            let mut no_dict_size = 0;
+            for (i, record) in records.iter().enumerate() {
+                let mut fresh = CompressorOxide::new(flags);
+                fresh.set_dictionary(dictionary);
+                let expected = compress_with(record, fresh);
+
+                // Leave codes or pending output behind, which the restore has to drop.
+                let flush = if i % 2 == 0 {
+                    TDEFLFlush::None
+                } else {
+                    TDEFLFlush::Finish
+                };
+                compress(&mut d, record, &mut [0; 10], flush);
+                assert_eq!(d.reset_to_snapshot(&snapshot), TDEFLStatus::Okay);
+                let mut encoded = vec![];
+                let (status, _) =
+                    compress_to_output(&mut d, record, TDEFLFlush::Finish, |out: &[u8]| {
+                        encoded.extend_from_slice(out);
+                        true
+                    });
+                assert_eq!(status, TDEFLStatus::Done);
+                assert!(encoded == expected);
+
+                inflate_state.reset_to_snapshot(&window);
+                let mut decoded = vec![0; record.len() + 1];
+                let res = inflate(&mut inflate_state, &encoded, &mut decoded, MZFlush::Finish);
+                assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+                assert_eq!(&decoded[..res.bytes_written], *record);
+
+                with_dict_size += encoded.len();
+                no_dict_size += compress_with_flags(record, flags).len();
+            }
+            assert!(with_dict_size < no_dict_size);
+        }
+
+        // Dictionaries are only supported at the start of raw streams.
+        let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(6, 15, 0));
+        assert_eq!(d.set_dictionary(dictionary), TDEFLStatus::BadParam);
+        let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(6, 0, 0));
+        compress(&mut d, dictionary, &mut [0; 100], TDEFLFlush::None);
+        assert_eq!(d.set_dictionary(dictionary), TDEFLStatus::BadParam);
+        let snapshot = d.dict_snapshot();
+        let mut d = CompressorOxide::new(TDEFL_DEFLATE64);
+        assert_eq!(d.reset_to_snapshot(&snapshot), TDEFLStatus::BadParam);
+    }
+
+    #[test]
+    fn tuning() {
+        let data = std::fs::read("../miniz/miniz.c").unwrap();
+        let flags = create_comp_flags_from_zip_params(6, 0, 0);
+        let default = compress_with_flags(&data, flags);
+
+        // The defaults for the level shouldn't change the output.
+        let mut d = CompressorOxide::new(flags);
+        d.set_tuning(32, 128, 258, flags & 0xFFF);
+        assert!(compress_with(&data, d) == default);
+
+        let mut sizes = vec![];
+        for &(good, lazy, nice, chain) in &[(4, 4, 8, 4), (32, 258, 258, 4096)] {
+            let mut d = CompressorOxide::new(flags);
+            d.set_tuning(good, lazy, nice, chain);
+            let encoded = compress_with(&data, d);
+            assert!(decompress_to_vec(&encoded).unwrap() == data);
+            sizes.push(encoded.len());
+        }
+        assert!(sizes[0] > default.len());
+        assert!(sizes[1] < default.len());
+
+        // Changing the level resets the tuning.
+        let mut d = CompressorOxide::new(flags);
+        d.set_tuning(4, 4, 8, 4);
+        d.set_compression_level_raw(6);
+        assert!(compress_with(&data, d) == default);
+    }
+
+    /// The heuristic used to limit code lengths before package-merge was implemented.
+    fn enforce_max_code_size(num_codes: &mut [i32], code_list_len: usize, max_code_size: usize) {
+        if code_list_len <= 1 {
+            return;
+        }
+
+        num_codes[max_code_size] += num_codes[max_code_size + 1..].iter().sum::<i32>();
+        let total = num_codes[1..=max_code_size]
+            .iter()
+            .rev()
+            .enumerate()
+            .fold(0u32, |total, (i, &x)| total + ((x as u32) << i));
+
+        for _ in (1 << max_code_size)..total {
+            num_codes[max_code_size] -= 1;
+            for i in (1..max_code_size).rev() {
+                if num_codes[i] != 0 {
+                    num_codes[i] -= 1;
+                    num_codes[i + 1] += 2;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Get the number of bits needed to code `counts` with the code lengths from
+    /// `optimize_table` and with the ones from the old heuristic.
+    fn code_costs(counts: &[u16], code_size_limit: usize) -> (u64, u64) {
+        let mut h = HuffmanOxide::default();
+        h.count[0][..counts.len()].copy_from_slice(counts);
+        h.optimize_table(0, counts.len(), code_size_limit, false);
+
+        let code_sizes = &h.code_sizes[0][..counts.len()];
+        assert!(code_sizes.iter().all(|&l| l as usize <= code_size_limit));
+        let kraft_sum: u64 = code_sizes
+            .iter()
+            .filter(|&&l| l != 0)
+            .map(|&l| 1 << (code_size_limit - l as usize))
+            .sum();
+        assert!(kraft_sum <= 1 << code_size_limit);
+        let optimal = counts
+            .iter()
+            .zip(code_sizes)
+            .map(|(&c, &l)| u64::from(c) * u64::from(l))
+            .sum();
+
+        let mut symbols0 = [SymFreq {
+            key: 0,
+            sym_index: 0,
+        }; MAX_HUFF_SYMBOLS];
+        let mut symbols1 = symbols0;
+        let mut num_used_symbols = 0;
+        for (i, &c) in counts.iter().enumerate() {
+            if c != 0 {
+                symbols0[num_used_symbols] = SymFreq {
+                    key: c,
+                    sym_index: i as u16,
+                };
+                num_used_symbols += 1;
+            }
+        }
+        let symbols = HuffmanOxide::radix_sort_symbols(
+            &mut symbols0[..num_used_symbols],
+            &mut symbols1[..num_used_symbols],
+        );
+        HuffmanOxide::calculate_minimum_redundancy(symbols);
+        let mut num_codes = [0i32; MAX_SUPPORTED_HUFF_CODESIZE + 1];
+        for symbol in symbols.iter() {
+            num_codes[symbol.key as usize] += 1;
+        }
+        enforce_max_code_size(&mut num_codes, num_used_symbols, code_size_limit);
+
+        let mut heuristic = 0;
+        let mut last = num_used_symbols;
+        for i in 1..=code_size_limit {
+            let first = last - num_codes[i] as usize;
+            for symbol in &symbols[first..last] {
+                heuristic += u64::from(counts[symbol.sym_index as usize]) * i as u64;
+            }
+            last = first;
+        }
+
+        (optimal, heuristic)
+    }
+
+    #[test]
+    fn package_merge_vs_heuristic() {
+        let mut histograms: Vec<Vec<u16>> = Vec::new();
+
+        // Byte histograms of blocks of real data.
+        for name in &[
+            "../miniz/miniz.c",
+            "tests/test_data/issue_14.zlib",
+            "tests/test_data/issue_19.deflate",
+        ] {
+            let data = std::fs::read(name).unwrap();
+            for &block_size in &[1024, 8 * 1024, 64 * 1024 - 1] {
+                for block in data.chunks(block_size) {
+                    let mut counts = vec![0u16; 256];
+                    for &b in block {
+                        counts[b as usize] += 1;
+                    }
+                    histograms.push(counts);
+                }
+            }
+        }
+
+        // Skewed distributions whose unrestricted codes are far too long.
+        let mut fib = vec![1u16, 1];
+        while fib.len() < 22 {
+            let next = fib[fib.len() - 1] + fib[fib.len() - 2];
+            fib.push(next);
+        }
+        histograms.push(fib.clone());
+        histograms.push(fib.iter().map(|&f| f / 2 + 1).chain(vec![1; 200]).collect());
+        histograms.push((0..288).map(|i| if i < 10 { 6000 } else { 1 }).collect());
+
+        let mut total_optimal = 0;
+        let mut total_heuristic = 0;
+        for counts in &histograms {
+            // Limits for the literal/length and the code length tables.
+            for &(limit, len) in &[(15, counts.len()), (7, std::cmp::min(19, counts.len()))] {
+                let (optimal, heuristic) = code_costs(&counts[..len], limit);
+                assert!(optimal <= heuristic);
+                total_optimal += optimal;
+                total_heuristic += heuristic;
+            }
+        }
+        assert!(total_optimal < total_heuristic);
+    }
+
+    #[test]
+    fn block_type_selection() {
+        let mut seed = 7u32;
+        let random: Vec<u8> = (0..2000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            b"a".to_vec(),
+            br#"{"id":42,"method":"ping","params":[]}"#.to_vec(),
+            b"Hello, world! "
+                .iter()
+                .cycle()
+                .take(800)
+                .cloned()
+                .collect(),
+            random[..20].to_vec(),
+            random.clone(),
+            (0..=255).collect(),
+        ];
+
+        for input in &inputs {
+            for &level in &[1, 6, 9] {
+                let flags = create_comp_flags_from_zip_params(level, -MZ_DEFAULT_WINDOW_BITS, 0);
+                let encoded = compress_with_flags(input, flags);
+                assert!(decompress_to_vec(&encoded).unwrap() == *input);
+
+                // The inputs fit in a single block, so the chosen block type should never be
+                // larger than forcing one of the other types.
+                for &forced in &[TDEFL_FORCE_ALL_STATIC_BLOCKS, TDEFL_FORCE_ALL_RAW_BLOCKS] {
+                    let forced = compress_with_flags(input, flags | forced);
+                    assert!(decompress_to_vec(&forced).unwrap() == *input);
+                    assert!(encoded.len() <= forced.len());
+                }
+            }
+        }
+    }
 }
@@ -1,10 +1,12 @@
 //! This module contains functionality for compression.
 
+use std::cmp;
 use std::prelude::v1::*;
 
 mod buffer;
 pub mod core;
 pub mod stream;
+pub mod zlib_compat;
 use self::core::*;
 
 /// How much processing the compressor should do to compress the data.
@@ -121,7 +123,8 @@ fn compress_to_vec_inner(input: &[u8], level: u8, window_bits: i32, strategy: i3
     // The comp flags function sets the zlib flag if the window_bits parameter is > 0.
     let flags = create_comp_flags_from_zip_params(level.into(), window_bits, strategy);
     let mut compressor = CompressorOxide::new(flags);
-    let mut output = vec![0; input.len() / 2];
+    // Empty or one byte inputs would give an empty buffer that never grows.
+    let mut output = vec![0; cmp::max(input.len() / 2, 2)];
 
     let mut in_pos = 0;
     let mut out_pos = 0;
@@ -222,4 +225,13 @@ mod test {
         // as neither checks matches against the byte at index 0.)
         assert!(c.len() <= 6);
     }
+
+    #[test]
+    fn empty() {
+        for &level in &[0, 1, 6, 9] {
+            let c = compress_to_vec(&[], level);
+            let d = decompress_to_vec(c.as_slice()).expect("Failed to decompress!");
+            assert!(d.is_empty());
+        }
+    }
 }
@@ -0,0 +1,1425 @@
+//! A compressor that produces exactly the same output as zlib.
+//!
+//! The regular compressor in this crate is derived from miniz, and while its output is valid
+//! deflate data, it is not identical to what zlib produces at the same level. This module is a
+//! port of the compressor from zlib 1.2.13 (`deflate.c` and `trees.c`), using zlib's hash function,
+//! match finder, lazy evaluation rules and block splitting heuristics, so the output is byte for
+//! byte identical to zlib's for the same level, window bits, memory level and strategy.
+//!
+//! The output corresponds to compressing the whole input in a single call to zlib's `deflate` with
+//! `Z_FINISH` and an output buffer large enough to hold the result, as `compress2` does. (Only
+//! stored blocks depend on how the input and output buffers are split up, so this only matters for
+//! level 0.) The gzip wrapper is not supported, as zlib writes the operating system it was built
+//! for into the gzip header.
+//!
+//! ``` rust
+//! use miniz_oxide::deflate::zlib_compat::{compress_to_vec, ZlibParams};
+//! use miniz_oxide::inflate::decompress_to_vec_zlib;
+//!
+//! let data = b"Hello, zlib! Hello, zlib!";
+//! let params = ZlibParams {
+//!     level: 9,
+//!     ..ZlibParams::default()
+//! };
+//! let compressed = compress_to_vec(data, &params).unwrap();
+//! assert_eq!(decompress_to_vec_zlib(&compressed).unwrap(), &data[..]);
+//! ```
+
+use std::cmp;
+use std::prelude::v1::*;
+
+use crate::deflate::core::CompressionStrategy;
+use crate::shared::{update_adler32, HUFFMAN_LENGTH_ORDER, MZ_ADLER32_INIT};
+use crate::MZError;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Minimum amount of lookahead, except at the end of the input.
+const MIN_LOOKAHEAD: usize = MAX_MATCH + MIN_MATCH + 1;
+/// Matches of length 3 are discarded if their distance exceeds this.
+const TOO_FAR: usize = 4096;
+const MAX_STORED: usize = 65535;
+
+const MAX_MEM_LEVEL: i32 = 9;
+const MAX_WBITS: i32 = 15;
+
+const LITERALS: usize = 256;
+const END_BLOCK: usize = 256;
+const LENGTH_CODES: usize = 29;
+const L_CODES: usize = LITERALS + 1 + LENGTH_CODES;
+const D_CODES: usize = 30;
+const BL_CODES: usize = 19;
+const HEAP_SIZE: usize = 2 * L_CODES + 1;
+const MAX_BITS: usize = 15;
+const MAX_BL_BITS: usize = 7;
+
+/// Repeat the previous code length 3-6 times.
+const REP_3_6: usize = 16;
+/// Repeat a zero length 3-10 times.
+const REPZ_3_10: usize = 17;
+/// Repeat a zero length 11-138 times.
+const REPZ_11_138: usize = 18;
+
+/// Compression parameters, with the same meaning as the arguments to zlib's `deflateInit2`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ZlibParams {
+    /// Compression level from 0 to 9, or -1 for the default level of 6.
+    pub level: i32,
+    /// Base two logarithm of the window size. 8 to 15 produces zlib-wrapped data, while -9 to -15
+    /// produces raw deflate data.
+    pub window_bits: i32,
+    /// Memory level from 1 to 9, which determines the size of the hash table and how many
+    /// symbols are collected before a block is written.
+    pub mem_level: i32,
+    /// Compression strategy.
+    pub strategy: CompressionStrategy,
+}
+
+impl Default for ZlibParams {
+    fn default() -> ZlibParams {
+        ZlibParams {
+            level: -1,
+            window_bits: MAX_WBITS,
+            mem_level: 8,
+            strategy: CompressionStrategy::Default,
+        }
+    }
+}
+
+/// Compress the input data to a vector, producing the same output as zlib with the given
+/// parameters.
+///
+/// Returns `MZError::Param` if the parameters are out of range, or describe a gzip stream.
+pub fn compress_to_vec(input: &[u8], params: &ZlibParams) -> Result<Vec<u8>, MZError> {
+    Ok(Deflater::new(input, params)?.compress())
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum BlockFunc {
+    Stored,
+    Fast,
+    Slow,
+}
+
+/// Match finder settings for a compression level.
+struct Config {
+    /// Reduce lazy search above this match length.
+    good_length: usize,
+    /// Don't perform lazy search above this match length. For the fast function, this is instead
+    /// the longest match for which all strings are inserted into the hash table.
+    max_lazy: usize,
+    /// Stop searching when a match of this length is found.
+    nice_length: usize,
+    max_chain: usize,
+    func: BlockFunc,
+}
+
+#[rustfmt::skip]
+const CONFIGURATION_TABLE: [Config; 10] = [
+    Config { good_length: 0, max_lazy: 0, nice_length: 0, max_chain: 0, func: BlockFunc::Stored },
+    Config { good_length: 4, max_lazy: 4, nice_length: 8, max_chain: 4, func: BlockFunc::Fast },
+    Config { good_length: 4, max_lazy: 5, nice_length: 16, max_chain: 8, func: BlockFunc::Fast },
+    Config { good_length: 4, max_lazy: 6, nice_length: 32, max_chain: 32, func: BlockFunc::Fast },
+    Config { good_length: 4, max_lazy: 4, nice_length: 16, max_chain: 16, func: BlockFunc::Slow },
+    Config { good_length: 8, max_lazy: 16, nice_length: 32, max_chain: 32, func: BlockFunc::Slow },
+    Config { good_length: 8, max_lazy: 16, nice_length: 128, max_chain: 128, func: BlockFunc::Slow },
+    Config { good_length: 8, max_lazy: 32, nice_length: 128, max_chain: 256, func: BlockFunc::Slow },
+    Config { good_length: 32, max_lazy: 128, nice_length: 258, max_chain: 1024, func: BlockFunc::Slow },
+    Config { good_length: 32, max_lazy: 258, nice_length: 258, max_chain: 4096, func: BlockFunc::Slow },
+];
+
+/// Length code (0-28) for a match length minus `MIN_MATCH`.
+fn length_code(lc: usize) -> usize {
+    if lc < 8 {
+        lc
+    } else if lc == MAX_MATCH - MIN_MATCH {
+        // Length 258 could also be sent as code 27 with all extra bits set, but the dedicated code
+        // is shorter.
+        LENGTH_CODES - 1
+    } else {
+        let bits = 31 - (lc as u32).leading_zeros() as usize;
+        4 * (bits - 1) + ((lc >> (bits - 2)) & 3)
+    }
+}
+
+fn length_extra_bits(code: usize) -> u32 {
+    if code < 8 || code == LENGTH_CODES - 1 {
+        0
+    } else {
+        (code as u32 >> 2) - 1
+    }
+}
+
+fn length_base(code: usize) -> usize {
+    if code < 8 {
+        code
+    } else {
+        (4 + (code & 3)) << ((code >> 2) - 1)
+    }
+}
+
+/// Distance code (0-29) for a match distance minus one.
+fn dist_code(dist: usize) -> usize {
+    if dist < 4 {
+        dist
+    } else {
+        let bits = 31 - (dist as u32).leading_zeros() as usize;
+        2 * bits + ((dist >> (bits - 1)) & 1)
+    }
+}
+
+fn dist_extra_bits(code: usize) -> u32 {
+    if code < 4 {
+        0
+    } else {
+        (code as u32 >> 1) - 1
+    }
+}
+
+fn dist_base(code: usize) -> usize {
+    if code < 4 {
+        code
+    } else {
+        (2 + (code & 1)) << ((code >> 1) - 1)
+    }
+}
+
+/// Reverse the lowest `len` bits of `code`, as huffman codes are sent starting with the most
+/// significant bit.
+fn bi_reverse(mut code: u16, len: u16) -> u16 {
+    let mut res = 0;
+    for _ in 0..len {
+        res = (res << 1) | (code & 1);
+        code >>= 1;
+    }
+    res
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum TreeKind {
+    Literal,
+    Distance,
+    BitLength,
+}
+
+impl TreeKind {
+    fn elems(self) -> usize {
+        match self {
+            TreeKind::Literal => L_CODES,
+            TreeKind::Distance => D_CODES,
+            TreeKind::BitLength => BL_CODES,
+        }
+    }
+
+    fn max_length(self) -> usize {
+        match self {
+            TreeKind::BitLength => MAX_BL_BITS,
+            _ => MAX_BITS,
+        }
+    }
+
+    fn extra_bits(self, n: usize) -> u32 {
+        match self {
+            TreeKind::Literal if n > LITERALS => length_extra_bits(n - LITERALS - 1),
+            TreeKind::Literal => 0,
+            TreeKind::Distance => dist_extra_bits(n),
+            TreeKind::BitLength => match n {
+                REP_3_6 => 2,
+                REPZ_3_10 => 3,
+                REPZ_11_138 => 7,
+                _ => 0,
+            },
+        }
+    }
+
+    /// Code length of `n` in the fixed huffman code, if the tree has one.
+    fn static_len(self, n: usize) -> Option<u32> {
+        match self {
+            TreeKind::Literal => Some(match n {
+                0..=143 => 8,
+                144..=255 => 9,
+                256..=279 => 7,
+                _ => 8,
+            }),
+            TreeKind::Distance => Some(5),
+            TreeKind::BitLength => None,
+        }
+    }
+}
+
+/// A huffman tree, with room for the internal nodes after the leaves.
+///
+/// zlib stores the frequency and code, and the parent and length, of a node in unions. They are
+/// kept apart here, which makes no difference as each is only used after the other is done with.
+struct Tree {
+    kind: TreeKind,
+    freq: Vec<u32>,
+    code: Vec<u16>,
+    dad: Vec<u16>,
+    len: Vec<u16>,
+    /// Largest code with a non-zero frequency.
+    max_code: usize,
+}
+
+impl Tree {
+    fn new(kind: TreeKind) -> Tree {
+        let size = 2 * kind.elems() + 1;
+        Tree {
+            kind,
+            freq: vec![0; size],
+            code: vec![0; size],
+            dad: vec![0; size],
+            len: vec![0; size],
+            max_code: 0,
+        }
+    }
+
+    /// The fixed huffman code for literals and lengths.
+    fn static_literal() -> Tree {
+        let mut tree = Tree::new(TreeKind::Literal);
+        let mut bl_count = [0; MAX_BITS + 1];
+        for n in 0..L_CODES + 2 {
+            let len = TreeKind::Literal.static_len(n).unwrap() as u16;
+            tree.len[n] = len;
+            bl_count[len as usize] += 1;
+        }
+        gen_codes(&mut tree, L_CODES + 1, &bl_count);
+        tree
+    }
+
+    /// The fixed huffman code for distances.
+    fn static_distance() -> Tree {
+        let mut tree = Tree::new(TreeKind::Distance);
+        for n in 0..D_CODES {
+            tree.len[n] = 5;
+            tree.code[n] = bi_reverse(n as u16, 5);
+        }
+        tree
+    }
+}
+
+/// Assign codes to the leaves of a tree from their code lengths and the number of codes of each
+/// length.
+fn gen_codes(tree: &mut Tree, max_code: usize, bl_count: &[u16; MAX_BITS + 1]) {
+    let mut next_code = [0u16; MAX_BITS + 1];
+    let mut code = 0u32;
+    for bits in 1..=MAX_BITS {
+        code = (code + u32::from(bl_count[bits - 1])) << 1;
+        next_code[bits] = code as u16;
+    }
+
+    for n in 0..=max_code {
+        let len = tree.len[n];
+        if len != 0 {
+            tree.code[n] = bi_reverse(next_code[len as usize], len);
+            next_code[len as usize] = next_code[len as usize].wrapping_add(1);
+        }
+    }
+}
+
+/// Scratch space for building huffman trees, and the estimated size of the current block.
+struct TreeBuilder {
+    heap: Vec<usize>,
+    heap_len: usize,
+    heap_max: usize,
+    /// Depth of each subtree, used to break ties between nodes of equal frequency.
+    depth: Vec<u8>,
+    bl_count: [u16; MAX_BITS + 1],
+    /// Block length in bits with the dynamic trees, including the tree descriptions.
+    opt_len: i64,
+    /// Block length in bits with the fixed trees.
+    static_len: i64,
+}
+
+impl TreeBuilder {
+    fn new() -> TreeBuilder {
+        TreeBuilder {
+            heap: vec![0; HEAP_SIZE],
+            heap_len: 0,
+            heap_max: 0,
+            depth: vec![0; HEAP_SIZE],
+            bl_count: [0; MAX_BITS + 1],
+            opt_len: 0,
+            static_len: 0,
+        }
+    }
+
+    fn smaller(&self, tree: &Tree, n: usize, m: usize) -> bool {
+        tree.freq[n] < tree.freq[m]
+            || (tree.freq[n] == tree.freq[m] && self.depth[n] <= self.depth[m])
+    }
+
+    /// Restore the heap property by moving down the tree starting at node `k`.
+    fn pqdownheap(&mut self, tree: &Tree, mut k: usize) {
+        let v = self.heap[k];
+        let mut j = k << 1;
+        while j <= self.heap_len {
+            if j < self.heap_len && self.smaller(tree, self.heap[j + 1], self.heap[j]) {
+                j += 1;
+            }
+            if self.smaller(tree, v, self.heap[j]) {
+                break;
+            }
+            self.heap[k] = self.heap[j];
+            k = j;
+            j <<= 1;
+        }
+        self.heap[k] = v;
+    }
+
+    /// Build a huffman tree from the frequencies in `tree`, and set the code lengths and codes.
+    /// Also updates `opt_len` and `static_len`.
+    fn build_tree(&mut self, tree: &mut Tree) {
+        let elems = tree.kind.elems();
+        let mut max_code: isize = -1;
+
+        self.heap_len = 0;
+        self.heap_max = HEAP_SIZE;
+        for n in 0..elems {
+            if tree.freq[n] != 0 {
+                self.heap_len += 1;
+                self.heap[self.heap_len] = n;
+                max_code = n as isize;
+                self.depth[n] = 0;
+            } else {
+                tree.len[n] = 0;
+            }
+        }
+
+        // The format requires at least one distance code, and at least one bit for a single
+        // code, so force at least two codes of non-zero frequency.
+        while self.heap_len < 2 {
+            let node = if max_code < 2 {
+                max_code += 1;
+                max_code as usize
+            } else {
+                0
+            };
+            self.heap_len += 1;
+            self.heap[self.heap_len] = node;
+            tree.freq[node] = 1;
+            self.depth[node] = 0;
+            self.opt_len -= 1;
+            if let Some(len) = tree.kind.static_len(node) {
+                self.static_len -= i64::from(len);
+            }
+        }
+        tree.max_code = max_code as usize;
+
+        for n in (1..=self.heap_len / 2).rev() {
+            self.pqdownheap(tree, n);
+        }
+
+        // Repeatedly combine the two least frequent nodes.
+        let mut node = elems;
+        loop {
+            let n = self.heap[1];
+            self.heap[1] = self.heap[self.heap_len];
+            self.heap_len -= 1;
+            self.pqdownheap(tree, 1);
+            let m = self.heap[1];
+
+            // Keep the nodes sorted by frequency.
+            self.heap_max -= 1;
+            self.heap[self.heap_max] = n;
+            self.heap_max -= 1;
+            self.heap[self.heap_max] = m;
+
+            tree.freq[node] = tree.freq[n] + tree.freq[m];
+            self.depth[node] = cmp::max(self.depth[n], self.depth[m]) + 1;
+            tree.dad[n] = node as u16;
+            tree.dad[m] = node as u16;
+
+            self.heap[1] = node;
+            node += 1;
+            self.pqdownheap(tree, 1);
+            if self.heap_len < 2 {
+                break;
+            }
+        }
+        self.heap_max -= 1;
+        self.heap[self.heap_max] = self.heap[1];
+
+        self.gen_bitlen(tree);
+        gen_codes(tree, tree.max_code, &self.bl_count);
+    }
+
+    /// Compute the code lengths from the tree built by `build_tree`, limiting them to the maximum
+    /// length of the tree kind.
+    fn gen_bitlen(&mut self, tree: &mut Tree) {
+        let max_code = tree.max_code;
+        let max_length = tree.kind.max_length();
+        let mut overflow = 0i32;
+
+        self.bl_count = [0; MAX_BITS + 1];
+
+        // The root is at heap_max, and parents come before their children.
+        tree.len[self.heap[self.heap_max]] = 0;
+        for h in self.heap_max + 1..HEAP_SIZE {
+            let n = self.heap[h];
+            let mut bits = tree.len[tree.dad[n] as usize] as usize + 1;
+            if bits > max_length {
+                bits = max_length;
+                overflow += 1;
+            }
+            tree.len[n] = bits as u16;
+
+            if n > max_code {
+                // Not a leaf.
+                continue;
+            }
+
+            self.bl_count[bits] += 1;
+            let xbits = tree.kind.extra_bits(n);
+            let f = i64::from(tree.freq[n]);
+            self.opt_len += f * (bits as i64 + i64::from(xbits));
+            if let Some(len) = tree.kind.static_len(n) {
+                self.static_len += f * i64::from(len + xbits);
+            }
+        }
+        if overflow == 0 {
+            return;
+        }
+
+        // Move leaves down from the deepest level that can take them until the overflowing
+        // leaves fit.
+        loop {
+            let mut bits = max_length - 1;
+            while self.bl_count[bits] == 0 {
+                bits -= 1;
+            }
+            self.bl_count[bits] -= 1;
+            self.bl_count[bits + 1] += 2;
+            self.bl_count[max_length] -= 1;
+            overflow -= 2;
+            if overflow <= 0 {
+                break;
+            }
+        }
+
+        // Reassign the lengths in order of increasing frequency.
+        let mut h = HEAP_SIZE;
+        for bits in (1..=max_length).rev() {
+            let mut n = self.bl_count[bits];
+            while n != 0 {
+                h -= 1;
+                let m = self.heap[h];
+                if m > max_code {
+                    continue;
+                }
+                if tree.len[m] as usize != bits {
+                    self.opt_len +=
+                        (bits as i64 - i64::from(tree.len[m])) * i64::from(tree.freq[m]);
+                    tree.len[m] = bits as u16;
+                }
+                n -= 1;
+            }
+        }
+    }
+}
+
+/// Count the code length codes needed to send `tree` in `bl_tree`.
+fn scan_tree(tree: &mut Tree, bl_tree: &mut Tree) {
+    let max_code = tree.max_code;
+    let mut prevlen = -1i32;
+    let mut nextlen = i32::from(tree.len[0]);
+    let mut count = 0;
+    let (mut max_count, mut min_count) = if nextlen == 0 { (138, 3) } else { (7, 4) };
+
+    // Guard.
+    tree.len[max_code + 1] = 0xffff;
+
+    for n in 0..=max_code {
+        let curlen = nextlen;
+        nextlen = i32::from(tree.len[n + 1]);
+        count += 1;
+        if count < max_count && curlen == nextlen {
+            continue;
+        } else if count < min_count {
+            bl_tree.freq[curlen as usize] += count;
+        } else if curlen != 0 {
+            if curlen != prevlen {
+                bl_tree.freq[curlen as usize] += 1;
+            }
+            bl_tree.freq[REP_3_6] += 1;
+        } else if count <= 10 {
+            bl_tree.freq[REPZ_3_10] += 1;
+        } else {
+            bl_tree.freq[REPZ_11_138] += 1;
+        }
+        count = 0;
+        prevlen = curlen;
+        let (max, min) = if nextlen == 0 {
+            (138, 3)
+        } else if curlen == nextlen {
+            (6, 3)
+        } else {
+            (7, 4)
+        };
+        max_count = max;
+        min_count = min;
+    }
+}
+
+/// Simple LSB-first bit writer. zlib buffers 16 bits at a time, but as the bits are written in
+/// the same order this results in the same bytes.
+struct BitWriter {
+    out: Vec<u8>,
+    bit_buf: u32,
+    bits_in: u32,
+}
+
+impl BitWriter {
+    fn send_bits(&mut self, value: u32, len: u32) {
+        self.bit_buf |= value << self.bits_in;
+        self.bits_in += len;
+        while self.bits_in >= 8 {
+            self.out.push(self.bit_buf as u8);
+            self.bit_buf >>= 8;
+            self.bits_in -= 8;
+        }
+    }
+
+    fn send_code(&mut self, tree: &Tree, n: usize) {
+        self.send_bits(u32::from(tree.code[n]), u32::from(tree.len[n]));
+    }
+
+    /// Pad the output to a byte boundary.
+    fn windup(&mut self) {
+        if self.bits_in > 0 {
+            self.out.push(self.bit_buf as u8);
+        }
+        self.bit_buf = 0;
+        self.bits_in = 0;
+    }
+
+    fn stored_block(&mut self, data: &[u8], last: bool) {
+        self.send_bits(last as u32, 3);
+        self.windup();
+        let len = data.len() as u16;
+        self.out.extend_from_slice(&len.to_le_bytes());
+        self.out.extend_from_slice(&(!len).to_le_bytes());
+        self.out.extend_from_slice(data);
+    }
+}
+
+struct Deflater<'a> {
+    input: &'a [u8],
+    in_pos: usize,
+    wrap: bool,
+    level: usize,
+    strategy: CompressionStrategy,
+
+    w_bits: usize,
+    w_size: usize,
+    w_mask: usize,
+    /// Sliding window of twice the window size. Strings are matched against the lower half,
+    /// and the upper half is moved down when more room is needed.
+    window: Vec<u8>,
+    window_size: usize,
+    /// Previous position with the same hash, indexed by position modulo the window size.
+    prev: Vec<u16>,
+    /// Most recent position for each hash value.
+    head: Vec<u16>,
+    ins_h: usize,
+    hash_mask: usize,
+    hash_shift: usize,
+
+    /// Window position of the start of the current block. Negative once the block start has been
+    /// slid out of the window.
+    block_start: isize,
+    match_length: usize,
+    prev_match: usize,
+    match_available: bool,
+    strstart: usize,
+    match_start: usize,
+    lookahead: usize,
+    prev_length: usize,
+    /// Number of bytes before `strstart` that still need to be inserted into the hash table.
+    insert: usize,
+
+    max_chain_length: usize,
+    max_lazy_match: usize,
+    good_match: usize,
+    nice_match: usize,
+
+    /// Symbols of the current block, as (distance, literal or length - MIN_MATCH). A distance of
+    /// 0 means a literal.
+    syms: Vec<(u16, u8)>,
+    sym_end: usize,
+
+    dyn_ltree: Tree,
+    dyn_dtree: Tree,
+    bl_tree: Tree,
+    static_ltree: Tree,
+    static_dtree: Tree,
+    builder: TreeBuilder,
+    bits: BitWriter,
+}
+
+impl<'a> Deflater<'a> {
+    fn new(input: &'a [u8], params: &ZlibParams) -> Result<Deflater<'a>, MZError> {
+        let level = if params.level == -1 { 6 } else { params.level };
+        let (wrap, w_bits) = match params.window_bits {
+            8..=MAX_WBITS => (true, params.window_bits),
+            -15..=-8 => (false, -params.window_bits),
+            _ => return Err(MZError::Param),
+        };
+        let w_bits = match (level, params.mem_level, w_bits) {
+            (0..=9, 1..=MAX_MEM_LEVEL, 9..=MAX_WBITS) => w_bits,
+            // zlib doesn't support a window size of 256 bytes, but accepts it for zlib streams
+            // and uses a 512 byte window instead.
+            (0..=9, 1..=MAX_MEM_LEVEL, 8) if wrap => 9,
+            _ => return Err(MZError::Param),
+        };
+        let w_bits = w_bits as usize;
+        let w_size = 1 << w_bits;
+        let hash_bits = params.mem_level as usize + 7;
+        let lit_bufsize = 1 << (params.mem_level + 6);
+        let config = &CONFIGURATION_TABLE[level as usize];
+
+        Ok(Deflater {
+            input,
+            in_pos: 0,
+            wrap,
+            level: level as usize,
+            strategy: params.strategy,
+
+            w_bits,
+            w_size,
+            w_mask: w_size - 1,
+            window: vec![0; 2 * w_size],
+            window_size: 2 * w_size,
+            prev: vec![0; w_size],
+            head: vec![0; 1 << hash_bits],
+            ins_h: 0,
+            hash_mask: (1 << hash_bits) - 1,
+            hash_shift: (hash_bits + MIN_MATCH - 1) / MIN_MATCH,
+
+            block_start: 0,
+            match_length: MIN_MATCH - 1,
+            prev_match: 0,
+            match_available: false,
+            strstart: 0,
+            match_start: 0,
+            lookahead: 0,
+            prev_length: MIN_MATCH - 1,
+            insert: 0,
+
+            max_chain_length: config.max_chain,
+            max_lazy_match: config.max_lazy,
+            good_match: config.good_length,
+            nice_match: config.nice_length,
+
+            syms: Vec::with_capacity(lit_bufsize - 1),
+            sym_end: lit_bufsize - 1,
+
+            dyn_ltree: Tree::new(TreeKind::Literal),
+            dyn_dtree: Tree::new(TreeKind::Distance),
+            bl_tree: Tree::new(TreeKind::BitLength),
+            static_ltree: Tree::static_literal(),
+            static_dtree: Tree::static_distance(),
+            builder: TreeBuilder::new(),
+            bits: BitWriter {
+                out: Vec::with_capacity(input.len() / 2 + 64),
+                bit_buf: 0,
+                bits_in: 0,
+            },
+        })
+    }
+
+    fn compress(mut self) -> Vec<u8> {
+        if self.wrap {
+            let mut header = (8 + ((self.w_bits - 8) << 4)) << 8;
+            let level_flags = if self.strategy as i32 >= CompressionStrategy::HuffmanOnly as i32
+                || self.level < 2
+            {
+                0
+            } else if self.level < 6 {
+                1
+            } else if self.level == 6 {
+                2
+            } else {
+                3
+            };
+            header |= level_flags << 6;
+            header += 31 - (header % 31);
+            self.bits.out.push((header >> 8) as u8);
+            self.bits.out.push(header as u8);
+        }
+
+        self.init_block();
+        if self.level == 0 {
+            self.deflate_stored();
+        } else if self.strategy == CompressionStrategy::HuffmanOnly {
+            self.deflate_huff();
+        } else if self.strategy == CompressionStrategy::RLE {
+            self.deflate_rle();
+        } else if CONFIGURATION_TABLE[self.level].func == BlockFunc::Fast {
+            self.deflate_fast();
+        } else {
+            self.deflate_slow();
+        }
+
+        if self.wrap {
+            let adler = update_adler32(MZ_ADLER32_INIT, self.input);
+            self.bits.out.extend_from_slice(&adler.to_be_bytes());
+        }
+        self.bits.out
+    }
+
+    fn max_dist(&self) -> usize {
+        self.w_size - MIN_LOOKAHEAD
+    }
+
+    fn update_hash(&mut self, c: u8) {
+        self.ins_h = ((self.ins_h << self.hash_shift) ^ usize::from(c)) & self.hash_mask;
+    }
+
+    /// Insert the string at `pos` into the hash table, and return the previous head of its hash
+    /// chain.
+    fn insert_string(&mut self, pos: usize) -> usize {
+        self.update_hash(self.window[pos + MIN_MATCH - 1]);
+        let head = self.head[self.ins_h];
+        self.prev[pos & self.w_mask] = head;
+        self.head[self.ins_h] = pos as u16;
+        usize::from(head)
+    }
+
+    fn slide_hash(&mut self) {
+        let w_size = self.w_size;
+        let slide = |p: &mut u16| {
+            *p = if usize::from(*p) >= w_size {
+                (usize::from(*p) - w_size) as u16
+            } else {
+                0
+            };
+        };
+        self.head.iter_mut().for_each(slide);
+        self.prev.iter_mut().for_each(slide);
+    }
+
+    /// Read more input into the window, sliding it down first if needed.
+    fn fill_window(&mut self) {
+        let w_size = self.w_size;
+        loop {
+            let mut more = self.window_size - self.lookahead - self.strstart;
+
+            if self.strstart >= w_size + self.max_dist() {
+                let (lower, upper) = self.window.split_at_mut(w_size);
+                lower[..w_size - more].copy_from_slice(&upper[..w_size - more]);
+                self.match_start = self.match_start.wrapping_sub(w_size);
+                self.strstart -= w_size;
+                self.block_start -= w_size as isize;
+                if self.insert > self.strstart {
+                    self.insert = self.strstart;
+                }
+                self.slide_hash();
+                more += w_size;
+            }
+            if self.in_pos == self.input.len() {
+                break;
+            }
+
+            let n = cmp::min(more, self.input.len() - self.in_pos);
+            let dest = self.strstart + self.lookahead;
+            self.window[dest..dest + n].copy_from_slice(&self.input[self.in_pos..self.in_pos + n]);
+            self.in_pos += n;
+            self.lookahead += n;
+
+            // Initialize the hash value now that we have some input.
+            if self.lookahead + self.insert >= MIN_MATCH {
+                let mut pos = self.strstart - self.insert;
+                self.ins_h = usize::from(self.window[pos]);
+                self.update_hash(self.window[pos + 1]);
+                while self.insert != 0 {
+                    self.update_hash(self.window[pos + MIN_MATCH - 1]);
+                    self.prev[pos & self.w_mask] = self.head[self.ins_h];
+                    self.head[self.ins_h] = pos as u16;
+                    pos += 1;
+                    self.insert -= 1;
+                    if self.lookahead + self.insert < MIN_MATCH {
+                        break;
+                    }
+                }
+            }
+
+            if self.lookahead >= MIN_LOOKAHEAD || self.in_pos == self.input.len() {
+                break;
+            }
+        }
+    }
+
+    /// Find the longest match starting at `strstart` along the hash chain starting at
+    /// `cur_match`, that is longer than `prev_length`. Sets `match_start` if one is found.
+    fn longest_match(&mut self, mut cur_match: usize) -> usize {
+        let mut chain_length = self.max_chain_length;
+        let scan = self.strstart;
+        let mut best_len = self.prev_length;
+        let mut nice_match = self.nice_match;
+        let limit = if self.strstart > self.max_dist() {
+            self.strstart - self.max_dist()
+        } else {
+            0
+        };
+        let window = &self.window[..];
+        let mut scan_end1 = window[scan + best_len - 1];
+        let mut scan_end = window[scan + best_len];
+
+        if self.prev_length >= self.good_match {
+            chain_length >>= 2;
+        }
+        if nice_match > self.lookahead {
+            nice_match = self.lookahead;
+        }
+
+        loop {
+            let m = cur_match;
+            if window[m + best_len] == scan_end
+                && window[m + best_len - 1] == scan_end1
+                && window[m] == window[scan]
+                && window[m + 1] == window[scan + 1]
+            {
+                // The third byte is not compared, as it is implied by the hash when the first two
+                // are equal. zlib compares 8 bytes at a time and checks for the maximum length
+                // between each group, which is always at a multiple of 8 from here.
+                let mut len = 2;
+                loop {
+                    len += 1;
+                    if window[scan + len] != window[m + len] {
+                        break;
+                    }
+                    if len == MAX_MATCH {
+                        break;
+                    }
+                }
+
+                if len > best_len {
+                    self.match_start = cur_match;
+                    best_len = len;
+                    if len >= nice_match {
+                        break;
+                    }
+                    scan_end1 = window[scan + best_len - 1];
+                    scan_end = window[scan + best_len];
+                }
+            }
+
+            cur_match = usize::from(self.prev[cur_match & self.w_mask]);
+            if cur_match <= limit {
+                break;
+            }
+            chain_length -= 1;
+            if chain_length == 0 {
+                break;
+            }
+        }
+
+        cmp::min(best_len, self.lookahead)
+    }
+
+    fn init_block(&mut self) {
+        for f in &mut self.dyn_ltree.freq[..L_CODES] {
+            *f = 0;
+        }
+        for f in &mut self.dyn_dtree.freq[..D_CODES] {
+            *f = 0;
+        }
+        for f in &mut self.bl_tree.freq[..BL_CODES] {
+            *f = 0;
+        }
+        self.dyn_ltree.freq[END_BLOCK] = 1;
+        self.builder.opt_len = 0;
+        self.builder.static_len = 0;
+        self.syms.clear();
+    }
+
+    /// Record a literal, and return whether the block is full.
+    fn tally_lit(&mut self, c: u8) -> bool {
+        self.syms.push((0, c));
+        self.dyn_ltree.freq[usize::from(c)] += 1;
+        self.syms.len() == self.sym_end
+    }
+
+    /// Record a match, and return whether the block is full.
+    fn tally_dist(&mut self, dist: usize, lc: usize) -> bool {
+        self.syms.push((dist as u16, lc as u8));
+        self.dyn_ltree.freq[length_code(lc) + LITERALS + 1] += 1;
+        self.dyn_dtree.freq[dist_code(dist - 1)] += 1;
+        self.syms.len() == self.sym_end
+    }
+
+    /// End the current block at `strstart`.
+    fn flush_block(&mut self, last: bool) {
+        let stored_len = (self.strstart as isize - self.block_start) as usize;
+        let buf = if self.block_start >= 0 {
+            Some(self.block_start as usize)
+        } else {
+            None
+        };
+        self.tr_flush_block(buf, stored_len, last);
+        self.block_start = self.strstart as isize;
+    }
+
+    /// Write the current block using whichever of a stored, fixed or dynamic block is smallest.
+    /// `buf` is the window position of the block data, if it is still in the window.
+    fn tr_flush_block(&mut self, buf: Option<usize>, stored_len: usize, last: bool) {
+        self.builder.build_tree(&mut self.dyn_ltree);
+        self.builder.build_tree(&mut self.dyn_dtree);
+        let max_blindex = self.build_bl_tree();
+
+        // Block lengths in bytes, including the block header.
+        let static_lenb = (self.builder.static_len + 3 + 7) >> 3;
+        let dyn_lenb = (self.builder.opt_len + 3 + 7) >> 3;
+        let opt_lenb = if static_lenb <= dyn_lenb || self.strategy == CompressionStrategy::Fixed {
+            static_lenb
+        } else {
+            dyn_lenb
+        };
+
+        match buf {
+            Some(start) if stored_len as i64 + 4 <= opt_lenb => {
+                self.bits
+                    .stored_block(&self.window[start..start + stored_len], last);
+            }
+            _ if static_lenb == opt_lenb => {
+                self.bits.send_bits(2 + last as u32, 3);
+                self.compress_block(true);
+            }
+            _ => {
+                self.bits.send_bits(4 + last as u32, 3);
+                self.send_all_trees(max_blindex + 1);
+                self.compress_block(false);
+            }
+        }
+        self.init_block();
+
+        if last {
+            self.bits.windup();
+        }
+    }
+
+    /// Build the code length tree, and return the index in `HUFFMAN_LENGTH_ORDER` of the last code
+    /// length code to send.
+    fn build_bl_tree(&mut self) -> usize {
+        scan_tree(&mut self.dyn_ltree, &mut self.bl_tree);
+        scan_tree(&mut self.dyn_dtree, &mut self.bl_tree);
+        self.builder.build_tree(&mut self.bl_tree);
+
+        // At least 4 code length codes have to be sent, which always happens as the end of block
+        // code has a non-zero length.
+        let mut max_blindex = BL_CODES - 1;
+        while max_blindex >= 3 && self.bl_tree.len[HUFFMAN_LENGTH_ORDER[max_blindex] as usize] == 0
+        {
+            max_blindex -= 1;
+        }
+        self.builder.opt_len += 3 * (max_blindex as i64 + 1) + 5 + 5 + 4;
+        max_blindex
+    }
+
+    fn send_all_trees(&mut self, blcodes: usize) {
+        let lcodes = self.dyn_ltree.max_code + 1;
+        let dcodes = self.dyn_dtree.max_code + 1;
+        self.bits.send_bits((lcodes - 257) as u32, 5);
+        self.bits.send_bits((dcodes - 1) as u32, 5);
+        self.bits.send_bits((blcodes - 4) as u32, 4);
+        for &code in &HUFFMAN_LENGTH_ORDER[..blcodes] {
+            self.bits
+                .send_bits(u32::from(self.bl_tree.len[code as usize]), 3);
+        }
+        send_tree(&mut self.bits, &self.dyn_ltree, &self.bl_tree);
+        send_tree(&mut self.bits, &self.dyn_dtree, &self.bl_tree);
+    }
+
+    fn compress_block(&mut self, fixed: bool) {
+        let (ltree, dtree) = if fixed {
+            (&self.static_ltree, &self.static_dtree)
+        } else {
+            (&self.dyn_ltree, &self.dyn_dtree)
+        };
+        let bits = &mut self.bits;
+
+        for &(dist, lc) in &self.syms {
+            let lc = usize::from(lc);
+            if dist == 0 {
+                bits.send_code(ltree, lc);
+            } else {
+                let code = length_code(lc);
+                bits.send_code(ltree, code + LITERALS + 1);
+                let extra = length_extra_bits(code);
+                if extra != 0 {
+                    bits.send_bits((lc - length_base(code)) as u32, extra);
+                }
+
+                let dist = usize::from(dist) - 1;
+                let code = dist_code(dist);
+                bits.send_code(dtree, code);
+                let extra = dist_extra_bits(code);
+                if extra != 0 {
+                    bits.send_bits((dist - dist_base(code)) as u32, extra);
+                }
+            }
+        }
+
+        bits.send_code(ltree, END_BLOCK);
+    }
+
+    /// Level 0: copy the input into stored blocks of the maximum size.
+    fn deflate_stored(&mut self) {
+        let mut remaining = self.input;
+        loop {
+            let len = cmp::min(MAX_STORED, remaining.len());
+            let last = len == remaining.len();
+            self.bits.stored_block(&remaining[..len], last);
+            remaining = &remaining[len..];
+            if last {
+                break;
+            }
+        }
+    }
+
+    /// Greedy matching, used for levels 1 to 3.
+    fn deflate_fast(&mut self) {
+        loop {
+            if self.lookahead < MIN_LOOKAHEAD {
+                self.fill_window();
+                if self.lookahead == 0 {
+                    break;
+                }
+            }
+
+            let mut hash_head = 0;
+            if self.lookahead >= MIN_MATCH {
+                hash_head = self.insert_string(self.strstart);
+            }
+
+            if hash_head != 0 && self.strstart - hash_head <= self.max_dist() {
+                self.match_length = self.longest_match(hash_head);
+            }
+
+            let bflush;
+            if self.match_length >= MIN_MATCH {
+                bflush = self.tally_dist(
+                    self.strstart - self.match_start,
+                    self.match_length - MIN_MATCH,
+                );
+                self.lookahead -= self.match_length;
+
+                // Insert the strings of the match into the hash table, unless the match is long.
+                if self.match_length <= self.max_lazy_match && self.lookahead >= MIN_MATCH {
+                    self.match_length -= 1;
+                    loop {
+                        self.strstart += 1;
+                        self.insert_string(self.strstart);
+                        self.match_length -= 1;
+                        if self.match_length == 0 {
+                            break;
+                        }
+                    }
+                    self.strstart += 1;
+                } else {
+                    self.strstart += self.match_length;
+                    self.match_length = 0;
+                    self.ins_h = usize::from(self.window[self.strstart]);
+                    self.update_hash(self.window[self.strstart + 1]);
+                }
+            } else {
+                bflush = self.tally_lit(self.window[self.strstart]);
+                self.lookahead -= 1;
+                self.strstart += 1;
+            }
+            if bflush {
+                self.flush_block(false);
+            }
+        }
+        self.flush_block(true);
+    }
+
+    /// Lazy matching, used for levels 4 to 9. A match is only used if there is no longer match
+    /// at the next position.
+    fn deflate_slow(&mut self) {
+        loop {
+            if self.lookahead < MIN_LOOKAHEAD {
+                self.fill_window();
+                if self.lookahead == 0 {
+                    break;
+                }
+            }
+
+            let mut hash_head = 0;
+            if self.lookahead >= MIN_MATCH {
+                hash_head = self.insert_string(self.strstart);
+            }
+
+            self.prev_length = self.match_length;
+            self.prev_match = self.match_start;
+            self.match_length = MIN_MATCH - 1;
+
+            if hash_head != 0
+                && self.prev_length < self.max_lazy_match
+                && self.strstart - hash_head <= self.max_dist()
+            {
+                self.match_length = self.longest_match(hash_head);
+
+                if self.match_length <= 5
+                    && (self.strategy == CompressionStrategy::Filtered
+                        || (self.match_length == MIN_MATCH
+                            && self.strstart - self.match_start > TOO_FAR))
+                {
+                    // Short matches that are far away are likely to be more expensive than
+                    // literals.
+                    self.match_length = MIN_MATCH - 1;
+                }
+            }
+
+            if self.prev_length >= MIN_MATCH && self.match_length <= self.prev_length {
+                // The previous match was at least as good, so use it.
+                let max_insert = self.strstart + self.lookahead - MIN_MATCH;
+                let bflush = self.tally_dist(
+                    self.strstart - 1 - self.prev_match,
+                    self.prev_length - MIN_MATCH,
+                );
+
+                self.lookahead -= self.prev_length - 1;
+                self.prev_length -= 2;
+                loop {
+                    self.strstart += 1;
+                    if self.strstart <= max_insert {
+                        self.insert_string(self.strstart);
+                    }
+                    self.prev_length -= 1;
+                    if self.prev_length == 0 {
+                        break;
+                    }
+                }
+                self.match_available = false;
+                self.match_length = MIN_MATCH - 1;
+                self.strstart += 1;
+
+                if bflush {
+                    self.flush_block(false);
+                }
+            } else if self.match_available {
+                // No better match, so output the previous byte as a literal.
+                if self.tally_lit(self.window[self.strstart - 1]) {
+                    self.flush_block(false);
+                }
+                self.strstart += 1;
+                self.lookahead -= 1;
+            } else {
+                // Wait for the next step to decide.
+                self.match_available = true;
+                self.strstart += 1;
+                self.lookahead -= 1;
+            }
+        }
+
+        if self.match_available {
+            self.tally_lit(self.window[self.strstart - 1]);
+            self.match_available = false;
+        }
+        self.flush_block(true);
+    }
+
+    /// Only look for runs of the previous byte, i.e. matches with a distance of 1.
+    fn deflate_rle(&mut self) {
+        loop {
+            // Make sure there is room for the longest run, except at the end of the input.
+            if self.lookahead <= MAX_MATCH {
+                self.fill_window();
+                if self.lookahead == 0 {
+                    break;
+                }
+            }
+
+            self.match_length = 0;
+            if self.lookahead >= MIN_MATCH && self.strstart > 0 {
+                let window = &self.window[..];
+                let prev = window[self.strstart - 1];
+                if window[self.strstart..self.strstart + MIN_MATCH]
+                    .iter()
+                    .all(|&b| b == prev)
+                {
+                    let mut len = MIN_MATCH - 1;
+                    loop {
+                        len += 1;
+                        if window[self.strstart + len] != prev || len == MAX_MATCH {
+                            break;
+                        }
+                    }
+                    self.match_length = cmp::min(len, self.lookahead);
+                }
+            }
+
+            let bflush;
+            if self.match_length >= MIN_MATCH {
+                bflush = self.tally_dist(1, self.match_length - MIN_MATCH);
+                self.lookahead -= self.match_length;
+                self.strstart += self.match_length;
+                self.match_length = 0;
+            } else {
+                bflush = self.tally_lit(self.window[self.strstart]);
+                self.lookahead -= 1;
+                self.strstart += 1;
+            }
+            if bflush {
+                self.flush_block(false);
+            }
+        }
+        self.flush_block(true);
+    }
+
+    /// Only huffman code the literals, without looking for matches.
+    fn deflate_huff(&mut self) {
+        loop {
+            if self.lookahead == 0 {
+                self.fill_window();
+                if self.lookahead == 0 {
+                    break;
+                }
+            }
+
+            self.match_length = 0;
+            let bflush = self.tally_lit(self.window[self.strstart]);
+            self.lookahead -= 1;
+            self.strstart += 1;
+            if bflush {
+                self.flush_block(false);
+            }
+        }
+        self.flush_block(true);
+    }
+}
+
+/// Send a tree in compressed form using the code length codes.
+fn send_tree(bits: &mut BitWriter, tree: &Tree, bl_tree: &Tree) {
+    // The guard after max_code was set by `scan_tree`.
+    let max_code = tree.max_code;
+    let mut prevlen = -1i32;
+    let mut nextlen = i32::from(tree.len[0]);
+    let mut count = 0;
+    let (mut max_count, mut min_count) = if nextlen == 0 { (138, 3) } else { (7, 4) };
+
+    for n in 0..=max_code {
+        let curlen = nextlen;
+        nextlen = i32::from(tree.len[n + 1]);
+        count += 1;
+        if count < max_count && curlen == nextlen {
+            continue;
+        } else if count < min_count {
+            for _ in 0..count {
+                bits.send_code(bl_tree, curlen as usize);
+            }
+        } else if curlen != 0 {
+            if curlen != prevlen {
+                bits.send_code(bl_tree, curlen as usize);
+                count -= 1;
+            }
+            bits.send_code(bl_tree, REP_3_6);
+            bits.send_bits(count - 3, 2);
+        } else if count <= 10 {
+            bits.send_code(bl_tree, REPZ_3_10);
+            bits.send_bits(count - 3, 3);
+        } else {
+            bits.send_code(bl_tree, REPZ_11_138);
+            bits.send_bits(count - 11, 7);
+        }
+        count = 0;
+        prevlen = curlen;
+        let (max, min) = if nextlen == 0 {
+            (138, 3)
+        } else if curlen == nextlen {
+            (6, 3)
+        } else {
+            (7, 4)
+        };
+        max_count = max;
+        min_count = min;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compress_to_vec, ZlibParams};
+    use crate::deflate::core::CompressionStrategy;
+    use crate::inflate::{decompress_to_vec, decompress_to_vec_zlib};
+    use crate::MZError;
+    use std::prelude::v1::*;
+
+    /// Output of zlib 1.2.13 for short inputs.
+    #[test]
+    fn known_output() {
+        let params = ZlibParams::default();
+        assert_eq!(
+            compress_to_vec(b"", &params).unwrap(),
+            [0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01]
+        );
+
+        let params = ZlibParams {
+            level: 9,
+            ..ZlibParams::default()
+        };
+        assert_eq!(
+            compress_to_vec(b"Hello, zlib! Hello, zlib!", &params).unwrap(),
+            [
+                0x78, 0xda, 0xf3, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0xa8, 0xca, 0xc9, 0x4c, 0x52,
+                0x54, 0xf0, 0x40, 0xe2, 0x00, 0x00, 0x6d, 0x55, 0x08, 0x45
+            ]
+        );
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data: Vec<u8> = (0..100_000u32)
+            .map(|i| ((i * 7 / 13) ^ (i >> 9) ^ (i % 1031 * 97 >> 5)) as u8 % 23)
+            .collect();
+        for &level in &[0, 1, 4, 9] {
+            for &window_bits in &[9, -12] {
+                for &strategy in &[
+                    CompressionStrategy::Default,
+                    CompressionStrategy::Filtered,
+                    CompressionStrategy::HuffmanOnly,
+                    CompressionStrategy::RLE,
+                    CompressionStrategy::Fixed,
+                ] {
+                    let params = ZlibParams {
+                        level,
+                        window_bits,
+                        mem_level: 2,
+                        strategy,
+                    };
+                    let compressed = compress_to_vec(&data, &params).unwrap();
+                    let decompressed = if window_bits > 0 {
+                        decompress_to_vec_zlib(&compressed)
+                    } else {
+                        decompress_to_vec(&compressed)
+                    };
+                    assert!(decompressed.unwrap() == data);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_params() {
+        // (level, window bits, memory level)
+        for &(level, window_bits, mem_level) in &[
+            (10, 15, 8),
+            (-2, 15, 8),
+            (6, 16, 8),
+            (6, 31, 8),
+            (6, 7, 8),
+            (6, -8, 8),
+            (6, 15, 0),
+            (6, 15, 10),
+        ] {
+            let params = ZlibParams {
+                level,
+                window_bits,
+                mem_level,
+                strategy: CompressionStrategy::Default,
+            };
+            assert_eq!(compress_to_vec(b"data", &params), Err(MZError::Param));
+        }
+    }
+}
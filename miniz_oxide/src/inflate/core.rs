@@ -111,6 +111,10 @@ pub mod inflate_flags {
     pub const TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF: u32 = 4;
     /// Should we calculate the adler32 checksum of the output data?
     pub const TINFL_FLAG_COMPUTE_ADLER32: u32 = 8;
+    /// The input is a Deflate64 (enhanced deflate) stream.
+    ///
+    /// Deflate64 uses a 64 KiB window, so a wrapping output buffer has to be at least that large.
+    pub const TINFL_FLAG_DEFLATE64: u32 = 16;
 }
 
 use self::inflate_flags::*;
@@ -304,20 +308,63 @@ const LENGTH_EXTRA: [u8; 32] = [
 ];
 
 /// Base length for each distance code.
+///
+/// The last two codes are only valid in Deflate64 streams.
 #[rustfmt::skip]
 const DIST_BASE: [u16; 32] = [
     1,    2,    3,    4,    5,    7,      9,      13,     17,     25,    33,
     49,   65,   97,   129,  193,  257,    385,    513,    769,    1025,  1537,
-    2049, 3073, 4097, 6145, 8193, 12_289, 16_385, 24_577, 32_768, 32_768
+    2049, 3073, 4097, 6145, 8193, 12_289, 16_385, 24_577, 32_769, 49_153
 ];
 
 /// Number of extra bits for each distance code.
 #[rustfmt::skip]
 const DIST_EXTRA: [u8; 32] = [
     0, 0, 0, 0, 1, 1, 2,  2,  3,  3,  4,  4,  5,  5,  6,  6,
-    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13, 13, 13
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13, 14, 14
 ];
 
+/// Get the base length and number of extra bits for length symbol `symbol`.
+///
+/// Deflate64 redefines the last length code to have a base of 3 and 16 extra bits,
+/// allowing matches of up to 65538 bytes.
+#[inline]
+fn length_base_and_extra(symbol: u32, flags: u32) -> (u32, u32) {
+    if symbol == 285 && flags & TINFL_FLAG_DEFLATE64 != 0 {
+        (3, 16)
+    } else {
+        // # Optimization
+        // Mask the value to avoid bounds checks
+        // We could use get_unchecked later if can statically verify that
+        // this will never go out of bounds.
+        let i = (symbol - 257) as usize & BASE_EXTRA_MASK;
+        (u32::from(LENGTH_BASE[i]), u32::from(LENGTH_EXTRA[i]))
+    }
+}
+
+/// The highest valid distance symbol.
+#[inline]
+fn max_dist_symbol(flags: u32) -> i32 {
+    if flags & TINFL_FLAG_DEFLATE64 != 0 {
+        31
+    } else {
+        29
+    }
+}
+
+/// Minimum number of input bytes needed to use the fast decompression loop.
+///
+/// Deflate64 streams can use up to 16 length extra bits and 14 distance extra bits,
+/// which may need one more refill of the bit buffer.
+#[inline]
+fn fast_min_in_len(flags: u32) -> usize {
+    if flags & TINFL_FLAG_DEFLATE64 != 0 {
+        18
+    } else {
+        14
+    }
+}
+
 /// The mask used when indexing the base/extra arrays.
 const BASE_EXTRA_MASK: usize = 32 - 1;
 
@@ -849,7 +896,7 @@ fn decompress_fast(
             // + 29 + 32 (left in bit buf, including last 13 dist extra) = 111 bits < 14 bytes
             // We need the one extra byte as we may write one length and one full match
             // before checking again.
-            if out_buf.bytes_left() < 259 || in_iter.len() < 14 {
+            if out_buf.bytes_left() < 259 || in_iter.len() < fast_min_in_len(flags) {
                 state = State::DecodeLitlen;
                 break 'o TINFLStatus::Done;
             }
@@ -909,16 +956,18 @@ fn decompress_fast(
             break 'o TINFLStatus::Failed;
         } else {
             // The symbol was a length code.
-            // # Optimization
-            // Mask the value to avoid bounds checks
-            // We could use get_unchecked later if can statically verify that
-            // this will never go out of bounds.
-            l.num_extra = u32::from(LENGTH_EXTRA[(l.counter - 257) as usize & BASE_EXTRA_MASK]);
-            l.counter = u32::from(LENGTH_BASE[(l.counter - 257) as usize & BASE_EXTRA_MASK]);
+            let (base, num_extra) = length_base_and_extra(l.counter, flags);
+            l.num_extra = num_extra;
+            l.counter = base;
             // Length and distance codes have a number of extra bits depending on
             // the base, which together with the base gives us the exact value.
 
             fill_bit_buffer(&mut l, &mut in_iter);
+            if l.num_extra > l.num_bits {
+                // Only possible with Deflate64 lengths when using a 32-bit bit buffer.
+                l.bit_buf |= BitBuffer::from(read_u16_le(in_iter)) << l.num_bits;
+                l.num_bits += 16;
+            }
             if l.num_extra != 0 {
                 let extra_bits = l.bit_buf & ((1 << l.num_extra) - 1);
                 l.bit_buf >>= l.num_extra;
@@ -928,7 +977,7 @@ fn decompress_fast(
 
             // We found a length code, so a distance code should follow.
 
-            if cfg!(not(target_pointer_width = "64")) {
+            if cfg!(not(target_pointer_width = "64")) || l.num_extra > 5 {
                 fill_bit_buffer(&mut l, &mut in_iter);
             }
 
@@ -936,7 +985,7 @@ fn decompress_fast(
                 symbol &= 511;
                 l.bit_buf >>= code_len;
                 l.num_bits -= code_len;
-                if symbol > 29 {
+                if symbol > max_dist_symbol(flags) {
                     state.begin(InvalidDist);
                     break 'o TINFLStatus::Failed;
                 }
@@ -956,6 +1005,12 @@ fn decompress_fast(
                 l.dist += extra_bits as u32;
             }
 
+            if l.counter as usize > out_buf.bytes_left() {
+                // A Deflate64 match that doesn't fit, let the slow path write what it can.
+                state.begin(HuffDecodeOuterLoop2);
+                break 'o TINFLStatus::Done;
+            }
+
             let position = out_buf.position();
             if l.dist as usize > out_buf.position()
                 && (flags & TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF != 0)
@@ -1050,8 +1105,10 @@ fn decompress_inner(
     // is large enough to hold the entire output file (in which case it doesn't
     // matter).
     // Also make sure that the output buffer position is not past the end of the output buffer.
+    // Deflate64 also needs a wrapping buffer large enough for the 64 KiB window.
     if (out_buf_size_mask.wrapping_add(1) & out_buf_size_mask) != 0
         || out_cur.position() > out_cur.get_ref().len() as u64
+        || (flags & TINFL_FLAG_DEFLATE64 != 0 && out_buf_size_mask < 0xFFFF)
     {
         return (TINFLStatus::BadParam, 0, 0);
     }
@@ -1348,7 +1405,7 @@ fn decompress_inner(
                 // If there is enough space, use the fast inner decompression
                 // function.
                     out_buf.bytes_left() >= 259 &&
-                    in_iter.len() >= 14
+                    in_iter.len() >= fast_min_in_len(flags)
                 {
                     let (status, new_state) = decompress_fast(
                         r,
@@ -1433,13 +1490,9 @@ fn decompress_inner(
                     // We already verified earlier that the code is > 256.
                     Action::Jump(InvalidLitlen)
                 } else {
-                    // # Optimization
-                    // Mask the value to avoid bounds checks
-                    // We could use get_unchecked later if can statically verify that
-                    // this will never go out of bounds.
-                    l.num_extra =
-                        u32::from(LENGTH_EXTRA[(l.counter - 257) as usize & BASE_EXTRA_MASK]);
-                    l.counter = u32::from(LENGTH_BASE[(l.counter - 257) as usize & BASE_EXTRA_MASK]);
+                    let (base, num_extra) = length_base_and_extra(l.counter, flags);
+                    l.num_extra = num_extra;
+                    l.counter = base;
                     // Length and distance codes have a number of extra bits depending on
                     // the base, which together with the base gives us the exact value.
                     if l.num_extra != 0 {
@@ -1462,7 +1515,7 @@ fn decompress_inner(
                 // Try to read a huffman code from the input buffer and look up what
                 // length code the decoded symbol refers to.
                 decode_huffman_code(r, &mut l, DIST_TABLE, flags, &mut in_iter, |_r, l, symbol| {
-                    if symbol > 29 {
+                    if symbol > max_dist_symbol(flags) {
                         // Invalid distance code.
                         return Action::Jump(InvalidDist)
                     }
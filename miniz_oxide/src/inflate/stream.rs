@@ -35,6 +35,13 @@ pub struct InflateState {
     last_status: TINFLStatus,
 }
 
+/// A saved decompressor window, see `InflateState::window_snapshot`.
+#[derive(Clone)]
+pub struct WindowSnapshot {
+    dict: Box<[u8]>,
+    dict_ofs: usize,
+}
+
 impl Default for InflateState {
     fn default() -> Self {
         InflateState {
@@ -108,6 +115,55 @@ impl InflateState {
         self.data_format = data_format;
         self.last_status = TINFLStatus::NeedsMoreInput;
     }
+
+    /// Use `dictionary` as a preset dictionary, which the compressed data can refer back to.
+    /// Only the last 32 KiB are used.
+    ///
+    /// Preset dictionaries are only supported for raw deflate streams, and have to be set once,
+    /// before decompression starts. Returns `MZError::Param` otherwise.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<(), MZError> {
+        if self.data_format != DataFormat::Raw || !self.first_call {
+            return Err(MZError::Param);
+        }
+        let dictionary = &dictionary[dictionary.len().saturating_sub(TINFL_LZ_DICT_SIZE)..];
+        // Place the dictionary right before the current position in the ring buffer.
+        let start = TINFL_LZ_DICT_SIZE - dictionary.len();
+        self.dict[start..].copy_from_slice(dictionary);
+        self.dict_ofs = 0;
+        // Decompressing directly to the output buffer would lose the dictionary.
+        self.first_call = false;
+        Ok(())
+    }
+
+    /// Save the current window of decompressed data.
+    ///
+    /// This is meant to be called right after `set_dictionary`, so that many small streams
+    /// can be decompressed against the same dictionary with `reset_to_snapshot`.
+    pub fn window_snapshot(&self) -> WindowSnapshot {
+        WindowSnapshot {
+            dict: self.dict.to_vec().into_boxed_slice(),
+            dict_ofs: self.dict_ofs,
+        }
+    }
+
+    /// Reset the decompressor like `reset`, keeping the data format, and then restore the
+    /// window saved in `snapshot`.
+    pub fn reset_to_snapshot(&mut self, snapshot: &WindowSnapshot) {
+        let data_format = self.data_format;
+        self.reset(data_format);
+        self.dict.copy_from_slice(&snapshot.dict);
+        self.dict_ofs = snapshot.dict_ofs;
+        self.first_call = false;
+    }
+
+    /// Prepare to decompress a new stream that may refer back to the data
+    /// decompressed so far, keeping the contents of the window.
+    pub(crate) fn restart_keep_window(&mut self) {
+        self.decompressor().init();
+        self.first_call = true;
+        self.has_flushed = false;
+        self.last_status = TINFLStatus::NeedsMoreInput;
+    }
 }
 
 /// Try to decompress from `input` to `output` with the given `InflateState`
@@ -220,6 +276,38 @@ pub fn inflate(
     }
 }
 
+/// The result of a call to `inflate_chunk`.
+pub(crate) struct ChunkResult {
+    /// The number of bytes consumed from the input slice.
+    pub bytes_consumed: usize,
+    /// The number of bytes written to the output slice.
+    pub bytes_written: usize,
+    /// Whether the end of the stream was reached.
+    pub stream_end: Result<bool, MZError>,
+}
+
+/// Decompress as much of `input` to `output` as possible, for callers that feed in the
+/// compressed data a buffer at a time and check for truncated input themselves.
+///
+/// `MZError::Buf` only means that no progress could be made with the given input, so it is
+/// not an error here: it gives `Ok(false)` like any other status before the end of the stream.
+pub(crate) fn inflate_chunk(
+    state: &mut InflateState,
+    input: &[u8],
+    output: &mut [u8],
+) -> ChunkResult {
+    let res = inflate(state, input, output, MZFlush::None);
+    ChunkResult {
+        bytes_consumed: res.bytes_consumed,
+        bytes_written: res.bytes_written,
+        stream_end: match res.status {
+            Ok(MZStatus::StreamEnd) => Ok(true),
+            Ok(_) | Err(MZError::Buf) => Ok(false),
+            Err(e) => Err(e),
+        },
+    }
+}
+
 fn inflate_loop(
     state: &mut InflateState,
     next_in: &mut &[u8],
@@ -301,7 +389,7 @@ fn push_dict_out(state: &mut InflateState, next_out: &mut &mut [u8]) -> usize {
 #[cfg(test)]
 mod test {
     use super::{inflate, InflateState};
-    use crate::{DataFormat, MZFlush, MZStatus};
+    use crate::{DataFormat, MZError, MZFlush, MZStatus};
     #[test]
     fn test_state() {
         let encoded = [
@@ -322,4 +410,20 @@ mod test {
         assert_eq!(out[..res.bytes_written as usize], b"Hello, zlib!"[..]);
         assert_eq!(res.bytes_consumed, encoded.len());
     }
+
+    #[test]
+    fn test_dictionary() {
+        // "Hello, zlib!" as a raw stream with the match "ello, " referring to the dictionary.
+        let encoded = [0xf3, 0x80, 0xb0, 0xab, 0x72, 0x32, 0x93, 0x14, 0x01];
+        let mut out = vec![0; 50];
+        let mut state = InflateState::new_boxed(DataFormat::Raw);
+        state.set_dictionary(b"Jello, world").unwrap();
+        let res = inflate(&mut state, &encoded, &mut out, MZFlush::Finish);
+        assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+        assert_eq!(out[..res.bytes_written as usize], b"Hello, zlib!"[..]);
+        assert_eq!(state.set_dictionary(b"Jello"), Err(MZError::Param));
+
+        let mut state = InflateState::new_boxed(DataFormat::Zlib);
+        assert_eq!(state.set_dictionary(b"Jello"), Err(MZError::Param));
+    }
 }
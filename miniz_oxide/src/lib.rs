@@ -21,7 +21,8 @@
 //!
 //! ```
 
-#![forbid(unsafe_code)]
+#![cfg_attr(not(feature = "simd"), forbid(unsafe_code))]
+#![cfg_attr(feature = "simd", deny(unsafe_code))]
 #![cfg_attr(all(feature = "mesalock_sgx",
                 not(target_env = "sgx")), no_std)]
 #![cfg_attr(all(target_env = "sgx", target_vendor = "mesalock"), feature(rustc_private))]
@@ -30,10 +31,16 @@
 #[macro_use]
 extern crate sgx_tstd as std;
 
-extern crate adler32;
+extern crate crc32fast;
 
+mod adler32;
+pub mod checksum;
 pub mod deflate;
 pub mod inflate;
+pub mod message;
+pub mod png;
+pub mod predictor;
+pub mod zip;
 mod shared;
 
 pub use crate::shared::update_adler32 as mz_adler32_oxide;
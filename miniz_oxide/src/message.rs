@@ -0,0 +1,289 @@
+//! Per-message compression as used by the WebSocket permessage-deflate extension
+//! ([RFC 7692](https://tools.ietf.org/html/rfc7692)).
+//!
+//! Each message is compressed to raw deflate data ending in a sync flush, and the trailing
+//! `00 00 ff ff` of the empty stored block is removed before sending. The decompressor appends
+//! the tail again before decompressing.
+//!
+//! By default the LZ77 window is carried over from one message to the next ("context
+//! takeover"), which lets later messages refer back to earlier ones. Turning this off
+//! corresponds to the `client_no_context_takeover`/`server_no_context_takeover` parameters.
+use std::prelude::v1::*;
+
+use crate::deflate::core::{
+    compress_to_output, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush, TDEFLStatus,
+};
+use crate::inflate::stream::{inflate_chunk, InflateState};
+use crate::inflate::TINFLStatus;
+use crate::{DataFormat, MZError};
+
+/// The empty stored block that ends every message, removed by the compressor.
+const MESSAGE_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// How much output space to add when decompressing.
+const OUT_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Compressor producing permessage-deflate message payloads.
+pub struct MessageCompressor {
+    compressor: Box<CompressorOxide>,
+    context_takeover: bool,
+}
+
+impl MessageCompressor {
+    /// Create a new compressor using the specified compression level (0-10).
+    ///
+    /// Context takeover is enabled and the full 32 KiB window is used.
+    pub fn new(level: u8) -> MessageCompressor {
+        let flags = create_comp_flags_from_zip_params(level.into(), -15, 0);
+        MessageCompressor {
+            compressor: Box::new(CompressorOxide::new(flags)),
+            context_takeover: true,
+        }
+    }
+
+    /// Set whether later messages may refer back to data from earlier ones.
+    ///
+    /// Pass `false` when `*_no_context_takeover` was negotiated for this direction.
+    pub fn set_context_takeover(&mut self, context_takeover: bool) {
+        self.context_takeover = context_takeover;
+    }
+
+    /// Limit the window to `2^bits` bytes, as negotiated with `*_max_window_bits`.
+    ///
+    /// Values are clamped to the range 8-15.
+    pub fn set_max_window_bits(&mut self, bits: u8) {
+        self.compressor.set_window_bits(bits);
+    }
+
+    /// Compress a complete message, returning the payload with the trailing `00 00 ff ff`
+    /// removed.
+    pub fn compress(&mut self, message: &[u8]) -> Vec<u8> {
+        // All earlier data has been flushed, so an empty message is just an empty stored
+        // block, which is a single zero byte without the tail. Going through the compressor
+        // would output an extra empty block.
+        if message.is_empty() {
+            return vec![0x00];
+        }
+
+        // A full flush resets the dictionary so the next message starts from scratch.
+        let flush = if self.context_takeover {
+            TDEFLFlush::Sync
+        } else {
+            TDEFLFlush::Full
+        };
+
+        let mut output = Vec::with_capacity(message.len() / 2 + MESSAGE_TAIL.len());
+        let (status, _) = compress_to_output(&mut self.compressor, message, flush, |out| {
+            output.extend_from_slice(out);
+            true
+        });
+        // Not supposed to happen unless there is a bug.
+        assert_eq!(
+            status,
+            TDEFLStatus::Okay,
+            "Bug! Unexpectedly failed to compress!"
+        );
+
+        debug_assert!(output.ends_with(&MESSAGE_TAIL));
+        let len = output.len() - MESSAGE_TAIL.len();
+        output.truncate(len);
+        output
+    }
+
+    /// Reset the compressor, discarding any context from earlier messages.
+    pub fn reset(&mut self) {
+        self.compressor.reset();
+    }
+}
+
+/// Decompressor for permessage-deflate message payloads.
+///
+/// The decompressor always keeps a 32 KiB window, so it can decompress data produced with any
+/// negotiated window size.
+pub struct MessageDecompressor {
+    state: Box<InflateState>,
+    context_takeover: bool,
+}
+
+impl MessageDecompressor {
+    /// Create a new decompressor with context takeover enabled.
+    pub fn new() -> MessageDecompressor {
+        MessageDecompressor {
+            state: InflateState::new_boxed(DataFormat::Raw),
+            context_takeover: true,
+        }
+    }
+
+    /// Set whether messages may refer back to data from earlier ones.
+    ///
+    /// Pass `false` when `*_no_context_takeover` was negotiated for this direction.
+    pub fn set_context_takeover(&mut self, context_takeover: bool) {
+        self.context_takeover = context_takeover;
+    }
+
+    /// Decompress a complete message payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MZError::Data` if the payload is not valid deflate data.
+    pub fn decompress(&mut self, message: &[u8]) -> Result<Vec<u8>, MZError> {
+        let mut output = Vec::with_capacity(message.len() * 2);
+        // The tail is ignored if the message already ended the deflate stream.
+        let res = match self.inflate_to_vec(message, &mut output) {
+            Ok(false) => self.inflate_to_vec(&MESSAGE_TAIL, &mut output),
+            res => res,
+        };
+
+        match res {
+            Ok(stream_end) => {
+                if !self.context_takeover {
+                    self.reset();
+                } else if stream_end {
+                    // A final block ends the deflate stream, but the next message still
+                    // continues from the same window.
+                    self.state.restart_keep_window();
+                }
+                Ok(output)
+            }
+            Err(e) => {
+                self.reset();
+                Err(e)
+            }
+        }
+    }
+
+    /// Reset the decompressor, discarding any context from earlier messages.
+    pub fn reset(&mut self) {
+        self.state.reset(DataFormat::Raw);
+    }
+
+    /// Decompress `input` and append the result to `output`.
+    ///
+    /// Returns whether the end of the deflate stream was reached.
+    fn inflate_to_vec(&mut self, mut input: &[u8], output: &mut Vec<u8>) -> Result<bool, MZError> {
+        loop {
+            let out_pos = output.len();
+            output.resize(out_pos + OUT_CHUNK_SIZE, 0);
+            let res = inflate_chunk(&mut self.state, input, &mut output[out_pos..]);
+            output.truncate(out_pos + res.bytes_written);
+            input = &input[res.bytes_consumed..];
+
+            if res.stream_end? {
+                return Ok(true);
+            }
+            let out_full = res.bytes_written == OUT_CHUNK_SIZE;
+            let has_more_output = self.state.last_status() == TINFLStatus::HasMoreOutput;
+            if input.is_empty() && !out_full && !has_more_output {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+impl Default for MessageDecompressor {
+    fn default() -> Self {
+        MessageDecompressor::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MessageCompressor, MessageDecompressor};
+
+    fn messages() -> Vec<Vec<u8>> {
+        let text = b"Hello, WebSocket! This message is repeated. ".to_vec();
+        vec![
+            text.clone(),
+            text.clone(),
+            Vec::new(),
+            (0..50_000u32)
+                .map(|n| (n % 251) as u8 ^ (n >> 9) as u8)
+                .collect(),
+            text,
+        ]
+    }
+
+    fn roundtrip(level: u8, context_takeover: bool, window_bits: u8) {
+        let mut comp = MessageCompressor::new(level);
+        comp.set_context_takeover(context_takeover);
+        comp.set_max_window_bits(window_bits);
+        let mut decomp = MessageDecompressor::new();
+        decomp.set_context_takeover(context_takeover);
+
+        for message in messages() {
+            let payload = comp.compress(&message);
+            assert!(!payload.ends_with(&[0, 0, 0xff, 0xff]));
+            let res = decomp.decompress(&payload).expect("Failed to decompress!");
+            assert!(res == message);
+        }
+    }
+
+    #[test]
+    fn roundtrip_messages() {
+        for &level in &[0, 1, 6, 9] {
+            for &context_takeover in &[true, false] {
+                roundtrip(level, context_takeover, 15);
+                roundtrip(level, context_takeover, 9);
+            }
+        }
+    }
+
+    /// Examples from section 7.2.3 of RFC 7692.
+    #[test]
+    fn rfc_examples() {
+        let mut decomp = MessageDecompressor::new();
+        let res = decomp.decompress(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]);
+        assert_eq!(res.unwrap(), b"Hello");
+        // Refers back to the previous message.
+        let res = decomp.decompress(&[0xf2, 0x00, 0x11, 0x00, 0x00]);
+        assert_eq!(res.unwrap(), b"Hello");
+        // Ends with a final block, so the tail is ignored.
+        let res = decomp.decompress(&[0xf3, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]);
+        assert_eq!(res.unwrap(), b"Hello");
+        // A final stored block.
+        let res = decomp.decompress(&[0x01, 0x05, 0x00, 0xfa, 0xff, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+        assert_eq!(res.unwrap(), b"Hello");
+        let res = decomp.decompress(&[0x00]);
+        assert_eq!(res.unwrap(), b"");
+
+        let mut comp = MessageCompressor::new(6);
+        assert_eq!(
+            comp.compress(b"Hello"),
+            [0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]
+        );
+        assert_eq!(comp.compress(b""), [0x00]);
+    }
+
+    #[test]
+    fn context_takeover() {
+        let message = messages().swap_remove(0);
+
+        let mut comp = MessageCompressor::new(6);
+        let first = comp.compress(&message);
+        let second = comp.compress(&message);
+        assert!(second.len() < first.len());
+
+        let mut comp = MessageCompressor::new(6);
+        comp.set_context_takeover(false);
+        let first = comp.compress(&message);
+        let second = comp.compress(&message);
+        assert_eq!(first, second);
+
+        // Without context takeover the decompressor must not keep the window either.
+        let mut comp = MessageCompressor::new(6);
+        comp.compress(&message);
+        let payload = comp.compress(&message);
+        let mut decomp = MessageDecompressor::new();
+        decomp.set_context_takeover(false);
+        decomp.decompress(&comp.compress(&message)).unwrap();
+        assert!(decomp.decompress(&payload).unwrap() != message);
+    }
+
+    #[test]
+    fn invalid_data() {
+        let mut decomp = MessageDecompressor::new();
+        assert!(decomp.decompress(&[0xff, 0xff, 0xff]).is_err());
+        // The decompressor is usable again after an error.
+        assert_eq!(decomp.decompress(&[0x00]).unwrap(), b"");
+    }
+}
@@ -0,0 +1,420 @@
+//! Simple PNG writer, ported from miniz's `tdefl_write_image_to_png_file_in_memory`.
+//!
+//! Images are written with 8 bits per channel as grayscale, grayscale with alpha, RGB or RGBA,
+//! depending on the number of channels. The compressed data is stored in a single IDAT chunk
+//! unless it is too large for one.
+
+use std::cmp;
+use std::prelude::v1::*;
+
+use crate::checksum::Crc32;
+use crate::deflate::core::deflate_flags::TDEFL_WRITE_ZLIB_HEADER;
+use crate::deflate::core::NUM_PROBES;
+use crate::deflate::core::{compress_to_output, CompressorOxide, TDEFLFlush, TDEFLStatus};
+use crate::MZError;
+
+/// The PNG file signature.
+const PNG_SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a];
+/// PNG color type for each number of channels.
+const COLOR_TYPES: [u8; 5] = [0x00, 0x00, 0x04, 0x02, 0x06];
+/// The largest allowed chunk length, and image width or height.
+const MAX_PNG_VALUE: usize = 0x7FFF_FFFF;
+
+/// Filter applied to each scanline before compression.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PngFilter {
+    /// Store the pixels unchanged. This is what miniz does.
+    None = 0,
+    /// Difference from the pixel to the left.
+    Sub = 1,
+    /// Difference from the pixel above.
+    Up = 2,
+    /// Difference from the average of the pixels to the left and above.
+    Average = 3,
+    /// Difference from the Paeth predictor of the pixels to the left, above and above left.
+    Paeth = 4,
+    /// Choose a filter for each scanline, using the heuristic suggested by the PNG
+    /// specification: the filter with the smallest sum of the filtered bytes, taken as
+    /// signed absolute values.
+    Adaptive = 5,
+}
+
+/// Parameters for writing PNG images.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PngOptions {
+    /// Compression level (0-10). Like in miniz, this only sets the number of probes, so level 0
+    /// stores the filtered data with huffman coding only.
+    pub level: u8,
+    /// Write the rows from bottom to top, for images stored upside down.
+    pub flip: bool,
+    /// Filter applied to each scanline.
+    pub filter: PngFilter,
+}
+
+impl Default for PngOptions {
+    fn default() -> PngOptions {
+        PngOptions {
+            level: 6,
+            flip: false,
+            filter: PngFilter::None,
+        }
+    }
+}
+
+/// Write an image to a PNG file in memory using the default options, which are the same as
+/// those used by miniz.
+///
+/// `image` holds `height` rows of `width` pixels with `num_chans` (1-4) bytes each.
+///
+/// # Errors
+///
+/// Returns `MZError::Param` if the number of channels or the image dimensions are invalid, or
+/// if `image` is too small.
+pub fn write_image_to_png(
+    image: &[u8],
+    width: u32,
+    height: u32,
+    num_chans: u8,
+) -> Result<Vec<u8>, MZError> {
+    write_image_to_png_with_options(image, width, height, num_chans, &PngOptions::default())
+}
+
+/// Write an image to a PNG file in memory.
+///
+/// See `write_image_to_png` for the image layout and errors.
+pub fn write_image_to_png_with_options(
+    image: &[u8],
+    width: u32,
+    height: u32,
+    num_chans: u8,
+    options: &PngOptions,
+) -> Result<Vec<u8>, MZError> {
+    let (width, height, bpp) = (width as usize, height as usize, usize::from(num_chans));
+    if bpp < 1 || bpp > 4 || width == 0 || height == 0 {
+        return Err(MZError::Param);
+    }
+    if width > MAX_PNG_VALUE || height > MAX_PNG_VALUE {
+        return Err(MZError::Param);
+    }
+    let bpl = width.checked_mul(bpp).ok_or(MZError::Param)?;
+    match bpl.checked_mul(height) {
+        Some(len) if len <= image.len() => (),
+        _ => return Err(MZError::Param),
+    }
+
+    let flags = NUM_PROBES[cmp::min(10, usize::from(options.level))] | TDEFL_WRITE_ZLIB_HEADER;
+    let mut compressor = CompressorOxide::new(flags);
+    let mut idat = Vec::with_capacity(57 + cmp::max(64, (1 + bpl) * height));
+    let mut filter = ScanlineFilter::new(options.filter, bpp, bpl);
+    for y in 0..height {
+        let row_index = if options.flip { height - 1 - y } else { y };
+        let row = &image[row_index * bpl..(row_index + 1) * bpl];
+        let filtered = filter.filter(row);
+        let (status, _) = compress_to_output(
+            &mut compressor,
+            filtered,
+            TDEFLFlush::None,
+            |out: &[u8]| {
+                idat.extend_from_slice(out);
+                true
+            },
+        );
+        debug_assert_eq!(status, TDEFLStatus::Okay);
+    }
+    let (status, _) = compress_to_output(&mut compressor, &[], TDEFLFlush::Finish, |out| {
+        idat.extend_from_slice(out);
+        true
+    });
+    // Not supposed to happen unless there is a bug.
+    assert_eq!(
+        status,
+        TDEFLStatus::Done,
+        "Bug! Unexpectedly failed to compress!"
+    );
+
+    let mut ihdr = [0; 13];
+    ihdr[0..4].copy_from_slice(&(width as u32).to_be_bytes());
+    ihdr[4..8].copy_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth.
+    ihdr[8] = 8;
+    ihdr[9] = COLOR_TYPES[bpp];
+    // Compression method, filter method and interlace method are all 0.
+
+    let mut png = Vec::with_capacity(idat.len() + 57);
+    png.extend_from_slice(&PNG_SIGNATURE);
+    write_chunk(&mut png, b"IHDR", &ihdr);
+    for data in idat.chunks(MAX_PNG_VALUE) {
+        write_chunk(&mut png, b"IDAT", data);
+    }
+    write_chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+/// Append a chunk with the given type and data, followed by its CRC-32.
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = Crc32::checksum_of(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Applies a filter to each scanline in turn, keeping the previous scanline around.
+pub(crate) struct ScanlineFilter {
+    filter: PngFilter,
+    /// Bytes per pixel.
+    bpp: usize,
+    /// The previous scanline, unfiltered. All zeroes before the first one.
+    prev: Vec<u8>,
+    /// Filter type byte followed by the filtered scanline.
+    out: Vec<u8>,
+    /// Scratch space for trying out filters when choosing adaptively.
+    scratch: Vec<u8>,
+}
+
+impl ScanlineFilter {
+    pub(crate) fn new(filter: PngFilter, bpp: usize, bpl: usize) -> ScanlineFilter {
+        ScanlineFilter {
+            filter,
+            bpp,
+            prev: vec![0; bpl],
+            out: vec![0; bpl + 1],
+            scratch: if filter == PngFilter::Adaptive {
+                vec![0; bpl + 1]
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    /// Filter `row`, returning the filter type byte followed by the filtered data.
+    pub(crate) fn filter(&mut self, row: &[u8]) -> &[u8] {
+        if self.filter == PngFilter::Adaptive {
+            let mut best_sum = u64::max_value();
+            for &filter in &[
+                PngFilter::None,
+                PngFilter::Sub,
+                PngFilter::Up,
+                PngFilter::Average,
+                PngFilter::Paeth,
+            ] {
+                apply_filter(filter, self.bpp, row, &self.prev, &mut self.scratch);
+                let sum = self.scratch[1..]
+                    .iter()
+                    .map(|&b| u64::from((b as i8).wrapping_abs() as u8))
+                    .sum::<u64>();
+                if sum < best_sum {
+                    best_sum = sum;
+                    std::mem::swap(&mut self.out, &mut self.scratch);
+                }
+            }
+        } else {
+            apply_filter(self.filter, self.bpp, row, &self.prev, &mut self.out);
+        }
+        self.prev.copy_from_slice(row);
+        &self.out
+    }
+}
+
+/// Write the filter type byte for `filter` and the filtered `row` to `out`.
+fn apply_filter(filter: PngFilter, bpp: usize, row: &[u8], prev: &[u8], out: &mut [u8]) {
+    out[0] = filter as u8;
+    let out = &mut out[1..];
+    match filter {
+        PngFilter::None | PngFilter::Adaptive => out.copy_from_slice(row),
+        PngFilter::Sub => {
+            out[..bpp].copy_from_slice(&row[..bpp]);
+            for i in bpp..row.len() {
+                out[i] = row[i].wrapping_sub(row[i - bpp]);
+            }
+        }
+        PngFilter::Up => {
+            for i in 0..row.len() {
+                out[i] = row[i].wrapping_sub(prev[i]);
+            }
+        }
+        PngFilter::Average => {
+            for i in 0..row.len() {
+                let left = if i >= bpp { u16::from(row[i - bpp]) } else { 0 };
+                let avg = ((left + u16::from(prev[i])) / 2) as u8;
+                out[i] = row[i].wrapping_sub(avg);
+            }
+        }
+        PngFilter::Paeth => {
+            for i in 0..row.len() {
+                let (left, up_left) = if i >= bpp {
+                    (row[i - bpp], prev[i - bpp])
+                } else {
+                    (0, 0)
+                };
+                out[i] = row[i].wrapping_sub(paeth_predictor(left, prev[i], up_left));
+            }
+        }
+    }
+}
+
+/// The predictor from the PNG specification: whichever of the neighbouring pixels is closest
+/// to `a + b - c`.
+pub(crate) fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let pa = (p - i16::from(a)).abs();
+    let pb = (p - i16::from(b)).abs();
+    let pc = (p - i16::from(c)).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{paeth_predictor, write_image_to_png, write_image_to_png_with_options};
+    use super::{PngFilter, PngOptions};
+    use crate::checksum::Crc32;
+    use crate::inflate::decompress_to_vec_zlib;
+    use crate::MZError;
+
+    /// Split a PNG file into its chunks, checking the CRCs.
+    fn parse_chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert_eq!(png[..8], [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a]);
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+        while pos < png.len() {
+            let read_u32 = |pos: usize| {
+                u32::from(png[pos]) << 24
+                    | u32::from(png[pos + 1]) << 16
+                    | u32::from(png[pos + 2]) << 8
+                    | u32::from(png[pos + 3])
+            };
+            let len = read_u32(pos) as usize;
+            let crc = read_u32(pos + 8 + len);
+            assert_eq!(Crc32::checksum_of(&png[pos + 4..pos + 8 + len]), crc);
+            let mut kind = [0; 4];
+            kind.copy_from_slice(&png[pos + 4..pos + 8]);
+            chunks.push((kind, png[pos + 8..pos + 8 + len].to_vec()));
+            pos += 12 + len;
+        }
+        chunks
+    }
+
+    /// Decode a PNG written by this module, returning the rows from top to bottom.
+    fn decode(png: &[u8], bpp: usize) -> (u32, u32, Vec<u8>, Vec<u8>) {
+        let chunks = parse_chunks(png);
+        assert_eq!(&chunks[0].0, b"IHDR");
+        let ihdr = &chunks[0].1;
+        let width = u32::from(ihdr[2]) << 8 | u32::from(ihdr[3]);
+        let height = u32::from(ihdr[6]) << 8 | u32::from(ihdr[7]);
+        assert_eq!(&chunks[1].0, b"IDAT");
+        assert_eq!(&chunks[2].0, b"IEND");
+
+        let filtered = decompress_to_vec_zlib(&chunks[1].1).unwrap();
+        let bpl = width as usize * bpp;
+        let mut image = vec![0; bpl * height as usize];
+        let mut filters = Vec::new();
+        for y in 0..height as usize {
+            let line = &filtered[y * (bpl + 1)..(y + 1) * (bpl + 1)];
+            filters.push(line[0]);
+            for i in 0..bpl {
+                let left = if i >= bpp {
+                    image[y * bpl + i - bpp]
+                } else {
+                    0
+                };
+                let up = if y > 0 { image[(y - 1) * bpl + i] } else { 0 };
+                let up_left = if i >= bpp && y > 0 {
+                    image[(y - 1) * bpl + i - bpp]
+                } else {
+                    0
+                };
+                let pred = match line[0] {
+                    0 => 0,
+                    1 => left,
+                    2 => up,
+                    3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+                    4 => paeth_predictor(left, up, up_left),
+                    _ => panic!("Invalid filter type"),
+                };
+                image[y * bpl + i] = line[i + 1].wrapping_add(pred);
+            }
+        }
+        (width, height, image, filters)
+    }
+
+    fn test_image(width: usize, height: usize, bpp: usize) -> Vec<u8> {
+        (0..width * height * bpp)
+            .map(|i| {
+                let (x, y) = ((i / bpp) % width, i / bpp / width);
+                (x * 3 + y * 5 + (i % bpp) * 40 + (x * y) % 7) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn write_png() {
+        for bpp in 1..5 {
+            let image = test_image(37, 11, bpp);
+            let png = write_image_to_png(&image, 37, 11, bpp as u8).unwrap();
+            let chunks = parse_chunks(&png);
+            assert_eq!(
+                chunks[0].1,
+                [0, 0, 0, 37, 0, 0, 0, 11, 8, [0, 0, 4, 2, 6][bpp], 0, 0, 0]
+            );
+            let (width, height, decoded, filters) = decode(&png, bpp);
+            assert_eq!((width, height), (37, 11));
+            assert!(decoded == image);
+            assert!(filters.iter().all(|&f| f == 0));
+        }
+    }
+
+    #[test]
+    fn filters_and_flip() {
+        let image = test_image(50, 20, 3);
+        let mut flipped = Vec::new();
+        for row in image.chunks(150).rev() {
+            flipped.extend_from_slice(row);
+        }
+        let none_len = write_image_to_png(&image, 50, 20, 3).unwrap().len();
+
+        for &filter in &[
+            PngFilter::None,
+            PngFilter::Sub,
+            PngFilter::Up,
+            PngFilter::Average,
+            PngFilter::Paeth,
+            PngFilter::Adaptive,
+        ] {
+            for &flip in &[false, true] {
+                let options = PngOptions {
+                    level: 9,
+                    flip,
+                    filter,
+                };
+                let png = write_image_to_png_with_options(&image, 50, 20, 3, &options).unwrap();
+                let (_, _, decoded, filters) = decode(&png, 3);
+                assert!(decoded == if flip { &flipped[..] } else { &image[..] });
+                if filter != PngFilter::Adaptive {
+                    assert!(filters.iter().all(|&f| f == filter as u8));
+                } else {
+                    // Smooth gradients should not be stored unfiltered.
+                    assert!(filters.iter().all(|&f| f != 0));
+                    assert!(png.len() < none_len);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_params() {
+        let image = test_image(4, 4, 3);
+        assert_eq!(write_image_to_png(&image, 4, 4, 0), Err(MZError::Param));
+        assert_eq!(write_image_to_png(&image, 4, 4, 5), Err(MZError::Param));
+        assert_eq!(write_image_to_png(&image, 0, 4, 3), Err(MZError::Param));
+        assert_eq!(write_image_to_png(&image, 4, 5, 3), Err(MZError::Param));
+        assert!(write_image_to_png(&image, 4, 4, 3).is_ok());
+    }
+}
@@ -0,0 +1,675 @@
+//! PNG and TIFF predictors, as used with the `FlateDecode` filter in PDF and with deflate
+//! compressed TIFF images.
+//!
+//! A predictor transforms the data row by row before compression so that it compresses better.
+//! `PredictorEncoder` applies the forward transform and `PredictorDecoder` undoes it again after
+//! decompression. Both work on data given in arbitrary pieces, so they can be placed directly
+//! after an `InflateState` or in front of a `CompressorOxide`.
+//!
+//! The parameters are the same as the `Predictor`, `Colors`, `BitsPerComponent` and `Columns`
+//! entries of the PDF decode parameters.
+
+use std::cmp;
+use std::prelude::v1::*;
+
+use crate::deflate::core::{
+    compress_to_output, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush, TDEFLStatus,
+};
+use crate::inflate::stream::{inflate_chunk, InflateState};
+use crate::png::{paeth_predictor, PngFilter, ScanlineFilter};
+use crate::{DataFormat, MZError};
+
+/// How much data to process at a time in the `*_with_predictor` functions.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// The predictor algorithm, with the values used in PDF files.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Predictor {
+    /// No prediction.
+    None = 1,
+    /// TIFF predictor 2: each component is stored as the difference from the same component of
+    /// the pixel to the left.
+    Tiff = 2,
+    /// PNG prediction with the None filter on each row.
+    PngNone = 10,
+    /// PNG prediction with the Sub filter on each row.
+    PngSub = 11,
+    /// PNG prediction with the Up filter on each row.
+    PngUp = 12,
+    /// PNG prediction with the Average filter on each row.
+    PngAverage = 13,
+    /// PNG prediction with the Paeth filter on each row.
+    PngPaeth = 14,
+    /// PNG prediction choosing the filter for each row adaptively.
+    PngOptimum = 15,
+}
+
+impl Predictor {
+    /// Get the predictor for the value of a PDF `Predictor` entry: 1 for none, 2 for the TIFF
+    /// predictor, and 10 to 15 for the PNG filters None, Sub, Up, Average, Paeth and Optimum.
+    ///
+    /// Returns `None` for any other value.
+    pub fn from_i32(value: i32) -> Option<Predictor> {
+        use self::Predictor::*;
+        match value {
+            1 => Some(None),
+            2 => Some(Tiff),
+            10 => Some(PngNone),
+            11 => Some(PngSub),
+            12 => Some(PngUp),
+            13 => Some(PngAverage),
+            14 => Some(PngPaeth),
+            15 => Some(PngOptimum),
+            _ => Option::None,
+        }
+    }
+
+    /// The PNG filter used when encoding, or `None` if this is not a PNG predictor.
+    ///
+    /// When decoding, every row carries its own filter type, so all the PNG predictors are
+    /// decoded the same way.
+    fn png_filter(self) -> Option<PngFilter> {
+        match self {
+            Predictor::None | Predictor::Tiff => None,
+            Predictor::PngNone => Some(PngFilter::None),
+            Predictor::PngSub => Some(PngFilter::Sub),
+            Predictor::PngUp => Some(PngFilter::Up),
+            Predictor::PngAverage => Some(PngFilter::Average),
+            Predictor::PngPaeth => Some(PngFilter::Paeth),
+            Predictor::PngOptimum => Some(PngFilter::Adaptive),
+        }
+    }
+}
+
+/// Predictor parameters.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PredictorParams {
+    /// The predictor algorithm.
+    pub predictor: Predictor,
+    /// Number of color components per pixel.
+    pub colors: u8,
+    /// Number of bits per color component (1, 2, 4, 8 or 16).
+    pub bits_per_component: u8,
+    /// Number of pixels in each row.
+    pub columns: u32,
+}
+
+impl Default for PredictorParams {
+    /// The defaults from the PDF specification.
+    fn default() -> PredictorParams {
+        PredictorParams {
+            predictor: Predictor::None,
+            colors: 1,
+            bits_per_component: 8,
+            columns: 1,
+        }
+    }
+}
+
+impl PredictorParams {
+    /// Return the number of bytes per pixel, rounded up, and the number of bytes per row.
+    fn layout(&self) -> Result<(usize, usize), MZError> {
+        match self.bits_per_component {
+            1 | 2 | 4 | 8 | 16 => (),
+            _ => return Err(MZError::Param),
+        }
+        if self.colors == 0 || self.columns == 0 {
+            return Err(MZError::Param);
+        }
+        let bits_per_pixel = usize::from(self.colors) * usize::from(self.bits_per_component);
+        let row_len = (self.columns as usize)
+            .checked_mul(bits_per_pixel)
+            .and_then(|bits| bits.checked_add(7))
+            .ok_or(MZError::Param)?
+            / 8;
+        Ok(((bits_per_pixel + 7) / 8, row_len))
+    }
+}
+
+/// Removes the prediction from decompressed data.
+pub struct PredictorDecoder {
+    params: PredictorParams,
+    /// Bytes per pixel, rounded up.
+    bpp: usize,
+    /// The previous row, all zeroes before the first one. Only used for PNG predictors.
+    prev: Vec<u8>,
+    /// The current row, starting with the filter type byte for PNG predictors.
+    row: Vec<u8>,
+    /// How many bytes of `row` have been filled in.
+    filled: usize,
+}
+
+impl PredictorDecoder {
+    /// Create a new decoder.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MZError::Param` if the parameters are invalid.
+    pub fn new(params: &PredictorParams) -> Result<PredictorDecoder, MZError> {
+        let (bpp, row_len) = params.layout()?;
+        let is_png = params.predictor.png_filter().is_some();
+        Ok(PredictorDecoder {
+            params: *params,
+            bpp,
+            prev: if is_png { vec![0; row_len] } else { Vec::new() },
+            row: vec![0; row_len + is_png as usize],
+            filled: 0,
+        })
+    }
+
+    /// Decode `input` and append the result to `output`.
+    ///
+    /// Any incomplete row at the end of `input` is kept until the rest of it is supplied.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MZError::Data` if a row uses an invalid PNG filter type. The decoder has to be
+    /// reset before it can be used again.
+    pub fn decode(&mut self, mut input: &[u8], output: &mut Vec<u8>) -> Result<(), MZError> {
+        if self.params.predictor == Predictor::None {
+            output.extend_from_slice(input);
+            return Ok(());
+        }
+
+        while !input.is_empty() {
+            let n = cmp::min(self.row.len() - self.filled, input.len());
+            self.row[self.filled..self.filled + n].copy_from_slice(&input[..n]);
+            self.filled += n;
+            input = &input[n..];
+            if self.filled == self.row.len() {
+                self.decode_row(output)?;
+                self.filled = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode what is left of an incomplete last row, if any, and reset the decoder.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MZError::Data` if the row uses an invalid PNG filter type.
+    pub fn finish(&mut self, output: &mut Vec<u8>) -> Result<(), MZError> {
+        let header_len = if self.params.predictor == Predictor::Tiff {
+            0
+        } else {
+            1
+        };
+        let res = if self.filled > header_len {
+            // The predictors only look at data to the left, so padding the row doesn't change
+            // the part we have.
+            let missing = self.row.len() - self.filled;
+            for b in &mut self.row[self.filled..] {
+                *b = 0;
+            }
+            let res = self.decode_row(output);
+            if res.is_ok() {
+                let len = output.len() - missing;
+                output.truncate(len);
+            }
+            res
+        } else {
+            Ok(())
+        };
+        self.reset();
+        res
+    }
+
+    /// Reset the decoder so it can be used for a new stream.
+    pub fn reset(&mut self) {
+        for b in &mut self.prev {
+            *b = 0;
+        }
+        self.filled = 0;
+    }
+
+    /// Decode the complete row in `self.row` and append it to `output`.
+    fn decode_row(&mut self, output: &mut Vec<u8>) -> Result<(), MZError> {
+        if self.params.predictor == Predictor::Tiff {
+            undo_tiff_predictor(&self.params, &mut self.row);
+            output.extend_from_slice(&self.row);
+        } else {
+            let (filter, row) = self.row.split_first_mut().expect("Bug! Empty PNG row!");
+            undo_png_filter(*filter, self.bpp, row, &self.prev)?;
+            output.extend_from_slice(row);
+            self.prev.copy_from_slice(row);
+        }
+        Ok(())
+    }
+}
+
+/// Applies a predictor to data before compression.
+pub struct PredictorEncoder {
+    params: PredictorParams,
+    /// Bytes per pixel, rounded up.
+    bpp: usize,
+    /// Only used for PNG predictors.
+    filter: Option<ScanlineFilter>,
+    /// The current row.
+    row: Vec<u8>,
+    /// How many bytes of `row` have been filled in.
+    filled: usize,
+}
+
+impl PredictorEncoder {
+    /// Create a new encoder.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MZError::Param` if the parameters are invalid.
+    pub fn new(params: &PredictorParams) -> Result<PredictorEncoder, MZError> {
+        let (bpp, row_len) = params.layout()?;
+        Ok(PredictorEncoder {
+            params: *params,
+            bpp,
+            filter: params
+                .predictor
+                .png_filter()
+                .map(|filter| ScanlineFilter::new(filter, bpp, row_len)),
+            row: vec![0; row_len],
+            filled: 0,
+        })
+    }
+
+    /// Encode `input` and append the result to `output`.
+    ///
+    /// Any incomplete row at the end of `input` is kept until the rest of it is supplied.
+    pub fn encode(&mut self, mut input: &[u8], output: &mut Vec<u8>) {
+        if self.params.predictor == Predictor::None {
+            output.extend_from_slice(input);
+            return;
+        }
+
+        while !input.is_empty() {
+            let n = cmp::min(self.row.len() - self.filled, input.len());
+            self.row[self.filled..self.filled + n].copy_from_slice(&input[..n]);
+            self.filled += n;
+            input = &input[n..];
+            if self.filled == self.row.len() {
+                self.encode_row(output);
+                self.filled = 0;
+            }
+        }
+    }
+
+    /// Encode what is left of an incomplete last row, if any, and reset the encoder.
+    pub fn finish(&mut self, output: &mut Vec<u8>) {
+        if self.filled > 0 {
+            // As when decoding, padding the row doesn't change the part we have.
+            let missing = self.row.len() - self.filled;
+            for b in &mut self.row[self.filled..] {
+                *b = 0;
+            }
+            self.encode_row(output);
+            let len = output.len() - missing;
+            output.truncate(len);
+        }
+        self.reset();
+    }
+
+    /// Reset the encoder so it can be used for a new stream.
+    pub fn reset(&mut self) {
+        let (bpp, row_len) = (self.bpp, self.row.len());
+        if let Some(filter) = self.params.predictor.png_filter() {
+            self.filter = Some(ScanlineFilter::new(filter, bpp, row_len));
+        }
+        self.filled = 0;
+    }
+
+    /// Encode the complete row in `self.row` and append it to `output`.
+    fn encode_row(&mut self, output: &mut Vec<u8>) {
+        match self.filter {
+            Some(ref mut filter) => output.extend_from_slice(filter.filter(&self.row)),
+            None => {
+                let start = output.len();
+                output.extend_from_slice(&self.row);
+                apply_tiff_predictor(&self.params, &mut output[start..]);
+            }
+        }
+    }
+}
+
+/// Undo the PNG filter `filter` on `row` in place, using the already decoded previous row.
+fn undo_png_filter(filter: u8, bpp: usize, row: &mut [u8], prev: &[u8]) -> Result<(), MZError> {
+    match filter {
+        0 => (),
+        1 => {
+            for i in bpp..row.len() {
+                row[i] = row[i].wrapping_add(row[i - bpp]);
+            }
+        }
+        2 => {
+            for i in 0..row.len() {
+                row[i] = row[i].wrapping_add(prev[i]);
+            }
+        }
+        3 => {
+            for i in 0..row.len() {
+                let left = if i >= bpp { u16::from(row[i - bpp]) } else { 0 };
+                let avg = ((left + u16::from(prev[i])) / 2) as u8;
+                row[i] = row[i].wrapping_add(avg);
+            }
+        }
+        4 => {
+            for i in 0..row.len() {
+                let (left, up_left) = if i >= bpp {
+                    (row[i - bpp], prev[i - bpp])
+                } else {
+                    (0, 0)
+                };
+                row[i] = row[i].wrapping_add(paeth_predictor(left, prev[i], up_left));
+            }
+        }
+        _ => return Err(MZError::Data),
+    }
+    Ok(())
+}
+
+/// Undo TIFF predictor 2 on a row in place.
+fn undo_tiff_predictor(params: &PredictorParams, row: &mut [u8]) {
+    let colors = usize::from(params.colors);
+    match params.bits_per_component {
+        8 => {
+            for i in colors..row.len() {
+                row[i] = row[i].wrapping_add(row[i - colors]);
+            }
+        }
+        16 => {
+            for n in colors..row.len() / 2 {
+                let left = read_u16_be(row, 2 * (n - colors));
+                write_u16_be(row, 2 * n, read_u16_be(row, 2 * n).wrapping_add(left));
+            }
+        }
+        bits => {
+            let bits = usize::from(bits);
+            let mask = (1 << bits) - 1;
+            for n in colors..num_samples(params, row.len()) {
+                let left = read_sample(row, n - colors, bits);
+                let sample = (read_sample(row, n, bits) + left) & mask;
+                write_sample(row, n, bits, sample);
+            }
+        }
+    }
+}
+
+/// Apply TIFF predictor 2 to a row in place.
+fn apply_tiff_predictor(params: &PredictorParams, row: &mut [u8]) {
+    let colors = usize::from(params.colors);
+    // Going from right to left, so the samples to the left are still unchanged.
+    match params.bits_per_component {
+        8 => {
+            for i in (colors..row.len()).rev() {
+                row[i] = row[i].wrapping_sub(row[i - colors]);
+            }
+        }
+        16 => {
+            for n in (colors..row.len() / 2).rev() {
+                let left = read_u16_be(row, 2 * (n - colors));
+                write_u16_be(row, 2 * n, read_u16_be(row, 2 * n).wrapping_sub(left));
+            }
+        }
+        bits => {
+            let bits = usize::from(bits);
+            let mask = (1 << bits) - 1;
+            for n in (colors..num_samples(params, row.len())).rev() {
+                let left = read_sample(row, n - colors, bits);
+                let sample = read_sample(row, n, bits).wrapping_sub(left) & mask;
+                write_sample(row, n, bits, sample);
+            }
+        }
+    }
+}
+
+/// The number of samples in a row of `row_len` bytes, not counting the padding at the end.
+fn num_samples(params: &PredictorParams, row_len: usize) -> usize {
+    let samples = usize::from(params.colors) * params.columns as usize;
+    cmp::min(
+        samples,
+        row_len * 8 / usize::from(params.bits_per_component),
+    )
+}
+
+/// Read sample `n` of a row of `bits`-bit samples, with the most significant bits first.
+fn read_sample(row: &[u8], n: usize, bits: usize) -> u8 {
+    let shift = 8 - bits - n * bits % 8;
+    (row[n * bits / 8] >> shift) & ((1 << bits) - 1)
+}
+
+/// Write sample `n` of a row of `bits`-bit samples, with the most significant bits first.
+fn write_sample(row: &mut [u8], n: usize, bits: usize, sample: u8) {
+    let shift = 8 - bits - n * bits % 8;
+    let mask = ((1 << bits) - 1) << shift;
+    let byte = &mut row[n * bits / 8];
+    *byte = (*byte & !mask) | (sample << shift);
+}
+
+fn read_u16_be(row: &[u8], pos: usize) -> u16 {
+    u16::from(row[pos]) << 8 | u16::from(row[pos + 1])
+}
+
+fn write_u16_be(row: &mut [u8], pos: usize, value: u16) {
+    row[pos] = (value >> 8) as u8;
+    row[pos + 1] = value as u8;
+}
+
+/// Decompress zlib-wrapped data in `input` and remove the prediction from the result.
+///
+/// # Errors
+///
+/// Returns `MZError::Param` if the parameters are invalid, `MZError::Buf` if the input ends
+/// before the end of the zlib stream, and `MZError::Data` if the input is not valid.
+pub fn decompress_to_vec_zlib_with_predictor(
+    mut input: &[u8],
+    params: &PredictorParams,
+) -> Result<Vec<u8>, MZError> {
+    let mut decoder = PredictorDecoder::new(params)?;
+    let mut state = InflateState::new_boxed(DataFormat::Zlib);
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut output = Vec::with_capacity(input.len() * 2);
+
+    loop {
+        let res = inflate_chunk(&mut state, input, &mut buf);
+        input = &input[res.bytes_consumed..];
+        decoder.decode(&buf[..res.bytes_written], &mut output)?;
+
+        if res.stream_end? {
+            break;
+        }
+        if res.bytes_consumed == 0 && res.bytes_written == 0 {
+            return Err(MZError::Buf);
+        }
+    }
+
+    decoder.finish(&mut output)?;
+    Ok(output)
+}
+
+/// Apply the predictor to `input` and compress the result with a zlib wrapper, using the
+/// specified compression level (0-10).
+///
+/// # Errors
+///
+/// Returns `MZError::Param` if the parameters are invalid.
+pub fn compress_to_vec_zlib_with_predictor(
+    input: &[u8],
+    level: u8,
+    params: &PredictorParams,
+) -> Result<Vec<u8>, MZError> {
+    let mut encoder = PredictorEncoder::new(params)?;
+    let flags = create_comp_flags_from_zip_params(level.into(), 1, 0);
+    let mut compressor = CompressorOxide::new(flags);
+    let mut filtered = Vec::with_capacity(CHUNK_SIZE + CHUNK_SIZE / 8);
+    let mut output = Vec::with_capacity(input.len() / 2);
+
+    let mut chunks = input.chunks(CHUNK_SIZE);
+    loop {
+        filtered.clear();
+        let flush = match chunks.next() {
+            Some(chunk) => {
+                encoder.encode(chunk, &mut filtered);
+                TDEFLFlush::None
+            }
+            None => {
+                encoder.finish(&mut filtered);
+                TDEFLFlush::Finish
+            }
+        };
+        let (status, _) = compress_to_output(&mut compressor, &filtered, flush, |out| {
+            output.extend_from_slice(out);
+            true
+        });
+
+        match status {
+            TDEFLStatus::Done => break,
+            TDEFLStatus::Okay => (),
+            // Not supposed to happen unless there is a bug.
+            _ => panic!("Bug! Unexpectedly failed to compress!"),
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compress_to_vec_zlib_with_predictor, decompress_to_vec_zlib_with_predictor};
+    use super::{Predictor, PredictorDecoder, PredictorEncoder, PredictorParams};
+    use crate::deflate::compress_to_vec_zlib;
+    use crate::inflate::decompress_to_vec_zlib;
+    use crate::MZError;
+
+    const PREDICTORS: [Predictor; 8] = [
+        Predictor::None,
+        Predictor::Tiff,
+        Predictor::PngNone,
+        Predictor::PngSub,
+        Predictor::PngUp,
+        Predictor::PngAverage,
+        Predictor::PngPaeth,
+        Predictor::PngOptimum,
+    ];
+
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|n| ((n * n / 7) as u8).wrapping_add((n / 13) as u8))
+            .collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data = test_data(5000);
+        for &predictor in &PREDICTORS {
+            for &bits_per_component in &[1, 2, 4, 8, 16] {
+                for &(colors, columns) in &[(1, 1), (1, 17), (3, 5), (4, 64)] {
+                    let params = PredictorParams {
+                        predictor,
+                        colors,
+                        bits_per_component,
+                        columns,
+                    };
+                    // Also check that an incomplete last row works.
+                    for &len in &[0, 1, 1000, data.len()] {
+                        let encoded =
+                            compress_to_vec_zlib_with_predictor(&data[..len], 6, &params).unwrap();
+                        let decoded =
+                            decompress_to_vec_zlib_with_predictor(&encoded, &params).unwrap();
+                        assert!(decoded == &data[..len], "{:?} {}", params, len);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn streaming() {
+        let data = test_data(3000);
+        let params = PredictorParams {
+            predictor: Predictor::PngOptimum,
+            colors: 3,
+            bits_per_component: 8,
+            columns: 33,
+        };
+
+        let mut encoder = PredictorEncoder::new(&params).unwrap();
+        let mut encoded = Vec::new();
+        encoder.encode(&data, &mut encoded);
+        encoder.finish(&mut encoded);
+
+        // Feed the encoded data to the decoder in odd sized pieces.
+        let mut decoder = PredictorDecoder::new(&params).unwrap();
+        let mut decoded = Vec::new();
+        for piece in encoded.chunks(37) {
+            decoder.decode(piece, &mut decoded).unwrap();
+        }
+        decoder.finish(&mut decoded).unwrap();
+        assert!(decoded == data);
+
+        // The encoder was reset by `finish`, so it gives the same output again.
+        let mut encoded_again = Vec::new();
+        for piece in data.chunks(50) {
+            encoder.encode(piece, &mut encoded_again);
+        }
+        encoder.finish(&mut encoded_again);
+        assert!(encoded_again == encoded);
+    }
+
+    #[test]
+    fn known_rows() {
+        // Two RGB rows of two pixels, the first one with the Sub filter and the second with Up.
+        let filtered = [1, 10, 20, 30, 1, 2, 3, 2, 1, 1, 1, 255, 255, 255];
+        let params = PredictorParams {
+            predictor: Predictor::PngUp,
+            colors: 3,
+            bits_per_component: 8,
+            columns: 2,
+        };
+        let encoded = compress_to_vec_zlib(&filtered, 6);
+        assert_eq!(
+            decompress_to_vec_zlib_with_predictor(&encoded, &params).unwrap(),
+            [10, 20, 30, 11, 22, 33, 11, 21, 31, 10, 21, 32]
+        );
+
+        // 4-bit TIFF samples with two components per pixel.
+        let params = PredictorParams {
+            predictor: Predictor::Tiff,
+            colors: 2,
+            bits_per_component: 4,
+            columns: 3,
+        };
+        let encoded = compress_to_vec_zlib(&[0x12, 0x11, 0xf1], 6);
+        assert_eq!(
+            decompress_to_vec_zlib_with_predictor(&encoded, &params).unwrap(),
+            [0x12, 0x23, 0x14]
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let mut params = PredictorParams::default();
+        params.bits_per_component = 3;
+        assert_eq!(PredictorDecoder::new(&params).err(), Some(MZError::Param));
+        params.bits_per_component = 8;
+        params.colors = 0;
+        assert_eq!(PredictorEncoder::new(&params).err(), Some(MZError::Param));
+        assert_eq!(Predictor::from_i32(3), None);
+        assert_eq!(Predictor::from_i32(12), Some(Predictor::PngUp));
+
+        let params = PredictorParams {
+            predictor: Predictor::PngNone,
+            ..PredictorParams::default()
+        };
+        let encoded = compress_to_vec_zlib(&[0, 1, 5, 1], 6);
+        assert_eq!(
+            decompress_to_vec_zlib_with_predictor(&encoded, &params),
+            Err(MZError::Data)
+        );
+        assert!(decompress_to_vec_zlib(&encoded).is_ok());
+
+        let encoded = compress_to_vec_zlib(&[0, 1, 0, 1], 6);
+        assert_eq!(
+            decompress_to_vec_zlib_with_predictor(&encoded[..encoded.len() - 4], &params),
+            Err(MZError::Buf)
+        );
+    }
+}
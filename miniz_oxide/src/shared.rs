@@ -1,5 +1,3 @@
-use adler32::RollingAdler32;
-
 #[doc(hidden)]
 pub const MZ_ADLER32_INIT: u32 = 1;
 
@@ -12,7 +10,5 @@ pub const HUFFMAN_LENGTH_ORDER: [u8; 19] = [
 
 #[doc(hidden)]
 pub fn update_adler32(adler: u32, data: &[u8]) -> u32 {
-    let mut hash = RollingAdler32::from_value(adler);
-    hash.update_buffer(data);
-    hash.hash()
+    crate::adler32::update(adler, data)
 }
@@ -0,0 +1,119 @@
+//! Decryption of entries encrypted with the traditional PKWARE encryption, also called
+//! ZipCrypto.
+//!
+//! This is a stream cipher with three 32-bit keys, set up from the password and updated with
+//! every byte of plaintext. It is weak, but it is still what most tools use when asked to protect
+//! an archive with a password. The encrypted data starts with a 12 byte header of random bytes,
+//! except for the last one, which is used to check the password.
+
+use crate::checksum::CRC32_POLY;
+
+/// Size of the encryption header in front of the data.
+pub(crate) const ENCRYPTION_HEADER_SIZE: usize = 12;
+
+pub(crate) struct ZipCrypto {
+    keys: [u32; 3],
+    /// Table for updating a CRC-32 a byte at a time, which is how the keys are mixed.
+    table: [u32; 256],
+}
+
+impl ZipCrypto {
+    /// Set up the keys for `password`.
+    pub(crate) fn new(password: &[u8]) -> ZipCrypto {
+        let mut table = [0; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            *entry = (0..8).fold(n as u32, |c, _| {
+                if c & 1 != 0 {
+                    (c >> 1) ^ CRC32_POLY
+                } else {
+                    c >> 1
+                }
+            });
+        }
+        let mut crypto = ZipCrypto {
+            keys: [0x1234_5678, 0x2345_6789, 0x3456_7890],
+            table,
+        };
+        for &byte in password {
+            crypto.update_keys(byte);
+        }
+        crypto
+    }
+
+    /// Decrypt the encryption header, returning whether its last byte matches `check`.
+    ///
+    /// A wrong password still passes the check once in 256 tries, in which case the data fails
+    /// to decompress or fails the CRC-32 check instead.
+    pub(crate) fn decrypt_header(
+        &mut self,
+        header: &mut [u8; ENCRYPTION_HEADER_SIZE],
+        check: u8,
+    ) -> bool {
+        self.decrypt(header);
+        header[ENCRYPTION_HEADER_SIZE - 1] == check
+    }
+
+    /// Decrypt `data` in place.
+    pub(crate) fn decrypt(&mut self, data: &mut [u8]) {
+        for byte in data {
+            *byte ^= self.key_byte();
+            self.update_keys(*byte);
+        }
+    }
+
+    fn crc32_byte(&self, crc: u32, byte: u8) -> u32 {
+        self.table[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8)
+    }
+
+    fn update_keys(&mut self, byte: u8) {
+        self.keys[0] = self.crc32_byte(self.keys[0], byte);
+        self.keys[1] = self.keys[1]
+            .wrapping_add(self.keys[0] & 0xFF)
+            .wrapping_mul(134_775_813)
+            .wrapping_add(1);
+        self.keys[2] = self.crc32_byte(self.keys[2], (self.keys[1] >> 24) as u8);
+    }
+
+    /// The byte the next byte of data is XORed with.
+    fn key_byte(&self) -> u8 {
+        let temp = (self.keys[2] | 2) & 0xFFFF;
+        ((temp * (temp ^ 1)) >> 8) as u8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ZipCrypto, ENCRYPTION_HEADER_SIZE};
+
+    #[test]
+    fn keys() {
+        let crypto = ZipCrypto::new(b"secret");
+        assert_eq!(crypto.keys, [0xdda9_e469, 0x9621_2999, 0xf9fc_9651]);
+    }
+
+    #[test]
+    fn decrypt() {
+        let mut data = [
+            128, 117, 246, 50, 32, 77, 232, 168, 79, 247, 187, 63, 251, 145, 37, 15, 51, 13, 24,
+            247, 229, 39, 76, 125, 113, 180, 214,
+        ];
+        // In pieces, as the keys depend on all the data before.
+        let mut crypto = ZipCrypto::new(b"secret");
+        let (first, second) = data.split_at_mut(10);
+        crypto.decrypt(first);
+        crypto.decrypt(second);
+        assert_eq!(&data[..], &b"Hello, world! Hello, world!"[..]);
+    }
+
+    #[test]
+    fn header_check() {
+        let header = [171, 26, 191, 197, 134, 123, 71, 197, 141, 242, 34, 187];
+        let mut decrypted = header;
+        assert!(ZipCrypto::new(b"").decrypt_header(&mut decrypted, 0));
+        assert_eq!(decrypted, [0; ENCRYPTION_HEADER_SIZE]);
+        let mut decrypted = header;
+        assert!(!ZipCrypto::new(b"").decrypt_header(&mut decrypted, 1));
+        let mut decrypted = header;
+        assert!(!ZipCrypto::new(b"secret").decrypt_header(&mut decrypted, 0));
+    }
+}
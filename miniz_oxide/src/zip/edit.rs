@@ -0,0 +1,386 @@
+//! Changing the entries of existing ZIP archives.
+//!
+//! Entries can't be removed or resized in place without moving everything after them, so the
+//! changes are collected first and then written as a new archive. Entries that aren't replaced
+//! are copied as they are, without decompressing and compressing them again.
+//!
+//! The original archive is only read while the new one is written, so writing to a temporary
+//! file and renaming it over the original replaces the archive atomically. With the `std`
+//! feature, `edit_archive_file_in_place` does that for an archive file.
+
+#[cfg(feature = "std")]
+use std::ffi::OsString;
+#[cfg(feature = "std")]
+use std::fs::{self, File};
+#[cfg(feature = "std")]
+use std::io::BufWriter;
+use std::io::{Read, Seek, Write};
+#[cfg(feature = "std")]
+use std::path::Path;
+use std::prelude::v1::*;
+#[cfg(feature = "std")]
+use std::process;
+
+use super::read::ZipArchive;
+use super::write::{EntryOptions, ZipWriter};
+use super::ZipError;
+
+/// What happens to an entry of the original archive.
+enum Edit {
+    Keep,
+    Delete,
+    Rename(Vec<u8>),
+    Replace {
+        name: Vec<u8>,
+        data: Vec<u8>,
+        options: EntryOptions,
+    },
+}
+
+/// An entry added by `ZipEditor::replace`.
+struct NewEntry {
+    name: Vec<u8>,
+    data: Vec<u8>,
+    options: EntryOptions,
+}
+
+/// Changes to be made to an archive.
+///
+/// Entries are identified by their current name, so an entry that has been renamed has to be
+/// referred to by its new name.
+pub struct ZipEditor<R> {
+    archive: ZipArchive<R>,
+    /// One for each entry of `archive`.
+    edits: Vec<Edit>,
+    added: Vec<NewEntry>,
+    comment: Vec<u8>,
+}
+
+/// The position of an entry in a `ZipEditor`.
+#[derive(Copy, Clone)]
+enum Target {
+    Original(usize),
+    Added(usize),
+}
+
+impl<R: Read + Seek> ZipEditor<R> {
+    /// Start editing `archive`, with no changes yet.
+    pub fn new(archive: ZipArchive<R>) -> ZipEditor<R> {
+        let edits = (0..archive.len()).map(|_| Edit::Keep).collect();
+        let comment = archive.comment().to_vec();
+        ZipEditor {
+            archive,
+            edits,
+            added: Vec::new(),
+            comment,
+        }
+    }
+
+    /// Remove the entry `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ZipError::FileNotFound` if there is no such entry.
+    pub fn delete(&mut self, name: &[u8]) -> Result<(), ZipError> {
+        match self.find(name).ok_or(ZipError::FileNotFound)? {
+            Target::Original(index) => self.edits[index] = Edit::Delete,
+            Target::Added(index) => {
+                self.added.remove(index);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rename the entry `from` to `to`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ZipError::FileNotFound` if there is no entry `from`, and
+    /// `ZipError::InvalidParameter` if there already is an entry `to` or only one of the names
+    /// is a directory.
+    pub fn rename(&mut self, from: &[u8], to: &[u8]) -> Result<(), ZipError> {
+        let target = self.find(from).ok_or(ZipError::FileNotFound)?;
+        if (from.last() == Some(&b'/')) != (to.last() == Some(&b'/')) {
+            return Err(ZipError::InvalidParameter);
+        }
+        if from == to {
+            return Ok(());
+        }
+        if self.find(to).is_some() {
+            return Err(ZipError::InvalidParameter);
+        }
+        match target {
+            Target::Original(index) => {
+                let edit = &mut self.edits[index];
+                match *edit {
+                    Edit::Replace { ref mut name, .. } => *name = to.to_vec(),
+                    _ => *edit = Edit::Rename(to.to_vec()),
+                }
+            }
+            Target::Added(index) => self.added[index].name = to.to_vec(),
+        }
+        Ok(())
+    }
+
+    /// Replace the contents of the entry `name` with `data`, compressed according to
+    /// `options`, keeping its place in the archive.
+    ///
+    /// If there is no entry `name`, it is added at the end of the archive.
+    pub fn replace(
+        &mut self,
+        name: &[u8],
+        data: &[u8],
+        options: &EntryOptions,
+    ) -> Result<(), ZipError> {
+        let new_data = data.to_vec();
+        let new_options = options.clone();
+        match self.find(name) {
+            Some(Target::Original(index)) => {
+                self.edits[index] = Edit::Replace {
+                    name: name.to_vec(),
+                    data: new_data,
+                    options: new_options,
+                }
+            }
+            Some(Target::Added(index)) => {
+                let entry = &mut self.added[index];
+                entry.data = new_data;
+                entry.options = new_options;
+            }
+            None => self.added.push(NewEntry {
+                name: name.to_vec(),
+                data: new_data,
+                options: new_options,
+            }),
+        }
+        Ok(())
+    }
+
+    /// Set the comment of the new archive, which is the old comment by default.
+    pub fn set_comment(&mut self, comment: &[u8]) {
+        self.comment = comment.to_vec();
+    }
+
+    /// The names the entries will have, in the order they will be written.
+    pub fn names(&self) -> Vec<&[u8]> {
+        let mut names = Vec::with_capacity(self.edits.len() + self.added.len());
+        for index in 0..self.edits.len() {
+            if let Some(name) = self.current_name(index) {
+                names.push(name);
+            }
+        }
+        names.extend(self.added.iter().map(|entry| &entry.name[..]));
+        names
+    }
+
+    /// Write the archive with the changes to `writer`, returning it once the archive is
+    /// finished.
+    ///
+    /// The original archive isn't changed. Nothing in the editor is changed either, so this
+    /// can be called again if writing fails.
+    pub fn write_to<W: Write>(&mut self, writer: W) -> Result<W, ZipError> {
+        let mut zip = ZipWriter::new(writer);
+        zip.set_comment(&self.comment)?;
+        for index in 0..self.edits.len() {
+            match self.edits[index] {
+                Edit::Keep => {
+                    let name = self.archive.entries()[index].name.clone();
+                    zip.copy_entry(&mut self.archive, index, &name)?;
+                }
+                Edit::Delete => (),
+                Edit::Rename(ref name) => zip.copy_entry(&mut self.archive, index, name)?,
+                Edit::Replace {
+                    ref name,
+                    ref data,
+                    ref options,
+                } => zip.add_entry(name, data, options)?,
+            }
+        }
+        for entry in &self.added {
+            zip.add_entry(&entry.name, &entry.data, &entry.options)?;
+        }
+        zip.finish()?;
+        Ok(zip.into_inner())
+    }
+
+    /// Return the original archive.
+    pub fn into_inner(self) -> ZipArchive<R> {
+        self.archive
+    }
+
+    /// The name entry `index` of the original archive will have, or `None` if it is deleted.
+    fn current_name(&self, index: usize) -> Option<&[u8]> {
+        match self.edits[index] {
+            Edit::Keep => Some(&self.archive.entries()[index].name),
+            Edit::Delete => None,
+            Edit::Rename(ref name) | Edit::Replace { ref name, .. } => Some(name),
+        }
+    }
+
+    fn find(&self, name: &[u8]) -> Option<Target> {
+        if let Some(index) = (0..self.edits.len()).find(|&i| self.current_name(i) == Some(name)) {
+            return Some(Target::Original(index));
+        }
+        self.added
+            .iter()
+            .position(|entry| entry.name == name)
+            .map(Target::Added)
+    }
+}
+
+/// Change the entries of the archive at `path` with `edit`, then replace the archive with the
+/// edited one.
+///
+/// The new archive is written to a temporary file next to the original, which is renamed over
+/// the original once it is complete, so the archive is left as it was if anything fails.
+#[cfg(feature = "std")]
+pub fn edit_archive_file_in_place<P, F>(path: P, edit: F) -> Result<(), ZipError>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut ZipEditor<File>) -> Result<(), ZipError>,
+{
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|_| ZipError::FileOpenFailed)?;
+    let permissions = file
+        .metadata()
+        .map_err(|_| ZipError::FileStatFailed)?
+        .permissions();
+    let mut editor = ZipEditor::new(ZipArchive::new(file)?);
+    edit(&mut editor)?;
+
+    let mut temp_name = path
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_else(OsString::new);
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp_path = path.with_file_name(temp_name);
+    let temp = File::create(&temp_path).map_err(|_| ZipError::FileCreateFailed)?;
+    let res = editor
+        .write_to(BufWriter::new(temp))
+        .and_then(|writer| writer.into_inner().map_err(|_| ZipError::FileWriteFailed))
+        .and_then(|temp| {
+            // The new file has the default permissions otherwise.
+            temp.set_permissions(permissions)
+                .map_err(|_| ZipError::FileWriteFailed)?;
+            temp.sync_all().map_err(|_| ZipError::FileWriteFailed)
+        });
+    // Windows can't rename over a file that is still open.
+    drop(editor);
+    let res = res.and_then(|_| fs::rename(&temp_path, path).map_err(|_| ZipError::FileWriteFailed));
+    if res.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read};
+
+    use super::super::test_util::build;
+    use super::super::{EntryOptions, ZipArchive, ZipError};
+    use super::ZipEditor;
+
+    fn raw_data(archive: &mut ZipArchive<Cursor<Vec<u8>>>, index: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        archive
+            .by_index_raw(index)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn edit() {
+        let built = build(false);
+        let mut original = ZipArchive::new(Cursor::new(built.data)).unwrap();
+        let raw_large = raw_data(&mut original, 4);
+        let entry_large = original.entry(4).unwrap().clone();
+
+        let mut editor = ZipEditor::new(original);
+        editor.rename(b"large", b"dir/renamed").unwrap();
+        editor.delete(b"large_stored").unwrap();
+        editor
+            .replace(b"dir/stored", b"new contents", &EntryOptions::default())
+            .unwrap();
+        editor
+            .replace(b"new", b"added", &EntryOptions::default())
+            .unwrap();
+        editor.rename(b"new", b"added").unwrap();
+        assert_eq!(
+            editor.names(),
+            vec![
+                &b"dir/"[..],
+                b"dir/stored",
+                b"dir/empty",
+                b"dir/tricky",
+                b"dir/renamed",
+                b"zip64",
+                b"last",
+                b"added"
+            ]
+        );
+        let data = editor.write_to(Cursor::new(Vec::new())).unwrap();
+
+        let mut archive = ZipArchive::new(Cursor::new(data.into_inner())).unwrap();
+        assert_eq!(archive.len(), 8);
+        assert_eq!(archive.comment(), b"comment");
+        assert_eq!(archive.validate(&Default::default()), Ok(()));
+        assert!(archive.entry(0).unwrap().is_dir());
+
+        // The renamed entry is copied as it is.
+        let renamed = archive.entry(4).unwrap().clone();
+        assert_eq!(renamed.name, b"dir/renamed");
+        assert_eq!(renamed.comment, entry_large.comment);
+        assert_eq!(renamed.external_attributes, entry_large.external_attributes);
+        assert_eq!(renamed.crc32, entry_large.crc32);
+        assert!(raw_data(&mut archive, 4) == raw_large);
+        assert!(archive.extract_to_vec(4).unwrap() == built.entries[4].1);
+
+        assert_eq!(archive.extract_to_vec(1).unwrap(), b"new contents");
+        assert!(archive.extract_to_vec(3).unwrap() == built.entries[3].1);
+        assert_eq!(archive.entry(7).unwrap().name, b"added");
+        assert_eq!(archive.extract_to_vec(7).unwrap(), b"added");
+    }
+
+    #[test]
+    fn data_descriptors() {
+        let built = build(true);
+        let archive = ZipArchive::new(Cursor::new(built.data)).unwrap();
+
+        let mut editor = ZipEditor::new(archive);
+        editor.rename(b"large", b"renamed").unwrap();
+        let data = editor.write_to(Cursor::new(Vec::new())).unwrap();
+        let mut archive = ZipArchive::new(data).unwrap();
+        assert_eq!(archive.validate(&Default::default()), Ok(()));
+        assert_eq!(archive.entry(4).unwrap().name, b"renamed");
+        for (index, (_, contents)) in built.entries.iter().enumerate() {
+            assert!(archive.extract_to_vec(index).unwrap() == *contents);
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let archive = ZipArchive::new(Cursor::new(build(false).data)).unwrap();
+        let mut editor = ZipEditor::new(archive);
+        assert_eq!(editor.delete(b"missing"), Err(ZipError::FileNotFound));
+        assert_eq!(editor.rename(b"missing", b"x"), Err(ZipError::FileNotFound));
+        assert_eq!(
+            editor.rename(b"large_stored", b"last"),
+            Err(ZipError::InvalidParameter)
+        );
+        assert_eq!(
+            editor.rename(b"large_stored", b"large_stored/"),
+            Err(ZipError::InvalidParameter)
+        );
+        editor.delete(b"last").unwrap();
+        editor.rename(b"large_stored", b"last").unwrap();
+        assert_eq!(editor.delete(b"large_stored"), Err(ZipError::FileNotFound));
+
+        // Nothing is changed until the new archive is written.
+        let archive = editor.into_inner();
+        assert_eq!(archive.len(), 8);
+        assert_eq!(archive.entry(5).unwrap().name, b"large_stored");
+    }
+}
@@ -0,0 +1,482 @@
+//! Metadata kept in the extra fields and external attributes of ZIP entries.
+//!
+//! Extra fields are a list of records, each with a 16-bit header ID and a 16-bit size followed
+//! by the data. `ExtraField` is implemented for the fields written by Info-ZIP and most other
+//! tools on Unix: the extended timestamp, the Unix owner and the Unicode path. Other fields can
+//! be found with `ZipEntry::extra_fields`.
+//!
+//! Entries made on Unix store the file type and permissions in the high 16 bits of the external
+//! attributes, which is also how symbolic links are marked. Their data is the target of the
+//! link.
+
+use std::prelude::v1::*;
+use std::str;
+
+use super::spec::{self, put_u16, put_u32, read_u16, read_u32};
+use super::write::EntryOptions;
+use super::{ZipEntry, ZipError, FLAG_UTF8};
+use crate::checksum::Crc32;
+
+/// Largest size of the extra field of a header.
+const MAX_EXTRA_SIZE: usize = 0xFFFF;
+
+/// Host system in the high byte of `ZipEntry::version_made_by` for entries made on Unix.
+pub const HOST_UNIX: u8 = 3;
+
+/// File type bits of a Unix mode.
+const S_IFMT: u32 = 0o170_000;
+/// File type of a symbolic link.
+pub const S_IFLNK: u32 = 0o120_000;
+
+/// A typed extra field.
+pub trait ExtraField: Sized {
+    /// Header ID of the field.
+    const ID: u16;
+
+    /// Parse the data of the field, without the header ID and size.
+    ///
+    /// Returns `None` if the data is invalid or uses a version of the field that isn't
+    /// supported.
+    fn parse(data: &[u8]) -> Option<Self>;
+
+    /// The data of the field as written to the local header.
+    fn local_data(&self) -> Vec<u8>;
+
+    /// The data of the field as written to the central directory, the same as in the local
+    /// header unless the field says otherwise.
+    fn central_data(&self) -> Vec<u8> {
+        self.local_data()
+    }
+}
+
+/// Iterator over the extra fields of an entry, returning the header ID and data of each.
+///
+/// Iteration stops at a field that doesn't fit.
+#[derive(Debug, Clone)]
+pub struct ExtraFields<'a> {
+    extra: &'a [u8],
+}
+
+impl<'a> Iterator for ExtraFields<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<(u16, &'a [u8])> {
+        if self.extra.len() < 4 {
+            return None;
+        }
+        let id = read_u16(self.extra, 0);
+        let end = 4 + usize::from(read_u16(self.extra, 2));
+        match self.extra.get(4..end) {
+            Some(data) => {
+                self.extra = &self.extra[end..];
+                Some((id, data))
+            }
+            None => {
+                self.extra = &[];
+                None
+            }
+        }
+    }
+}
+
+/// The extended timestamp field, 0x5455, with times in seconds since the Unix epoch in UTC.
+///
+/// The local header may hold all three times, the central directory only holds the
+/// modification time.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ExtendedTimestamp {
+    /// Last modification time.
+    pub modified: Option<i32>,
+    /// Last access time.
+    pub accessed: Option<i32>,
+    /// Creation time.
+    pub created: Option<i32>,
+}
+
+impl ExtendedTimestamp {
+    /// A timestamp with only the modification time.
+    pub fn new(modified: i32) -> ExtendedTimestamp {
+        ExtendedTimestamp {
+            modified: Some(modified),
+            ..ExtendedTimestamp::default()
+        }
+    }
+
+    fn flags(&self) -> u8 {
+        self.modified.map_or(0, |_| 1)
+            | self.accessed.map_or(0, |_| 2)
+            | self.created.map_or(0, |_| 4)
+    }
+}
+
+impl ExtraField for ExtendedTimestamp {
+    const ID: u16 = 0x5455;
+
+    fn parse(data: &[u8]) -> Option<ExtendedTimestamp> {
+        let (&flags, mut times) = data.split_first()?;
+        let mut timestamp = ExtendedTimestamp::default();
+        // The flags say which times are in the local header, while the central directory only
+        // has the modification time, so stop at the end of the data.
+        let mut fields = [
+            &mut timestamp.modified,
+            &mut timestamp.accessed,
+            &mut timestamp.created,
+        ];
+        for (bit, field) in fields.iter_mut().enumerate() {
+            if flags & (1 << bit) != 0 && times.len() >= 4 {
+                **field = Some(read_u32(times, 0) as i32);
+                times = &times[4..];
+            }
+        }
+        Some(timestamp)
+    }
+
+    fn local_data(&self) -> Vec<u8> {
+        let mut data = vec![self.flags()];
+        for time in [self.modified, self.accessed, self.created].iter() {
+            if let Some(time) = *time {
+                put_u32(&mut data, time as u32);
+            }
+        }
+        data
+    }
+
+    fn central_data(&self) -> Vec<u8> {
+        let mut data = vec![self.flags()];
+        if let Some(modified) = self.modified {
+            put_u32(&mut data, modified as u32);
+        }
+        data
+    }
+}
+
+/// The Info-ZIP Unix owner field, 0x7875, with the user and group IDs of the file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct UnixOwner {
+    /// User ID.
+    pub uid: u32,
+    /// Group ID.
+    pub gid: u32,
+}
+
+impl ExtraField for UnixOwner {
+    const ID: u16 = 0x7875;
+
+    fn parse(data: &[u8]) -> Option<UnixOwner> {
+        // Version 1, then the size and value of each ID, which may be up to 8 bytes.
+        if data.first() != Some(&1) {
+            return None;
+        }
+        let mut pos = 1;
+        let mut ids = [0u32; 2];
+        for id in ids.iter_mut() {
+            let size = usize::from(*data.get(pos)?);
+            let bytes = data.get(pos + 1..pos + 1 + size)?;
+            let mut value = 0u64;
+            for (i, &byte) in bytes.iter().enumerate() {
+                if i >= 8 || (i >= 4 && byte != 0) {
+                    return None;
+                }
+                value |= u64::from(byte) << (8 * i);
+            }
+            *id = value as u32;
+            pos += 1 + size;
+        }
+        Some(UnixOwner {
+            uid: ids[0],
+            gid: ids[1],
+        })
+    }
+
+    fn local_data(&self) -> Vec<u8> {
+        let mut data = vec![1, 4];
+        put_u32(&mut data, self.uid);
+        data.push(4);
+        put_u32(&mut data, self.gid);
+        data
+    }
+}
+
+/// The Info-ZIP Unicode path field, 0x7075, with the name in UTF-8 for entries whose name in
+/// the header is in another encoding.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UnicodePath {
+    /// CRC-32 of the name in the header, which tells whether the name has been changed by a
+    /// program that doesn't know about this field.
+    pub name_crc32: u32,
+    /// The name in UTF-8.
+    pub name: String,
+}
+
+impl UnicodePath {
+    /// The field for an entry named `header_name` in the header and `name` in UTF-8.
+    pub fn new(header_name: &[u8], name: &str) -> UnicodePath {
+        UnicodePath {
+            name_crc32: Crc32::checksum_of(header_name),
+            name: name.to_owned(),
+        }
+    }
+}
+
+impl ExtraField for UnicodePath {
+    const ID: u16 = 0x7075;
+
+    fn parse(data: &[u8]) -> Option<UnicodePath> {
+        if data.len() < 5 || data[0] != 1 {
+            return None;
+        }
+        Some(UnicodePath {
+            name_crc32: read_u32(data, 1),
+            name: str::from_utf8(&data[5..]).ok()?.to_owned(),
+        })
+    }
+
+    fn local_data(&self) -> Vec<u8> {
+        let mut data = vec![1];
+        put_u32(&mut data, self.name_crc32);
+        data.extend_from_slice(self.name.as_bytes());
+        data
+    }
+}
+
+impl ZipEntry {
+    /// The fields in the extra field of the entry.
+    pub fn extra_fields(&self) -> ExtraFields<'_> {
+        ExtraFields {
+            extra: &self.extra_field,
+        }
+    }
+
+    /// Find and parse the extra field of type `T`.
+    ///
+    /// Entries from `ZipArchive` have the extra field of the central directory, those from
+    /// `ZipStreamReader` that of the local header.
+    pub fn extra_field<T: ExtraField>(&self) -> Option<T> {
+        spec::find_extra_field(&self.extra_field, T::ID).and_then(T::parse)
+    }
+
+    /// Whether the name and comment are marked as UTF-8. Otherwise they are in code page 437,
+    /// although many programs use the local encoding instead.
+    pub fn is_utf8(&self) -> bool {
+        self.flags & FLAG_UTF8 != 0
+    }
+
+    /// The name of the entry in UTF-8, if it is known.
+    ///
+    /// That is the name itself if it is marked as UTF-8 or only has ASCII characters, or the
+    /// name in the Unicode path field if that matches the name.
+    pub fn utf8_name(&self) -> Option<&str> {
+        if self.is_utf8() || self.name.iter().all(|&c| c < 0x80) {
+            return str::from_utf8(&self.name).ok();
+        }
+        let data = spec::find_extra_field(&self.extra_field, UnicodePath::ID)?;
+        if data.len() < 5 || data[0] != 1 || read_u32(data, 1) != Crc32::checksum_of(&self.name) {
+            return None;
+        }
+        str::from_utf8(&data[5..]).ok()
+    }
+
+    /// The host system the entry was made on, 0 for MS-DOS and `HOST_UNIX` for Unix.
+    pub fn host_system(&self) -> u8 {
+        (self.version_made_by >> 8) as u8
+    }
+
+    /// The Unix file type and permissions, if the entry was made on Unix.
+    pub fn unix_mode(&self) -> Option<u32> {
+        let mode = self.external_attributes >> 16;
+        if self.host_system() == HOST_UNIX && mode != 0 {
+            Some(mode)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the entry is a symbolic link, whose data is the target of the link.
+    pub fn is_symlink(&self) -> bool {
+        self.unix_mode().map(|mode| mode & S_IFMT) == Some(S_IFLNK)
+    }
+}
+
+impl EntryOptions {
+    /// Add `field` to the local and central extra fields, replacing any field with the same
+    /// header ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ZipError::InvalidParameter` if the field or the extra fields with it don't fit
+    /// in the 16-bit sizes of the headers, in which case the options are left as they were.
+    pub fn set_extra_field<T: ExtraField>(&mut self, field: &T) -> Result<(), ZipError> {
+        let local = with_field(&self.local_extra_field, T::ID, &field.local_data())?;
+        let central = with_field(&self.central_extra_field, T::ID, &field.central_data())?;
+        self.local_extra_field = local;
+        self.central_extra_field = central;
+        Ok(())
+    }
+}
+
+/// Copy `extra` with the field `id` set to `data`.
+fn with_field(extra: &[u8], id: u16, data: &[u8]) -> Result<Vec<u8>, ZipError> {
+    let mut out = spec::remove_extra_field(extra, id);
+    if data.len() > MAX_EXTRA_SIZE || out.len() + 4 + data.len() > MAX_EXTRA_SIZE {
+        return Err(ZipError::InvalidParameter);
+    }
+    put_u16(&mut out, id);
+    put_u16(&mut out, data.len() as u16);
+    out.extend_from_slice(data);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read};
+
+    use super::super::{EntryOptions, ZipArchive, ZipError, ZipStreamReader, ZipWriter};
+    use super::{ExtendedTimestamp, ExtraField, UnicodePath, UnixOwner, HOST_UNIX};
+
+    #[test]
+    fn round_trip() {
+        let timestamp = ExtendedTimestamp {
+            modified: Some(1_500_000_000),
+            accessed: Some(1_500_000_100),
+            created: None,
+        };
+        let owner = UnixOwner {
+            uid: 1000,
+            gid: 100,
+        };
+        // "café" in code page 437.
+        let cp437_name = b"caf\x82";
+        let mut options = EntryOptions::default();
+        options.unix_mode = Some(0o100_640);
+        options.set_extra_field(&timestamp).unwrap();
+        options.set_extra_field(&owner).unwrap();
+        options
+            .set_extra_field(&ExtendedTimestamp::new(1_600_000_000))
+            .unwrap();
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.add_entry("naïve.txt".as_bytes(), b"data", &options)
+            .unwrap();
+        let mut cp437 = EntryOptions::default();
+        cp437
+            .set_extra_field(&UnicodePath::new(cp437_name, "café"))
+            .unwrap();
+        zip.add_entry(cp437_name, b"data", &cp437).unwrap();
+        zip.add_entry(b"\xff", b"data", &EntryOptions::default())
+            .unwrap();
+        zip.add_symlink(b"link", "naïve.txt".as_bytes(), &EntryOptions::default())
+            .unwrap();
+        zip.finish().unwrap();
+        let data = zip.into_inner().into_inner();
+
+        let archive = ZipArchive::new(Cursor::new(&data[..])).unwrap();
+        let entry = archive.entry(0).unwrap();
+        assert!(entry.is_utf8());
+        assert_eq!(entry.utf8_name(), Some("naïve.txt"));
+        assert_eq!(entry.host_system(), HOST_UNIX);
+        assert_eq!(entry.unix_mode(), Some(0o100_640));
+        assert!(!entry.is_symlink());
+        assert_eq!(entry.extra_field(), Some(owner));
+        // Setting a field again replaces it, and the central directory only has the
+        // modification time.
+        assert_eq!(
+            entry.extra_field(),
+            Some(ExtendedTimestamp::new(1_600_000_000))
+        );
+        let ids: Vec<_> = entry.extra_fields().map(|(id, _)| id).collect();
+        assert_eq!(ids, vec![UnixOwner::ID, ExtendedTimestamp::ID]);
+
+        let entry = archive.entry(1).unwrap();
+        assert!(!entry.is_utf8());
+        assert_eq!(entry.utf8_name(), Some("café"));
+        assert_eq!(entry.unix_mode(), None);
+
+        // Names that aren't UTF-8 aren't marked as such.
+        let entry = archive.entry(2).unwrap();
+        assert!(!entry.is_utf8());
+        assert_eq!(entry.utf8_name(), None);
+
+        let entry = archive.entry(3).unwrap();
+        assert!(entry.is_symlink());
+        assert_eq!(entry.unix_mode(), Some(0o120_777));
+        assert_eq!(entry.method, 0);
+
+        // The local header has all the times.
+        let mut options = EntryOptions::default();
+        options.set_extra_field(&timestamp).unwrap();
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.add_entry(b"times", b"data", &options).unwrap();
+        zip.finish().unwrap();
+        let mut reader = ZipStreamReader::new(Cursor::new(zip.into_inner().into_inner()));
+        let mut entry = reader.next_entry().unwrap().unwrap();
+        assert_eq!(entry.entry().extra_field(), Some(timestamp));
+        entry.read_to_end(&mut Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn parse() {
+        // IDs of 2 and 8 bytes.
+        let data = [1, 2, 0xE8, 0x03, 8, 100, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            UnixOwner::parse(&data),
+            Some(UnixOwner {
+                uid: 1000,
+                gid: 100
+            })
+        );
+        let too_large = [1, 1, 0, 8, 0, 0, 0, 0, 1, 0, 0, 0];
+        assert_eq!(UnixOwner::parse(&too_large), None);
+        assert_eq!(UnixOwner::parse(&[2, 1, 0, 1, 0]), None);
+        assert_eq!(UnixOwner::parse(&[1, 4, 0]), None);
+
+        // Only the access time, and a flag without the time.
+        let data = [6, 1, 0, 0, 0];
+        assert_eq!(
+            ExtendedTimestamp::parse(&data),
+            Some(ExtendedTimestamp {
+                modified: None,
+                accessed: Some(1),
+                created: None,
+            })
+        );
+        assert_eq!(ExtendedTimestamp::parse(&[]), None);
+        assert_eq!(UnicodePath::parse(&[1, 0, 0, 0, 0, 0xff]), None);
+
+        let mut options = EntryOptions::default();
+        options.central_extra_field = vec![0x34, 0x12, 1, 0, 7, 0x78, 0x56, 4, 0];
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.add_entry(b"a", b"", &options).unwrap();
+        zip.finish().unwrap();
+        let archive = ZipArchive::new(zip.into_inner()).unwrap();
+        let fields: Vec<_> = archive.entry(0).unwrap().extra_fields().collect();
+        assert_eq!(fields, vec![(0x1234, &[7][..])]);
+    }
+
+    #[test]
+    fn too_large() {
+        let mut options = EntryOptions::default();
+        let name = "a".repeat(0xFFFF);
+        assert_eq!(
+            options.set_extra_field(&UnicodePath::new(b"a", &name)),
+            Err(ZipError::InvalidParameter)
+        );
+        assert_eq!(options, EntryOptions::default());
+
+        // The timestamp takes 9 bytes and the Unicode path 9 plus the name.
+        options
+            .set_extra_field(&ExtendedTimestamp::new(1_500_000_000))
+            .unwrap();
+        let before = options.clone();
+        let name = "a".repeat(0xFFFF - 17);
+        assert_eq!(
+            options.set_extra_field(&UnicodePath::new(b"a", &name)),
+            Err(ZipError::InvalidParameter)
+        );
+        assert_eq!(options, before);
+        let path = UnicodePath::new(b"a", &name[1..]);
+        options.set_extra_field(&path).unwrap();
+        assert_eq!(options.local_extra_field.len(), 0xFFFF);
+        // The field it replaces doesn't count.
+        options.set_extra_field(&path).unwrap();
+    }
+}
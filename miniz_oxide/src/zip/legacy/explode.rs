@@ -0,0 +1,256 @@
+//! Decoder for Implode (method 6).
+//!
+//! Implode is LZ77 with a 4K or 8K sliding dictionary and static Shannon-Fano codes for the
+//! lengths, the high bits of the distances and optionally the literals. Bit 1 of the general
+//! purpose flags selects the 8K dictionary and bit 2 the literal code.
+
+use std::prelude::v1::*;
+
+use super::{BitReader, Window};
+use crate::zip::ZipError;
+
+const FLAG_LARGE_WINDOW: u16 = 0x0002;
+const FLAG_LITERAL_TREE: u16 = 0x0004;
+const MAX_CODE_LEN: usize = 16;
+/// Length code that is followed by another 8 bits of length.
+const LONG_LENGTH: u16 = 63;
+
+/// A Shannon-Fano code.
+///
+/// The codes are the bitwise complement of canonical Huffman codes with the same lengths, so
+/// they are decoded the same way with every bit flipped.
+struct Tree {
+    /// Number of codes of each length.
+    counts: [u16; MAX_CODE_LEN + 1],
+    /// The symbols ordered by code length, and by value within each length.
+    symbols: Vec<u16>,
+}
+
+impl Tree {
+    fn new() -> Tree {
+        Tree {
+            counts: [0; MAX_CODE_LEN + 1],
+            symbols: Vec::new(),
+        }
+    }
+
+    /// Read a tree of `num_symbols` symbols.
+    ///
+    /// The code lengths are stored as runs, in a byte giving the number of runs minus one and
+    /// a byte for each run with the count minus one in the high nibble and the length minus
+    /// one in the low nibble.
+    fn read<F>(bits: &mut BitReader, input: &mut F, num_symbols: usize) -> Result<Tree, ZipError>
+    where
+        F: FnMut() -> Result<Option<u8>, ZipError>,
+    {
+        let mut lengths = Vec::with_capacity(num_symbols);
+        let runs = bits.bits(input, 8)? + 1;
+        for _ in 0..runs {
+            let run = bits.bits(input, 8)? as usize;
+            let len = (run & 0xF) + 1;
+            let count = (run >> 4) + 1;
+            if lengths.len() + count > num_symbols {
+                return Err(ZipError::DecompressionFailed);
+            }
+            lengths.extend((0..count).map(|_| len));
+        }
+        if lengths.len() != num_symbols {
+            return Err(ZipError::DecompressionFailed);
+        }
+
+        let mut tree = Tree::new();
+        for &len in &lengths {
+            tree.counts[len] += 1;
+        }
+        // Only complete codes are valid.
+        let mut left: i32 = 1;
+        for &count in &tree.counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(ZipError::DecompressionFailed);
+            }
+        }
+        if left != 0 {
+            return Err(ZipError::DecompressionFailed);
+        }
+        for len in 1..=MAX_CODE_LEN {
+            tree.symbols.extend(
+                (0..num_symbols as u16).filter(|&symbol| lengths[usize::from(symbol)] == len),
+            );
+        }
+        Ok(tree)
+    }
+
+    /// Decode a symbol, reading the code a bit at a time.
+    fn decode<F>(&self, bits: &mut BitReader, input: &mut F) -> Result<u16, ZipError>
+    where
+        F: FnMut() -> Result<Option<u8>, ZipError>,
+    {
+        // `code` is the complemented code read so far, and `first` the first code of its
+        // length.
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &self.counts[1..] {
+            code |= bits.bits(input, 1)? ^ 1;
+            let count = u32::from(count);
+            if code < first + count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        // Not reached with a complete code.
+        Err(ZipError::DecompressionFailed)
+    }
+}
+
+pub(crate) struct Explode {
+    bits: BitReader,
+    /// Number of low distance bits stored as they are, 7 with the 8K dictionary and 6 with
+    /// the 4K one.
+    distance_bits: u32,
+    /// 3 with a literal tree, 2 without.
+    min_match: usize,
+    literals: Option<Tree>,
+    lengths: Tree,
+    distances: Tree,
+    /// Whether the trees have been read.
+    started: bool,
+    window: Window,
+}
+
+impl Explode {
+    pub(crate) fn new(flags: u16, size: u64) -> Explode {
+        let literal_tree = flags & FLAG_LITERAL_TREE != 0;
+        Explode {
+            bits: BitReader::new(),
+            distance_bits: if flags & FLAG_LARGE_WINDOW != 0 { 7 } else { 6 },
+            min_match: if literal_tree { 3 } else { 2 },
+            literals: if literal_tree {
+                Some(Tree::new())
+            } else {
+                None
+            },
+            lengths: Tree::new(),
+            distances: Tree::new(),
+            started: false,
+            window: Window::new(size),
+        }
+    }
+
+    pub(crate) fn read<F>(&mut self, input: &mut F, out: &mut [u8]) -> Result<usize, ZipError>
+    where
+        F: FnMut() -> Result<Option<u8>, ZipError>,
+    {
+        let mut n = 0;
+        loop {
+            n += self.window.drain(&mut out[n..]);
+            if n == out.len() || self.window.is_done() {
+                return Ok(n);
+            }
+            if !self.started {
+                self.read_trees(input)?;
+                self.started = true;
+            }
+            self.decode(input)?;
+        }
+    }
+
+    /// Read the trees, which come before the data in the order literals, lengths, distances.
+    fn read_trees<F>(&mut self, input: &mut F) -> Result<(), ZipError>
+    where
+        F: FnMut() -> Result<Option<u8>, ZipError>,
+    {
+        if self.literals.is_some() {
+            self.literals = Some(Tree::read(&mut self.bits, input, 256)?);
+        }
+        self.lengths = Tree::read(&mut self.bits, input, 64)?;
+        self.distances = Tree::read(&mut self.bits, input, 64)?;
+        Ok(())
+    }
+
+    /// Decode a literal or a copy.
+    fn decode<F>(&mut self, input: &mut F) -> Result<(), ZipError>
+    where
+        F: FnMut() -> Result<Option<u8>, ZipError>,
+    {
+        if self.bits.bits(input, 1)? == 1 {
+            let literal = match self.literals {
+                Some(ref tree) => tree.decode(&mut self.bits, input)?,
+                None => self.bits.bits(input, 8)? as u16,
+            };
+            self.window.push(literal as u8);
+            return Ok(());
+        }
+
+        let low = self.bits.bits(input, self.distance_bits)? as usize;
+        let high = usize::from(self.distances.decode(&mut self.bits, input)?);
+        let dist = (high << self.distance_bits | low) + 1;
+        let code = self.lengths.decode(&mut self.bits, input)?;
+        let mut len = usize::from(code) + self.min_match;
+        if code == LONG_LENGTH {
+            len += self.bits.bits(input, 8)? as usize;
+        }
+        self.window.copy(dist, len);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::v1::*;
+
+    use super::super::test::decode;
+    use super::super::LegacyDecoder;
+    use super::Explode;
+    use crate::zip::ZipError;
+
+    fn explode(flags: u16, data: &[u8], size: u64) -> Result<Vec<u8>, ZipError> {
+        decode(LegacyDecoder::Imploded(Explode::new(flags, size)), data, 9)
+    }
+
+    fn expected() -> Vec<u8> {
+        let mut data = vec![0; 100];
+        for _ in 0..3 {
+            data.extend_from_slice(b"Hello, hello, hello! ");
+        }
+        data.extend_from_slice(b"Jello.");
+        data
+    }
+
+    #[test]
+    fn explode_vectors() {
+        // 4K dictionary and literals stored as they are.
+        let data = [
+            0x03, 0xf5, 0xf5, 0xf5, 0xf5, 0x05, 0x04, 0x16, 0xf5, 0xf5, 0xf5, 0xc5, 0x80, 0x0f,
+            0x8c, 0x44, 0x5a, 0x96, 0x2d, 0xdb, 0xb7, 0x2c, 0x41, 0xa2, 0x31, 0xfe, 0x36, 0x24,
+            0x08, 0xea, 0xeb, 0x95, 0x18, 0xff, 0xed, 0x02,
+        ];
+        assert_eq!(explode(0, &data, 169).unwrap(), expected());
+        assert_eq!(explode(0, &data, 120).unwrap(), &expected()[..120]);
+        assert_eq!(explode(0, &data, 170), Err(ZipError::DecompressionFailed));
+
+        // 8K dictionary and a literal tree.
+        let data = [
+            0x0f, 0xf7, 0xf7, 0xf7, 0xf7, 0xf7, 0xf7, 0xf7, 0xf7, 0xf7, 0xf7, 0xf7, 0xf7, 0xf7,
+            0xf7, 0xf7, 0xf7, 0x03, 0xf5, 0xf5, 0xf5, 0xf5, 0x05, 0x04, 0x16, 0xf5, 0xf5, 0xf5,
+            0xc5, 0x00, 0x1f, 0x10, 0xd9, 0x3e, 0x6b, 0xf2, 0xe4, 0x09, 0x97, 0xef, 0x4f, 0x67,
+            0xf8, 0xfb, 0xbd, 0xfb, 0x28, 0xdf, 0xd8, 0xca, 0xf0, 0xbf, 0x8b,
+        ];
+        assert_eq!(explode(6, &data, 169).unwrap(), expected());
+    }
+
+    #[test]
+    fn invalid_trees() {
+        // 64 codes of 7 bits leave half the code space unused.
+        let mut data = vec![0x03, 0xf6, 0xf6, 0xf6, 0xf6];
+        data.extend_from_slice(&[0x03, 0xf5, 0xf5, 0xf5, 0xf5, 0]);
+        assert_eq!(explode(0, &data, 1), Err(ZipError::DecompressionFailed));
+        // 63 codes.
+        let data = [0x03, 0xf5, 0xf5, 0xf5, 0xe5, 0];
+        assert_eq!(explode(0, &data, 1), Err(ZipError::DecompressionFailed));
+        // 65 codes.
+        let data = [0x04, 0xf5, 0xf5, 0xf5, 0xf5, 0x05, 0];
+        assert_eq!(explode(0, &data, 1), Err(ZipError::DecompressionFailed));
+    }
+}
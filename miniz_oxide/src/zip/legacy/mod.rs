@@ -0,0 +1,242 @@
+//! Decoders for the compression methods of PKZIP 1.x: Shrink, Reduce and Implode.
+//!
+//! These were replaced by deflate in PKZIP 2.0, but old archives still contain them. They are
+//! only decoded, never written. The descriptions in APPNOTE.TXT leave out a few details, which
+//! follow what PKZIP and Info-ZIP's UnZip do:
+//!
+//! * Shrink is LZW with codes of 9 to 13 bits. A partial clear frees every code that isn't the
+//!   prefix of another one, and freed codes are reused lowest first.
+//! * Reduce and Implode copy earlier data, and references to before the start of the data
+//!   read zeros.
+//!
+//! The decoders pull the compressed data a byte at a time from a function returning `None`
+//! at the end of it, and stop once the uncompressed size of the entry has been produced.
+
+use std::cmp;
+use std::prelude::v1::*;
+
+use super::{CompressionMethod, ZipError};
+
+mod explode;
+mod unreduce;
+mod unshrink;
+
+use self::explode::Explode;
+use self::unreduce::Unreduce;
+use self::unshrink::Unshrink;
+
+/// Decoder for one of the legacy compression methods.
+pub(crate) enum LegacyDecoder {
+    Shrunk(Unshrink),
+    Reduced(Unreduce),
+    Imploded(Explode),
+}
+
+impl LegacyDecoder {
+    /// Create a decoder for `size` bytes of data compressed with `method`.
+    ///
+    /// Returns `None` if `method` isn't one of the legacy methods. The general purpose `flags`
+    /// of the entry select the variant of Implode.
+    pub(crate) fn new(method: CompressionMethod, flags: u16, size: u64) -> Option<LegacyDecoder> {
+        let decoder = match method {
+            CompressionMethod::Shrunk => LegacyDecoder::Shrunk(Unshrink::new(size)),
+            CompressionMethod::Reduced1 => LegacyDecoder::Reduced(Unreduce::new(1, size)),
+            CompressionMethod::Reduced2 => LegacyDecoder::Reduced(Unreduce::new(2, size)),
+            CompressionMethod::Reduced3 => LegacyDecoder::Reduced(Unreduce::new(3, size)),
+            CompressionMethod::Reduced4 => LegacyDecoder::Reduced(Unreduce::new(4, size)),
+            CompressionMethod::Imploded => LegacyDecoder::Imploded(Explode::new(flags, size)),
+            CompressionMethod::Stored | CompressionMethod::Deflated => return None,
+        };
+        Some(decoder)
+    }
+
+    /// Decode data into `out`, taking compressed bytes from `input` as needed.
+    ///
+    /// Returns the number of bytes written, which is 0 once all the data has been decoded.
+    pub(crate) fn read<F>(&mut self, input: &mut F, out: &mut [u8]) -> Result<usize, ZipError>
+    where
+        F: FnMut() -> Result<Option<u8>, ZipError>,
+    {
+        match *self {
+            LegacyDecoder::Shrunk(ref mut decoder) => decoder.read(input, out),
+            LegacyDecoder::Reduced(ref mut decoder) => decoder.read(input, out),
+            LegacyDecoder::Imploded(ref mut decoder) => decoder.read(input, out),
+        }
+    }
+}
+
+/// Reads bits least significant first, which is how all three methods pack them.
+struct BitReader {
+    bit_buf: u32,
+    num_bits: u32,
+}
+
+impl BitReader {
+    fn new() -> BitReader {
+        BitReader {
+            bit_buf: 0,
+            num_bits: 0,
+        }
+    }
+
+    /// Read `n` bits, at most 16.
+    ///
+    /// Running out of input is an error, as the decoders never read past the end of valid
+    /// data.
+    fn bits<F>(&mut self, input: &mut F, n: u32) -> Result<u32, ZipError>
+    where
+        F: FnMut() -> Result<Option<u8>, ZipError>,
+    {
+        while self.num_bits < n {
+            let byte = input()?.ok_or(ZipError::DecompressionFailed)?;
+            self.bit_buf |= u32::from(byte) << self.num_bits;
+            self.num_bits += 8;
+        }
+        let value = self.bit_buf & ((1 << n) - 1);
+        self.bit_buf >>= n;
+        self.num_bits -= n;
+        Ok(value)
+    }
+}
+
+/// Size of the `Window`, enough for the 8K dictionary of Implode and a copy that hasn't been
+/// returned yet.
+const WINDOW_SIZE: usize = 16 * 1024;
+const WINDOW_MASK: usize = WINDOW_SIZE - 1;
+
+/// The most recent output of Reduce and Implode, which copy data from it.
+struct Window {
+    data: Vec<u8>,
+    /// Number of bytes written, modulo the window size.
+    pos: usize,
+    /// Number of bytes before `pos` that haven't been returned yet.
+    pending: usize,
+    /// Number of bytes of output still to be produced.
+    remaining: u64,
+}
+
+impl Window {
+    /// Create a window for `size` bytes of output.
+    ///
+    /// The window starts out filled with zeros, which is what references to before the start
+    /// of the data read.
+    fn new(size: u64) -> Window {
+        Window {
+            data: vec![0; WINDOW_SIZE],
+            pos: 0,
+            pending: 0,
+            remaining: size,
+        }
+    }
+
+    /// Whether all the output has been produced and returned.
+    fn is_done(&self) -> bool {
+        self.remaining == 0 && self.pending == 0
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.remaining > 0 {
+            self.data[self.pos] = byte;
+            self.pos = (self.pos + 1) & WINDOW_MASK;
+            self.pending += 1;
+            self.remaining -= 1;
+        }
+    }
+
+    /// Copy `len` bytes starting `dist` bytes back, stopping at the end of the output.
+    ///
+    /// `dist` must be at most 8K, and `len` at most the rest of the window.
+    fn copy(&mut self, dist: usize, len: usize) {
+        let len = cmp::min(len as u64, self.remaining) as usize;
+        for _ in 0..len {
+            self.data[self.pos] = self.data[self.pos.wrapping_sub(dist) & WINDOW_MASK];
+            self.pos = (self.pos + 1) & WINDOW_MASK;
+        }
+        self.pending += len;
+        self.remaining -= len as u64;
+    }
+
+    /// Move as many pending bytes as fit to `out`, returning how many were moved.
+    fn drain(&mut self, out: &mut [u8]) -> usize {
+        let n = cmp::min(self.pending, out.len());
+        let start = self.pos.wrapping_sub(self.pending) & WINDOW_MASK;
+        for (i, byte) in out[..n].iter_mut().enumerate() {
+            *byte = self.data[(start + i) & WINDOW_MASK];
+        }
+        self.pending -= n;
+        n
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LegacyDecoder, Window};
+    use crate::zip::{CompressionMethod, ZipError};
+
+    /// Decode `data` with `decoder`, returning the output in pieces of `piece` bytes.
+    pub(super) fn decode(
+        mut decoder: LegacyDecoder,
+        data: &[u8],
+        piece: usize,
+    ) -> Result<Vec<u8>, ZipError> {
+        let mut bytes = data.iter().cloned();
+        let mut input = || Ok(bytes.next());
+        let mut output = Vec::new();
+        let mut buf = vec![0; piece];
+        loop {
+            match decoder.read(&mut input, &mut buf)? {
+                0 => return Ok(output),
+                n => output.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    /// Pack `(value, bits)` fields least significant bit first.
+    pub(super) fn pack(fields: &[(u32, u32)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let (mut bit_buf, mut num_bits) = (0, 0);
+        for &(value, bits) in fields {
+            bit_buf |= value << num_bits;
+            num_bits += bits;
+            while num_bits >= 8 {
+                out.push(bit_buf as u8);
+                bit_buf >>= 8;
+                num_bits -= 8;
+            }
+        }
+        if num_bits > 0 {
+            out.push(bit_buf as u8);
+        }
+        out
+    }
+
+    #[test]
+    fn methods() {
+        assert!(LegacyDecoder::new(CompressionMethod::Stored, 0, 0).is_none());
+        assert!(LegacyDecoder::new(CompressionMethod::Deflated, 0, 0).is_none());
+        // Nothing is read for empty entries.
+        for &method in &[1, 2, 3, 4, 5, 6] {
+            let method = CompressionMethod::from_u16(method).unwrap();
+            let decoder = LegacyDecoder::new(method, 0, 0).unwrap();
+            assert_eq!(decode(decoder, &[], 1), Ok(Vec::new()));
+        }
+    }
+
+    #[test]
+    fn window() {
+        let mut window = Window::new(10);
+        window.push(1);
+        // The bytes before the start are zeros.
+        window.copy(3, 4);
+        let mut out = [9; 3];
+        assert_eq!(window.drain(&mut out), 3);
+        assert_eq!(out, [1, 0, 0]);
+        // Copies overlapping the output repeat it, and stop at the end of the output.
+        window.push(7);
+        window.copy(1, 20);
+        let mut out = [9; 10];
+        assert_eq!(window.drain(&mut out), 7);
+        assert_eq!(out[..7], [1, 0, 7, 7, 7, 7, 7]);
+        assert!(window.is_done());
+    }
+}
@@ -0,0 +1,244 @@
+//! Decoder for Reduce (methods 2 to 5).
+//!
+//! The data goes through two stages. Each byte is first coded according to the byte before
+//! it, with a short index into a set of likely followers. The bytes that come out of that are
+//! literals, except for DLE (144), which starts a copy of earlier data. The compression
+//! factor, 1 to 4, is the number of bits of the copy distance taken from the length byte.
+
+use std::cmp;
+use std::prelude::v1::*;
+
+use super::{BitReader, Window};
+use crate::zip::ZipError;
+
+const DLE: u8 = 144;
+/// Copies are at least this long.
+const MIN_MATCH: usize = 3;
+
+/// Where the next byte of the first stage goes.
+#[derive(Copy, Clone)]
+enum State {
+    Literal,
+    /// After DLE.
+    Length,
+    /// After a length byte with all length bits set, more length follows.
+    ExtraLength {
+        value: u8,
+        len: usize,
+    },
+    Distance {
+        value: u8,
+        len: usize,
+    },
+}
+
+pub(crate) struct Unreduce {
+    bits: BitReader,
+    factor: u32,
+    /// The follower set of each byte, read before the first byte of data.
+    followers: Vec<Vec<u8>>,
+    last: u8,
+    state: State,
+    window: Window,
+}
+
+impl Unreduce {
+    pub(crate) fn new(factor: u32, size: u64) -> Unreduce {
+        Unreduce {
+            bits: BitReader::new(),
+            factor,
+            followers: Vec::new(),
+            last: 0,
+            state: State::Literal,
+            window: Window::new(size),
+        }
+    }
+
+    pub(crate) fn read<F>(&mut self, input: &mut F, out: &mut [u8]) -> Result<usize, ZipError>
+    where
+        F: FnMut() -> Result<Option<u8>, ZipError>,
+    {
+        let mut n = 0;
+        loop {
+            n += self.window.drain(&mut out[n..]);
+            if n == out.len() || self.window.is_done() {
+                return Ok(n);
+            }
+            if self.followers.is_empty() {
+                self.read_followers(input)?;
+            }
+            let byte = self.next_byte(input)?;
+            self.expand(byte);
+        }
+    }
+
+    /// Read the follower sets, which are stored last byte first.
+    fn read_followers<F>(&mut self, input: &mut F) -> Result<(), ZipError>
+    where
+        F: FnMut() -> Result<Option<u8>, ZipError>,
+    {
+        let mut followers = vec![Vec::new(); 256];
+        for set in followers.iter_mut().rev() {
+            let len = self.bits.bits(input, 6)?;
+            for _ in 0..len {
+                set.push(self.bits.bits(input, 8)? as u8);
+            }
+        }
+        self.followers = followers;
+        Ok(())
+    }
+
+    /// Decode the next byte of the first stage.
+    fn next_byte<F>(&mut self, input: &mut F) -> Result<u8, ZipError>
+    where
+        F: FnMut() -> Result<Option<u8>, ZipError>,
+    {
+        let set = &self.followers[usize::from(self.last)];
+        let byte = if set.is_empty() || self.bits.bits(input, 1)? == 1 {
+            self.bits.bits(input, 8)? as u8
+        } else {
+            // Enough bits for the largest index, but at least one.
+            let index_bits = cmp::max(1, 32 - (set.len() as u32 - 1).leading_zeros());
+            let index = self.bits.bits(input, index_bits)? as usize;
+            *set.get(index).ok_or(ZipError::DecompressionFailed)?
+        };
+        self.last = byte;
+        Ok(byte)
+    }
+
+    /// Pass a byte through the second stage.
+    fn expand(&mut self, byte: u8) {
+        let len_mask = 0x7F >> (self.factor - 1);
+        self.state = match self.state {
+            State::Literal if byte == DLE => State::Length,
+            State::Literal => {
+                self.window.push(byte);
+                State::Literal
+            }
+            State::Length if byte == 0 => {
+                self.window.push(DLE);
+                State::Literal
+            }
+            State::Length => {
+                let len = usize::from(byte & len_mask);
+                if len == usize::from(len_mask) {
+                    State::ExtraLength { value: byte, len }
+                } else {
+                    State::Distance { value: byte, len }
+                }
+            }
+            State::ExtraLength { value, len } => State::Distance {
+                value,
+                len: len + usize::from(byte),
+            },
+            State::Distance { value, len } => {
+                let high = usize::from(value >> (8 - self.factor));
+                self.window
+                    .copy((high << 8) + usize::from(byte) + 1, len + MIN_MATCH);
+                State::Literal
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::v1::*;
+
+    use super::super::test::{decode, pack};
+    use super::super::LegacyDecoder;
+    use super::Unreduce;
+    use crate::zip::ZipError;
+
+    fn unreduce(factor: u32, data: &[u8], size: u64) -> Result<Vec<u8>, ZipError> {
+        decode(LegacyDecoder::Reduced(Unreduce::new(factor, size)), data, 7)
+    }
+
+    /// Follower sets that are empty except for `set` following `byte`.
+    fn followers(byte: u8, set: &[u8]) -> Vec<(u32, u32)> {
+        let mut fields = Vec::new();
+        for j in (0..=255).rev() {
+            if j == byte {
+                fields.push((set.len() as u32, 6));
+                fields.extend(set.iter().map(|&c| (u32::from(c), 8)));
+            } else {
+                fields.push((0, 6));
+            }
+        }
+        fields
+    }
+
+    #[test]
+    fn unreduce_factor_2() {
+        let mut fields = followers(b'a', b"bc");
+        fields.extend_from_slice(&[
+            (u32::from(b'a'), 8),
+            // "b" from the followers of "a".
+            (0, 1),
+            (0, 1),
+            (u32::from(b'a'), 8),
+            // "d" isn't a follower of "a".
+            (1, 1),
+            (u32::from(b'd'), 8),
+            // DLE itself.
+            (144, 8),
+            (0, 8),
+            // 5 bytes from 261 bytes back, before the start.
+            (144, 8),
+            (0x42, 8),
+            (4, 8),
+            // 76 bytes from 10 bytes back, with the length continued in the next byte.
+            (144, 8),
+            (0x3F, 8),
+            (10, 8),
+            (9, 8),
+            (u32::from(b'z'), 8),
+        ]);
+        let data = pack(&fields);
+
+        let mut expected = b"abad\x90\0\0\0\0\0".to_vec();
+        for _ in 0..76 {
+            let byte = expected[expected.len() - 10];
+            expected.push(byte);
+        }
+        expected.push(b'z');
+        assert_eq!(unreduce(2, &data, 87).unwrap(), expected);
+        // The decoder stops at the end of the output.
+        assert_eq!(unreduce(2, &data, 30).unwrap(), &expected[..30]);
+        assert_eq!(unreduce(2, &data, 88), Err(ZipError::DecompressionFailed));
+    }
+
+    #[test]
+    fn unreduce_factors() {
+        let mut fields = followers(0, &[]);
+        fields.extend_from_slice(&[
+            (u32::from(b'x'), 8),
+            (u32::from(b'y'), 8),
+            (144, 8),
+            (0xF1, 8),
+            (1, 8),
+            (u32::from(b'z'), 8),
+        ]);
+        let data = pack(&fields);
+        // The same length byte means a different length with each factor, and a distance
+        // that goes back to before the start.
+        for &(factor, len) in &[(1, 0x71), (2, 0x31), (3, 0x11), (4, 1)] {
+            let mut expected = b"xy".to_vec();
+            expected.resize(2 + len + 3, 0);
+            expected.push(b'z');
+            let out = unreduce(factor, &data, expected.len() as u64).unwrap();
+            assert_eq!(out, expected);
+        }
+    }
+
+    #[test]
+    fn invalid_follower() {
+        let mut fields = followers(0, b"abc");
+        // Index 3 of a set of 3.
+        fields.extend_from_slice(&[(0, 1), (3, 2)]);
+        assert_eq!(
+            unreduce(1, &pack(&fields), 1),
+            Err(ZipError::DecompressionFailed)
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Decoder for Shrink (method 1), a variant of LZW.
+//!
+//! Codes start at 9 bits and grow to at most 13, but only when the compressor says so with
+//! control code 256 followed by 1. Control code 256 followed by 2 is a partial clear, which
+//! frees the codes that aren't a prefix of any other code.
+
+use std::cmp;
+use std::prelude::v1::*;
+
+use super::BitReader;
+use crate::zip::ZipError;
+
+/// Number of codes with 13 bits.
+const TABLE_SIZE: usize = 8192;
+const CONTROL_CODE: u16 = 256;
+const FIRST_FREE_CODE: u16 = 257;
+const MIN_CODE_SIZE: u32 = 9;
+const MAX_CODE_SIZE: u32 = 13;
+/// Parent of codes that aren't in use.
+const FREE: u16 = 0xFFFF;
+
+pub(crate) struct Unshrink {
+    bits: BitReader,
+    code_size: u32,
+    /// The code each code extends by one byte, or `FREE`. The literals have `CONTROL_CODE` as
+    /// their parent.
+    parent: Vec<u16>,
+    /// The last byte of the string of each code.
+    value: Vec<u8>,
+    /// The lowest code that isn't in use, or `TABLE_SIZE` if they all are.
+    next_free: usize,
+    /// The previous code, none before the first one.
+    prev: Option<u16>,
+    /// The first byte of the string of the previous code.
+    first: u8,
+    /// The string of the last code in reverse order, as much of it as hasn't been returned.
+    stack: Vec<u8>,
+    /// Number of bytes of output still to be produced.
+    remaining: u64,
+}
+
+impl Unshrink {
+    pub(crate) fn new(size: u64) -> Unshrink {
+        let mut parent = vec![FREE; TABLE_SIZE];
+        for code in parent.iter_mut().take(usize::from(CONTROL_CODE)) {
+            *code = CONTROL_CODE;
+        }
+        Unshrink {
+            bits: BitReader::new(),
+            code_size: MIN_CODE_SIZE,
+            parent,
+            value: (0..TABLE_SIZE).map(|code| code as u8).collect(),
+            next_free: usize::from(FIRST_FREE_CODE),
+            prev: None,
+            first: 0,
+            stack: Vec::with_capacity(TABLE_SIZE),
+            remaining: size,
+        }
+    }
+
+    pub(crate) fn read<F>(&mut self, input: &mut F, out: &mut [u8]) -> Result<usize, ZipError>
+    where
+        F: FnMut() -> Result<Option<u8>, ZipError>,
+    {
+        let mut n = 0;
+        loop {
+            while n < out.len() {
+                match self.stack.pop() {
+                    Some(byte) => {
+                        out[n] = byte;
+                        n += 1;
+                    }
+                    None => break,
+                }
+            }
+            if n == out.len() || self.remaining == 0 {
+                return Ok(n);
+            }
+            self.decode(input)?;
+        }
+    }
+
+    /// Read the next code, putting its string on the stack.
+    fn decode<F>(&mut self, input: &mut F) -> Result<(), ZipError>
+    where
+        F: FnMut() -> Result<Option<u8>, ZipError>,
+    {
+        let code = self.bits.bits(input, self.code_size)? as u16;
+        let prev = match self.prev {
+            Some(prev) => prev,
+            None => {
+                // The first code is always a literal.
+                if code >= CONTROL_CODE {
+                    return Err(ZipError::DecompressionFailed);
+                }
+                self.prev = Some(code);
+                self.first = code as u8;
+                self.stack.push(code as u8);
+                self.remaining -= 1;
+                return Ok(());
+            }
+        };
+
+        if code == CONTROL_CODE {
+            match self.bits.bits(input, self.code_size)? {
+                1 if self.code_size < MAX_CODE_SIZE => self.code_size += 1,
+                2 => self.partial_clear(),
+                _ => return Err(ZipError::DecompressionFailed),
+            }
+            return Ok(());
+        }
+
+        // Build the string backwards on the stack, which is empty at this point.
+        let mut current = code;
+        if self.parent[usize::from(code)] == FREE {
+            // A code that isn't in the table yet is the one about to be added, the previous
+            // string followed by its own first byte.
+            self.stack.push(self.first);
+            current = prev;
+        }
+        while current != CONTROL_CODE {
+            let parent = self.parent[usize::from(current)];
+            if parent == FREE || self.stack.len() == TABLE_SIZE {
+                return Err(ZipError::DecompressionFailed);
+            }
+            self.stack.push(self.value[usize::from(current)]);
+            current = parent;
+        }
+        self.first = *self.stack.last().expect("Bug! Empty string!");
+        // Only the start of the string is needed if it goes past the end of the output.
+        let len = cmp::min(self.stack.len() as u64, self.remaining) as usize;
+        let excess = self.stack.len() - len;
+        self.stack.drain(..excess);
+        self.remaining -= len as u64;
+
+        if self.next_free < TABLE_SIZE {
+            self.parent[self.next_free] = prev;
+            self.value[self.next_free] = self.first;
+            self.next_free = self.find_free(self.next_free + 1);
+        }
+        self.prev = Some(code);
+        Ok(())
+    }
+
+    /// Free the codes that aren't the prefix of another code.
+    fn partial_clear(&mut self) {
+        let mut is_prefix = vec![false; TABLE_SIZE];
+        for &parent in &self.parent[usize::from(FIRST_FREE_CODE)..] {
+            if parent != FREE && parent > CONTROL_CODE {
+                is_prefix[usize::from(parent)] = true;
+            }
+        }
+        let codes = self.parent.iter_mut().zip(is_prefix);
+        for (parent, is_prefix) in codes.skip(usize::from(FIRST_FREE_CODE)) {
+            if !is_prefix {
+                *parent = FREE;
+            }
+        }
+        self.next_free = self.find_free(usize::from(FIRST_FREE_CODE));
+    }
+
+    fn find_free(&self, start: usize) -> usize {
+        (start..TABLE_SIZE)
+            .find(|&code| self.parent[code] == FREE)
+            .unwrap_or(TABLE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::v1::*;
+
+    use super::super::test::{decode, pack};
+    use super::super::LegacyDecoder;
+    use super::Unshrink;
+    use crate::zip::ZipError;
+
+    fn unshrink(data: &[u8], size: u64) -> Result<Vec<u8>, ZipError> {
+        decode(LegacyDecoder::Shrunk(Unshrink::new(size)), data, 5)
+    }
+
+    #[test]
+    fn unshrink_vectors() {
+        let data = [
+            0x54, 0x9e, 0x08, 0x29, 0xf2, 0x44, 0x8a, 0x93, 0x27, 0x54, 0x02, 0x0e, 0x2c, 0xa8,
+            0x90, 0xa0, 0x41, 0x84,
+        ];
+        let expected = b"TOBEORNOTTOBEORTOBEORNOT";
+        assert_eq!(unshrink(&data, 24).unwrap(), &expected[..]);
+        assert_eq!(unshrink(&data, 10).unwrap(), &expected[..10]);
+
+        // Mostly codes that are used right after being added.
+        let data = [0x61, 0x02, 0x0a, 0x1c, 0x48, 0xb0, 0xa0, 0xc1, 0x80];
+        assert_eq!(unshrink(&data, 30).unwrap(), vec![b'a'; 30]);
+        assert_eq!(unshrink(&data, 31), Err(ZipError::DecompressionFailed));
+    }
+
+    #[test]
+    fn code_size() {
+        // Codes grow to 13 bits, but no further.
+        let mut fields = vec![(u32::from(b'a'), 9)];
+        for size in 9..13 {
+            fields.extend_from_slice(&[(256, size), (1, size)]);
+        }
+        fields.push((u32::from(b'b'), 13));
+        let data = pack(&fields);
+        assert_eq!(unshrink(&data, 2).unwrap(), b"ab");
+        fields.pop();
+        fields.extend_from_slice(&[(256, 13), (1, 13), (u32::from(b'b'), 13)]);
+        let data = pack(&fields);
+        assert_eq!(unshrink(&data, 2), Err(ZipError::DecompressionFailed));
+    }
+
+    #[test]
+    fn invalid() {
+        // The first code has to be a literal.
+        let data = pack(&[(257, 9), (0, 9)]);
+        assert_eq!(unshrink(&data, 2), Err(ZipError::DecompressionFailed));
+        let data = pack(&[(b'a'.into(), 9), (256, 9), (3, 9), (0, 9)]);
+        assert_eq!(unshrink(&data, 2), Err(ZipError::DecompressionFailed));
+    }
+}
@@ -0,0 +1,431 @@
+//! Reading and writing ZIP archives.
+//!
+//! The format is described in PKWARE's
+//! [APPNOTE.TXT](https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT). Entries can be
+//! stored or compressed with deflate, which is decoded with `InflateState` and encoded with
+//! `CompressorOxide`. Entries compressed with the methods of PKZIP 1.x, Shrink, Reduce and
+//! Implode, can also be extracted, as can entries with the traditional PKWARE encryption once
+//! a password has been set.
+
+use std::io;
+use std::prelude::v1::*;
+
+mod crypto;
+pub mod edit;
+pub mod extra;
+mod legacy;
+pub mod read;
+pub mod recover;
+mod spec;
+pub mod stream;
+pub mod validate;
+pub mod write;
+
+#[cfg(feature = "std")]
+pub use self::edit::edit_archive_file_in_place;
+pub use self::edit::ZipEditor;
+pub use self::extra::{ExtendedTimestamp, ExtraField, ExtraFields, UnicodePath, UnixOwner};
+pub use self::read::{EntryDecoder, ZipArchive, ZipEntryReader};
+pub use self::recover::{RecoveredEntry, RecoveryStatus, ZipRecovery};
+pub use self::stream::{ZipStreamEntry, ZipStreamReader};
+pub use self::validate::ValidationLimits;
+pub use self::write::{EntryOptions, EntryWriter, ZipWriter};
+
+/// Size of the buffers used for reading and writing data, the same as in miniz.
+const IO_BUF_SIZE: usize = 64 * 1024;
+
+/// Flags accepted by some of the ZIP functions. The values are the same as in miniz.
+pub mod zip_flags {
+    /// Compare names case sensitively when locating entries.
+    pub const ZIP_FLAG_CASE_SENSITIVE: u32 = 0x0100;
+    /// Ignore the directory part of the names when locating entries.
+    pub const ZIP_FLAG_IGNORE_PATH: u32 = 0x0200;
+    /// Extract the compressed data of an entry as is, without decompressing it.
+    pub const ZIP_FLAG_COMPRESSED_DATA: u32 = 0x0400;
+}
+
+/// Compression methods used by ZIP entries.
+#[repr(u16)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CompressionMethod {
+    /// The data is stored uncompressed.
+    Stored = 0,
+    /// The data is compressed with Shrink, from early versions of PKZIP.
+    Shrunk = 1,
+    /// The data is compressed with Reduce with compression factor 1, from early versions of
+    /// PKZIP.
+    Reduced1 = 2,
+    /// The data is compressed with Reduce with compression factor 2.
+    Reduced2 = 3,
+    /// The data is compressed with Reduce with compression factor 3.
+    Reduced3 = 4,
+    /// The data is compressed with Reduce with compression factor 4.
+    Reduced4 = 5,
+    /// The data is compressed with Implode, from early versions of PKZIP.
+    Imploded = 6,
+    /// The data is compressed with deflate.
+    Deflated = 8,
+}
+
+impl CompressionMethod {
+    pub fn from_u16(value: u16) -> Option<CompressionMethod> {
+        match value {
+            0 => Some(CompressionMethod::Stored),
+            1 => Some(CompressionMethod::Shrunk),
+            2 => Some(CompressionMethod::Reduced1),
+            3 => Some(CompressionMethod::Reduced2),
+            4 => Some(CompressionMethod::Reduced3),
+            5 => Some(CompressionMethod::Reduced4),
+            6 => Some(CompressionMethod::Imploded),
+            8 => Some(CompressionMethod::Deflated),
+            _ => None,
+        }
+    }
+}
+
+/// Errors returned by the ZIP functions.
+///
+/// The values are the same as miniz's `mz_zip_error`, with `WrongPassword` added at the end.
+#[repr(i32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ZipError {
+    Undefined = 1,
+    TooManyFiles = 2,
+    FileTooLarge = 3,
+    UnsupportedMethod = 4,
+    UnsupportedEncryption = 5,
+    UnsupportedFeature = 6,
+    FailedFindingCentralDir = 7,
+    NotAnArchive = 8,
+    InvalidHeaderOrCorrupted = 9,
+    UnsupportedMultidisk = 10,
+    DecompressionFailed = 11,
+    CompressionFailed = 12,
+    UnexpectedDecompressedSize = 13,
+    CrcCheckFailed = 14,
+    UnsupportedCdirSize = 15,
+    AllocFailed = 16,
+    FileOpenFailed = 17,
+    FileCreateFailed = 18,
+    FileWriteFailed = 19,
+    FileReadFailed = 20,
+    FileCloseFailed = 21,
+    FileSeekFailed = 22,
+    FileStatFailed = 23,
+    InvalidParameter = 24,
+    InvalidFilename = 25,
+    BufTooSmall = 26,
+    InternalError = 27,
+    FileNotFound = 28,
+    ArchiveTooLarge = 29,
+    ValidationFailed = 30,
+    WriteCallbackFailed = 31,
+    WrongPassword = 32,
+}
+
+impl ZipError {
+    /// A short description of the error.
+    pub fn as_str(self) -> &'static str {
+        use self::ZipError::*;
+        match self {
+            Undefined => "undefined error",
+            TooManyFiles => "too many files",
+            FileTooLarge => "file too large",
+            UnsupportedMethod => "unsupported method",
+            UnsupportedEncryption => "unsupported encryption",
+            UnsupportedFeature => "unsupported feature",
+            FailedFindingCentralDir => "failed finding central directory",
+            NotAnArchive => "not a ZIP archive",
+            InvalidHeaderOrCorrupted => "invalid header or archive is corrupted",
+            UnsupportedMultidisk => "unsupported multidisk archive",
+            DecompressionFailed => "decompression failed or archive is corrupted",
+            CompressionFailed => "compression failed",
+            UnexpectedDecompressedSize => "unexpected decompressed size",
+            CrcCheckFailed => "CRC-32 check failed",
+            UnsupportedCdirSize => "unsupported central directory size",
+            AllocFailed => "allocation failed",
+            FileOpenFailed => "file open failed",
+            FileCreateFailed => "file create failed",
+            FileWriteFailed => "file write failed",
+            FileReadFailed => "file read failed",
+            FileCloseFailed => "file close failed",
+            FileSeekFailed => "file seek failed",
+            FileStatFailed => "file stat failed",
+            InvalidParameter => "invalid parameter",
+            InvalidFilename => "invalid filename",
+            BufTooSmall => "buffer too small",
+            InternalError => "internal error",
+            FileNotFound => "file not found",
+            ArchiveTooLarge => "archive is too large",
+            ValidationFailed => "validation failed",
+            WriteCallbackFailed => "write callback failed",
+            WrongPassword => "wrong password",
+        }
+    }
+}
+
+impl From<ZipError> for io::Error {
+    fn from(error: ZipError) -> io::Error {
+        let kind = match error {
+            ZipError::FileNotFound => io::ErrorKind::NotFound,
+            ZipError::InvalidParameter => io::ErrorKind::InvalidInput,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, error.as_str())
+    }
+}
+
+/// Date and time in the MS-DOS format used by ZIP headers.
+///
+/// The time has a resolution of two seconds and no time zone, by convention it is local time.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct DateTime {
+    /// Year, 1980-2107.
+    pub year: u16,
+    /// Month, 1-12.
+    pub month: u8,
+    /// Day of the month, 1-31.
+    pub day: u8,
+    /// Hour, 0-23.
+    pub hour: u8,
+    /// Minute, 0-59.
+    pub minute: u8,
+    /// Second, 0-58.
+    pub second: u8,
+}
+
+impl Default for DateTime {
+    /// The earliest time that can be represented, 1980-01-01 00:00:00.
+    fn default() -> DateTime {
+        DateTime::from_dos(0, 0x21)
+    }
+}
+
+impl DateTime {
+    /// Unpack a date and time from their MS-DOS representation.
+    ///
+    /// The fields are not validated, so invalid values are kept as they are.
+    pub fn from_dos(time: u16, date: u16) -> DateTime {
+        DateTime {
+            year: 1980 + (date >> 9),
+            month: ((date >> 5) & 0xF) as u8,
+            day: (date & 0x1F) as u8,
+            hour: (time >> 11) as u8,
+            minute: ((time >> 5) & 0x3F) as u8,
+            second: ((time & 0x1F) * 2) as u8,
+        }
+    }
+
+    /// Pack the date and time into their MS-DOS representation, returning `(time, date)`.
+    pub fn to_dos(&self) -> (u16, u16) {
+        let time = u16::from(self.hour) << 11
+            | (u16::from(self.minute) & 0x3F) << 5
+            | (u16::from(self.second) / 2) & 0x1F;
+        let date = (self.year.saturating_sub(1980) & 0x7F) << 9
+            | (u16::from(self.month) & 0xF) << 5
+            | u16::from(self.day) & 0x1F;
+        (time, date)
+    }
+}
+
+/// Metadata of an entry in a ZIP archive, as stored in the central directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipEntry {
+    /// Name of the entry. Directories end in a `/`.
+    pub name: Vec<u8>,
+    /// Entry comment.
+    pub comment: Vec<u8>,
+    /// Extra field from the central directory.
+    pub extra_field: Vec<u8>,
+    /// Version of the format supported by the program that made the entry in the low byte,
+    /// and the host system in the high byte.
+    pub version_made_by: u16,
+    /// Version of the format needed to extract the entry.
+    pub version_needed: u16,
+    /// General purpose bit flags.
+    pub flags: u16,
+    /// Compression method, see `CompressionMethod`.
+    pub method: u16,
+    /// Last modification time.
+    pub last_modified: DateTime,
+    /// CRC-32 of the uncompressed data.
+    pub crc32: u32,
+    /// Size of the compressed data.
+    pub compressed_size: u64,
+    /// Size of the uncompressed data.
+    pub uncompressed_size: u64,
+    /// Internal file attributes.
+    pub internal_attributes: u16,
+    /// External file attributes, which depend on the host system.
+    pub external_attributes: u32,
+    /// Offset of the local file header in the archive.
+    pub header_offset: u64,
+}
+
+/// General purpose flag: the entry is encrypted.
+pub(crate) const FLAG_ENCRYPTED: u16 = 1;
+/// General purpose flag: the CRC-32 and sizes follow the data in a data descriptor.
+pub(crate) const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+/// General purpose flag: the entry is a compressed patch, which needs data we don't have.
+pub(crate) const FLAG_COMPRESSED_PATCH: u16 = 1 << 5;
+/// General purpose flag: the entry uses strong encryption.
+pub(crate) const FLAG_STRONG_ENCRYPTION: u16 = 1 << 6;
+/// General purpose flag: the name and comment are encoded in UTF-8.
+pub(crate) const FLAG_UTF8: u16 = 1 << 11;
+/// MS-DOS directory attribute in the external file attributes.
+pub(crate) const DOS_DIRECTORY_ATTRIBUTE: u32 = 0x10;
+
+impl ZipEntry {
+    /// Whether the entry appears to be a directory.
+    pub fn is_dir(&self) -> bool {
+        // Like miniz, check the MS-DOS attribute too, even though the meaning of the external
+        // attributes depends on the host system.
+        self.name.last() == Some(&b'/') || self.external_attributes & DOS_DIRECTORY_ATTRIBUTE != 0
+    }
+
+    /// Whether the entry is encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & (FLAG_ENCRYPTED | FLAG_STRONG_ENCRYPTION) != 0
+    }
+
+    /// Whether the entry can be extracted: it is not encrypted or a patch, and the compression
+    /// method is supported.
+    pub fn is_supported(&self) -> bool {
+        !self.is_encrypted() && self.is_method_supported()
+    }
+
+    /// Whether the entry is not a patch and the compression method is supported, whether it
+    /// is encrypted or not.
+    pub(crate) fn is_method_supported(&self) -> bool {
+        self.flags & FLAG_COMPRESSED_PATCH == 0
+            && CompressionMethod::from_u16(self.method).is_some()
+    }
+
+    /// Whether the entry is supported and stored or deflated, which is all that
+    /// `ZipStreamReader` and `ZipRecovery` can extract.
+    pub(crate) fn is_stored_or_deflated(&self) -> bool {
+        self.is_supported()
+            && (self.method == CompressionMethod::Stored as u16
+                || self.method == CompressionMethod::Deflated as u16)
+    }
+}
+
+/// Fixtures shared by the tests of the ZIP modules.
+#[cfg(test)]
+mod test_util {
+    use std::io::Write;
+    use std::prelude::v1::*;
+
+    use super::{EntryOptions, ZipWriter};
+
+    /// Text of `size` bytes, which compresses well, but not as well as zeros.
+    pub fn text(size: usize) -> Vec<u8> {
+        b"The sky above the port was the color of television, tuned to a dead channel. "
+            .iter()
+            .cycle()
+            .take(size)
+            .cloned()
+            .collect()
+    }
+
+    /// An archive built by `build`.
+    pub struct TestArchive {
+        pub data: Vec<u8>,
+        /// The names and contents of the entries, in order.
+        pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+        /// Offset of the central directory.
+        pub central_dir: usize,
+    }
+
+    /// Build an archive with a directory, stored and deflated entries, a ZIP64 entry, and
+    /// stored data containing signatures of local headers and data descriptors. With
+    /// `streaming` every entry but the directory has a data descriptor.
+    ///
+    /// The entries are:
+    ///
+    /// 0. `dir/`
+    /// 1. `dir/stored`, stored
+    /// 2. `dir/empty`, stored
+    /// 3. `dir/tricky`, stored with the signatures
+    /// 4. `large`, deflated, with a comment and Unix permissions
+    /// 5. `large_stored`, stored
+    /// 6. `zip64`, deflated with a ZIP64 extra field
+    /// 7. `last`, deflated
+    pub fn build(streaming: bool) -> TestArchive {
+        let large = text(300_000);
+        let mut tricky = b"before PK\x03\x04".to_vec();
+        tricky.extend_from_slice(&[0; 26]);
+        tricky.extend_from_slice(b" middle PK\x07\x08");
+        tricky.extend_from_slice(&[0; 12]);
+        tricky.extend_from_slice(b" after");
+
+        let mut zip = if streaming {
+            ZipWriter::new_streaming(Vec::new())
+        } else {
+            ZipWriter::new(Vec::new())
+        };
+        zip.set_comment(b"comment").unwrap();
+        let stored = EntryOptions::new(0);
+        zip.add_entry(b"dir/", &[], &EntryOptions::default())
+            .unwrap();
+        zip.add_entry(b"dir/stored", b"stored data", &stored)
+            .unwrap();
+        zip.add_entry(b"dir/empty", &[], &stored).unwrap();
+        zip.add_entry(b"dir/tricky", &tricky, &stored).unwrap();
+        let mut options = EntryOptions::new(9);
+        options.comment = b"entry comment".to_vec();
+        options.external_attributes = 0o644 << 16;
+        zip.add_entry_from_reader(b"large", &mut &large[..], &options)
+            .unwrap();
+        zip.add_entry_from_reader(b"large_stored", &mut &large[..], &stored)
+            .unwrap();
+        let mut options = EntryOptions::default();
+        options.large_file = true;
+        let mut entry = zip.start_entry(b"zip64", &options).unwrap();
+        entry.write_all(&large[..5000]).unwrap();
+        entry.finish().unwrap();
+        zip.add_entry(b"last", b"last entry", &EntryOptions::default())
+            .unwrap();
+        let central_dir = zip.archive_size() as usize;
+        zip.finish().unwrap();
+
+        let entries = vec![
+            (b"dir/".to_vec(), Vec::new()),
+            (b"dir/stored".to_vec(), b"stored data".to_vec()),
+            (b"dir/empty".to_vec(), Vec::new()),
+            (b"dir/tricky".to_vec(), tricky),
+            (b"large".to_vec(), large.clone()),
+            (b"large_stored".to_vec(), large.clone()),
+            (b"zip64".to_vec(), large[..5000].to_vec()),
+            (b"last".to_vec(), b"last entry".to_vec()),
+        ];
+        TestArchive {
+            data: zip.into_inner(),
+            entries,
+            central_dir,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DateTime;
+
+    #[test]
+    fn dos_date_time() {
+        let dt = DateTime::from_dos(0x7d1c, 0x354b);
+        assert_eq!(
+            dt,
+            DateTime {
+                year: 2006,
+                month: 10,
+                day: 11,
+                hour: 15,
+                minute: 40,
+                second: 56,
+            }
+        );
+        assert_eq!(dt.to_dos(), (0x7d1c, 0x354b));
+        assert_eq!(DateTime::default().to_dos(), (0, 0x21));
+    }
+}
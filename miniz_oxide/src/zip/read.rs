@@ -0,0 +1,959 @@
+//! Reading entries from ZIP archives using the central directory.
+
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::prelude::v1::*;
+
+use super::crypto::{ZipCrypto, ENCRYPTION_HEADER_SIZE};
+use super::legacy::LegacyDecoder;
+use super::spec::{self, EndOfCentralDir, LocalHeader, Zip64EndOfCentralDir, Zip64Locator};
+use super::zip_flags::{ZIP_FLAG_CASE_SENSITIVE, ZIP_FLAG_IGNORE_PATH};
+use super::{
+    CompressionMethod, ZipEntry, ZipError, FLAG_COMPRESSED_PATCH, FLAG_DATA_DESCRIPTOR,
+    FLAG_STRONG_ENCRYPTION, IO_BUF_SIZE,
+};
+use crate::checksum::Crc32;
+use crate::inflate::stream::{inflate_chunk, InflateState};
+use crate::DataFormat;
+
+/// The largest possible compression ratio of deflate, used to avoid allocating huge buffers
+/// for entries that claim to be larger than they can be.
+pub(crate) const MAX_DEFLATE_RATIO: u64 = 1032;
+
+/// A ZIP archive opened for reading.
+///
+/// The central directory is read and checked when the archive is opened, the local headers and
+/// data are only read when extracting entries.
+pub struct ZipArchive<R> {
+    reader: R,
+    entries: Vec<ZipEntry>,
+    /// Offset of each entry's header from the start of the central directory.
+    central_header_offsets: Vec<u64>,
+    comment: Vec<u8>,
+    archive_size: u64,
+    central_dir_offset: u64,
+    central_dir_size: u64,
+    zip64: bool,
+    /// Password for entries with the traditional PKWARE encryption.
+    password: Option<Vec<u8>>,
+}
+
+impl<R: Read + Seek> ZipArchive<R> {
+    /// Open an archive, reading its central directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ZipError::NotAnArchive` or `ZipError::FailedFindingCentralDir` if `reader`
+    /// doesn't contain a ZIP archive, and `ZipError::InvalidHeaderOrCorrupted` if the central
+    /// directory is damaged.
+    pub fn new(mut reader: R) -> Result<ZipArchive<R>, ZipError> {
+        let archive_size = reader
+            .seek(SeekFrom::End(0))
+            .map_err(|_| ZipError::FileSeekFailed)?;
+        if archive_size < spec::END_OF_CENTRAL_DIR_SIZE as u64 {
+            return Err(ZipError::NotAnArchive);
+        }
+
+        let tail_len = cmp::min(
+            archive_size,
+            (spec::END_OF_CENTRAL_DIR_SIZE + spec::MAX_COMMENT_SIZE) as u64,
+        );
+        let mut tail = vec![0; tail_len as usize];
+        read_exact_at(&mut reader, archive_size - tail_len, &mut tail)?;
+        let (eocd, eocd_pos) = EndOfCentralDir::find(&tail)?;
+        let eocd_offset = archive_size - tail_len + eocd_pos as u64;
+
+        // Some tools write 1 for both disk numbers in single disk archives.
+        let single_disk = |disks: (u32, u32), entries_on_disk: u64, entries: u64| {
+            (disks == (0, 0) || disks == (1, 1)) && entries_on_disk == entries
+        };
+        let zip64 = read_zip64_end_of_central_dir(&mut reader, eocd_offset)?;
+        let (num_entries, central_dir_offset, central_dir_size) = match zip64 {
+            Some(ref zip64) => {
+                let disks = (zip64.disk_number, zip64.central_dir_disk);
+                if !single_disk(disks, zip64.entries_on_disk, zip64.entries) {
+                    return Err(ZipError::UnsupportedMultidisk);
+                }
+                (
+                    zip64.entries,
+                    zip64.central_dir_offset,
+                    zip64.central_dir_size,
+                )
+            }
+            None => {
+                let disks = (
+                    u32::from(eocd.disk_number),
+                    u32::from(eocd.central_dir_disk),
+                );
+                let entries = u64::from(eocd.entries);
+                if !single_disk(disks, u64::from(eocd.entries_on_disk), entries) {
+                    return Err(ZipError::UnsupportedMultidisk);
+                }
+                (
+                    entries,
+                    u64::from(eocd.central_dir_offset),
+                    u64::from(eocd.central_dir_size),
+                )
+            }
+        };
+        let mut zip64 = zip64.is_some();
+
+        // The same limits as miniz, the central directory is kept in memory.
+        if num_entries > u64::from(u32::max_value()) {
+            return Err(ZipError::TooManyFiles);
+        }
+        if central_dir_size > u64::from(u32::max_value()) {
+            return Err(ZipError::UnsupportedCdirSize);
+        }
+        if central_dir_size < num_entries * spec::CENTRAL_DIR_HEADER_SIZE as u64
+            || central_dir_offset.saturating_add(central_dir_size) > archive_size
+        {
+            return Err(ZipError::InvalidHeaderOrCorrupted);
+        }
+        let num_entries = num_entries as usize;
+
+        let mut central_dir = vec![0; central_dir_size as usize];
+        read_exact_at(&mut reader, central_dir_offset, &mut central_dir)?;
+
+        let mut entries = Vec::with_capacity(num_entries);
+        let mut central_header_offsets = Vec::with_capacity(num_entries);
+        let mut pos = 0;
+        for _ in 0..num_entries {
+            let (entry, header_len) = spec::parse_central_header(&central_dir[pos..])?;
+            check_entry(&entry, archive_size)?;
+            zip64 |=
+                spec::find_extra_field(&entry.extra_field, spec::ZIP64_EXTRA_FIELD_ID).is_some();
+            entries.push(entry);
+            central_header_offsets.push(pos as u64);
+            pos += header_len;
+        }
+
+        Ok(ZipArchive {
+            reader,
+            entries,
+            central_header_offsets,
+            comment: eocd.comment,
+            archive_size,
+            central_dir_offset,
+            central_dir_size,
+            zip64,
+            password: None,
+        })
+    }
+
+    /// Set the password used to extract entries with the traditional PKWARE encryption, or
+    /// clear it with `None`.
+    ///
+    /// The same password is used for all the entries. Entries with strong encryption can't be
+    /// extracted either way.
+    pub fn set_password(&mut self, password: Option<&[u8]>) {
+        self.password = password.map(|password| password.to_vec());
+    }
+
+    /// Whether entry `index` can be extracted.
+    ///
+    /// This is the same as `ZipEntry::is_supported`, except that entries with the traditional
+    /// PKWARE encryption can be extracted once a password has been set.
+    pub fn can_extract(&self, index: usize) -> bool {
+        match self.entries.get(index) {
+            Some(entry) => self.can_decrypt(entry) && entry.is_method_supported(),
+            None => false,
+        }
+    }
+
+    /// Whether `entry` is not encrypted, or encrypted in a way we can decrypt.
+    pub(crate) fn can_decrypt(&self, entry: &ZipEntry) -> bool {
+        !entry.is_encrypted()
+            || (self.password.is_some() && entry.flags & FLAG_STRONG_ENCRYPTION == 0)
+    }
+
+    /// Find where the data of entry `index` starts, by reading its local header.
+    pub fn data_offset(&mut self, index: usize) -> Result<u64, ZipError> {
+        let entry = self.entries.get(index).ok_or(ZipError::InvalidParameter)?;
+        let mut buf = [0; spec::LOCAL_FILE_HEADER_SIZE];
+        read_exact_at(&mut self.reader, entry.header_offset, &mut buf)?;
+        let header = LocalHeader::parse(&buf)?;
+        let data_offset = entry.header_offset + header.total_len();
+        if data_offset.saturating_add(entry.compressed_size) > self.archive_size {
+            return Err(ZipError::InvalidHeaderOrCorrupted);
+        }
+        Ok(data_offset)
+    }
+
+    /// Create a decoder for the data of entry `index`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ZipError::UnsupportedEncryption` or `ZipError::UnsupportedMethod` if the entry
+    /// can't be extracted, and `ZipError::WrongPassword` if it is encrypted and the password
+    /// doesn't match.
+    pub fn decoder(&mut self, index: usize) -> Result<EntryDecoder, ZipError> {
+        let entry = self.entries.get(index).ok_or(ZipError::InvalidParameter)?;
+        if !self.can_decrypt(entry) {
+            return Err(ZipError::UnsupportedEncryption);
+        }
+        let method = match CompressionMethod::from_u16(entry.method) {
+            Some(method) if entry.flags & FLAG_COMPRESSED_PATCH == 0 => method,
+            _ => return Err(ZipError::UnsupportedMethod),
+        };
+        let (crc32, mut compressed_size, uncompressed_size) =
+            (entry.crc32, entry.compressed_size, entry.uncompressed_size);
+        let encrypted = entry.is_encrypted();
+        let legacy = LegacyDecoder::new(method, entry.flags, uncompressed_size);
+        let mut data_offset = self.data_offset(index)?;
+        let mut crypto = None;
+        if encrypted {
+            crypto = Some(Box::new(self.decrypt_header(index, data_offset)?));
+            data_offset += ENCRYPTION_HEADER_SIZE as u64;
+            compressed_size -= ENCRYPTION_HEADER_SIZE as u64;
+        }
+        let mut decoder = EntryDecoder::new(data_offset, compressed_size);
+        decoder.crypto = crypto;
+        // An empty deflate stream takes at least two bytes, but some tools store empty
+        // entries as deflated with no data at all.
+        if method == CompressionMethod::Deflated && compressed_size > 0 {
+            decoder.state = Some(InflateState::new_boxed(DataFormat::Raw));
+            decoder.buf = vec![0; IO_BUF_SIZE];
+        } else if legacy.is_some() {
+            decoder.legacy = legacy.map(Box::new);
+            decoder.buf = vec![0; IO_BUF_SIZE];
+        }
+        decoder.expected = Some((crc32, uncompressed_size));
+        Ok(decoder)
+    }
+
+    /// Read the encryption header in front of the data of entry `index` and check it against
+    /// the password, returning the cipher state for decrypting the data after it.
+    fn decrypt_header(&mut self, index: usize, data_offset: u64) -> Result<ZipCrypto, ZipError> {
+        let entry = &self.entries[index];
+        let password = self
+            .password
+            .as_ref()
+            .ok_or(ZipError::UnsupportedEncryption)?;
+        if entry.compressed_size < ENCRYPTION_HEADER_SIZE as u64 {
+            return Err(ZipError::InvalidHeaderOrCorrupted);
+        }
+        let mut header = [0; ENCRYPTION_HEADER_SIZE];
+        read_exact_at(&mut self.reader, data_offset, &mut header)?;
+        // The header ends with the high byte of the CRC-32, or of the modification time if the
+        // CRC-32 wasn't known yet when the header was written.
+        let check = if entry.flags & FLAG_DATA_DESCRIPTOR != 0 {
+            (entry.last_modified.to_dos().0 >> 8) as u8
+        } else {
+            (entry.crc32 >> 24) as u8
+        };
+        let mut crypto = ZipCrypto::new(password);
+        if !crypto.decrypt_header(&mut header, check) {
+            return Err(ZipError::WrongPassword);
+        }
+        Ok(crypto)
+    }
+
+    /// Create a decoder that returns the data of entry `index` as it is stored in the archive,
+    /// without decompressing or checking it.
+    pub fn raw_decoder(&mut self, index: usize) -> Result<EntryDecoder, ZipError> {
+        let data_offset = self.data_offset(index)?;
+        Ok(EntryDecoder::new(
+            data_offset,
+            self.entries[index].compressed_size,
+        ))
+    }
+
+    /// Get a reader for the decompressed data of entry `index`.
+    ///
+    /// The CRC-32 and size are checked when the end of the data is reached, and a mismatch is
+    /// returned as an error with kind `io::ErrorKind::InvalidData`.
+    pub fn by_index(&mut self, index: usize) -> Result<ZipEntryReader<'_, R>, ZipError> {
+        let decoder = self.decoder(index)?;
+        Ok(ZipEntryReader {
+            reader: &mut self.reader,
+            decoder,
+        })
+    }
+
+    /// Get a reader for the data of entry `index` as it is stored in the archive.
+    pub fn by_index_raw(&mut self, index: usize) -> Result<ZipEntryReader<'_, R>, ZipError> {
+        let decoder = self.raw_decoder(index)?;
+        Ok(ZipEntryReader {
+            reader: &mut self.reader,
+            decoder,
+        })
+    }
+
+    /// Extract entry `index` to a vector.
+    pub fn extract_to_vec(&mut self, index: usize) -> Result<Vec<u8>, ZipError> {
+        let entry = self.entries.get(index).ok_or(ZipError::InvalidParameter)?;
+        let max_size = entry
+            .compressed_size
+            .saturating_mul(MAX_DEFLATE_RATIO)
+            .saturating_add(1024);
+        let capacity = cmp::min(entry.uncompressed_size, max_size);
+        if capacity > usize::max_value() as u64 {
+            return Err(ZipError::FileTooLarge);
+        }
+
+        let mut output = Vec::with_capacity(capacity as usize);
+        self.extract_to_callback(index, |data| {
+            output.extend_from_slice(data);
+            true
+        })?;
+        Ok(output)
+    }
+
+    /// Extract entry `index` to the start of `buf`, returning the size of the entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ZipError::BufTooSmall` if the entry doesn't fit in `buf`.
+    pub fn extract_to_slice(&mut self, index: usize, buf: &mut [u8]) -> Result<usize, ZipError> {
+        let mut decoder = self.decoder(index)?;
+        if self.entries[index].uncompressed_size > buf.len() as u64 {
+            return Err(ZipError::BufTooSmall);
+        }
+        let size = self.entries[index].uncompressed_size as usize;
+        decoder.read_to_slice(&mut self.reader, &mut buf[..size])?;
+        Ok(size)
+    }
+
+    /// Extract entry `index`, writing the data to `writer`.
+    ///
+    /// Returns the number of bytes written.
+    pub fn extract_to_writer<W: Write>(
+        &mut self,
+        index: usize,
+        writer: &mut W,
+    ) -> Result<u64, ZipError> {
+        let mut write_failed = false;
+        let res = self.extract_to_callback(index, |data| {
+            write_failed = writer.write_all(data).is_err();
+            !write_failed
+        });
+        match res {
+            Err(ZipError::WriteCallbackFailed) if write_failed => Err(ZipError::FileWriteFailed),
+            res => res,
+        }
+    }
+
+    /// Extract entry `index`, passing the data to `callback` a piece at a time.
+    ///
+    /// Returns the number of bytes extracted. If the callback returns `false`, extraction is
+    /// stopped with `ZipError::WriteCallbackFailed`.
+    pub fn extract_to_callback<F>(&mut self, index: usize, callback: F) -> Result<u64, ZipError>
+    where
+        F: FnMut(&[u8]) -> bool,
+    {
+        let mut decoder = self.decoder(index)?;
+        decoder.read_to_callback(&mut self.reader, callback)
+    }
+
+    /// Find an entry by name, comparing names exactly.
+    pub fn index_of(&self, name: &[u8]) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.name == name)
+    }
+
+    /// Find an entry by name, and optionally comment, the same way as miniz.
+    ///
+    /// Names are compared case insensitively unless `ZIP_FLAG_CASE_SENSITIVE` is set. With
+    /// `ZIP_FLAG_IGNORE_PATH`, only the part of each entry's name after the last path separator
+    /// is compared.
+    pub fn locate(&self, name: &[u8], comment: Option<&[u8]>, flags: u32) -> Option<usize> {
+        let case_sensitive = flags & ZIP_FLAG_CASE_SENSITIVE != 0;
+        self.entries.iter().position(|entry| {
+            if let Some(comment) = comment {
+                if !names_equal(&entry.comment, comment, case_sensitive) {
+                    return false;
+                }
+            }
+            let entry_name = if flags & ZIP_FLAG_IGNORE_PATH != 0 {
+                let start = entry
+                    .name
+                    .iter()
+                    .rposition(|&c| c == b'/' || c == b'\\' || c == b':')
+                    .map_or(0, |pos| pos + 1);
+                &entry.name[start..]
+            } else {
+                &entry.name[..]
+            };
+            names_equal(entry_name, name, case_sensitive)
+        })
+    }
+
+    /// The entries of the archive, in central directory order.
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Get entry `index`.
+    pub fn entry(&self, index: usize) -> Option<&ZipEntry> {
+        self.entries.get(index)
+    }
+
+    /// The number of entries in the archive.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the archive has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The archive comment.
+    pub fn comment(&self) -> &[u8] {
+        &self.comment
+    }
+
+    /// The size of the archive.
+    pub fn archive_size(&self) -> u64 {
+        self.archive_size
+    }
+
+    /// The offset of the central directory in the archive.
+    pub fn central_dir_offset(&self) -> u64 {
+        self.central_dir_offset
+    }
+
+    /// The size of the central directory.
+    pub fn central_dir_size(&self) -> u64 {
+        self.central_dir_size
+    }
+
+    /// Whether the archive has a ZIP64 end of central directory record or ZIP64 extra fields.
+    pub fn is_zip64(&self) -> bool {
+        self.zip64
+    }
+
+    /// The offset of the central directory header of entry `index`, counted from the start of
+    /// the central directory.
+    pub fn central_header_offset(&self, index: usize) -> Option<u64> {
+        self.central_header_offsets.get(index).cloned()
+    }
+
+    /// Get a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Get a mutable reference to the underlying reader.
+    ///
+    /// This can be used with an `EntryDecoder` from this archive.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Return the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Check that the sizes and offset of an entry are plausible.
+fn check_entry(entry: &ZipEntry, archive_size: u64) -> Result<(), ZipError> {
+    let stored = entry.method == CompressionMethod::Stored as u16;
+    // Encrypted data is preceded by an encryption header, so the sizes differ.
+    if (stored && !entry.is_encrypted() && entry.compressed_size != entry.uncompressed_size)
+        || (entry.uncompressed_size != 0 && entry.compressed_size == 0)
+    {
+        return Err(ZipError::InvalidHeaderOrCorrupted);
+    }
+    let min_end = entry
+        .header_offset
+        .saturating_add(spec::LOCAL_FILE_HEADER_SIZE as u64)
+        .saturating_add(entry.compressed_size);
+    if min_end > archive_size {
+        return Err(ZipError::InvalidHeaderOrCorrupted);
+    }
+    Ok(())
+}
+
+fn names_equal(a: &[u8], b: &[u8], case_sensitive: bool) -> bool {
+    if case_sensitive {
+        a == b
+    } else {
+        a.eq_ignore_ascii_case(b)
+    }
+}
+
+/// Read the ZIP64 end of central directory record, if there is a locator in front of the end of
+/// central directory record at `eocd_offset`.
+fn read_zip64_end_of_central_dir<R: Read + Seek>(
+    reader: &mut R,
+    eocd_offset: u64,
+) -> Result<Option<Zip64EndOfCentralDir>, ZipError> {
+    let locator_size = spec::ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIZE as u64;
+    let record_size = spec::ZIP64_END_OF_CENTRAL_DIR_SIZE as u64;
+    if eocd_offset < locator_size + record_size {
+        return Ok(None);
+    }
+    let mut buf = [0; spec::ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIZE];
+    read_exact_at(reader, eocd_offset - locator_size, &mut buf)?;
+    let locator = match Zip64Locator::parse(&buf) {
+        Some(locator) => locator,
+        None => return Ok(None),
+    };
+    if locator.disks > 1 {
+        return Err(ZipError::UnsupportedMultidisk);
+    }
+    if locator.end_of_central_dir_offset > eocd_offset - locator_size - record_size {
+        return Err(ZipError::NotAnArchive);
+    }
+    let mut buf = [0; spec::ZIP64_END_OF_CENTRAL_DIR_SIZE];
+    read_exact_at(reader, locator.end_of_central_dir_offset, &mut buf)?;
+    Zip64EndOfCentralDir::parse(&buf).map(Some)
+}
+
+/// Read exactly `buf.len()` bytes at `offset`.
+pub(crate) fn read_exact_at<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    buf: &mut [u8],
+) -> Result<(), ZipError> {
+    reader
+        .seek(SeekFrom::Start(offset))
+        .map_err(|_| ZipError::FileSeekFailed)?;
+    reader.read_exact(buf).map_err(|_| ZipError::FileReadFailed)
+}
+
+/// Decoder for the data of a single entry.
+///
+/// The decoder doesn't hold on to the reader, so the archive can be used for other things in
+/// between calls. It seeks to the right position on every read.
+pub struct EntryDecoder {
+    /// Position in the archive of the next compressed byte.
+    pos: u64,
+    /// Number of compressed bytes not read from the archive yet.
+    remaining: u64,
+    /// Only used for deflated entries.
+    state: Option<Box<InflateState>>,
+    /// Only used for entries compressed with Shrink, Reduce or Implode.
+    legacy: Option<Box<LegacyDecoder>>,
+    /// Only used for encrypted entries.
+    crypto: Option<Box<ZipCrypto>>,
+    /// Compressed data that has been read but not decompressed.
+    buf: Vec<u8>,
+    buf_pos: usize,
+    buf_len: usize,
+    /// Expected CRC-32 and size of the output, if they should be checked.
+    expected: Option<(u32, u64)>,
+    crc: Crc32,
+    output_size: u64,
+    stream_end: bool,
+    finished: bool,
+}
+
+impl EntryDecoder {
+    /// Create a decoder returning `size` bytes starting at `pos` unchanged.
+    fn new(pos: u64, size: u64) -> EntryDecoder {
+        EntryDecoder {
+            pos,
+            remaining: size,
+            state: None,
+            legacy: None,
+            crypto: None,
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_len: 0,
+            expected: None,
+            crc: Crc32::new(),
+            output_size: 0,
+            stream_end: false,
+            finished: false,
+        }
+    }
+
+    /// Read the next piece of data into `out`, returning the number of bytes read.
+    ///
+    /// Returns 0 once all the data has been read, after checking the CRC-32 and size.
+    pub fn read<R: Read + Seek>(
+        &mut self,
+        reader: &mut R,
+        out: &mut [u8],
+    ) -> Result<usize, ZipError> {
+        if self.finished || out.is_empty() {
+            return Ok(0);
+        }
+
+        let n = if self.state.is_some() {
+            self.read_deflated(reader, out)?
+        } else if self.legacy.is_some() {
+            self.read_legacy(reader, out)?
+        } else {
+            self.read_stored(reader, out)?
+        };
+
+        if let Some((crc32, size)) = self.expected {
+            self.crc.update(&out[..n]);
+            self.output_size += n as u64;
+            // Stop early rather than producing more data than the entry should have.
+            if self.output_size > size {
+                return Err(ZipError::UnexpectedDecompressedSize);
+            }
+            if n == 0 {
+                if self.output_size != size {
+                    return Err(ZipError::UnexpectedDecompressedSize);
+                }
+                if self.crc.checksum() != crc32 {
+                    return Err(ZipError::CrcCheckFailed);
+                }
+            }
+        }
+        if n == 0 {
+            self.finished = true;
+        }
+        Ok(n)
+    }
+
+    /// Check that there is no more data, after reading as much as was expected.
+    pub fn finish<R: Read + Seek>(&mut self, reader: &mut R) -> Result<(), ZipError> {
+        let mut scratch = [0; 1];
+        while !self.finished {
+            if self.read(reader, &mut scratch)? != 0 && self.expected.is_none() {
+                return Err(ZipError::UnexpectedDecompressedSize);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill `out` with data and check that nothing is left after it.
+    pub fn read_to_slice<R: Read + Seek>(
+        &mut self,
+        reader: &mut R,
+        out: &mut [u8],
+    ) -> Result<(), ZipError> {
+        let mut pos = 0;
+        while pos < out.len() {
+            match self.read(reader, &mut out[pos..])? {
+                0 => return Err(ZipError::UnexpectedDecompressedSize),
+                n => pos += n,
+            }
+        }
+        self.finish(reader)
+    }
+
+    /// Read all the data, passing it to `callback`. Returns the number of bytes read.
+    pub fn read_to_callback<R, F>(
+        &mut self,
+        reader: &mut R,
+        mut callback: F,
+    ) -> Result<u64, ZipError>
+    where
+        R: Read + Seek,
+        F: FnMut(&[u8]) -> bool,
+    {
+        let mut out = vec![0; IO_BUF_SIZE];
+        let mut total = 0;
+        loop {
+            let n = self.read(reader, &mut out)?;
+            if n == 0 {
+                return Ok(total);
+            }
+            if !callback(&out[..n]) {
+                return Err(ZipError::WriteCallbackFailed);
+            }
+            total += n as u64;
+        }
+    }
+
+    fn read_stored<R: Read + Seek>(
+        &mut self,
+        reader: &mut R,
+        out: &mut [u8],
+    ) -> Result<usize, ZipError> {
+        let n = cmp::min(out.len() as u64, self.remaining) as usize;
+        if n > 0 {
+            read_exact_at(reader, self.pos, &mut out[..n])?;
+            if let Some(ref mut crypto) = self.crypto {
+                crypto.decrypt(&mut out[..n]);
+            }
+            self.pos += n as u64;
+            self.remaining -= n as u64;
+        }
+        Ok(n)
+    }
+
+    fn read_deflated<R: Read + Seek>(
+        &mut self,
+        reader: &mut R,
+        out: &mut [u8],
+    ) -> Result<usize, ZipError> {
+        if self.stream_end {
+            return Ok(0);
+        }
+        let state = self.state.as_mut().expect("Bug! No inflate state!");
+        loop {
+            if self.buf_pos == self.buf_len && self.remaining > 0 {
+                let n = cmp::min(self.buf.len() as u64, self.remaining) as usize;
+                read_exact_at(reader, self.pos, &mut self.buf[..n])?;
+                if let Some(ref mut crypto) = self.crypto {
+                    crypto.decrypt(&mut self.buf[..n]);
+                }
+                self.pos += n as u64;
+                self.remaining -= n as u64;
+                self.buf_pos = 0;
+                self.buf_len = n;
+            }
+
+            let res = inflate_chunk(state, &self.buf[self.buf_pos..self.buf_len], out);
+            self.buf_pos += res.bytes_consumed;
+            match res.stream_end {
+                Ok(true) => {
+                    self.stream_end = true;
+                    return Ok(res.bytes_written);
+                }
+                Ok(false) => {
+                    if res.bytes_written > 0 {
+                        return Ok(res.bytes_written);
+                    }
+                    if self.buf_pos == self.buf_len && self.remaining == 0 {
+                        // The compressed data ended before the end of the deflate stream.
+                        return Err(ZipError::DecompressionFailed);
+                    }
+                }
+                Err(_) => return Err(ZipError::DecompressionFailed),
+            }
+        }
+    }
+
+    /// Decode Shrink, Reduce or Implode, reading the compressed data a buffer at a time.
+    fn read_legacy<R: Read + Seek>(
+        &mut self,
+        reader: &mut R,
+        out: &mut [u8],
+    ) -> Result<usize, ZipError> {
+        let decoder = self.legacy.as_mut().expect("Bug! No legacy decoder!");
+        let (pos, remaining) = (&mut self.pos, &mut self.remaining);
+        let (buf, buf_pos, buf_len) = (&mut self.buf, &mut self.buf_pos, &mut self.buf_len);
+        let crypto = &mut self.crypto;
+        let mut input = || {
+            if *buf_pos == *buf_len {
+                if *remaining == 0 {
+                    return Ok(None);
+                }
+                let n = cmp::min(buf.len() as u64, *remaining) as usize;
+                read_exact_at(reader, *pos, &mut buf[..n])?;
+                if let Some(ref mut crypto) = *crypto {
+                    crypto.decrypt(&mut buf[..n]);
+                }
+                *pos += n as u64;
+                *remaining -= n as u64;
+                *buf_pos = 0;
+                *buf_len = n;
+            }
+            *buf_pos += 1;
+            Ok(Some(buf[*buf_pos - 1]))
+        };
+        decoder.read(&mut input, out)
+    }
+}
+
+/// Reader for the data of an entry, returned by `ZipArchive::by_index`.
+pub struct ZipEntryReader<'a, R> {
+    reader: &'a mut R,
+    decoder: EntryDecoder,
+}
+
+impl<'a, R: Read + Seek> Read for ZipEntryReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(self.decoder.read(self.reader, buf)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read};
+
+    use super::super::zip_flags::{ZIP_FLAG_CASE_SENSITIVE, ZIP_FLAG_IGNORE_PATH};
+    use super::super::ZipError;
+    use super::ZipArchive;
+    use crate::checksum::Crc32;
+    use crate::deflate::compress_to_vec;
+
+    fn put_u16(out: &mut Vec<u8>, value: u16) {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u32(out: &mut Vec<u8>, value: u32) {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Build an archive from `(name, data, method)` triples.
+    fn build_archive(files: &[(&[u8], &[u8], u16)], comment: &[u8]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        let mut central_dir = Vec::new();
+        for &(name, data, method) in files {
+            let compressed = if method == 8 {
+                compress_to_vec(data, 6)
+            } else {
+                data.to_vec()
+            };
+            let crc = Crc32::checksum_of(data);
+            let offset = archive.len() as u32;
+
+            put_u32(&mut archive, 0x0403_4b50);
+            for &value in &[20, 0, method, 0x7d1c, 0x354b] {
+                put_u16(&mut archive, value);
+            }
+            put_u32(&mut archive, crc);
+            put_u32(&mut archive, compressed.len() as u32);
+            put_u32(&mut archive, data.len() as u32);
+            put_u16(&mut archive, name.len() as u16);
+            put_u16(&mut archive, 0);
+            archive.extend_from_slice(name);
+            archive.extend_from_slice(&compressed);
+
+            put_u32(&mut central_dir, 0x0201_4b50);
+            for &value in &[20, 20, 0, method, 0x7d1c, 0x354b] {
+                put_u16(&mut central_dir, value);
+            }
+            put_u32(&mut central_dir, crc);
+            put_u32(&mut central_dir, compressed.len() as u32);
+            put_u32(&mut central_dir, data.len() as u32);
+            for &value in &[name.len() as u16, 0, 0, 0, 0] {
+                put_u16(&mut central_dir, value);
+            }
+            put_u32(&mut central_dir, 0);
+            put_u32(&mut central_dir, offset);
+            central_dir.extend_from_slice(name);
+        }
+
+        let central_dir_offset = archive.len() as u32;
+        archive.extend_from_slice(&central_dir);
+        put_u32(&mut archive, 0x0605_4b50);
+        for &value in &[0, 0, files.len() as u16, files.len() as u16] {
+            put_u16(&mut archive, value);
+        }
+        put_u32(&mut archive, central_dir.len() as u32);
+        put_u32(&mut archive, central_dir_offset);
+        put_u16(&mut archive, comment.len() as u16);
+        archive.extend_from_slice(comment);
+        archive
+    }
+
+    fn test_files() -> Vec<(&'static [u8], Vec<u8>, u16)> {
+        let text: Vec<u8> = b"All work and no play makes Jack a dull boy. "
+            .iter()
+            .cycle()
+            .take(100_000)
+            .cloned()
+            .collect();
+        vec![
+            (b"hello.txt", b"Hello, zip!".to_vec(), 0),
+            (b"dir/", Vec::new(), 0),
+            (b"dir/Text.txt", text, 8),
+            (b"empty", Vec::new(), 8),
+        ]
+    }
+
+    fn test_archive() -> Vec<u8> {
+        let files = test_files();
+        let files: Vec<_> = files
+            .iter()
+            .map(|&(name, ref data, method)| (name, &data[..], method))
+            .collect();
+        build_archive(&files, b"PK\x05\x06 in the comment")
+    }
+
+    #[test]
+    fn read_entries() {
+        let mut archive = ZipArchive::new(Cursor::new(test_archive())).unwrap();
+        assert_eq!(archive.len(), 4);
+        assert_eq!(archive.comment(), b"PK\x05\x06 in the comment");
+        assert!(archive.entry(1).unwrap().is_dir());
+        assert!(!archive.entry(2).unwrap().is_dir());
+
+        for (index, (name, data, method)) in test_files().into_iter().enumerate() {
+            let entry = archive.entry(index).unwrap().clone();
+            assert_eq!(entry.name, name);
+            assert_eq!(entry.method, method);
+            assert_eq!(entry.uncompressed_size, data.len() as u64);
+            assert_eq!(entry.last_modified.year, 2006);
+
+            assert!(archive.extract_to_vec(index).unwrap() == data);
+
+            let mut buf = vec![0; data.len() + 10];
+            assert_eq!(archive.extract_to_slice(index, &mut buf), Ok(data.len()));
+            assert!(buf[..data.len()] == data[..]);
+
+            let mut out = Vec::new();
+            archive
+                .by_index(index)
+                .unwrap()
+                .read_to_end(&mut out)
+                .unwrap();
+            assert!(out == data);
+
+            let mut out = Vec::new();
+            archive.extract_to_writer(index, &mut out).unwrap();
+            assert!(out == data);
+
+            let mut raw = Vec::new();
+            archive
+                .by_index_raw(index)
+                .unwrap()
+                .read_to_end(&mut raw)
+                .unwrap();
+            assert_eq!(raw.len() as u64, entry.compressed_size);
+        }
+
+        let mut buf = [0; 5];
+        assert_eq!(
+            archive.extract_to_slice(0, &mut buf),
+            Err(ZipError::BufTooSmall)
+        );
+        assert_eq!(
+            archive.extract_to_callback(2, |_| false),
+            Err(ZipError::WriteCallbackFailed)
+        );
+    }
+
+    #[test]
+    fn locate() {
+        let archive = ZipArchive::new(Cursor::new(test_archive())).unwrap();
+        assert_eq!(archive.index_of(b"dir/Text.txt"), Some(2));
+        assert_eq!(archive.index_of(b"dir/text.txt"), None);
+        assert_eq!(archive.locate(b"DIR/TEXT.TXT", None, 0), Some(2));
+        assert_eq!(
+            archive.locate(b"DIR/TEXT.TXT", None, ZIP_FLAG_CASE_SENSITIVE),
+            None
+        );
+        assert_eq!(
+            archive.locate(b"text.txt", None, ZIP_FLAG_IGNORE_PATH),
+            Some(2)
+        );
+        assert_eq!(archive.locate(b"hello.txt", Some(b""), 0), Some(0));
+        assert_eq!(archive.locate(b"hello.txt", Some(b"comment"), 0), None);
+    }
+
+    #[test]
+    fn corrupted() {
+        let archive = test_archive();
+        assert_eq!(
+            ZipArchive::new(Cursor::new(&archive[..archive.len() - 40])).err(),
+            Some(ZipError::FailedFindingCentralDir)
+        );
+        assert_eq!(
+            ZipArchive::new(Cursor::new(&b"PK"[..])).err(),
+            Some(ZipError::NotAnArchive)
+        );
+
+        // Flip a bit in the compressed data of the deflated entry.
+        let mut damaged = archive.clone();
+        damaged[200] ^= 1;
+        let mut zip = ZipArchive::new(Cursor::new(damaged)).unwrap();
+        assert!(zip.extract_to_vec(2).is_err());
+
+        // Change the stored data, which is only caught by the CRC.
+        let mut damaged = archive.clone();
+        damaged[40] ^= 1;
+        let mut zip = ZipArchive::new(Cursor::new(damaged)).unwrap();
+        assert_eq!(zip.extract_to_vec(0), Err(ZipError::CrcCheckFailed));
+        let mut out = Vec::new();
+        let err = zip.by_index(0).unwrap().read_to_end(&mut out).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        // The raw data is returned without checking.
+        assert_eq!(
+            zip.by_index_raw(0).unwrap().read_to_end(&mut out).unwrap(),
+            11
+        );
+    }
+}
@@ -0,0 +1,571 @@
+//! Recovering entries from damaged ZIP archives.
+//!
+//! Archives that were cut short have lost their central directory, which is at the end. The
+//! entries can still be found by scanning for local file headers, each of which is followed by
+//! the data of its entry. As much of the data as possible is extracted, and whether it matches
+//! the CRC-32 and sizes in the local header or the data descriptor decides if an entry was
+//! recovered completely.
+//!
+//! When the compressed size is only stored in the data descriptor, deflated data is read until
+//! the end of the deflate stream, and stored data until the first data descriptor signature
+//! followed by the CRC-32 and size of the data before it. If there is none, stored data goes up
+//! to the next local header.
+//!
+//! Only stored and deflated entries can be recovered. The methods of PKZIP 1.x are only decoded
+//! by `ZipArchive`.
+
+use std::cmp;
+use std::io::{Read, Seek, SeekFrom};
+use std::prelude::v1::*;
+
+use super::read::read_exact_at;
+use super::spec::{self, DATA_DESCRIPTOR_SIGNATURE, ZIP64_EXTRA_FIELD_ID, ZIP64_MARKER};
+use super::{CompressionMethod, ZipEntry, ZipError, FLAG_DATA_DESCRIPTOR, IO_BUF_SIZE};
+use crate::checksum::Crc32;
+use crate::inflate::stream::{inflate_chunk, InflateState};
+use crate::DataFormat;
+
+/// How much of an entry could be recovered.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RecoveryStatus {
+    /// All the data was extracted and matches the CRC-32 and sizes.
+    Complete,
+    /// Some of the data was extracted, but it is incomplete or doesn't match.
+    Partial,
+    /// None of the data could be extracted.
+    Failed,
+}
+
+/// An entry found in a damaged archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveredEntry {
+    /// The entry as described by its local header, with the CRC-32 and sizes from the data
+    /// descriptor if it has one. When they are unknown they are those of the data that was
+    /// recovered.
+    pub entry: ZipEntry,
+    /// How much of the entry could be recovered.
+    pub status: RecoveryStatus,
+    /// Size of the data that could be extracted.
+    pub recovered_size: u64,
+    /// Why the entry couldn't be recovered completely.
+    pub error: Option<ZipError>,
+}
+
+/// A damaged archive whose entries were found by scanning for local headers.
+pub struct ZipRecovery<R> {
+    reader: R,
+    entries: Vec<RecoveredEntry>,
+    /// Offset and largest possible compressed size of the data of each entry.
+    data_ranges: Vec<(u64, u64)>,
+}
+
+impl<R: Read + Seek> ZipRecovery<R> {
+    /// Scan `reader` for local headers and check how much of each entry can be recovered.
+    ///
+    /// This extracts every entry once, without keeping the data.
+    ///
+    /// # Errors
+    ///
+    /// Only fails if `reader` can't be read. Damaged entries are returned with a
+    /// `RecoveryStatus` other than `Complete`.
+    pub fn new(mut reader: R) -> Result<ZipRecovery<R>, ZipError> {
+        let archive_size = reader
+            .seek(SeekFrom::End(0))
+            .map_err(|_| ZipError::FileSeekFailed)?;
+        let mut entries = Vec::new();
+        let mut data_ranges = Vec::new();
+        let mut pos = 0;
+        while let Some(header_offset) = find_local_header(&mut reader, pos, archive_size)? {
+            pos = header_offset + 4;
+            let (mut entry, header_len) =
+                match read_local_header(&mut reader, header_offset, archive_size)? {
+                    Some(header) => header,
+                    None => continue,
+                };
+            entry.header_offset = header_offset;
+            let data_offset = header_offset + header_len as u64;
+
+            let data_descriptor = entry.flags & FLAG_DATA_DESCRIPTOR != 0;
+            let limit = if !data_descriptor {
+                entry.compressed_size
+            } else if entry.method == CompressionMethod::Stored as u16 {
+                match find_stored_descriptor(&mut reader, &entry, data_offset, archive_size)? {
+                    Some(len) => len,
+                    None => {
+                        // The data is damaged or cut short, so take everything up to the next
+                        // entry, without a data descriptor at the end.
+                        let next = find_local_header(&mut reader, data_offset, archive_size)?;
+                        let end = next.unwrap_or(archive_size);
+                        let descriptor_len =
+                            descriptor_len_at_end(&mut reader, &entry, data_offset, end)?;
+                        end - data_offset - descriptor_len
+                    }
+                }
+            } else {
+                u64::max_value()
+            };
+            let limit = cmp::min(limit, archive_size - data_offset);
+
+            let mut recovered = RecoveredEntry {
+                entry,
+                status: RecoveryStatus::Failed,
+                recovered_size: 0,
+                error: None,
+            };
+            let end = salvage(&mut reader, &mut recovered, data_offset, limit, |_| true)?;
+            if let Some(end) = end {
+                pos = cmp::max(pos, end);
+            }
+            entries.push(recovered);
+            data_ranges.push((data_offset, limit));
+        }
+
+        Ok(ZipRecovery {
+            reader,
+            entries,
+            data_ranges,
+        })
+    }
+
+    /// The entries that were found, in the order they appear in the archive.
+    pub fn entries(&self) -> &[RecoveredEntry] {
+        &self.entries
+    }
+
+    /// The number of entries that were found.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no entries were found.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Extract as much of entry `index` as can be recovered, passing it to `callback` in
+    /// pieces.
+    ///
+    /// Returns the size of the data. Damaged data isn't an error, the status of the entry
+    /// tells how much of it can be trusted.
+    pub fn extract_to_callback<F>(&mut self, index: usize, callback: F) -> Result<u64, ZipError>
+    where
+        F: FnMut(&[u8]) -> bool,
+    {
+        let mut recovered = self
+            .entries
+            .get(index)
+            .ok_or(ZipError::InvalidParameter)?
+            .clone();
+        let (data_offset, limit) = self.data_ranges[index];
+        salvage(
+            &mut self.reader,
+            &mut recovered,
+            data_offset,
+            limit,
+            callback,
+        )?;
+        Ok(recovered.recovered_size)
+    }
+
+    /// Extract as much of entry `index` as can be recovered to a vector.
+    pub fn extract_to_vec(&mut self, index: usize) -> Result<Vec<u8>, ZipError> {
+        let mut output = Vec::new();
+        self.extract_to_callback(index, |data| {
+            output.extend_from_slice(data);
+            true
+        })?;
+        Ok(output)
+    }
+
+    /// Return the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Find the first local header signature in `start..end`.
+fn find_local_header<R: Read + Seek>(
+    reader: &mut R,
+    mut start: u64,
+    end: u64,
+) -> Result<Option<u64>, ZipError> {
+    let signature = spec::LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes();
+    let mut buf = vec![0; IO_BUF_SIZE];
+    while start + 4 <= end {
+        let n = cmp::min(buf.len() as u64, end - start) as usize;
+        read_exact_at(reader, start, &mut buf[..n])?;
+        if let Some(pos) = buf[..n].windows(4).position(|w| w == signature) {
+            return Ok(Some(start + pos as u64));
+        }
+        // The signature may cross into the next piece.
+        start += n as u64 - 3;
+    }
+    Ok(None)
+}
+
+/// Read the local header at `offset`, returning `None` if it doesn't fit in the archive or is
+/// invalid.
+fn read_local_header<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    archive_size: u64,
+) -> Result<Option<(ZipEntry, usize)>, ZipError> {
+    if archive_size - offset < spec::LOCAL_FILE_HEADER_SIZE as u64 {
+        return Ok(None);
+    }
+    let mut header = vec![0; spec::LOCAL_FILE_HEADER_SIZE];
+    read_exact_at(reader, offset, &mut header)?;
+    let len = spec::LOCAL_FILE_HEADER_SIZE
+        + usize::from(spec::read_u16(&header, 26))
+        + usize::from(spec::read_u16(&header, 28));
+    if archive_size - offset < len as u64 {
+        return Ok(None);
+    }
+    header.resize(len, 0);
+    read_exact_at(
+        reader,
+        offset + spec::LOCAL_FILE_HEADER_SIZE as u64,
+        &mut header[spec::LOCAL_FILE_HEADER_SIZE..],
+    )?;
+    Ok(spec::parse_local_header(&header).ok())
+}
+
+/// Whether the data descriptor of `entry` has 64-bit sizes: the local header has a ZIP64
+/// extra field, or the sizes don't fit in 32 bits, which some writers do without the extra
+/// field.
+fn has_zip64_descriptor(entry: &ZipEntry, compressed_size: u64, size: u64) -> bool {
+    let marker = u64::from(ZIP64_MARKER);
+    spec::find_extra_field(&entry.extra_field, ZIP64_EXTRA_FIELD_ID).is_some()
+        || compressed_size >= marker
+        || size >= marker
+}
+
+/// Find the end of stored data starting at `data_offset` that is followed by a data descriptor,
+/// returning the size of the data.
+///
+/// The data may contain data descriptor signatures and local headers of its own, so a
+/// signature only counts if the CRC-32 and sizes after it match the data before it, which is
+/// the same check `ZipStreamReader` does.
+fn find_stored_descriptor<R: Read + Seek>(
+    reader: &mut R,
+    entry: &ZipEntry,
+    data_offset: u64,
+    archive_size: u64,
+) -> Result<Option<u64>, ZipError> {
+    let signature = DATA_DESCRIPTOR_SIGNATURE.to_le_bytes();
+    let mut buf = vec![0; IO_BUF_SIZE];
+    let mut crc = Crc32::new();
+    let mut start = data_offset;
+    while start < archive_size {
+        let n = cmp::min(buf.len() as u64, archive_size - start) as usize;
+        read_exact_at(reader, start, &mut buf[..n])?;
+        // A data descriptor closer to the end of the piece than this is checked in the next one.
+        let safe_len = if start + (n as u64) < archive_size {
+            n - (spec::ZIP64_DATA_DESCRIPTOR_SIZE - 1)
+        } else {
+            n
+        };
+        let mut checked = 0;
+        for pos in 0..safe_len {
+            if buf[pos..n].starts_with(&signature) {
+                crc.update(&buf[checked..pos]);
+                checked = pos;
+                let size = start - data_offset + pos as u64;
+                let zip64 = has_zip64_descriptor(entry, size, size);
+                if spec::is_stored_descriptor(&buf[pos..n], crc.checksum(), size, zip64) {
+                    return Ok(Some(size));
+                }
+            }
+        }
+        crc.update(&buf[checked..safe_len]);
+        start += safe_len as u64;
+    }
+    Ok(None)
+}
+
+/// The size of the data descriptor with a signature at the end of `data_offset..end`, or 0 if
+/// there is none.
+fn descriptor_len_at_end<R: Read + Seek>(
+    reader: &mut R,
+    entry: &ZipEntry,
+    data_offset: u64,
+    end: u64,
+) -> Result<u64, ZipError> {
+    let available = end - data_offset;
+    let len = if has_zip64_descriptor(entry, available, available) {
+        spec::ZIP64_DATA_DESCRIPTOR_SIZE as u64
+    } else {
+        spec::DATA_DESCRIPTOR_SIZE as u64
+    };
+    if available < len {
+        return Ok(0);
+    }
+    let mut signature = [0; 4];
+    read_exact_at(reader, end - len, &mut signature)?;
+    if spec::read_u32(&signature, 0) == DATA_DESCRIPTOR_SIGNATURE {
+        Ok(len)
+    } else {
+        Ok(0)
+    }
+}
+
+/// Read the data descriptor at `offset` if it fits before `archive_size`, returning the
+/// CRC-32, sizes and the size of the descriptor.
+fn read_descriptor<R: Read + Seek>(
+    reader: &mut R,
+    entry: &ZipEntry,
+    offset: u64,
+    archive_size: u64,
+    compressed_size: u64,
+    size: u64,
+) -> Result<Option<(u32, u64, u64, u64)>, ZipError> {
+    let zip64 = has_zip64_descriptor(entry, compressed_size, size);
+    let mut buf = [0; spec::ZIP64_DATA_DESCRIPTOR_SIZE];
+    let available = cmp::min(archive_size.saturating_sub(offset), buf.len() as u64) as usize;
+    read_exact_at(reader, offset, &mut buf[..available])?;
+    // The signature is optional.
+    let pos = if available >= 4 && spec::read_u32(&buf, 0) == DATA_DESCRIPTOR_SIGNATURE {
+        4
+    } else {
+        0
+    };
+    let len = pos + if zip64 { 20 } else { 12 };
+    if available < len {
+        return Ok(None);
+    }
+    let crc32 = spec::read_u32(&buf, pos);
+    Ok(Some(if zip64 {
+        let compressed_size = spec::read_u64(&buf, pos + 4);
+        (
+            crc32,
+            compressed_size,
+            spec::read_u64(&buf, pos + 12),
+            len as u64,
+        )
+    } else {
+        let compressed_size = u64::from(spec::read_u32(&buf, pos + 4));
+        let size = u64::from(spec::read_u32(&buf, pos + 8));
+        (crc32, compressed_size, size, len as u64)
+    }))
+}
+
+/// Extract the data of `recovered` starting at `data_offset`, reading at most `limit`
+/// compressed bytes, and update its status.
+///
+/// Returns the offset of the end of the entry if the end of the data was found.
+fn salvage<R, F>(
+    reader: &mut R,
+    recovered: &mut RecoveredEntry,
+    data_offset: u64,
+    limit: u64,
+    mut callback: F,
+) -> Result<Option<u64>, ZipError>
+where
+    R: Read + Seek,
+    F: FnMut(&[u8]) -> bool,
+{
+    recovered.status = RecoveryStatus::Failed;
+    recovered.recovered_size = 0;
+    recovered.error = None;
+    let entry = &mut recovered.entry;
+    if entry.is_encrypted() {
+        recovered.error = Some(ZipError::UnsupportedEncryption);
+        return Ok(None);
+    }
+    if !entry.is_stored_or_deflated() {
+        recovered.error = Some(ZipError::UnsupportedMethod);
+        return Ok(None);
+    }
+
+    let mut state = if entry.method == CompressionMethod::Deflated as u16 {
+        Some(InflateState::new_boxed(DataFormat::Raw))
+    } else {
+        None
+    };
+    let mut crc = Crc32::new();
+    let mut size = 0;
+    let mut consumed = 0;
+    let mut stream_end = state.is_none();
+    let mut error = None;
+    let mut buf = vec![0; IO_BUF_SIZE];
+    let mut out = vec![0; if state.is_some() { IO_BUF_SIZE } else { 0 }];
+    let mut buf_pos = 0;
+    let mut buf_len = 0;
+    let mut last_written = 0;
+
+    loop {
+        if buf_pos == buf_len {
+            let n = cmp::min(buf.len() as u64, limit - consumed) as usize;
+            if n > 0 {
+                read_exact_at(reader, data_offset + consumed, &mut buf[..n])?;
+                buf_pos = 0;
+                buf_len = n;
+            } else if state.is_none() {
+                break;
+            } else if last_written == 0 {
+                // The compressed data ended before the end of the deflate stream.
+                error = Some(ZipError::DecompressionFailed);
+                break;
+            }
+            // Otherwise keep going without input, to get the output the decompressor holds.
+        }
+
+        let (data, status) = match state {
+            Some(ref mut state) => {
+                let res = inflate_chunk(state, &buf[buf_pos..buf_len], &mut out);
+                buf_pos += res.bytes_consumed;
+                consumed += res.bytes_consumed as u64;
+                last_written = res.bytes_written;
+                (&out[..res.bytes_written], res.stream_end)
+            }
+            None => {
+                let data = &buf[buf_pos..buf_len];
+                consumed += data.len() as u64;
+                buf_pos = buf_len;
+                (data, Ok(false))
+            }
+        };
+        crc.update(data);
+        size += data.len() as u64;
+        if !data.is_empty() && !callback(data) {
+            return Err(ZipError::WriteCallbackFailed);
+        }
+        match status {
+            Ok(true) => {
+                stream_end = true;
+                break;
+            }
+            Ok(false) => (),
+            Err(_) => {
+                error = Some(ZipError::DecompressionFailed);
+                break;
+            }
+        }
+    }
+
+    let mut end = None;
+    if stream_end {
+        let data_end = data_offset + consumed;
+        let expected = if entry.flags & FLAG_DATA_DESCRIPTOR != 0 {
+            let archive_size = reader
+                .seek(SeekFrom::End(0))
+                .map_err(|_| ZipError::FileSeekFailed)?;
+            read_descriptor(reader, entry, data_end, archive_size, consumed, size)?.map(
+                |(crc32, compressed_size, size, len)| {
+                    end = Some(data_end + len);
+                    (crc32, compressed_size, size)
+                },
+            )
+        } else {
+            end = Some(data_end);
+            Some((entry.crc32, entry.compressed_size, entry.uncompressed_size))
+        };
+        match expected {
+            Some((crc32, compressed_size, uncompressed_size)) => {
+                entry.crc32 = crc32;
+                entry.compressed_size = compressed_size;
+                entry.uncompressed_size = uncompressed_size;
+                if compressed_size != consumed || uncompressed_size != size {
+                    error = Some(ZipError::UnexpectedDecompressedSize);
+                } else if crc32 != crc.checksum() {
+                    error = Some(ZipError::CrcCheckFailed);
+                }
+            }
+            // The archive ends before the data descriptor.
+            None => error = Some(ZipError::InvalidHeaderOrCorrupted),
+        }
+    }
+    if entry.flags & FLAG_DATA_DESCRIPTOR != 0 && end.is_none() {
+        entry.crc32 = crc.checksum();
+        entry.compressed_size = consumed;
+        entry.uncompressed_size = size;
+    }
+
+    recovered.status = if error.is_none() {
+        RecoveryStatus::Complete
+    } else if size > 0 {
+        RecoveryStatus::Partial
+    } else {
+        RecoveryStatus::Failed
+    };
+    recovered.recovered_size = size;
+    recovered.error = error;
+    Ok(end)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::super::test_util::build;
+    use super::super::ZipError;
+    use super::{RecoveryStatus, ZipRecovery};
+
+    #[test]
+    fn truncated_central_dir() {
+        for &streaming in &[false, true] {
+            let archive = build(streaming);
+            let data = &archive.data[..archive.central_dir];
+            let mut zip = ZipRecovery::new(Cursor::new(data)).unwrap();
+            assert_eq!(zip.len(), archive.entries.len());
+            // This includes "dir/tricky", whose stored data contains a local header signature
+            // and a data descriptor signature.
+            for (index, (name, contents)) in archive.entries.iter().enumerate() {
+                let recovered = zip.entries()[index].clone();
+                assert_eq!(recovered.entry.name, *name);
+                assert_eq!(recovered.status, RecoveryStatus::Complete);
+                assert_eq!(recovered.error, None);
+                assert_eq!(recovered.recovered_size, contents.len() as u64);
+                assert_eq!(recovered.entry.uncompressed_size, contents.len() as u64);
+                assert!(zip.extract_to_vec(index).unwrap() == *contents);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_data() {
+        for &streaming in &[false, true] {
+            let archive = build(streaming);
+            let data = &archive.data;
+            let zip = ZipRecovery::new(Cursor::new(&data[..])).unwrap();
+            let large = zip.entries()[4].entry.header_offset as usize;
+            let zip64 = zip.entries()[6].entry.header_offset as usize;
+
+            // In the middle of the deflated data of "large".
+            let mut zip = ZipRecovery::new(Cursor::new(&data[..large + 1000])).unwrap();
+            assert_eq!(zip.len(), 5);
+            assert_eq!(zip.entries()[3].status, RecoveryStatus::Complete);
+            let recovered = zip.entries()[4].clone();
+            assert_eq!(recovered.status, RecoveryStatus::Partial);
+            assert_eq!(recovered.error, Some(ZipError::DecompressionFailed));
+            let partial = zip.extract_to_vec(4).unwrap();
+            assert_eq!(partial.len() as u64, recovered.recovered_size);
+            assert!(!partial.is_empty() && archive.entries[4].1.starts_with(&partial));
+
+            // Just after the local header of "zip64", with the name and ZIP64 extra field.
+            let zip = ZipRecovery::new(Cursor::new(&data[..zip64 + 30 + 5 + 20])).unwrap();
+            assert_eq!(zip.len(), 7);
+            assert_eq!(zip.entries()[5].status, RecoveryStatus::Complete);
+            assert_eq!(zip.entries()[6].status, RecoveryStatus::Failed);
+        }
+    }
+
+    #[test]
+    fn corrupted() {
+        let mut data = build(false).data;
+        let pos = data.windows(11).position(|w| w == b"stored data").unwrap();
+        data[pos] ^= 1;
+        let zip = ZipRecovery::new(Cursor::new(&data[..])).unwrap();
+        assert_eq!(zip.len(), 8);
+        assert_eq!(zip.entries()[1].status, RecoveryStatus::Partial);
+        assert_eq!(zip.entries()[1].error, Some(ZipError::CrcCheckFailed));
+        for (index, recovered) in zip.entries().iter().enumerate() {
+            if index != 1 {
+                assert_eq!(recovered.status, RecoveryStatus::Complete);
+            }
+        }
+
+        let zip = ZipRecovery::new(Cursor::new(&b"not a zip archive"[..])).unwrap();
+        assert!(zip.is_empty());
+    }
+}
@@ -0,0 +1,521 @@
+//! Layout of the ZIP headers and records.
+
+use std::prelude::v1::*;
+
+use super::{DateTime, ZipEntry, ZipError, FLAG_DATA_DESCRIPTOR};
+
+pub const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+pub const CENTRAL_DIR_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+pub const END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x0605_4b50;
+pub const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+pub const ZIP64_END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x0606_4b50;
+pub const ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+
+pub const LOCAL_FILE_HEADER_SIZE: usize = 30;
+pub const CENTRAL_DIR_HEADER_SIZE: usize = 46;
+pub const END_OF_CENTRAL_DIR_SIZE: usize = 22;
+pub const DATA_DESCRIPTOR_SIZE: usize = 16;
+pub const ZIP64_DATA_DESCRIPTOR_SIZE: usize = 24;
+pub const ZIP64_END_OF_CENTRAL_DIR_SIZE: usize = 56;
+pub const ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIZE: usize = 20;
+
+/// Header ID of the ZIP64 extended information extra field.
+pub const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+/// Largest size of the ZIP64 extra field, with all three 64-bit values and the header.
+pub const ZIP64_EXTRA_FIELD_MAX_SIZE: usize = 4 + 3 * 8;
+
+/// Value of the 32-bit size and offset fields when the real value is in the ZIP64 extra field.
+pub const ZIP64_MARKER: u32 = 0xFFFF_FFFF;
+/// Value of the 16-bit entry count fields when the real value is in the ZIP64 record.
+pub const ZIP64_ENTRIES_MARKER: u16 = 0xFFFF;
+
+/// Version 4.5 of the format, needed for ZIP64.
+pub const VERSION_ZIP64: u16 = 45;
+
+/// The end of central directory record can be followed by a comment of up to this size.
+pub const MAX_COMMENT_SIZE: usize = 0xFFFF;
+
+pub fn read_u16(buf: &[u8], pos: usize) -> u16 {
+    u16::from(buf[pos]) | u16::from(buf[pos + 1]) << 8
+}
+
+pub fn read_u32(buf: &[u8], pos: usize) -> u32 {
+    u32::from(read_u16(buf, pos)) | u32::from(read_u16(buf, pos + 2)) << 16
+}
+
+pub fn read_u64(buf: &[u8], pos: usize) -> u64 {
+    u64::from(read_u32(buf, pos)) | u64::from(read_u32(buf, pos + 4)) << 32
+}
+
+pub fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+/// The 32-bit value to store for `value`, `ZIP64_MARKER` if it has to go in the ZIP64 extra field.
+fn u32_or_marker(value: u64) -> u32 {
+    if value >= u64::from(ZIP64_MARKER) {
+        ZIP64_MARKER
+    } else {
+        value as u32
+    }
+}
+
+/// Find the data of the extra field with header ID `id` in `extra`.
+pub fn find_extra_field(extra: &[u8], id: u16) -> Option<&[u8]> {
+    let mut pos = 0;
+    while pos + 4 <= extra.len() {
+        let len = usize::from(read_u16(extra, pos + 2));
+        let data = extra.get(pos + 4..pos + 4 + len)?;
+        if read_u16(extra, pos) == id {
+            return Some(data);
+        }
+        pos += 4 + len;
+    }
+    None
+}
+
+/// Copy `extra` without the fields with header ID `id`.
+///
+/// Anything after a field that doesn't fit is dropped.
+pub fn remove_extra_field(extra: &[u8], id: u16) -> Vec<u8> {
+    let mut out = Vec::with_capacity(extra.len());
+    let mut pos = 0;
+    while pos + 4 <= extra.len() {
+        let end = pos + 4 + usize::from(read_u16(extra, pos + 2));
+        if end > extra.len() {
+            break;
+        }
+        if read_u16(extra, pos) != id {
+            out.extend_from_slice(&extra[pos..end]);
+        }
+        pos = end;
+    }
+    out
+}
+
+/// Whether `descriptor` starts with a data descriptor of stored data with CRC-32 `crc` and
+/// size `size`, with 64-bit sizes if `zip64` is set.
+///
+/// Stored data with a data descriptor has no known end, so this is how the end is found. The
+/// signature is required, as without it any 12 bytes could pass.
+pub fn is_stored_descriptor(descriptor: &[u8], crc: u32, size: u64, zip64: bool) -> bool {
+    if descriptor.len() < DATA_DESCRIPTOR_SIZE
+        || read_u32(descriptor, 0) != DATA_DESCRIPTOR_SIGNATURE
+        || read_u32(descriptor, 4) != crc
+    {
+        return false;
+    }
+    if zip64 {
+        descriptor.len() >= ZIP64_DATA_DESCRIPTOR_SIZE
+            && read_u64(descriptor, 8) == size
+            && read_u64(descriptor, 16) == size
+    } else {
+        u64::from(read_u32(descriptor, 8)) == size && u64::from(read_u32(descriptor, 12)) == size
+    }
+}
+
+/// Serialize a ZIP64 extra field holding `values`.
+fn zip64_extra_field(values: &[u64]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(4 + values.len() * 8);
+    put_u16(&mut buf, ZIP64_EXTRA_FIELD_ID);
+    put_u16(&mut buf, (values.len() * 8) as u16);
+    for &value in values {
+        put_u64(&mut buf, value);
+    }
+    buf
+}
+
+/// The end of central directory record.
+#[derive(Debug, Clone)]
+pub struct EndOfCentralDir {
+    pub disk_number: u16,
+    pub central_dir_disk: u16,
+    pub entries_on_disk: u16,
+    pub entries: u16,
+    pub central_dir_size: u32,
+    pub central_dir_offset: u32,
+    pub comment: Vec<u8>,
+}
+
+impl EndOfCentralDir {
+    /// Find the end of central directory record in `tail`, the last bytes of the archive.
+    ///
+    /// Returns the record and its position in `tail`.
+    pub fn find(tail: &[u8]) -> Result<(EndOfCentralDir, usize), ZipError> {
+        if tail.len() < END_OF_CENTRAL_DIR_SIZE {
+            return Err(ZipError::NotAnArchive);
+        }
+        // Search backwards, as the signature could also appear in the comment.
+        let pos = (0..=tail.len() - END_OF_CENTRAL_DIR_SIZE)
+            .rev()
+            .find(|&pos| read_u32(tail, pos) == END_OF_CENTRAL_DIR_SIGNATURE)
+            .ok_or(ZipError::FailedFindingCentralDir)?;
+
+        let record = &tail[pos..];
+        let comment_len = usize::from(read_u16(record, 20));
+        // Some archives have garbage after the comment, so only check that it fits.
+        let comment = record
+            .get(END_OF_CENTRAL_DIR_SIZE..END_OF_CENTRAL_DIR_SIZE + comment_len)
+            .unwrap_or(&record[END_OF_CENTRAL_DIR_SIZE..]);
+        Ok((
+            EndOfCentralDir {
+                disk_number: read_u16(record, 4),
+                central_dir_disk: read_u16(record, 6),
+                entries_on_disk: read_u16(record, 8),
+                entries: read_u16(record, 10),
+                central_dir_size: read_u32(record, 12),
+                central_dir_offset: read_u32(record, 16),
+                comment: comment.to_vec(),
+            },
+            pos,
+        ))
+    }
+
+    /// Serialize the record, including the comment.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(END_OF_CENTRAL_DIR_SIZE + self.comment.len());
+        put_u32(&mut buf, END_OF_CENTRAL_DIR_SIGNATURE);
+        put_u16(&mut buf, self.disk_number);
+        put_u16(&mut buf, self.central_dir_disk);
+        put_u16(&mut buf, self.entries_on_disk);
+        put_u16(&mut buf, self.entries);
+        put_u32(&mut buf, self.central_dir_size);
+        put_u32(&mut buf, self.central_dir_offset);
+        put_u16(&mut buf, self.comment.len() as u16);
+        buf.extend_from_slice(&self.comment);
+        buf
+    }
+}
+
+/// The ZIP64 end of central directory record, used when the values don't fit in the normal one.
+#[derive(Debug, Clone)]
+pub struct Zip64EndOfCentralDir {
+    pub disk_number: u32,
+    pub central_dir_disk: u32,
+    pub entries_on_disk: u64,
+    pub entries: u64,
+    pub central_dir_size: u64,
+    pub central_dir_offset: u64,
+}
+
+impl Zip64EndOfCentralDir {
+    pub fn parse(
+        buf: &[u8; ZIP64_END_OF_CENTRAL_DIR_SIZE],
+    ) -> Result<Zip64EndOfCentralDir, ZipError> {
+        if read_u32(buf, 0) != ZIP64_END_OF_CENTRAL_DIR_SIGNATURE {
+            return Err(ZipError::InvalidHeaderOrCorrupted);
+        }
+        Ok(Zip64EndOfCentralDir {
+            disk_number: read_u32(buf, 16),
+            central_dir_disk: read_u32(buf, 20),
+            entries_on_disk: read_u64(buf, 24),
+            entries: read_u64(buf, 32),
+            central_dir_size: read_u64(buf, 40),
+            central_dir_offset: read_u64(buf, 48),
+        })
+    }
+
+    /// Serialize the record, without an extensible data sector.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(ZIP64_END_OF_CENTRAL_DIR_SIZE);
+        put_u32(&mut buf, ZIP64_END_OF_CENTRAL_DIR_SIGNATURE);
+        // Size of the rest of the record.
+        put_u64(&mut buf, (ZIP64_END_OF_CENTRAL_DIR_SIZE - 12) as u64);
+        put_u16(&mut buf, VERSION_ZIP64);
+        put_u16(&mut buf, VERSION_ZIP64);
+        put_u32(&mut buf, self.disk_number);
+        put_u32(&mut buf, self.central_dir_disk);
+        put_u64(&mut buf, self.entries_on_disk);
+        put_u64(&mut buf, self.entries);
+        put_u64(&mut buf, self.central_dir_size);
+        put_u64(&mut buf, self.central_dir_offset);
+        buf
+    }
+}
+
+/// The ZIP64 end of central directory locator, which comes right before the end of central
+/// directory record.
+#[derive(Debug, Clone)]
+pub struct Zip64Locator {
+    pub end_of_central_dir_offset: u64,
+    pub disks: u32,
+}
+
+impl Zip64Locator {
+    /// Parse the locator, returning `None` if the signature doesn't match.
+    pub fn parse(buf: &[u8; ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIZE]) -> Option<Zip64Locator> {
+        if read_u32(buf, 0) != ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIGNATURE {
+            return None;
+        }
+        Some(Zip64Locator {
+            end_of_central_dir_offset: read_u64(buf, 8),
+            disks: read_u32(buf, 16),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIZE);
+        put_u32(&mut buf, ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIGNATURE);
+        // Disk with the ZIP64 end of central directory record.
+        put_u32(&mut buf, 0);
+        put_u64(&mut buf, self.end_of_central_dir_offset);
+        put_u32(&mut buf, self.disks);
+        buf
+    }
+}
+
+/// Parse the central directory header at the start of `buf`.
+///
+/// Returns the entry and the total size of the header, including the variable length fields.
+pub fn parse_central_header(buf: &[u8]) -> Result<(ZipEntry, usize), ZipError> {
+    if buf.len() < CENTRAL_DIR_HEADER_SIZE || read_u32(buf, 0) != CENTRAL_DIR_HEADER_SIGNATURE {
+        return Err(ZipError::InvalidHeaderOrCorrupted);
+    }
+    let name_len = usize::from(read_u16(buf, 28));
+    let extra_len = usize::from(read_u16(buf, 30));
+    let comment_len = usize::from(read_u16(buf, 32));
+    let total_len = CENTRAL_DIR_HEADER_SIZE + name_len + extra_len + comment_len;
+    if buf.len() < total_len {
+        return Err(ZipError::InvalidHeaderOrCorrupted);
+    }
+    if read_u16(buf, 34) != 0 {
+        return Err(ZipError::UnsupportedMultidisk);
+    }
+
+    let name_end = CENTRAL_DIR_HEADER_SIZE + name_len;
+    let extra_end = name_end + extra_len;
+    let mut entry = ZipEntry {
+        name: buf[CENTRAL_DIR_HEADER_SIZE..name_end].to_vec(),
+        comment: buf[extra_end..total_len].to_vec(),
+        extra_field: buf[name_end..extra_end].to_vec(),
+        version_made_by: read_u16(buf, 4),
+        version_needed: read_u16(buf, 6),
+        flags: read_u16(buf, 8),
+        method: read_u16(buf, 10),
+        last_modified: DateTime::from_dos(read_u16(buf, 12), read_u16(buf, 14)),
+        crc32: read_u32(buf, 16),
+        compressed_size: u64::from(read_u32(buf, 20)),
+        uncompressed_size: u64::from(read_u32(buf, 24)),
+        internal_attributes: read_u16(buf, 36),
+        external_attributes: read_u32(buf, 38),
+        header_offset: u64::from(read_u32(buf, 42)),
+    };
+    read_zip64_fields(&mut entry)?;
+    Ok((entry, total_len))
+}
+
+/// Parse the local file header at the start of `buf`.
+///
+/// Returns the entry and the total size of the header. Fields that only exist in the central
+/// directory are left empty, and the header offset is 0.
+pub fn parse_local_header(buf: &[u8]) -> Result<(ZipEntry, usize), ZipError> {
+    if buf.len() < LOCAL_FILE_HEADER_SIZE || read_u32(buf, 0) != LOCAL_FILE_HEADER_SIGNATURE {
+        return Err(ZipError::InvalidHeaderOrCorrupted);
+    }
+    let name_end = LOCAL_FILE_HEADER_SIZE + usize::from(read_u16(buf, 26));
+    let total_len = name_end + usize::from(read_u16(buf, 28));
+    if buf.len() < total_len {
+        return Err(ZipError::InvalidHeaderOrCorrupted);
+    }
+
+    let mut entry = ZipEntry {
+        name: buf[LOCAL_FILE_HEADER_SIZE..name_end].to_vec(),
+        comment: Vec::new(),
+        extra_field: buf[name_end..total_len].to_vec(),
+        version_made_by: 0,
+        version_needed: read_u16(buf, 4),
+        flags: read_u16(buf, 6),
+        method: read_u16(buf, 8),
+        last_modified: DateTime::from_dos(read_u16(buf, 10), read_u16(buf, 12)),
+        crc32: read_u32(buf, 14),
+        compressed_size: u64::from(read_u32(buf, 18)),
+        uncompressed_size: u64::from(read_u32(buf, 22)),
+        internal_attributes: 0,
+        external_attributes: 0,
+        header_offset: 0,
+    };
+    read_zip64_fields(&mut entry)?;
+    Ok((entry, total_len))
+}
+
+/// Replace the sizes and offset of `entry` that are `ZIP64_MARKER` with the values from the
+/// ZIP64 extra field, which only holds those values, in this order.
+fn read_zip64_fields(entry: &mut ZipEntry) -> Result<(), ZipError> {
+    let marker = u64::from(ZIP64_MARKER);
+    let data = find_extra_field(&entry.extra_field, ZIP64_EXTRA_FIELD_ID).unwrap_or(&[]);
+    let mut pos = 0;
+    for value in &mut [
+        &mut entry.uncompressed_size,
+        &mut entry.compressed_size,
+        &mut entry.header_offset,
+    ] {
+        if **value == marker {
+            if pos + 8 > data.len() {
+                return Err(ZipError::InvalidHeaderOrCorrupted);
+            }
+            **value = read_u64(data, pos);
+            pos += 8;
+        }
+    }
+    Ok(())
+}
+
+/// The extra field of the central directory header of `entry`, with a ZIP64 extra field for
+/// the sizes and the offset that don't fit in 32 bits in place of any in `entry.extra_field`.
+fn central_extra_field(entry: &ZipEntry) -> Vec<u8> {
+    let zip64_values: Vec<u64> = [
+        entry.uncompressed_size,
+        entry.compressed_size,
+        entry.header_offset,
+    ]
+    .iter()
+    .cloned()
+    .filter(|&value| value >= u64::from(ZIP64_MARKER))
+    .collect();
+    let mut extra_field = Vec::new();
+    if !zip64_values.is_empty() {
+        extra_field = zip64_extra_field(&zip64_values);
+    }
+    if find_extra_field(&entry.extra_field, ZIP64_EXTRA_FIELD_ID).is_some() {
+        extra_field.extend_from_slice(&remove_extra_field(
+            &entry.extra_field,
+            ZIP64_EXTRA_FIELD_ID,
+        ));
+    } else {
+        extra_field.extend_from_slice(&entry.extra_field);
+    }
+    extra_field
+}
+
+/// The size of the central directory header `central_header` serializes for `entry`.
+pub fn central_header_size(entry: &ZipEntry) -> usize {
+    CENTRAL_DIR_HEADER_SIZE
+        + entry.name.len()
+        + central_extra_field(entry).len()
+        + entry.comment.len()
+}
+
+/// Serialize the central directory header of `entry`.
+///
+/// Sizes and the offset that don't fit in 32 bits are stored in a ZIP64 extra field, replacing
+/// any in `entry.extra_field`. The variable length fields must fit in 16 bits.
+pub fn central_header(entry: &ZipEntry) -> Vec<u8> {
+    let (time, date) = entry.last_modified.to_dos();
+    let extra_field = central_extra_field(entry);
+    let mut buf = Vec::with_capacity(
+        CENTRAL_DIR_HEADER_SIZE + entry.name.len() + extra_field.len() + entry.comment.len(),
+    );
+    put_u32(&mut buf, CENTRAL_DIR_HEADER_SIGNATURE);
+    put_u16(&mut buf, entry.version_made_by);
+    put_u16(&mut buf, entry.version_needed);
+    put_u16(&mut buf, entry.flags);
+    put_u16(&mut buf, entry.method);
+    put_u16(&mut buf, time);
+    put_u16(&mut buf, date);
+    put_u32(&mut buf, entry.crc32);
+    put_u32(&mut buf, u32_or_marker(entry.compressed_size));
+    put_u32(&mut buf, u32_or_marker(entry.uncompressed_size));
+    put_u16(&mut buf, entry.name.len() as u16);
+    put_u16(&mut buf, extra_field.len() as u16);
+    put_u16(&mut buf, entry.comment.len() as u16);
+    // Disk number.
+    put_u16(&mut buf, 0);
+    put_u16(&mut buf, entry.internal_attributes);
+    put_u32(&mut buf, entry.external_attributes);
+    put_u32(&mut buf, u32_or_marker(entry.header_offset));
+    buf.extend_from_slice(&entry.name);
+    buf.extend_from_slice(&extra_field);
+    buf.extend_from_slice(&entry.comment);
+    buf
+}
+
+/// Serialize the local header of `entry`, followed by the name and `extra_field`.
+///
+/// If the entry has a data descriptor, the CRC-32 and sizes are left as zero. With `zip64`,
+/// the sizes are stored in a ZIP64 extra field in front of `extra_field`, which also tells
+/// readers that the data descriptor has 64-bit sizes.
+pub fn local_header(entry: &ZipEntry, extra_field: &[u8], zip64: bool) -> Vec<u8> {
+    let (time, date) = entry.last_modified.to_dos();
+    let (crc32, compressed_size, uncompressed_size) = if entry.flags & FLAG_DATA_DESCRIPTOR != 0 {
+        (0, 0, 0)
+    } else {
+        (entry.crc32, entry.compressed_size, entry.uncompressed_size)
+    };
+    let mut extra = Vec::new();
+    let sizes = if zip64 {
+        extra = zip64_extra_field(&[uncompressed_size, compressed_size]);
+        (ZIP64_MARKER, ZIP64_MARKER)
+    } else {
+        (compressed_size as u32, uncompressed_size as u32)
+    };
+    extra.extend_from_slice(extra_field);
+
+    let mut buf = Vec::with_capacity(LOCAL_FILE_HEADER_SIZE + entry.name.len() + extra.len());
+    put_u32(&mut buf, LOCAL_FILE_HEADER_SIGNATURE);
+    put_u16(&mut buf, entry.version_needed);
+    put_u16(&mut buf, entry.flags);
+    put_u16(&mut buf, entry.method);
+    put_u16(&mut buf, time);
+    put_u16(&mut buf, date);
+    put_u32(&mut buf, crc32);
+    put_u32(&mut buf, sizes.0);
+    put_u32(&mut buf, sizes.1);
+    put_u16(&mut buf, entry.name.len() as u16);
+    put_u16(&mut buf, extra.len() as u16);
+    buf.extend_from_slice(&entry.name);
+    buf.extend_from_slice(&extra);
+    buf
+}
+
+/// Serialize the data descriptor of `entry`, with the optional signature.
+///
+/// With `zip64` the sizes are 64 bits.
+pub fn data_descriptor(entry: &ZipEntry, zip64: bool) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(if zip64 {
+        ZIP64_DATA_DESCRIPTOR_SIZE
+    } else {
+        DATA_DESCRIPTOR_SIZE
+    });
+    put_u32(&mut buf, DATA_DESCRIPTOR_SIGNATURE);
+    put_u32(&mut buf, entry.crc32);
+    if zip64 {
+        put_u64(&mut buf, entry.compressed_size);
+        put_u64(&mut buf, entry.uncompressed_size);
+    } else {
+        put_u32(&mut buf, entry.compressed_size as u32);
+        put_u32(&mut buf, entry.uncompressed_size as u32);
+    }
+    buf
+}
+
+/// The fixed size part of a local file header.
+#[derive(Debug, Clone)]
+pub struct LocalHeader {
+    pub name_len: u16,
+    pub extra_len: u16,
+}
+
+impl LocalHeader {
+    pub fn parse(buf: &[u8; LOCAL_FILE_HEADER_SIZE]) -> Result<LocalHeader, ZipError> {
+        if read_u32(buf, 0) != LOCAL_FILE_HEADER_SIGNATURE {
+            return Err(ZipError::InvalidHeaderOrCorrupted);
+        }
+        Ok(LocalHeader {
+            name_len: read_u16(buf, 26),
+            extra_len: read_u16(buf, 28),
+        })
+    }
+
+    /// Size of the header including the name and extra field.
+    pub fn total_len(&self) -> u64 {
+        LOCAL_FILE_HEADER_SIZE as u64 + u64::from(self.name_len) + u64::from(self.extra_len)
+    }
+}
@@ -0,0 +1,613 @@
+//! Reading ZIP archives front to back using the local headers, for input that can't seek.
+//!
+//! The central directory is at the end of the archive, so it isn't used. Entries are found
+//! from their local headers instead, which don't have the comment or file attributes, and the
+//! data of each entry is checked against the CRC-32 and sizes in the local header or the data
+//! descriptor that follows the data.
+//!
+//! When the compressed size is only stored in the data descriptor, the end of the data is
+//! found from the end of the deflate stream. Stored entries like that have no such marker, so
+//! the data is scanned for a data descriptor with a signature that matches the data before it.
+//!
+//! Only stored and deflated entries can be extracted. The methods of PKZIP 1.x are only decoded
+//! by `ZipArchive`.
+
+use std::cmp;
+use std::io::{self, Read};
+use std::prelude::v1::*;
+
+use super::spec::{self, DATA_DESCRIPTOR_SIGNATURE, ZIP64_EXTRA_FIELD_ID, ZIP64_MARKER};
+use super::{CompressionMethod, ZipEntry, ZipError, FLAG_DATA_DESCRIPTOR, IO_BUF_SIZE};
+use crate::checksum::Crc32;
+use crate::inflate::stream::{inflate_chunk, InflateState};
+use crate::DataFormat;
+
+/// Values this large are stored in a ZIP64 data descriptor.
+const MAX_U32: u64 = ZIP64_MARKER as u64;
+
+/// An archive read in order from a stream.
+///
+/// Entries are returned one at a time by `next_entry`, which skips whatever wasn't read of
+/// the previous one.
+pub struct ZipStreamReader<R> {
+    input: Input<R>,
+    /// The entry being read, if any.
+    current: Option<EntryState>,
+    /// Whether the central directory or the end of the archive was reached.
+    finished: bool,
+}
+
+impl<R: Read> ZipStreamReader<R> {
+    /// Create a reader for the archive at the start of `reader`.
+    pub fn new(reader: R) -> ZipStreamReader<R> {
+        ZipStreamReader {
+            input: Input {
+                reader,
+                buf: vec![0; IO_BUF_SIZE],
+                pos: 0,
+                len: 0,
+                offset: 0,
+            },
+            current: None,
+            finished: false,
+        }
+    }
+
+    /// Read the local header of the next entry.
+    ///
+    /// Returns `None` once the central directory is reached. The data of the previous entry
+    /// is read and checked first if it hasn't been read completely, except for entries that
+    /// can't be extracted, which are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ZipError::UnsupportedEncryption` or `ZipError::UnsupportedMethod` for entries
+    /// whose end can't be found without extracting them, and
+    /// `ZipError::FailedFindingCentralDir` if the input ends before the central directory.
+    pub fn next_entry(&mut self) -> Result<Option<ZipStreamEntry<'_, R>>, ZipError> {
+        self.skip_entry()?;
+        if self.finished {
+            return Ok(None);
+        }
+
+        let header_offset = self.input.offset;
+        let signature = match self.input.fill_at_least(4)? {
+            buf if buf.len() < 4 => return Err(ZipError::FailedFindingCentralDir),
+            buf => spec::read_u32(buf, 0),
+        };
+        match signature {
+            spec::LOCAL_FILE_HEADER_SIGNATURE => (),
+            spec::CENTRAL_DIR_HEADER_SIGNATURE
+            | spec::END_OF_CENTRAL_DIR_SIGNATURE
+            | spec::ZIP64_END_OF_CENTRAL_DIR_SIGNATURE => {
+                self.finished = true;
+                return Ok(None);
+            }
+            _ if header_offset == 0 => return Err(ZipError::NotAnArchive),
+            _ => return Err(ZipError::InvalidHeaderOrCorrupted),
+        }
+
+        let mut header = vec![0; spec::LOCAL_FILE_HEADER_SIZE];
+        self.input.read_exact(&mut header)?;
+        let variable_len =
+            usize::from(spec::read_u16(&header, 26)) + usize::from(spec::read_u16(&header, 28));
+        header.resize(spec::LOCAL_FILE_HEADER_SIZE + variable_len, 0);
+        self.input
+            .read_exact(&mut header[spec::LOCAL_FILE_HEADER_SIZE..])?;
+        let (mut entry, _) = spec::parse_local_header(&header)?;
+        entry.header_offset = header_offset;
+
+        let data_descriptor = entry.flags & FLAG_DATA_DESCRIPTOR != 0;
+        let method = CompressionMethod::from_u16(entry.method);
+        if data_descriptor && !entry.is_stored_or_deflated() {
+            return Err(if entry.is_encrypted() {
+                ZipError::UnsupportedEncryption
+            } else {
+                ZipError::UnsupportedMethod
+            });
+        }
+        let inflate = if method == Some(CompressionMethod::Deflated) {
+            Some(InflateState::new_boxed(DataFormat::Raw))
+        } else {
+            None
+        };
+        self.current = Some(EntryState {
+            zip64: spec::find_extra_field(&entry.extra_field, ZIP64_EXTRA_FIELD_ID).is_some(),
+            remaining: if data_descriptor {
+                None
+            } else {
+                Some(entry.compressed_size)
+            },
+            entry,
+            inflate,
+            compressed_size: 0,
+            crc: Crc32::new(),
+            size: 0,
+            stream_end: false,
+            finished: false,
+        });
+        Ok(Some(ZipStreamEntry { stream: self }))
+    }
+
+    /// Return the underlying reader.
+    ///
+    /// Data that has already been read from it into the buffer is lost.
+    pub fn into_inner(self) -> R {
+        self.input.reader
+    }
+
+    /// Read the rest of the current entry, if any.
+    fn skip_entry(&mut self) -> Result<(), ZipError> {
+        let state = match self.current.take() {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        if state.finished {
+            return Ok(());
+        }
+        if !state.entry.is_stored_or_deflated() {
+            // Only entries with a known size get this far.
+            let remaining = state.remaining.unwrap_or(0);
+            return self.input.skip(remaining);
+        }
+        self.current = Some(state);
+        let mut scratch = vec![0; IO_BUF_SIZE];
+        while self.read_data(&mut scratch)? != 0 {}
+        self.current = None;
+        Ok(())
+    }
+
+    /// Read the data of the current entry into `out`, returning the number of bytes read.
+    ///
+    /// Returns 0 once all the data has been read, after checking the CRC-32 and sizes.
+    fn read_data(&mut self, out: &mut [u8]) -> Result<usize, ZipError> {
+        let state = match self.current {
+            Some(ref mut state) if !state.finished => state,
+            _ => return Ok(0),
+        };
+        if out.is_empty() {
+            return Ok(0);
+        }
+        if state.entry.is_encrypted() {
+            return Err(ZipError::UnsupportedEncryption);
+        }
+        if !state.entry.is_stored_or_deflated() {
+            return Err(ZipError::UnsupportedMethod);
+        }
+
+        let n = if state.inflate.is_some() {
+            state.read_deflated(&mut self.input, out)?
+        } else if state.remaining.is_some() {
+            state.read_stored(&mut self.input, out)?
+        } else {
+            state.read_stored_until_descriptor(&mut self.input, out)?
+        };
+        state.crc.update(&out[..n]);
+        state.size += n as u64;
+        // Stop early rather than producing more data than the entry should have.
+        if state.remaining.is_some() && state.size > state.entry.uncompressed_size {
+            return Err(ZipError::UnexpectedDecompressedSize);
+        }
+        if n == 0 {
+            state.finish(&mut self.input)?;
+        }
+        Ok(n)
+    }
+}
+
+/// An entry of an archive being read with `ZipStreamReader`.
+///
+/// Reading it returns the decompressed data.
+pub struct ZipStreamEntry<'a, R> {
+    stream: &'a mut ZipStreamReader<R>,
+}
+
+impl<'a, R: Read> ZipStreamEntry<'a, R> {
+    /// The entry as described by its local header.
+    ///
+    /// The CRC-32 and sizes of entries with a data descriptor are only filled in once all the
+    /// data has been read.
+    pub fn entry(&self) -> &ZipEntry {
+        &self
+            .stream
+            .current
+            .as_ref()
+            .expect("Bug! No current entry!")
+            .entry
+    }
+}
+
+impl<'a, R: Read> Read for ZipStreamEntry<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(self.stream.read_data(buf)?)
+    }
+}
+
+/// Progress through the data of an entry.
+struct EntryState {
+    entry: ZipEntry,
+    /// Only used for deflated entries.
+    inflate: Option<Box<InflateState>>,
+    /// Number of compressed bytes not read yet, if the compressed size is known.
+    remaining: Option<u64>,
+    /// Whether the local header has a ZIP64 extra field, which means that the data descriptor
+    /// has 64-bit sizes.
+    zip64: bool,
+    compressed_size: u64,
+    crc: Crc32,
+    size: u64,
+    stream_end: bool,
+    finished: bool,
+}
+
+impl EntryState {
+    fn read_stored<R: Read>(
+        &mut self,
+        input: &mut Input<R>,
+        out: &mut [u8],
+    ) -> Result<usize, ZipError> {
+        let remaining = self.remaining.unwrap_or(0);
+        if remaining == 0 {
+            return Ok(0);
+        }
+        let buf = input.fill()?;
+        let n = cmp::min(cmp::min(out.len() as u64, remaining), buf.len() as u64) as usize;
+        if n == 0 {
+            return Err(ZipError::InvalidHeaderOrCorrupted);
+        }
+        out[..n].copy_from_slice(&buf[..n]);
+        input.consume(n);
+        self.remaining = Some(remaining - n as u64);
+        self.compressed_size += n as u64;
+        Ok(n)
+    }
+
+    /// Read stored data up to the first data descriptor signature followed by the CRC-32 and
+    /// size of the data before it.
+    fn read_stored_until_descriptor<R: Read>(
+        &mut self,
+        input: &mut Input<R>,
+        out: &mut [u8],
+    ) -> Result<usize, ZipError> {
+        let buf = input.fill_at_least(spec::ZIP64_DATA_DESCRIPTOR_SIZE)?;
+        // Positions closer to the end than this can't be checked until more data is read.
+        let mut safe_len = buf
+            .len()
+            .saturating_sub(spec::ZIP64_DATA_DESCRIPTOR_SIZE - 1);
+        let signature = DATA_DESCRIPTOR_SIGNATURE.to_le_bytes();
+        for pos in 0..buf.len().saturating_sub(3) {
+            if buf[pos..pos + 4] == signature && self.is_descriptor(&buf[..pos], &buf[pos..]) {
+                safe_len = pos;
+                break;
+            }
+        }
+        if safe_len == 0 && !self.is_descriptor(&[], buf) {
+            // The input ended without a data descriptor.
+            return Err(ZipError::InvalidHeaderOrCorrupted);
+        }
+        let n = cmp::min(out.len(), safe_len);
+        out[..n].copy_from_slice(&buf[..n]);
+        input.consume(n);
+        self.compressed_size += n as u64;
+        Ok(n)
+    }
+
+    /// Whether `descriptor` starts with a data descriptor matching the data read so far
+    /// followed by `data`.
+    fn is_descriptor(&self, data: &[u8], descriptor: &[u8]) -> bool {
+        if descriptor.len() < spec::DATA_DESCRIPTOR_SIZE
+            || spec::read_u32(descriptor, 0) != DATA_DESCRIPTOR_SIGNATURE
+        {
+            return false;
+        }
+        let mut crc = self.crc.clone();
+        crc.update(data);
+        let size = self.size + data.len() as u64;
+        spec::is_stored_descriptor(
+            descriptor,
+            crc.checksum(),
+            size,
+            self.zip64 || size >= MAX_U32,
+        )
+    }
+
+    fn read_deflated<R: Read>(
+        &mut self,
+        input: &mut Input<R>,
+        out: &mut [u8],
+    ) -> Result<usize, ZipError> {
+        if self.stream_end {
+            return Ok(0);
+        }
+        let state = self.inflate.as_mut().expect("Bug! No inflate state!");
+        loop {
+            let buf = input.fill()?;
+            let len = match self.remaining {
+                Some(remaining) => cmp::min(remaining, buf.len() as u64) as usize,
+                None => buf.len(),
+            };
+            let res = inflate_chunk(state, &buf[..len], out);
+            input.consume(res.bytes_consumed);
+            self.compressed_size += res.bytes_consumed as u64;
+            if let Some(ref mut remaining) = self.remaining {
+                *remaining -= res.bytes_consumed as u64;
+            }
+            match res.stream_end {
+                Ok(true) => {
+                    self.stream_end = true;
+                    return Ok(res.bytes_written);
+                }
+                Ok(false) => {
+                    if res.bytes_written > 0 {
+                        return Ok(res.bytes_written);
+                    }
+                    if len == 0 {
+                        // The compressed data ended before the end of the deflate stream.
+                        return Err(ZipError::DecompressionFailed);
+                    }
+                }
+                Err(_) => return Err(ZipError::DecompressionFailed),
+            }
+        }
+    }
+
+    /// Check the data that has been read against the local header or the data descriptor,
+    /// reading the data descriptor if there is one.
+    fn finish<R: Read>(&mut self, input: &mut Input<R>) -> Result<(), ZipError> {
+        let (crc32, compressed_size, uncompressed_size) = match self.remaining {
+            // Compressed data left over after the end of the deflate stream.
+            Some(remaining) if remaining != 0 => return Err(ZipError::DecompressionFailed),
+            Some(_) => (
+                self.entry.crc32,
+                self.entry.compressed_size,
+                self.entry.uncompressed_size,
+            ),
+            None => self.read_descriptor(input)?,
+        };
+        if compressed_size != self.compressed_size || uncompressed_size != self.size {
+            return Err(ZipError::UnexpectedDecompressedSize);
+        }
+        if crc32 != self.crc.checksum() {
+            return Err(ZipError::CrcCheckFailed);
+        }
+        self.entry.crc32 = crc32;
+        self.entry.compressed_size = compressed_size;
+        self.entry.uncompressed_size = uncompressed_size;
+        self.finished = true;
+        Ok(())
+    }
+
+    /// Read the data descriptor, returning the CRC-32 and sizes.
+    ///
+    /// The sizes are 64 bits if the local header has a ZIP64 extra field, or if they don't fit
+    /// in 32 bits, which some writers do without the extra field.
+    fn read_descriptor<R: Read>(&self, input: &mut Input<R>) -> Result<(u32, u64, u64), ZipError> {
+        let mut buf = [0; spec::ZIP64_DATA_DESCRIPTOR_SIZE];
+        // The signature is optional.
+        input.read_exact(&mut buf[..4])?;
+        if spec::read_u32(&buf, 0) == DATA_DESCRIPTOR_SIGNATURE {
+            input.read_exact(&mut buf[..4])?;
+        }
+        if self.zip64 || self.compressed_size >= MAX_U32 || self.size >= MAX_U32 {
+            input.read_exact(&mut buf[4..20])?;
+            Ok((
+                spec::read_u32(&buf, 0),
+                spec::read_u64(&buf, 4),
+                spec::read_u64(&buf, 12),
+            ))
+        } else {
+            input.read_exact(&mut buf[4..12])?;
+            Ok((
+                spec::read_u32(&buf, 0),
+                u64::from(spec::read_u32(&buf, 4)),
+                u64::from(spec::read_u32(&buf, 8)),
+            ))
+        }
+    }
+}
+
+/// Buffered input that keeps track of the position in the archive, and can look ahead.
+struct Input<R> {
+    reader: R,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    /// Offset in the archive of `buf[pos]`.
+    offset: u64,
+}
+
+impl<R: Read> Input<R> {
+    /// Return the buffered data, reading more if there is none. The result is only empty at
+    /// the end of the input.
+    fn fill(&mut self) -> Result<&[u8], ZipError> {
+        if self.pos == self.len {
+            self.pos = 0;
+            self.len = 0;
+            self.read_more()?;
+        }
+        Ok(&self.buf[self.pos..self.len])
+    }
+
+    /// Return the buffered data, reading more until there are at least `n` bytes or the end of
+    /// the input is reached.
+    fn fill_at_least(&mut self, n: usize) -> Result<&[u8], ZipError> {
+        debug_assert!(n <= self.buf.len());
+        if self.len - self.pos < n {
+            self.buf[..self.len].rotate_left(self.pos);
+            self.len -= self.pos;
+            self.pos = 0;
+            while self.len < n {
+                if self.read_more()? == 0 {
+                    break;
+                }
+            }
+        }
+        Ok(&self.buf[self.pos..self.len])
+    }
+
+    fn read_more(&mut self) -> Result<usize, ZipError> {
+        loop {
+            match self.reader.read(&mut self.buf[self.len..]) {
+                Ok(n) => {
+                    self.len += n;
+                    return Ok(n);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(_) => return Err(ZipError::FileReadFailed),
+            }
+        }
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.pos += n;
+        self.offset += n as u64;
+    }
+
+    /// Fill `out`, treating the end of the input as a truncated archive.
+    fn read_exact(&mut self, out: &mut [u8]) -> Result<(), ZipError> {
+        let mut done = 0;
+        while done < out.len() {
+            let buf = self.fill()?;
+            if buf.is_empty() {
+                return Err(ZipError::InvalidHeaderOrCorrupted);
+            }
+            let n = cmp::min(buf.len(), out.len() - done);
+            out[done..done + n].copy_from_slice(&buf[..n]);
+            self.consume(n);
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// Discard the next `n` bytes.
+    fn skip(&mut self, mut n: u64) -> Result<(), ZipError> {
+        while n > 0 {
+            let len = self.fill()?.len();
+            if len == 0 {
+                return Err(ZipError::InvalidHeaderOrCorrupted);
+            }
+            let len = cmp::min(len as u64, n) as usize;
+            self.consume(len);
+            n -= len as u64;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read};
+
+    use super::super::test_util::build;
+    use super::super::ZipError;
+    use super::ZipStreamReader;
+
+    /// A reader returning at most `chunk` bytes at a time, like a pipe.
+    struct Chunked<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl<'a> Read for Chunked<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.chunk.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    /// Read all the entries, returning the names and contents.
+    fn read_all<R: Read>(reader: R) -> Result<Vec<(Vec<u8>, Vec<u8>)>, ZipError> {
+        let mut zip = ZipStreamReader::new(reader);
+        let mut entries = Vec::new();
+        while let Some(mut entry) = zip.next_entry()? {
+            let mut data = Vec::new();
+            entry
+                .read_to_end(&mut data)
+                .map_err(|_| ZipError::Undefined)?;
+            entries.push((entry.entry().name.clone(), data));
+        }
+        Ok(entries)
+    }
+
+    #[test]
+    fn read_entries() {
+        for &streaming in &[false, true] {
+            let archive = build(streaming);
+            let (data, expected) = (&archive.data, &archive.entries);
+            assert_eq!(read_all(&data[..]), Ok(expected.clone()));
+            for &chunk in &[1, 7, 1000] {
+                let reader = Chunked { data, chunk };
+                assert_eq!(read_all(reader), Ok(expected.clone()));
+            }
+        }
+    }
+
+    #[test]
+    fn skip_entries() {
+        let archive = build(true);
+        let expected = &archive.entries;
+        let mut zip = ZipStreamReader::new(&archive.data[..]);
+        let mut names = Vec::new();
+        while let Some(mut entry) = zip.next_entry().unwrap() {
+            // Read only part of some of the entries.
+            let mut buf = [0; 3];
+            let n = entry.read(&mut buf).unwrap();
+            assert_eq!(&buf[..n], &expected[names.len()].1[..n]);
+            names.push(entry.entry().name.clone());
+        }
+        assert_eq!(names.len(), expected.len());
+        assert!(zip.next_entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn descriptor_fields() {
+        let archive = build(true);
+        let mut zip = ZipStreamReader::new(&archive.data[..]);
+        for _ in 0..4 {
+            zip.next_entry().unwrap();
+        }
+        // "large", which is deflated.
+        let mut entry = zip.next_entry().unwrap().unwrap();
+        assert_eq!(entry.entry().uncompressed_size, 0);
+        entry.read_to_end(&mut Vec::new()).unwrap();
+        // The values from the data descriptor are filled in once the data has been read.
+        let expected = &archive.entries[4].1;
+        assert_eq!(entry.entry().uncompressed_size, expected.len() as u64);
+        assert_eq!(
+            entry.entry().crc32,
+            crate::checksum::Crc32::checksum_of(expected)
+        );
+    }
+
+    #[test]
+    fn corrupted() {
+        let archive = build(false);
+        let (data, central_dir) = (archive.data, archive.central_dir);
+        assert_eq!(
+            read_all(&b"not a zip archive"[..]),
+            Err(ZipError::NotAnArchive)
+        );
+        // Truncated before the central directory.
+        assert_eq!(
+            read_all(&data[..central_dir]),
+            Err(ZipError::FailedFindingCentralDir)
+        );
+        assert!(read_all(&data[..central_dir - 10]).is_err());
+
+        // A byte of the stored data of "dir/tricky" changed.
+        let mut corrupted = data.clone();
+        let pos = corrupted.windows(6).position(|w| w == b"before").unwrap();
+        corrupted[pos] ^= 1;
+        assert_eq!(read_all(&corrupted[..]), Err(ZipError::Undefined));
+        let mut zip = ZipStreamReader::new(&corrupted[..]);
+        for _ in 0..3 {
+            zip.next_entry().unwrap();
+        }
+        let mut entry = zip.next_entry().unwrap().unwrap();
+        let mut buf = Vec::new();
+        let err = entry.read_to_end(&mut buf).unwrap_err();
+        assert_eq!(err.to_string(), ZipError::CrcCheckFailed.as_str());
+    }
+}
@@ -0,0 +1,326 @@
+//! Checking the structure and data of ZIP archives.
+//!
+//! Besides checking that the local headers match the central directory and that the data
+//! decompresses to the right CRC-32, validation rejects archives built to use up resources
+//! when extracted: entries whose data overlaps, so the same compressed data is extracted
+//! several times, and archives whose size when extracted exceeds the given limits.
+
+use std::cmp;
+use std::io::{Read, Seek};
+use std::prelude::v1::*;
+
+use super::read::{read_exact_at, ZipArchive};
+use super::spec::{self, DATA_DESCRIPTOR_SIGNATURE, ZIP64_EXTRA_FIELD_ID, ZIP64_MARKER};
+use super::{ZipError, FLAG_DATA_DESCRIPTOR};
+
+/// Limits checked when validating an archive.
+///
+/// The defaults only reject what can't be extracted anyway. Archives from untrusted sources
+/// should be checked with lower limits for what the caller is willing to extract.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationLimits {
+    /// Largest number of entries.
+    pub max_entries: usize,
+    /// Largest total uncompressed size of all the entries.
+    pub max_total_size: u64,
+    /// Largest ratio of the uncompressed size of an entry to its compressed size.
+    pub max_ratio: u64,
+}
+
+impl Default for ValidationLimits {
+    /// No limit on the number of entries, the total size or the ratio. Shrink can reach much
+    /// higher ratios than deflate, so no fixed ratio holds for every valid archive.
+    fn default() -> ValidationLimits {
+        ValidationLimits {
+            max_entries: usize::max_value(),
+            max_total_size: u64::max_value(),
+            max_ratio: u64::max_value(),
+        }
+    }
+}
+
+impl<R: Read + Seek> ZipArchive<R> {
+    /// Check the headers of all the entries like `validate_headers`, then extract each of them
+    /// to check the CRC-32 and size of the data.
+    ///
+    /// The data isn't kept, so this uses little memory no matter how large the entries are.
+    pub fn validate(&mut self, limits: &ValidationLimits) -> Result<(), ZipError> {
+        self.validate_headers(limits)?;
+        for index in 0..self.len() {
+            self.validate_data(index)?;
+        }
+        Ok(())
+    }
+
+    /// Check the archive without extracting the entries.
+    ///
+    /// Every entry has to be extractable, with a local header and data descriptor matching the
+    /// central directory, and data that is before the central directory and doesn't overlap
+    /// with any other entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ZipError::TooManyFiles` or `ZipError::ValidationFailed` if the archive exceeds
+    /// `limits`, and `ZipError::InvalidHeaderOrCorrupted` if the headers don't match or the
+    /// entries overlap.
+    pub fn validate_headers(&mut self, limits: &ValidationLimits) -> Result<(), ZipError> {
+        if self.len() > limits.max_entries {
+            return Err(ZipError::TooManyFiles);
+        }
+        let mut total_size = 0u64;
+        let mut ranges = Vec::with_capacity(self.len());
+        for index in 0..self.len() {
+            let end = self.validate_entry_header(index)?;
+            let entry = &self.entries()[index];
+            if entry.uncompressed_size > entry.compressed_size.saturating_mul(limits.max_ratio) {
+                return Err(ZipError::ValidationFailed);
+            }
+            total_size = total_size.saturating_add(entry.uncompressed_size);
+            if total_size > limits.max_total_size {
+                return Err(ZipError::ValidationFailed);
+            }
+            ranges.push((entry.header_offset, end));
+        }
+
+        ranges.sort();
+        if ranges.windows(2).any(|pair| pair[0].1 > pair[1].0) {
+            return Err(ZipError::InvalidHeaderOrCorrupted);
+        }
+        Ok(())
+    }
+
+    /// Check the headers of entry `index` and extract it to check the CRC-32 and size of the
+    /// data.
+    pub fn validate_entry(&mut self, index: usize) -> Result<(), ZipError> {
+        self.validate_entry_header(index)?;
+        self.validate_data(index)
+    }
+
+    /// Check that entry `index` can be extracted and that its local header and data descriptor
+    /// match the central directory, returning the offset of the end of the entry.
+    pub fn validate_entry_header(&mut self, index: usize) -> Result<u64, ZipError> {
+        let entry = self
+            .entries()
+            .get(index)
+            .ok_or(ZipError::InvalidParameter)?
+            .clone();
+        if !self.can_decrypt(&entry) {
+            return Err(ZipError::UnsupportedEncryption);
+        }
+        if !entry.is_method_supported() {
+            return Err(ZipError::UnsupportedMethod);
+        }
+
+        // `data_offset` checks that the data is within the archive, the central directory is
+        // checked below.
+        let data_offset = self.data_offset(index)?;
+        let mut header = vec![0; (data_offset - entry.header_offset) as usize];
+        read_exact_at(self.get_mut(), entry.header_offset, &mut header)?;
+        let (local, _) = spec::parse_local_header(&header)?;
+        if local.name != entry.name
+            || local.method != entry.method
+            || local.flags & FLAG_DATA_DESCRIPTOR != entry.flags & FLAG_DATA_DESCRIPTOR
+        {
+            return Err(ZipError::InvalidHeaderOrCorrupted);
+        }
+
+        let mut end = data_offset + entry.compressed_size;
+        let (crc32, compressed_size, uncompressed_size) = if local.flags & FLAG_DATA_DESCRIPTOR != 0
+        {
+            // The sizes are 64 bits if the local header has a ZIP64 extra field, or if they
+            // don't fit in 32 bits, which some writers do without the extra field.
+            let marker = u64::from(ZIP64_MARKER);
+            let zip64 = spec::find_extra_field(&local.extra_field, ZIP64_EXTRA_FIELD_ID).is_some()
+                || entry.compressed_size >= marker
+                || entry.uncompressed_size >= marker;
+            let len = if zip64 {
+                spec::ZIP64_DATA_DESCRIPTOR_SIZE
+            } else {
+                spec::DATA_DESCRIPTOR_SIZE
+            };
+            // The signature is optional, so the descriptor may be 4 bytes shorter.
+            let available = self.central_dir_offset().saturating_sub(end);
+            let mut buf = vec![0; cmp::min(len as u64, available) as usize];
+            read_exact_at(self.get_mut(), end, &mut buf)?;
+            let pos = if buf.len() >= 4 && spec::read_u32(&buf, 0) == DATA_DESCRIPTOR_SIGNATURE {
+                4
+            } else {
+                0
+            };
+            if buf.len() < pos + len - 4 {
+                return Err(ZipError::InvalidHeaderOrCorrupted);
+            }
+            end += (pos + len - 4) as u64;
+            if zip64 {
+                (
+                    spec::read_u32(&buf, pos),
+                    spec::read_u64(&buf, pos + 4),
+                    spec::read_u64(&buf, pos + 12),
+                )
+            } else {
+                (
+                    spec::read_u32(&buf, pos),
+                    u64::from(spec::read_u32(&buf, pos + 4)),
+                    u64::from(spec::read_u32(&buf, pos + 8)),
+                )
+            }
+        } else {
+            (local.crc32, local.compressed_size, local.uncompressed_size)
+        };
+        if crc32 != entry.crc32
+            || compressed_size != entry.compressed_size
+            || uncompressed_size != entry.uncompressed_size
+            || end > self.central_dir_offset()
+        {
+            return Err(ZipError::InvalidHeaderOrCorrupted);
+        }
+        Ok(end)
+    }
+
+    /// Extract entry `index` without keeping the data, which checks its CRC-32 and size.
+    fn validate_data(&mut self, index: usize) -> Result<(), ZipError> {
+        let mut decoder = self.decoder(index)?;
+        decoder.read_to_callback(self.get_mut(), |_| true)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use std::prelude::v1::*;
+
+    use super::super::read::MAX_DEFLATE_RATIO;
+    use super::super::test_util::build;
+    use super::super::write::EntryOptions;
+    use super::super::{CompressionMethod, ZipArchive, ZipError, ZipWriter};
+    use super::ValidationLimits;
+    use crate::checksum::Crc32;
+
+    #[test]
+    fn valid() {
+        for &streaming in &[false, true] {
+            let mut archive = ZipArchive::new(Cursor::new(build(streaming).data)).unwrap();
+            assert_eq!(archive.validate(&ValidationLimits::default()), Ok(()));
+            for index in 0..archive.len() {
+                assert_eq!(archive.validate_entry(index), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let mut archive = ZipArchive::new(Cursor::new(build(false).data)).unwrap();
+        let mut limits = ValidationLimits::default();
+        limits.max_entries = 7;
+        assert_eq!(archive.validate(&limits), Err(ZipError::TooManyFiles));
+
+        // Two copies of 300 KB of text and a bit more.
+        let mut limits = ValidationLimits::default();
+        limits.max_total_size = 600_000;
+        assert_eq!(archive.validate(&limits), Err(ZipError::ValidationFailed));
+        limits.max_total_size = 1 << 20;
+        assert_eq!(archive.validate(&limits), Ok(()));
+
+        // Repeated text compresses very well.
+        let mut limits = ValidationLimits::default();
+        limits.max_ratio = 50;
+        assert_eq!(
+            archive.validate_headers(&limits),
+            Err(ZipError::ValidationFailed)
+        );
+    }
+
+    #[test]
+    fn shrunk_ratio() {
+        // Each code stands for the string of the one before plus another `a`, so the output
+        // grows quadratically with the number of codes.
+        let (mut compressed, mut bit_buf, mut num_bits) = (Vec::new(), 0u32, 0);
+        let mut code_size = 9;
+        let mut fields = vec![(u32::from(b'a'), 9)];
+        let mut size = 1;
+        for code in 257..3600u32 {
+            if code >> code_size != 0 {
+                fields.extend_from_slice(&[(256, code_size), (1, code_size)]);
+                code_size += 1;
+            }
+            fields.push((code, code_size));
+            size += code as usize - 255;
+        }
+        for (value, bits) in fields {
+            bit_buf |= value << num_bits;
+            num_bits += bits;
+            while num_bits >= 8 {
+                compressed.push(bit_buf as u8);
+                bit_buf >>= 8;
+                num_bits -= 8;
+            }
+        }
+        compressed.push(bit_buf as u8);
+        assert!(size as u64 > compressed.len() as u64 * MAX_DEFLATE_RATIO);
+
+        let crc = Crc32::checksum_of(&vec![b'a'; size]);
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = EntryOptions::default();
+        zip.add_compressed_entry(b"shrunk", &compressed, size as u64, crc, &options)
+            .unwrap();
+        zip.finish().unwrap();
+        let central_dir = zip.central_dir_offset().unwrap() as usize;
+        let mut data = zip.into_inner().into_inner();
+        // Change the method in the local header and the central directory.
+        data[8] = CompressionMethod::Shrunk as u8;
+        data[central_dir + 10] = CompressionMethod::Shrunk as u8;
+
+        let mut archive = ZipArchive::new(Cursor::new(data)).unwrap();
+        assert_eq!(archive.validate(&ValidationLimits::default()), Ok(()));
+    }
+
+    #[test]
+    fn overlapping() {
+        let data = build(false).data;
+        let archive = ZipArchive::new(Cursor::new(data)).unwrap();
+        let offset = archive.central_dir_offset();
+        let mut entries = archive.entries().to_vec();
+        let mut io = archive.into_inner();
+        io.get_mut().truncate(offset as usize);
+        io.set_position(offset);
+
+        // A second entry pointing at the same data, which has to have the same name to match
+        // the local header.
+        let copy = entries[4].clone();
+        entries.push(copy);
+        let mut zip = ZipWriter::with_entries(io, offset, entries);
+        zip.finish().unwrap();
+        let mut archive = ZipArchive::new(zip.into_inner()).unwrap();
+        assert_eq!(archive.validate_entry(8), Ok(()));
+        assert_eq!(
+            archive.validate(&ValidationLimits::default()),
+            Err(ZipError::InvalidHeaderOrCorrupted)
+        );
+    }
+
+    #[test]
+    fn corrupted() {
+        let data = build(false).data;
+        let archive = ZipArchive::new(Cursor::new(&data[..])).unwrap();
+        let stored = archive.entry(1).unwrap().header_offset as usize;
+
+        // The CRC-32 in the local header.
+        let mut corrupted = data.clone();
+        corrupted[stored + 14] ^= 1;
+        let mut archive = ZipArchive::new(Cursor::new(corrupted)).unwrap();
+        assert_eq!(
+            archive.validate_entry_header(1),
+            Err(ZipError::InvalidHeaderOrCorrupted)
+        );
+
+        // The stored data, which only shows when extracting it.
+        let mut corrupted = data.clone();
+        corrupted[stored + 30 + 10] ^= 1;
+        let mut archive = ZipArchive::new(Cursor::new(corrupted)).unwrap();
+        let limits = ValidationLimits::default();
+        assert_eq!(archive.validate_headers(&limits), Ok(()));
+        assert_eq!(archive.validate(&limits), Err(ZipError::CrcCheckFailed));
+    }
+}
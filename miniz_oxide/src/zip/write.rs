@@ -0,0 +1,1456 @@
+//! Writing ZIP archives.
+//!
+//! Unlike miniz, which seeks back to fill in the local header once the data is written, the
+//! writer never seeks back. Entries whose compressed size isn't known before the data is
+//! written get a data descriptor after the data, and the central directory is written when the
+//! archive is finished.
+//!
+//! ZIP64 records and extra fields are added when sizes, offsets or the number of entries don't
+//! fit in the original format. As the local header comes before the data, entries that might
+//! end up 4 GiB or larger without the size being known in advance have to be marked with
+//! `EntryOptions::large_file`.
+//!
+//! For outputs that are consumed as they are produced, like an HTTP response, the streaming
+//! mode of `ZipWriter::new_streaming` gives every new entry a data descriptor, and entries can
+//! be written piece by piece with `ZipWriter::start_entry`. There, entries whose size isn't
+//! known in advance always get ZIP64 fields.
+
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::prelude::v1::*;
+use std::str;
+
+use super::extra::{HOST_UNIX, S_IFLNK};
+use super::read::{read_exact_at, ZipArchive};
+use super::spec::{self, EndOfCentralDir, Zip64EndOfCentralDir, Zip64Locator, VERSION_ZIP64};
+use super::{
+    CompressionMethod, DateTime, ZipEntry, ZipError, DOS_DIRECTORY_ATTRIBUTE, FLAG_DATA_DESCRIPTOR,
+    FLAG_UTF8, IO_BUF_SIZE,
+};
+use crate::checksum::Crc32;
+use crate::deflate::core::{
+    compress_to_output, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush, TDEFLStatus,
+};
+
+/// Version 1.0 of the format, enough for stored files.
+const VERSION_DEFAULT: u16 = 10;
+/// Version 2.0 of the format, needed for deflate, directories and data descriptors.
+const VERSION_DEFLATE: u16 = 20;
+
+/// Entries smaller than this are always stored, the same as in miniz.
+const MIN_COMPRESS_SIZE: usize = 4;
+
+/// Values this large don't fit in the 32-bit size and offset fields.
+const MAX_U32: u64 = spec::ZIP64_MARKER as u64;
+/// Archives with this many entries need a ZIP64 end of central directory record.
+const MAX_ENTRIES: usize = spec::ZIP64_ENTRIES_MARKER as usize;
+/// Largest number of entries in a ZIP64 archive, the same as in miniz.
+const MAX_ZIP64_ENTRIES: u64 = 0xFFFF_FFFF;
+/// Entries with a data descriptor of at least this size get ZIP64 fields, as the compressed
+/// data can be slightly larger than the input.
+const ZIP64_THRESHOLD: u64 = MAX_U32 - 0x0100_0000;
+/// Largest size of names, comments and extra fields.
+const MAX_FIELD_SIZE: usize = 0xFFFF;
+
+/// Options used when adding an entry to an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryOptions {
+    /// Compression level, 0-10. Level 0 stores the data uncompressed.
+    pub level: u8,
+    /// Last modification time.
+    pub last_modified: DateTime,
+    /// Entry comment.
+    pub comment: Vec<u8>,
+    /// Extra field written to the local header.
+    pub local_extra_field: Vec<u8>,
+    /// Extra field written to the central directory.
+    pub central_extra_field: Vec<u8>,
+    /// External file attributes. The MS-DOS directory attribute is added for directories.
+    pub external_attributes: u32,
+    /// Unix file type and permissions. If set, they are stored in the high 16 bits of the
+    /// external attributes and the entry is marked as made on Unix.
+    pub unix_mode: Option<u32>,
+    /// Whether to mark the name and comment as UTF-8. They are only marked if they are valid
+    /// UTF-8.
+    pub utf8: bool,
+    /// Whether the entry may be 4 GiB or larger, for entries added with
+    /// `ZipWriter::add_entry_from_reader` or `ZipWriter::start_entry` outside of streaming
+    /// mode. Other entries are checked automatically.
+    pub large_file: bool,
+}
+
+impl EntryOptions {
+    /// Options using compression level `level` and the defaults for everything else.
+    pub fn new(level: u8) -> EntryOptions {
+        EntryOptions {
+            level,
+            ..EntryOptions::default()
+        }
+    }
+}
+
+impl Default for EntryOptions {
+    /// Compression level 6, the earliest representable time, no comment or extra fields, and
+    /// the name marked as UTF-8 like in miniz.
+    fn default() -> EntryOptions {
+        EntryOptions {
+            level: 6,
+            last_modified: DateTime::default(),
+            comment: Vec::new(),
+            local_extra_field: Vec::new(),
+            central_extra_field: Vec::new(),
+            external_attributes: 0,
+            unix_mode: None,
+            utf8: true,
+            large_file: false,
+        }
+    }
+}
+
+/// A ZIP archive being written.
+///
+/// The archive is only valid once `finish` has been called.
+pub struct ZipWriter<W> {
+    writer: W,
+    /// Offset in the archive of the next byte written.
+    pos: u64,
+    entries: Vec<ZipEntry>,
+    comment: Vec<u8>,
+    alignment: u64,
+    central_dir_offset: u64,
+    zip64: bool,
+    /// Whether every entry gets a data descriptor.
+    streaming: bool,
+    finished: bool,
+}
+
+impl<W: Write> ZipWriter<W> {
+    /// Create a writer for a new archive.
+    pub fn new(writer: W) -> ZipWriter<W> {
+        ZipWriter::with_offset(writer, 0)
+    }
+
+    /// Create a writer for a new archive in streaming mode.
+    ///
+    /// Every entry except directories is written with general purpose bit 3 set and its
+    /// CRC-32 and sizes in a data descriptor after the data, so nothing written to `writer`
+    /// depends on what comes after it. Entries copied with `add_entry_from_archive` keep bit 3
+    /// as it is, as their CRC-32 and sizes are known in advance. Entries added with
+    /// `add_entry_from_reader` or `start_entry` always get a ZIP64 extra field in the local
+    /// header and a ZIP64 data descriptor, as they could turn out to be 4 GiB or larger and
+    /// the local header can't be changed afterwards. This makes the archive a ZIP64 archive.
+    pub fn new_streaming(writer: W) -> ZipWriter<W> {
+        let mut zip = ZipWriter::new(writer);
+        zip.streaming = true;
+        zip
+    }
+
+    /// Create a writer for a new archive following `offset` bytes that have already been
+    /// written, for example a self-extracting program.
+    ///
+    /// The offsets stored in the archive are counted from the start of those bytes.
+    pub fn with_offset(writer: W, offset: u64) -> ZipWriter<W> {
+        ZipWriter::with_entries(writer, offset, Vec::new())
+    }
+
+    /// Continue an archive that already has `entries`.
+    ///
+    /// `writer` should be positioned at `offset`, usually the start of the old central
+    /// directory, which is overwritten by the new entries.
+    pub fn with_entries(writer: W, offset: u64, entries: Vec<ZipEntry>) -> ZipWriter<W> {
+        ZipWriter {
+            writer,
+            pos: offset,
+            entries,
+            comment: Vec::new(),
+            alignment: 0,
+            central_dir_offset: 0,
+            zip64: false,
+            streaming: false,
+            finished: false,
+        }
+    }
+
+    /// Set the archive comment.
+    pub fn set_comment(&mut self, comment: &[u8]) -> Result<(), ZipError> {
+        if comment.len() > MAX_FIELD_SIZE {
+            return Err(ZipError::InvalidParameter);
+        }
+        self.comment = comment.to_vec();
+        Ok(())
+    }
+
+    /// Align the local header of each new entry to a multiple of `alignment` bytes by adding
+    /// padding in front of it.
+    ///
+    /// `alignment` has to be a power of two, or 0 to disable alignment.
+    pub fn set_file_offset_alignment(&mut self, alignment: u64) -> Result<(), ZipError> {
+        if alignment & alignment.wrapping_sub(1) != 0 {
+            return Err(ZipError::InvalidParameter);
+        }
+        self.alignment = alignment;
+        Ok(())
+    }
+
+    /// Always write the ZIP64 end of central directory records, like miniz's
+    /// `MZ_ZIP_FLAG_WRITE_ZIP64`.
+    ///
+    /// Without this they are only written when needed.
+    pub fn set_zip64(&mut self, zip64: bool) {
+        self.zip64 = zip64;
+    }
+
+    /// Whether the archive uses ZIP64, because it was requested with `set_zip64` or an entry
+    /// needed it so far.
+    pub fn is_zip64(&self) -> bool {
+        self.zip64
+    }
+
+    /// Add an entry with the contents of `data`.
+    ///
+    /// Names ending in a `/` are added as directories, which can't have any data.
+    pub fn add_entry(
+        &mut self,
+        name: &[u8],
+        data: &[u8],
+        options: &EntryOptions,
+    ) -> Result<(), ZipError> {
+        let mut entry = self.new_entry(name, options, data.len() as u64)?;
+        let crc32 = Crc32::checksum_of(data);
+        let level = if data.len() < MIN_COMPRESS_SIZE {
+            0
+        } else {
+            options.level
+        };
+
+        let size = data.len() as u64;
+        if level == 0 {
+            entry.crc32 = crc32;
+            entry.compressed_size = size;
+            entry.uncompressed_size = size;
+            self.write_entry(entry, options, size >= MAX_U32, |writer, pos| {
+                write_counted(writer, pos, data)?;
+                Ok((crc32, size))
+            })
+        } else {
+            set_deflated(&mut entry);
+            let zip64 = options.large_file || size >= ZIP64_THRESHOLD;
+            self.write_entry(entry, options, zip64, |writer, pos| {
+                write_data(writer, pos, level, data, &mut io::empty())
+            })
+        }
+    }
+
+    /// Add an entry with data that has already been compressed with deflate.
+    ///
+    /// `crc32` and `uncompressed_size` are those of the uncompressed data, and are stored
+    /// without being checked.
+    pub fn add_compressed_entry(
+        &mut self,
+        name: &[u8],
+        compressed: &[u8],
+        uncompressed_size: u64,
+        crc32: u32,
+        options: &EntryOptions,
+    ) -> Result<(), ZipError> {
+        let mut entry = self.new_entry(name, options, uncompressed_size)?;
+        entry.method = CompressionMethod::Deflated as u16;
+        entry.version_needed = VERSION_DEFLATE;
+        entry.crc32 = crc32;
+        entry.compressed_size = compressed.len() as u64;
+        entry.uncompressed_size = uncompressed_size;
+        let zip64 = cmp::max(compressed.len() as u64, uncompressed_size) >= MAX_U32;
+        self.write_entry(entry, options, zip64, |writer, pos| {
+            write_counted(writer, pos, compressed)?;
+            Ok((crc32, uncompressed_size))
+        })
+    }
+
+    /// Add an entry with the data read from `reader` until the end.
+    ///
+    /// Returns the size of the data.
+    pub fn add_entry_from_reader<R: Read>(
+        &mut self,
+        name: &[u8],
+        reader: &mut R,
+        options: &EntryOptions,
+    ) -> Result<u64, ZipError> {
+        // Small inputs are read completely, so the sizes can be stored in the local header.
+        let mut buf = vec![0; IO_BUF_SIZE];
+        let n = read_full(reader, &mut buf)?;
+        if n < buf.len() {
+            self.add_entry(name, &buf[..n], options)?;
+            return Ok(n as u64);
+        }
+
+        let mut entry = self.new_entry(name, options, n as u64)?;
+        entry.flags |= FLAG_DATA_DESCRIPTOR;
+        entry.version_needed = VERSION_DEFLATE;
+        if options.level > 0 {
+            set_deflated(&mut entry);
+        }
+        let zip64 = options.large_file || self.streaming;
+        self.write_entry(entry, options, zip64, |writer, pos| {
+            write_data(writer, pos, options.level, &buf, reader)
+        })?;
+        Ok(self.entries[self.entries.len() - 1].uncompressed_size)
+    }
+
+    /// Add a symbolic link to `target`, stored uncompressed with the file type from
+    /// `options.unix_mode` replaced by that of a symbolic link.
+    ///
+    /// If `options.unix_mode` isn't set, everyone gets all permissions, which is what most
+    /// systems do for links.
+    pub fn add_symlink(
+        &mut self,
+        name: &[u8],
+        target: &[u8],
+        options: &EntryOptions,
+    ) -> Result<(), ZipError> {
+        if name.last() == Some(&b'/') {
+            return Err(ZipError::InvalidParameter);
+        }
+        let mut options = options.clone();
+        options.level = 0;
+        options.unix_mode = Some(options.unix_mode.unwrap_or(0o777) & 0o7777 | S_IFLNK);
+        self.add_entry(name, target, &options)
+    }
+
+    /// Copy entry `index` of `archive` without decompressing and compressing it again, like
+    /// `mz_zip_writer_add_from_zip_reader`.
+    ///
+    /// The compressed data, CRC-32, sizes, flags and extra fields are copied as they are. If
+    /// `verify` is set, the data is decompressed first, without keeping it, to check its CRC-32
+    /// and size, and nothing is written if that fails.
+    pub fn add_entry_from_archive<R: Read + Seek>(
+        &mut self,
+        archive: &mut ZipArchive<R>,
+        index: usize,
+        verify: bool,
+    ) -> Result<(), ZipError> {
+        let name = archive
+            .entry(index)
+            .ok_or(ZipError::InvalidParameter)?
+            .name
+            .clone();
+        if verify {
+            let mut decoder = archive.decoder(index)?;
+            decoder.read_to_callback(archive.get_mut(), |_| true)?;
+        }
+        self.copy_entry(archive, index, &name)
+    }
+
+    /// Copy entry `index` of `archive` without decompressing it, naming it `name`.
+    ///
+    /// The compressed data, CRC-32, sizes, flags, attributes and extra fields are kept as they
+    /// are, so this works for entries that can't be extracted too.
+    pub(crate) fn copy_entry<R: Read + Seek>(
+        &mut self,
+        archive: &mut ZipArchive<R>,
+        index: usize,
+        name: &[u8],
+    ) -> Result<(), ZipError> {
+        let source = archive
+            .entry(index)
+            .ok_or(ZipError::InvalidParameter)?
+            .clone();
+        let data_offset = archive.data_offset(index)?;
+        let mut header = vec![0; (data_offset - source.header_offset) as usize];
+        read_exact_at(archive.get_mut(), source.header_offset, &mut header)?;
+        let (local, _) = spec::parse_local_header(&header)?;
+
+        // The ZIP64 extra fields are added again if they are still needed.
+        let options = EntryOptions {
+            local_extra_field: spec::remove_extra_field(
+                &local.extra_field,
+                spec::ZIP64_EXTRA_FIELD_ID,
+            ),
+            central_extra_field: spec::remove_extra_field(
+                &source.extra_field,
+                spec::ZIP64_EXTRA_FIELD_ID,
+            ),
+            ..EntryOptions::default()
+        };
+        let mut entry = self.new_entry(name, &options, source.uncompressed_size)?;
+        entry.comment = source.comment.clone();
+        entry.version_made_by = source.version_made_by;
+        entry.version_needed = source.version_needed;
+        entry.flags = source.flags;
+        entry.method = source.method;
+        entry.last_modified = source.last_modified;
+        entry.crc32 = source.crc32;
+        entry.compressed_size = source.compressed_size;
+        entry.uncompressed_size = source.uncompressed_size;
+        entry.internal_attributes = source.internal_attributes;
+        entry.external_attributes = source.external_attributes;
+
+        let zip64 = cmp::max(source.compressed_size, source.uncompressed_size) >= MAX_U32;
+        let mut decoder = archive.raw_decoder(index)?;
+        // The sizes are known, so even in streaming mode the copy only gets a data descriptor
+        // if the original has one. Adding one would also change what the encryption header of
+        // an encrypted entry is checked against.
+        self.write_entry_as_is(entry, &options, zip64, |writer, pos| {
+            decoder.read_to_callback(archive.get_mut(), |data| {
+                write_counted(writer, pos, data).is_ok()
+            })?;
+            Ok((source.crc32, source.uncompressed_size))
+        })
+        .map_err(|error| match error {
+            ZipError::WriteCallbackFailed => ZipError::FileWriteFailed,
+            error => error,
+        })
+    }
+
+    /// Start an entry whose data is written piece by piece with the returned `EntryWriter`.
+    ///
+    /// The data is compressed as it is written and followed by a data descriptor. Directories
+    /// have no data and have to be added with `add_entry`.
+    pub fn start_entry(
+        &mut self,
+        name: &[u8],
+        options: &EntryOptions,
+    ) -> Result<EntryWriter<'_, W>, ZipError> {
+        if name.last() == Some(&b'/') {
+            return Err(ZipError::InvalidParameter);
+        }
+        let mut entry = self.new_entry(name, options, 0)?;
+        entry.flags |= FLAG_DATA_DESCRIPTOR;
+        entry.version_needed = VERSION_DEFLATE;
+        if options.level > 0 {
+            set_deflated(&mut entry);
+        }
+        let zip64 = options.large_file || self.streaming;
+        let data_start = self.start_entry_data(&mut entry, options, zip64)?;
+        Ok(EntryWriter {
+            encoder: DataEncoder::new(options.level),
+            zip: self,
+            entry: Some(entry),
+            zip64,
+            data_start,
+        })
+    }
+
+    /// Write the central directory, finishing the archive.
+    ///
+    /// No more entries can be added afterwards.
+    pub fn finish(&mut self) -> Result<(), ZipError> {
+        if self.finished {
+            return Err(ZipError::InvalidParameter);
+        }
+        let central_dir_offset = self.pos;
+        let mut central_dir = Vec::new();
+        for entry in &self.entries {
+            central_dir.extend_from_slice(&spec::central_header(entry));
+        }
+        // The same limit as miniz, as the reader keeps the central directory in memory.
+        let central_dir_size = central_dir.len() as u64;
+        if central_dir_size > MAX_U32 {
+            return Err(ZipError::UnsupportedCdirSize);
+        }
+        let num_entries = self.entries.len();
+        if num_entries >= MAX_ENTRIES
+            || central_dir_size >= MAX_U32
+            || central_dir_offset >= MAX_U32
+        {
+            self.zip64 = true;
+        }
+
+        if self.zip64 {
+            let record = Zip64EndOfCentralDir {
+                disk_number: 0,
+                central_dir_disk: 0,
+                entries_on_disk: num_entries as u64,
+                entries: num_entries as u64,
+                central_dir_size,
+                central_dir_offset,
+            };
+            let locator = Zip64Locator {
+                end_of_central_dir_offset: central_dir_offset + central_dir_size,
+                disks: 1,
+            };
+            central_dir.extend_from_slice(&record.to_bytes());
+            central_dir.extend_from_slice(&locator.to_bytes());
+        }
+        let eocd = EndOfCentralDir {
+            disk_number: 0,
+            central_dir_disk: 0,
+            entries_on_disk: cmp::min(num_entries, MAX_ENTRIES) as u16,
+            entries: cmp::min(num_entries, MAX_ENTRIES) as u16,
+            central_dir_size: cmp::min(central_dir_size, MAX_U32) as u32,
+            central_dir_offset: cmp::min(central_dir_offset, MAX_U32) as u32,
+            comment: self.comment.clone(),
+        };
+        write_counted(&mut self.writer, &mut self.pos, &central_dir)?;
+        write_counted(&mut self.writer, &mut self.pos, &eocd.to_bytes())?;
+        self.writer.flush().map_err(|_| ZipError::FileWriteFailed)?;
+        self.central_dir_offset = central_dir_offset;
+        self.finished = true;
+        Ok(())
+    }
+
+    /// The entries added so far, including those of an archive being appended to.
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// The number of entries in the archive.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the archive has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The size of the archive written so far.
+    pub fn archive_size(&self) -> u64 {
+        self.pos
+    }
+
+    /// The size the central directory has or will have with the current entries, including the
+    /// ZIP64 extra fields of entries whose sizes or offset don't fit in 32 bits.
+    pub fn central_dir_size(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|entry| spec::central_header_size(entry) as u64)
+            .sum()
+    }
+
+    /// The offset of the central directory, once the archive is finished.
+    pub fn central_dir_offset(&self) -> Option<u64> {
+        if self.finished {
+            Some(self.central_dir_offset)
+        } else {
+            None
+        }
+    }
+
+    /// Whether `finish` has been called successfully.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Get a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Get a mutable reference to the underlying writer.
+    ///
+    /// Writing to it directly will corrupt the archive.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Return the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Check the name and options and fill in the fields of a new entry that don't depend on
+    /// the data.
+    fn new_entry(
+        &self,
+        name: &[u8],
+        options: &EntryOptions,
+        size: u64,
+    ) -> Result<ZipEntry, ZipError> {
+        if self.finished || options.level > 10 {
+            return Err(ZipError::InvalidParameter);
+        }
+        if self.entries.len() as u64 >= MAX_ZIP64_ENTRIES {
+            return Err(ZipError::TooManyFiles);
+        }
+        // The same checks as miniz: no absolute paths, drive letters or DOS path separators.
+        if name.len() > MAX_FIELD_SIZE
+            || name.first() == Some(&b'/')
+            || name.iter().any(|&c| c == b'\\' || c == b':')
+        {
+            return Err(ZipError::InvalidFilename);
+        }
+        // Leave room for the ZIP64 extra field.
+        let max_extra_size = MAX_FIELD_SIZE - spec::ZIP64_EXTRA_FIELD_MAX_SIZE;
+        if options.comment.len() > MAX_FIELD_SIZE
+            || options.local_extra_field.len() > max_extra_size
+            || options.central_extra_field.len() > max_extra_size
+        {
+            return Err(ZipError::InvalidParameter);
+        }
+
+        let mut external_attributes = options.external_attributes;
+        let mut version_made_by = VERSION_DEFLATE;
+        let mut version_needed = VERSION_DEFAULT;
+        if let Some(mode) = options.unix_mode {
+            external_attributes = external_attributes & 0xFFFF | mode << 16;
+            version_made_by |= u16::from(HOST_UNIX) << 8;
+        }
+        if name.last() == Some(&b'/') {
+            if size != 0 {
+                return Err(ZipError::InvalidParameter);
+            }
+            external_attributes |= DOS_DIRECTORY_ATTRIBUTE;
+            version_needed = VERSION_DEFLATE;
+        }
+        // Names that aren't UTF-8 are kept as they are, but can't be marked as UTF-8.
+        let utf8 = options.utf8
+            && str::from_utf8(name).is_ok()
+            && str::from_utf8(&options.comment).is_ok();
+
+        Ok(ZipEntry {
+            name: name.to_vec(),
+            comment: options.comment.clone(),
+            extra_field: options.central_extra_field.clone(),
+            version_made_by,
+            version_needed,
+            flags: if utf8 { FLAG_UTF8 } else { 0 },
+            method: CompressionMethod::Stored as u16,
+            last_modified: options.last_modified,
+            crc32: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            internal_attributes: 0,
+            external_attributes,
+            header_offset: 0,
+        })
+    }
+
+    /// Write the local header of `entry`, the data and the data descriptor if needed, and
+    /// add the entry to the central directory.
+    ///
+    /// With `zip64` the local header gets a ZIP64 extra field and the data descriptor 64-bit
+    /// sizes. `write_data` writes the data and returns its CRC-32 and uncompressed size.
+    fn write_entry<F>(
+        &mut self,
+        mut entry: ZipEntry,
+        options: &EntryOptions,
+        zip64: bool,
+        write_data: F,
+    ) -> Result<(), ZipError>
+    where
+        F: FnOnce(&mut W, &mut u64) -> Result<(u32, u64), ZipError>,
+    {
+        if self.streaming && entry.name.last() != Some(&b'/') {
+            entry.flags |= FLAG_DATA_DESCRIPTOR;
+            entry.version_needed = cmp::max(entry.version_needed, VERSION_DEFLATE);
+        }
+        self.write_entry_as_is(entry, options, zip64, write_data)
+    }
+
+    /// Like `write_entry`, but without adding a data descriptor in streaming mode.
+    fn write_entry_as_is<F>(
+        &mut self,
+        mut entry: ZipEntry,
+        options: &EntryOptions,
+        zip64: bool,
+        write_data: F,
+    ) -> Result<(), ZipError>
+    where
+        F: FnOnce(&mut W, &mut u64) -> Result<(u32, u64), ZipError>,
+    {
+        let data_start = self.start_entry_data(&mut entry, options, zip64)?;
+        let (crc32, uncompressed_size) = write_data(&mut self.writer, &mut self.pos)?;
+        self.finish_entry_data(entry, zip64, data_start, crc32, uncompressed_size)
+    }
+
+    /// Write the alignment padding and local header of `entry`, returning the offset of the
+    /// data.
+    fn start_entry_data(
+        &mut self,
+        entry: &mut ZipEntry,
+        options: &EntryOptions,
+        zip64: bool,
+    ) -> Result<u64, ZipError> {
+        if self.alignment != 0 {
+            let padding = (self.alignment - self.pos % self.alignment) % self.alignment;
+            write_counted(&mut self.writer, &mut self.pos, &vec![0; padding as usize])?;
+        }
+        entry.header_offset = self.pos;
+        if zip64 {
+            entry.version_needed = VERSION_ZIP64;
+        }
+        let header = spec::local_header(entry, &options.local_extra_field, zip64);
+        write_counted(&mut self.writer, &mut self.pos, &header)?;
+        Ok(self.pos)
+    }
+
+    /// Write the data descriptor of `entry` if it has one, now that the data starting at
+    /// `data_start` has been written, and add the entry to the central directory.
+    fn finish_entry_data(
+        &mut self,
+        mut entry: ZipEntry,
+        zip64: bool,
+        data_start: u64,
+        crc32: u32,
+        uncompressed_size: u64,
+    ) -> Result<(), ZipError> {
+        let compressed_size = self.pos - data_start;
+        if entry.flags & FLAG_DATA_DESCRIPTOR != 0 {
+            let large = compressed_size >= MAX_U32 || uncompressed_size >= MAX_U32;
+            // Without a ZIP64 extra field in the local header, readers expect 32-bit sizes.
+            if large && !zip64 {
+                return Err(ZipError::FileTooLarge);
+            }
+            entry.crc32 = crc32;
+            entry.compressed_size = compressed_size;
+            entry.uncompressed_size = uncompressed_size;
+            write_counted(
+                &mut self.writer,
+                &mut self.pos,
+                &spec::data_descriptor(&entry, zip64),
+            )?;
+        }
+        debug_assert_eq!(entry.compressed_size, compressed_size);
+
+        // Sizes and offsets that don't fit go in the ZIP64 extra field of the central directory.
+        if zip64
+            || entry.compressed_size >= MAX_U32
+            || entry.uncompressed_size >= MAX_U32
+            || entry.header_offset >= MAX_U32
+        {
+            entry.version_needed = VERSION_ZIP64;
+            self.zip64 = true;
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+}
+
+impl<W: Read + Write + Seek> ZipWriter<W> {
+    /// Open an existing archive for adding more entries.
+    ///
+    /// The new entries are written over the old central directory, so the archive is damaged
+    /// until `finish` is called.
+    pub fn new_append(archive: ZipArchive<W>) -> Result<ZipWriter<W>, ZipError> {
+        let offset = archive.central_dir_offset();
+        let entries = archive.entries().to_vec();
+        let comment = archive.comment().to_vec();
+        let zip64 = archive.is_zip64();
+        let mut writer = archive.into_inner();
+        writer
+            .seek(SeekFrom::Start(offset))
+            .map_err(|_| ZipError::FileSeekFailed)?;
+        let mut zip = ZipWriter::with_entries(writer, offset, entries);
+        zip.comment = comment;
+        zip.zip64 = zip64;
+        Ok(zip)
+    }
+}
+
+/// Mark the entry as deflated, with the compressed size in a data descriptor.
+fn set_deflated(entry: &mut ZipEntry) {
+    entry.method = CompressionMethod::Deflated as u16;
+    entry.version_needed = VERSION_DEFLATE;
+    entry.flags |= FLAG_DATA_DESCRIPTOR;
+}
+
+/// Write all of `buf`, keeping track of the position even if only part of it is written.
+fn write_counted<W: Write>(writer: &mut W, pos: &mut u64, mut buf: &[u8]) -> Result<(), ZipError> {
+    while !buf.is_empty() {
+        match writer.write(buf) {
+            Ok(0) => return Err(ZipError::FileWriteFailed),
+            Ok(n) => {
+                *pos += n as u64;
+                buf = &buf[n..];
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(_) => return Err(ZipError::FileWriteFailed),
+        }
+    }
+    Ok(())
+}
+
+/// Read until `buf` is full or the end of the input is reached, returning the number of bytes
+/// read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, ZipError> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        match reader.read(&mut buf[pos..]) {
+            Ok(0) => break,
+            Ok(n) => pos += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(_) => return Err(ZipError::FileReadFailed),
+        }
+    }
+    Ok(pos)
+}
+
+/// Write `first` followed by the rest of the data from `reader`, compressed with `level`, or
+/// stored if `level` is 0.
+///
+/// Returns the CRC-32 and size of the uncompressed data.
+fn write_data<W: Write, R: Read>(
+    writer: &mut W,
+    pos: &mut u64,
+    level: u8,
+    first: &[u8],
+    reader: &mut R,
+) -> Result<(u32, u64), ZipError> {
+    let mut encoder = DataEncoder::new(level);
+    let mut buf = Vec::new();
+    let mut input = first;
+    while !input.is_empty() {
+        encoder.write(writer, pos, input)?;
+        if buf.is_empty() {
+            buf = vec![0; IO_BUF_SIZE];
+        }
+        let n = read_full(reader, &mut buf)?;
+        input = &buf[..n];
+    }
+    encoder.finish(writer, pos)
+}
+
+/// Compresses the data of an entry as it is written, keeping track of its CRC-32 and size.
+struct DataEncoder {
+    /// Only used for deflated entries.
+    compressor: Option<Box<CompressorOxide>>,
+    crc: Crc32,
+    size: u64,
+}
+
+impl DataEncoder {
+    /// Create an encoder compressing with `level`, or storing the data if `level` is 0.
+    fn new(level: u8) -> DataEncoder {
+        let compressor = if level > 0 {
+            let flags = create_comp_flags_from_zip_params(level.into(), -15, 0);
+            Some(Box::new(CompressorOxide::new(flags)))
+        } else {
+            None
+        };
+        DataEncoder {
+            compressor,
+            crc: Crc32::new(),
+            size: 0,
+        }
+    }
+
+    /// Compress `input` and write the output that is ready.
+    fn write<W: Write>(
+        &mut self,
+        writer: &mut W,
+        pos: &mut u64,
+        input: &[u8],
+    ) -> Result<(), ZipError> {
+        self.crc.update(input);
+        self.size += input.len() as u64;
+        self.compress(writer, pos, input, TDEFLFlush::None)
+    }
+
+    /// Write the rest of the compressed data, returning the CRC-32 and size of the
+    /// uncompressed data.
+    fn finish<W: Write>(&mut self, writer: &mut W, pos: &mut u64) -> Result<(u32, u64), ZipError> {
+        self.compress(writer, pos, &[], TDEFLFlush::Finish)?;
+        Ok((self.crc.checksum(), self.size))
+    }
+
+    fn compress<W: Write>(
+        &mut self,
+        writer: &mut W,
+        pos: &mut u64,
+        input: &[u8],
+        flush: TDEFLFlush,
+    ) -> Result<(), ZipError> {
+        let compressor = match self.compressor {
+            Some(ref mut compressor) => compressor,
+            None => return write_counted(writer, pos, input),
+        };
+        let mut write_failed = false;
+        let (status, consumed) = compress_to_output(compressor, input, flush, |out| {
+            write_failed = write_counted(writer, pos, out).is_err();
+            !write_failed
+        });
+        // All the input is consumed when the output goes to a callback.
+        debug_assert!(status != TDEFLStatus::Okay || consumed == input.len());
+        match status {
+            TDEFLStatus::Okay | TDEFLStatus::Done => Ok(()),
+            _ if write_failed => Err(ZipError::FileWriteFailed),
+            _ => Err(ZipError::CompressionFailed),
+        }
+    }
+}
+
+/// Writer for the data of an entry, returned by `ZipWriter::start_entry`.
+///
+/// The entry is added to the archive by `finish`, or when the writer is dropped, ignoring any
+/// errors.
+pub struct EntryWriter<'a, W: Write> {
+    zip: &'a mut ZipWriter<W>,
+    /// Taken once the entry is finished.
+    entry: Option<ZipEntry>,
+    zip64: bool,
+    data_start: u64,
+    encoder: DataEncoder,
+}
+
+impl<'a, W: Write> EntryWriter<'a, W> {
+    /// Write the rest of the data and the data descriptor, and add the entry to the archive.
+    ///
+    /// Returns the size of the uncompressed data.
+    pub fn finish(mut self) -> Result<u64, ZipError> {
+        self.finish_entry()
+    }
+
+    fn finish_entry(&mut self) -> Result<u64, ZipError> {
+        let entry = match self.entry.take() {
+            Some(entry) => entry,
+            None => return Err(ZipError::InvalidParameter),
+        };
+        let zip = &mut *self.zip;
+        let (crc32, size) = self.encoder.finish(&mut zip.writer, &mut zip.pos)?;
+        zip.finish_entry_data(entry, self.zip64, self.data_start, crc32, size)?;
+        Ok(size)
+    }
+}
+
+impl<'a, W: Write> Write for EntryWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.entry.is_none() {
+            return Err(ZipError::InvalidParameter.into());
+        }
+        let zip = &mut *self.zip;
+        self.encoder.write(&mut zip.writer, &mut zip.pos, buf)?;
+        Ok(buf.len())
+    }
+
+    /// Flush the underlying writer. Data held by the compressor is only written when the entry
+    /// is finished.
+    fn flush(&mut self) -> io::Result<()> {
+        self.zip.writer.flush()
+    }
+}
+
+impl<'a, W: Write> Drop for EntryWriter<'a, W> {
+    fn drop(&mut self) {
+        if self.entry.is_some() {
+            let _ = self.finish_entry();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp;
+    use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+    use super::super::test_util::text;
+    use super::super::{DateTime, ZipArchive, ZipError, ZipStreamReader};
+    use super::{EntryOptions, ZipWriter};
+
+    /// A stream that reads as zeros up to `start`, where the stored data begins, to test
+    /// archives too large to keep in memory.
+    struct Sparse {
+        start: u64,
+        data: Vec<u8>,
+        pos: u64,
+    }
+
+    impl Sparse {
+        fn new(start: u64) -> Sparse {
+            Sparse {
+                start,
+                data: Vec::new(),
+                pos: start,
+            }
+        }
+    }
+
+    impl Write for Sparse {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let offset = (self.pos - self.start) as usize;
+            let end = offset + buf.len();
+            if self.data.len() < end {
+                self.data.resize(end, 0);
+            }
+            self.data[offset..end].copy_from_slice(buf);
+            self.pos += buf.len() as u64;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for Sparse {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let end = self.start + self.data.len() as u64;
+            let n = cmp::min(buf.len() as u64, end.saturating_sub(self.pos)) as usize;
+            for (i, b) in buf[..n].iter_mut().enumerate() {
+                let pos = self.pos + i as u64;
+                *b = if pos < self.start {
+                    0
+                } else {
+                    self.data[(pos - self.start) as usize]
+                };
+            }
+            self.pos += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Seek for Sparse {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            let end = self.start + self.data.len() as u64;
+            self.pos = match pos {
+                SeekFrom::Start(pos) => pos,
+                SeekFrom::End(offset) => (end as i64 + offset) as u64,
+                SeekFrom::Current(offset) => (self.pos as i64 + offset) as u64,
+            };
+            Ok(self.pos)
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let large = text(300_000);
+        let last_modified = DateTime::from_dos(0x7d1c, 0x354b);
+        let mut options = EntryOptions::new(9);
+        options.last_modified = last_modified;
+        options.comment = b"comment".to_vec();
+
+        let mut zip = ZipWriter::with_offset(Cursor::new(Vec::new()), 0);
+        zip.set_comment(b"archive comment").unwrap();
+        zip.add_entry(b"dir/", &[], &options).unwrap();
+        zip.add_entry(b"dir/small.txt", b"abc", &options).unwrap();
+        zip.add_entry(b"dir/text.txt", &large[..10_000], &options)
+            .unwrap();
+        zip.add_entry(b"stored.txt", &large[..1000], &EntryOptions::new(0))
+            .unwrap();
+        assert_eq!(
+            zip.add_entry_from_reader(b"large.txt", &mut &large[..], &options),
+            Ok(large.len() as u64)
+        );
+        assert_eq!(
+            zip.add_entry_from_reader(b"large_stored.txt", &mut &large[..], &EntryOptions::new(0)),
+            Ok(large.len() as u64)
+        );
+        let compressed = crate::deflate::compress_to_vec(b"compressed data", 6);
+        let crc = crate::checksum::Crc32::checksum_of(b"compressed data");
+        zip.add_compressed_entry(b"compressed.txt", &compressed, 15, crc, &options)
+            .unwrap();
+        zip.finish().unwrap();
+        let size = zip.archive_size();
+        let data = zip.into_inner().into_inner();
+        assert_eq!(data.len() as u64, size);
+
+        let mut archive = ZipArchive::new(Cursor::new(data)).unwrap();
+        assert_eq!(archive.len(), 7);
+        assert_eq!(archive.comment(), b"archive comment");
+        assert!(archive.entry(0).unwrap().is_dir());
+        let expected: [&[u8]; 7] = [
+            b"",
+            b"abc",
+            &large[..10_000],
+            &large[..1000],
+            &large,
+            &large,
+            b"compressed data",
+        ];
+        for (index, data) in expected.iter().enumerate() {
+            let entry = archive.entry(index).unwrap().clone();
+            assert!(archive.extract_to_vec(index).unwrap() == *data);
+            if index != 3 && index != 5 {
+                assert_eq!(entry.comment, b"comment");
+                assert_eq!(entry.last_modified, last_modified);
+            }
+        }
+        assert_eq!(archive.entry(1).unwrap().method, 0);
+        assert_eq!(archive.entry(2).unwrap().method, 8);
+        assert_eq!(archive.entry(2).unwrap().flags & 8, 8);
+        assert_eq!(archive.entry(3).unwrap().flags & 8, 0);
+        assert!(archive.entry(4).unwrap().compressed_size < 10_000);
+    }
+
+    #[test]
+    fn alignment_and_offset() {
+        let mut out = Vec::new();
+        out.write_all(b"stub").unwrap();
+        let mut zip = ZipWriter::with_offset(out, 4);
+        zip.set_file_offset_alignment(64).unwrap();
+        assert_eq!(
+            zip.set_file_offset_alignment(3),
+            Err(ZipError::InvalidParameter)
+        );
+        for name in &[&b"a"[..], b"b", b"c"] {
+            zip.add_entry(name, &text(100), &EntryOptions::default())
+                .unwrap();
+        }
+        zip.finish().unwrap();
+        let data = zip.into_inner();
+
+        let archive = ZipArchive::new(Cursor::new(&data[..])).unwrap();
+        for entry in archive.entries() {
+            assert_eq!(entry.header_offset % 64, 0);
+            assert_eq!(&data[entry.header_offset as usize..][..4], b"PK\x03\x04");
+        }
+    }
+
+    #[test]
+    fn append() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.set_comment(b"kept").unwrap();
+        zip.add_entry(b"first", &text(5000), &EntryOptions::default())
+            .unwrap();
+        zip.finish().unwrap();
+
+        let archive = ZipArchive::new(zip.into_inner()).unwrap();
+        let mut zip = ZipWriter::new_append(archive).unwrap();
+        zip.add_entry(b"second", &text(7000), &EntryOptions::default())
+            .unwrap();
+        zip.finish().unwrap();
+
+        let mut archive = ZipArchive::new(zip.into_inner()).unwrap();
+        assert_eq!(archive.len(), 2);
+        assert_eq!(archive.comment(), b"kept");
+        assert!(archive.extract_to_vec(0).unwrap() == text(5000));
+        assert!(archive.extract_to_vec(1).unwrap() == text(7000));
+    }
+
+    #[test]
+    fn copy_from_archive() {
+        let mut source = ZipWriter::new(Cursor::new(Vec::new()));
+        let mut options = EntryOptions::new(9);
+        options.comment = b"entry comment".to_vec();
+        options.central_extra_field = b"\x34\x12\x02\x00ab".to_vec();
+        source.add_entry(b"dir/", &[], &options).unwrap();
+        source
+            .add_entry(b"deflated", &text(20_000), &options)
+            .unwrap();
+        source
+            .add_entry(b"stored", b"stored data", &EntryOptions::new(0))
+            .unwrap();
+        source.finish().unwrap();
+        let data = source.into_inner().into_inner();
+        let mut source = ZipArchive::new(Cursor::new(&data[..])).unwrap();
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.add_entry(b"first", b"first", &EntryOptions::default())
+            .unwrap();
+        for index in 0..source.len() {
+            zip.add_entry_from_archive(&mut source, index, true)
+                .unwrap();
+        }
+        zip.finish().unwrap();
+        let mut archive = ZipArchive::new(zip.into_inner()).unwrap();
+        assert_eq!(archive.len(), 4);
+        for index in 0..source.len() {
+            let (copy, original) = (
+                archive.entry(index + 1).unwrap(),
+                source.entry(index).unwrap(),
+            );
+            assert_eq!(copy.name, original.name);
+            assert_eq!(copy.comment, original.comment);
+            assert_eq!(copy.extra_field, original.extra_field);
+            assert_eq!(copy.method, original.method);
+            assert_eq!(copy.crc32, original.crc32);
+            assert_eq!(copy.compressed_size, original.compressed_size);
+            assert_eq!(copy.uncompressed_size, original.uncompressed_size);
+        }
+        assert!(archive.extract_to_vec(2).unwrap() == text(20_000));
+
+        // Corrupted data is only noticed when verifying.
+        let mut corrupted = data.clone();
+        let offset = source.data_offset(2).unwrap() as usize;
+        corrupted[offset] ^= 1;
+        let mut source = ZipArchive::new(Cursor::new(&corrupted[..])).unwrap();
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        assert_eq!(
+            zip.add_entry_from_archive(&mut source, 2, true),
+            Err(ZipError::CrcCheckFailed)
+        );
+        assert_eq!((zip.len(), zip.archive_size()), (0, 0));
+        assert_eq!(zip.add_entry_from_archive(&mut source, 2, false), Ok(()));
+        assert_eq!(
+            zip.add_entry_from_archive(&mut source, 3, false),
+            Err(ZipError::InvalidParameter)
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let mut zip = ZipWriter::new(Vec::new());
+        let options = EntryOptions::default();
+        for name in &[&b"/abs"[..], b"c:file", b"dir\\file"] {
+            assert_eq!(
+                zip.add_entry(name, b"", &options),
+                Err(ZipError::InvalidFilename)
+            );
+        }
+        assert_eq!(
+            zip.add_entry(b"dir/", b"data", &options),
+            Err(ZipError::InvalidParameter)
+        );
+        assert_eq!(
+            zip.add_entry(b"file", b"data", &EntryOptions::new(11)),
+            Err(ZipError::InvalidParameter)
+        );
+        assert!(zip.is_empty());
+        zip.finish().unwrap();
+        assert_eq!(
+            zip.add_entry(b"file", b"data", &options),
+            Err(ZipError::InvalidParameter)
+        );
+        assert_eq!(zip.finish(), Err(ZipError::InvalidParameter));
+        // An empty archive is just the end of central directory record.
+        assert_eq!(zip.get_ref().len(), 22);
+    }
+
+    #[test]
+    fn zip64_offsets() {
+        let start = 5 << 30;
+        let large = text(100_000);
+        let mut zip = ZipWriter::with_offset(Sparse::new(start), start);
+        zip.add_entry(b"stored", b"stored data", &EntryOptions::new(0))
+            .unwrap();
+        zip.add_entry(b"deflated", &large, &EntryOptions::default())
+            .unwrap();
+        assert_eq!(
+            zip.add_entry_from_reader(b"streamed", &mut &large[..], &EntryOptions::default()),
+            Ok(large.len() as u64)
+        );
+        // A huge entry can't be written, so claim the size of a compressed one.
+        let compressed = crate::deflate::compress_to_vec(b"huge", 6);
+        zip.add_compressed_entry(b"huge", &compressed, 6 << 30, 0, &EntryOptions::default())
+            .unwrap();
+        assert!(zip.is_zip64());
+        // Every entry needs a ZIP64 extra field for its offset.
+        let central_dir_size = zip.central_dir_size();
+        zip.finish().unwrap();
+
+        let archive = ZipArchive::new(zip.into_inner()).unwrap();
+        assert!(archive.is_zip64());
+        assert!(archive.central_dir_offset() > start);
+        assert_eq!(archive.central_dir_size(), central_dir_size);
+        assert_eq!(archive.entry(3).unwrap().uncompressed_size, 6 << 30);
+        let mut zip = ZipWriter::new_append(archive).unwrap();
+        zip.add_entry(b"appended", b"appended data", &EntryOptions::default())
+            .unwrap();
+        zip.finish().unwrap();
+
+        let mut archive = ZipArchive::new(zip.into_inner()).unwrap();
+        assert_eq!(archive.len(), 5);
+        let expected: [&[u8]; 3] = [b"stored data", &large, &large];
+        for (index, data) in expected.iter().enumerate() {
+            assert!(archive.entry(index).unwrap().header_offset >= start);
+            assert_eq!(archive.entry(index).unwrap().version_needed, 45);
+            assert!(archive.extract_to_vec(index).unwrap() == *data);
+        }
+        assert_eq!(archive.extract_to_vec(4).unwrap(), b"appended data");
+    }
+
+    #[test]
+    fn zip64_entries() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let num_entries = 70_000;
+        for i in 0..num_entries {
+            zip.add_entry(format!("{}", i).as_bytes(), b"", &EntryOptions::default())
+                .unwrap();
+        }
+        assert!(!zip.is_zip64());
+        zip.finish().unwrap();
+        assert!(zip.is_zip64());
+
+        let data = zip.into_inner().into_inner();
+        // The entry count in the end of central directory record is saturated.
+        assert_eq!(&data[data.len() - 12..data.len() - 10], b"\xff\xff");
+        let archive = ZipArchive::new(Cursor::new(data)).unwrap();
+        assert!(archive.is_zip64());
+        assert_eq!(archive.len(), num_entries);
+        assert_eq!(archive.entry(num_entries - 1).unwrap().name, b"69999");
+    }
+
+    #[test]
+    fn zip64_data_descriptor() {
+        let large = text(100_000);
+        let mut options = EntryOptions::default();
+        options.large_file = true;
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.add_entry_from_reader(b"large", &mut &large[..], &options)
+            .unwrap();
+        zip.finish().unwrap();
+        let data = zip.into_inner().into_inner();
+
+        // Version needed and sizes in the local header, then the ZIP64 extra field after the name.
+        assert_eq!(&data[4..6], &[45, 0]);
+        assert_eq!(&data[18..26], &[0xff; 8]);
+        assert_eq!(&data[35..39], &[1, 0, 16, 0]);
+        let mut archive = ZipArchive::new(Cursor::new(&data[..])).unwrap();
+        assert!(archive.is_zip64());
+        let entry = archive.entry(0).unwrap().clone();
+        let descriptor = (archive.data_offset(0).unwrap() + entry.compressed_size) as usize;
+        assert_eq!(&data[descriptor..descriptor + 4], b"PK\x07\x08");
+        assert_eq!(
+            &data[descriptor + 8..descriptor + 24],
+            &[
+                &entry.compressed_size.to_le_bytes()[..],
+                &(large.len() as u64).to_le_bytes()[..]
+            ]
+            .concat()[..]
+        );
+        assert!(archive.extract_to_vec(0).unwrap() == large);
+
+        // The ZIP64 records can be forced for small archives.
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.set_zip64(true);
+        zip.add_entry(b"small", b"small", &EntryOptions::default())
+            .unwrap();
+        zip.finish().unwrap();
+        let archive = ZipArchive::new(zip.into_inner()).unwrap();
+        assert!(archive.is_zip64());
+        assert_eq!(archive.entry(0).unwrap().version_needed, 20);
+    }
+
+    #[test]
+    fn streaming() {
+        let large = text(200_000);
+        let options = EntryOptions::default();
+
+        // A `Vec` can't seek, so nothing written can be patched afterwards.
+        let mut zip = ZipWriter::new_streaming(Vec::new());
+        zip.add_entry(b"dir/", &[], &options).unwrap();
+        zip.add_entry(b"dir/stored.txt", b"stored", &EntryOptions::new(0))
+            .unwrap();
+        zip.add_entry_from_reader(b"dir/read.txt", &mut &large[..], &options)
+            .unwrap();
+        let mut entry = zip.start_entry(b"written.txt", &options).unwrap();
+        for chunk in large.chunks(7000) {
+            entry.write_all(chunk).unwrap();
+        }
+        assert_eq!(entry.finish(), Ok(large.len() as u64));
+        {
+            // Dropping the writer finishes the entry.
+            let mut entry = zip
+                .start_entry(b"dropped.txt", &EntryOptions::new(0))
+                .unwrap();
+            entry.write_all(b"dropped").unwrap();
+        }
+        assert_eq!(
+            zip.start_entry(b"dir2/", &options).err(),
+            Some(ZipError::InvalidParameter)
+        );
+        zip.finish().unwrap();
+        let data = zip.into_inner();
+
+        let mut archive = ZipArchive::new(Cursor::new(&data[..])).unwrap();
+        assert_eq!(archive.len(), 5);
+        assert!(archive.is_zip64());
+        let expected: [&[u8]; 5] = [b"", b"stored", &large, &large, b"dropped"];
+        for (index, contents) in expected.iter().enumerate() {
+            let entry = archive.entry(index).unwrap().clone();
+            assert_eq!(entry.flags & 8 != 0, index != 0);
+            assert!(archive.extract_to_vec(index).unwrap() == *contents);
+            if index == 0 {
+                continue;
+            }
+            // The local header has no CRC-32 or sizes, and a data descriptor follows the data.
+            let header = entry.header_offset as usize;
+            assert_eq!(&data[header + 6..header + 8], &[8, 8]);
+            assert_eq!(&data[header + 14..header + 18], &[0; 4]);
+            let descriptor = (archive.data_offset(index).unwrap() + entry.compressed_size) as usize;
+            assert_eq!(&data[descriptor..descriptor + 4], b"PK\x07\x08");
+            assert_eq!(
+                &data[descriptor + 4..descriptor + 8],
+                &entry.crc32.to_le_bytes()
+            );
+
+            // Only the stored entry has its size known in advance. The others get a ZIP64
+            // extra field after the name and 64-bit sizes in the data descriptor.
+            let name_end = header + 30 + entry.name.len();
+            let sizes = if index == 1 {
+                assert_eq!(&data[header + 4..header + 6], &[20, 0]);
+                assert_eq!(&data[header + 18..header + 26], &[0; 8]);
+                [&(6u32).to_le_bytes()[..], &(6u32).to_le_bytes()[..]].concat()
+            } else {
+                assert_eq!(&data[header + 4..header + 6], &[45, 0]);
+                assert_eq!(&data[header + 18..header + 26], &[0xff; 8]);
+                assert_eq!(&data[name_end..name_end + 4], &[1, 0, 16, 0]);
+                [
+                    &entry.compressed_size.to_le_bytes()[..],
+                    &(contents.len() as u64).to_le_bytes()[..],
+                ]
+                .concat()
+            };
+            assert_eq!(
+                &data[descriptor + 8..descriptor + 8 + sizes.len()],
+                &sizes[..]
+            );
+        }
+
+        // The stream reader goes by the local headers alone.
+        let mut reader = ZipStreamReader::new(&data[..]);
+        for contents in expected.iter() {
+            let mut entry = reader.next_entry().unwrap().unwrap();
+            let mut buf = Vec::new();
+            entry.read_to_end(&mut buf).unwrap();
+            assert!(buf == *contents);
+        }
+        assert!(reader.next_entry().unwrap().is_none());
+        assert_eq!(archive.entry(1).unwrap().method, 0);
+        assert_eq!(archive.entry(3).unwrap().method, 8);
+    }
+
+    // Compresses and decompresses more than 4 GiB several times, so it is slow without
+    // optimizations.
+    #[test]
+    #[ignore]
+    fn zip64_streamed_sizes() {
+        let size = (4 << 30) + 12345;
+        let zeros = vec![0; 1 << 20];
+        let options = EntryOptions::new(1);
+
+        // Zeros compress to about 4 MiB, so the archive fits in memory.
+        let mut zip = ZipWriter::new_streaming(Vec::new());
+        let mut reader = io::repeat(0).take(size);
+        assert_eq!(
+            zip.add_entry_from_reader(b"read", &mut reader, &options),
+            Ok(size)
+        );
+        let mut entry = zip.start_entry(b"written", &options).unwrap();
+        let mut left = size;
+        while left > 0 {
+            let n = cmp::min(left, zeros.len() as u64) as usize;
+            entry.write_all(&zeros[..n]).unwrap();
+            left -= n as u64;
+        }
+        assert_eq!(entry.finish(), Ok(size));
+        zip.add_entry(b"last", b"last", &options).unwrap();
+        zip.finish().unwrap();
+        let data = zip.into_inner();
+
+        let mut archive = ZipArchive::new(Cursor::new(&data[..])).unwrap();
+        for index in 0..2 {
+            assert_eq!(archive.entry(index).unwrap().uncompressed_size, size);
+            let mut zeros_only = true;
+            let n = archive
+                .extract_to_callback(index, |buf| {
+                    zeros_only &= buf.iter().all(|&b| b == 0);
+                    true
+                })
+                .unwrap();
+            assert_eq!(n, size);
+            assert!(zeros_only);
+        }
+        assert_eq!(archive.extract_to_vec(2).unwrap(), b"last");
+
+        let mut reader = ZipStreamReader::new(&data[..]);
+        for _ in 0..2 {
+            let mut entry = reader.next_entry().unwrap().unwrap();
+            let mut buf = vec![0; 1 << 20];
+            let mut total = 0;
+            loop {
+                let n = entry.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                assert!(buf[..n].iter().all(|&b| b == 0));
+                total += n as u64;
+            }
+            assert_eq!(total, size);
+        }
+        let mut entry = reader.next_entry().unwrap().unwrap();
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"last");
+        assert!(reader.next_entry().unwrap().is_none());
+    }
+}
@@ -80,3 +80,325 @@ fn roundtrip_lvl_1() {
 fn roundtrip_lvl_0() {
     roundtrip(0);
 }
+
+/// Data with symbol frequencies following the fibonacci sequence, which gives a huffman tree
+/// deeper than the maximum code length.
+fn fibonacci_data() -> Vec<u8> {
+    let mut data = Vec::new();
+    let (mut a, mut b) = (1, 1);
+    for symbol in 0..20u8 {
+        data.extend(std::iter::repeat(symbol).take(a));
+        let next = a + b;
+        a = b;
+        b = next;
+    }
+    data
+}
+
+/// Compare the output of the zlib compatible compressor to output from zlib 1.2.13 with the same
+/// parameters.
+#[test]
+fn zlib_compat_golden() {
+    use miniz_oxide::deflate::core::CompressionStrategy;
+    use miniz_oxide::deflate::zlib_compat::{self, ZlibParams};
+
+    let miniz_c = get_test_file_data("../miniz/miniz.c");
+    let fibonacci = fibonacci_data();
+    let strategies = [
+        CompressionStrategy::Default,
+        CompressionStrategy::Filtered,
+        CompressionStrategy::HuffmanOnly,
+        CompressionStrategy::RLE,
+        CompressionStrategy::Fixed,
+    ];
+
+    // (input, level, window bits, memory level, strategy)
+    let vectors = [
+        ("miniz_c", 0, 15, 8, 0),
+        ("miniz_c", 1, 15, 8, 0),
+        ("miniz_c", 2, 15, 8, 0),
+        ("miniz_c", 3, 15, 8, 0),
+        ("miniz_c", 4, 15, 8, 0),
+        ("miniz_c", 5, 15, 8, 0),
+        ("miniz_c", 6, 15, 8, 0),
+        ("miniz_c", 7, 15, 8, 0),
+        ("miniz_c", 8, 15, 8, 0),
+        ("miniz_c", 9, 15, 8, 0),
+        ("miniz_c", 6, 15, 8, 1),
+        ("miniz_c", 6, 15, 8, 2),
+        ("miniz_c", 6, 15, 8, 3),
+        ("miniz_c", 6, 15, 8, 4),
+        ("miniz_c", 1, 9, 8, 0),
+        ("miniz_c", 6, 9, 8, 0),
+        ("miniz_c", 9, 9, 8, 0),
+        ("miniz_c", 6, 8, 8, 0),
+        ("miniz_c", 6, 12, 8, 0),
+        ("miniz_c", 6, -15, 8, 0),
+        ("miniz_c", 1, 15, 1, 0),
+        ("miniz_c", 6, 15, 1, 0),
+        ("miniz_c", 9, 15, 1, 0),
+        ("miniz_c", 6, 15, 9, 0),
+        ("miniz_c", 9, 9, 1, 1),
+        ("miniz_c", 9, 9, 1, 3),
+        ("fibonacci", 6, 15, 9, 2),
+        ("fibonacci", 9, 15, 9, 0),
+    ];
+
+    for &(name, level, window_bits, mem_level, strategy) in &vectors {
+        let input = if name == "miniz_c" {
+            &miniz_c
+        } else {
+            &fibonacci
+        };
+        let params = ZlibParams {
+            level,
+            window_bits,
+            mem_level,
+            strategy: strategies[strategy],
+        };
+        let path = format!(
+            "tests/test_data/zlib/{}_l{}_w{}_m{}_s{}.zlib",
+            name, level, window_bits, mem_level, strategy
+        );
+        let expected = get_test_file_data(&path);
+        let output = zlib_compat::compress_to_vec(input, &params).unwrap();
+        assert!(output == expected, "output differs from {}", path);
+    }
+}
+
+/// Read an archive created by Python's `zipfile` module.
+#[test]
+fn zip_archive() {
+    use miniz_oxide::zip::ZipArchive;
+    use std::io::Cursor;
+
+    let data = get_test_file_data("tests/test_data/archive.zip");
+    let mut archive = ZipArchive::new(Cursor::new(data)).unwrap();
+    assert_eq!(archive.comment(), b"test archive");
+    let names: Vec<_> = archive.entries().iter().map(|e| e.name.clone()).collect();
+    assert_eq!(names, [&b"hello.txt"[..], b"dir/", b"dir/lines.txt"]);
+    assert_eq!(archive.entry(0).unwrap().comment, b"greeting");
+    assert!(archive.entry(1).unwrap().is_dir());
+
+    assert_eq!(archive.extract_to_vec(0).unwrap(), b"Hello, zip!\n");
+    let lines: String = (0..2000).map(|n| format!("line {}\n", n)).collect();
+    let index = archive.index_of(b"dir/lines.txt").unwrap();
+    assert!(archive.extract_to_vec(index).unwrap() == lines.as_bytes());
+}
+
+/// The text in the entries of `legacy.zip`.
+fn legacy_text(lines: usize) -> Vec<u8> {
+    const WORDS: [&str; 13] = [
+        "alpha", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel", "india",
+        "juliet", "kilo", "lima", "mike",
+    ];
+    let text: String = (0..lines)
+        .map(|i| {
+            let (word, other) = (WORDS[i * i * 7 % 13], WORDS[i % 13]);
+            format!("{:05} {} {} {}\n", i, i * i % 99991, word, other)
+        })
+        .collect();
+    text.into_bytes()
+}
+
+/// Read an archive with entries compressed with Shrink, Reduce and Implode. The Shrink and
+/// Implode entries were checked with Info-ZIP's UnZip, which no longer supports Reduce. The
+/// Shrink entry is large enough for the codes to reach 13 bits and for a partial clear.
+#[test]
+fn zip_legacy_methods() {
+    use miniz_oxide::zip::{CompressionMethod, ZipArchive};
+    use std::io::Cursor;
+
+    let data = get_test_file_data("tests/test_data/legacy.zip");
+    let mut archive = ZipArchive::new(Cursor::new(data)).unwrap();
+    let expected = [
+        (&b"shrunk.txt"[..], CompressionMethod::Shrunk, 0, 2000),
+        (b"reduced1.txt", CompressionMethod::Reduced1, 0, 150),
+        (b"reduced2.txt", CompressionMethod::Reduced2, 0, 150),
+        (b"reduced3.txt", CompressionMethod::Reduced3, 0, 150),
+        (b"reduced4.txt", CompressionMethod::Reduced4, 0, 150),
+        // Bit 1 selects the 8K dictionary and bit 2 the literal tree.
+        (b"imploded0.txt", CompressionMethod::Imploded, 0, 200),
+        (b"imploded2.txt", CompressionMethod::Imploded, 2, 200),
+        (b"imploded4.txt", CompressionMethod::Imploded, 4, 200),
+        (b"imploded6.txt", CompressionMethod::Imploded, 6, 200),
+    ];
+    assert_eq!(archive.len(), expected.len());
+    for (index, &(name, method, flags, lines)) in expected.iter().enumerate() {
+        let entry = archive.entry(index).unwrap();
+        assert_eq!(entry.name, name);
+        assert_eq!(entry.method, method as u16);
+        assert_eq!(entry.flags, flags);
+        assert!(entry.is_supported());
+        let text = legacy_text(lines);
+        assert!(archive.extract_to_vec(index).unwrap() == text);
+
+        // Read in small pieces, which stops and resumes the decoders all the time.
+        let mut reader = archive.by_index(index).unwrap();
+        let mut output = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => break,
+                n => output.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert!(output == text);
+    }
+    assert_eq!(archive.validate(&Default::default()), Ok(()));
+}
+
+/// Read an archive with entries encrypted with the traditional PKWARE encryption, checked with
+/// Info-ZIP's UnZip. The deflated entry has a data descriptor, so its encryption header is
+/// checked against the modification time instead of the CRC-32.
+#[test]
+fn zip_encrypted() {
+    use miniz_oxide::zip::{ZipArchive, ZipError};
+    use std::io::Cursor;
+
+    let data = get_test_file_data("tests/test_data/encrypted.zip");
+    let mut archive = ZipArchive::new(Cursor::new(data)).unwrap();
+    let text: String = (0..1000)
+        .map(|n| format!("line {} of the encrypted text\n", n))
+        .collect();
+    let expected = [
+        (&b"stored.txt"[..], &b"Hello, encrypted zip!\n"[..], true),
+        (b"deflated.txt", text.as_bytes(), true),
+        (b"empty", b"", true),
+        (b"plain.txt", b"Not encrypted.\n", false),
+    ];
+    assert_eq!(archive.len(), expected.len());
+
+    for (index, &(name, _, encrypted)) in expected.iter().enumerate() {
+        assert_eq!(archive.entry(index).unwrap().name, name);
+        assert_eq!(archive.entry(index).unwrap().is_encrypted(), encrypted);
+        assert_eq!(archive.can_extract(index), !encrypted);
+        if encrypted {
+            assert_eq!(
+                archive.extract_to_vec(index),
+                Err(ZipError::UnsupportedEncryption)
+            );
+        }
+    }
+    assert_eq!(
+        archive.validate(&Default::default()),
+        Err(ZipError::UnsupportedEncryption)
+    );
+
+    archive.set_password(Some(b"wrong"));
+    for (index, &(_, _, encrypted)) in expected.iter().enumerate() {
+        assert!(archive.can_extract(index));
+        if encrypted {
+            assert_eq!(archive.extract_to_vec(index), Err(ZipError::WrongPassword));
+        }
+    }
+
+    archive.set_password(Some(b"secret"));
+    for (index, &(_, data, _)) in expected.iter().enumerate() {
+        assert!(archive.extract_to_vec(index).unwrap() == data);
+
+        // Read in small pieces, which decrypts the data a piece at a time.
+        let mut reader = archive.by_index(index).unwrap();
+        let mut output = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => break,
+                n => output.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert!(output == data);
+    }
+    assert_eq!(archive.validate(&Default::default()), Ok(()));
+
+    // The raw data is still encrypted, with the encryption header in front.
+    let mut raw = Vec::new();
+    let mut reader = archive.by_index_raw(0).unwrap();
+    assert_eq!(reader.read_to_end(&mut raw).unwrap(), 12 + 22);
+    assert!(!raw.ends_with(b"Hello, encrypted zip!\n"));
+}
+
+/// Copy encrypted entries to an archive written in streaming mode. The copies keep general
+/// purpose bit 3 as it is, as it decides what the encryption header is checked against.
+#[test]
+fn zip_copy_encrypted_streaming() {
+    use miniz_oxide::zip::{ZipArchive, ZipWriter};
+    use std::io::Cursor;
+
+    let data = get_test_file_data("tests/test_data/encrypted.zip");
+    let mut source = ZipArchive::new(Cursor::new(data)).unwrap();
+    let mut zip = ZipWriter::new_streaming(Vec::new());
+    for index in 0..source.len() {
+        zip.add_entry_from_archive(&mut source, index, false)
+            .unwrap();
+    }
+    zip.finish().unwrap();
+
+    let mut archive = ZipArchive::new(Cursor::new(zip.into_inner())).unwrap();
+    archive.set_password(Some(b"secret"));
+    source.set_password(Some(b"secret"));
+    assert_eq!(archive.len(), source.len());
+    for index in 0..source.len() {
+        let (copy, original) = (archive.entry(index).unwrap(), source.entry(index).unwrap());
+        assert_eq!(copy.flags, original.flags);
+        let expected = source.extract_to_vec(index).unwrap();
+        assert!(archive.extract_to_vec(index).unwrap() == expected);
+    }
+    assert_eq!(archive.validate(&Default::default()), Ok(()));
+}
+
+/// Delete and rename entries of an archive file with `edit_archive_file_in_place`.
+#[cfg(feature = "std")]
+#[test]
+fn zip_edit_in_place() {
+    use miniz_oxide::zip::{
+        edit_archive_file_in_place, EntryOptions, ZipArchive, ZipError, ZipWriter,
+    };
+    use std::fs::{self, File};
+
+    let data = get_test_data();
+    let path = std::env::temp_dir().join("miniz_oxide_zip_edit.zip");
+    let mut zip = ZipWriter::new(File::create(&path).unwrap());
+    let options = EntryOptions::default();
+    zip.add_entry(b"data.bin", &data, &options).unwrap();
+    zip.add_entry(b"a.txt", b"a", &options).unwrap();
+    zip.add_entry(b"b.txt", b"b", &options).unwrap();
+    zip.finish().unwrap();
+    drop(zip);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+    }
+
+    edit_archive_file_in_place(&path, |editor| {
+        editor.delete(b"a.txt")?;
+        editor.rename(b"b.txt", b"c.txt")?;
+        editor.replace(b"d.txt", b"d", &EntryOptions::default())
+    })
+    .unwrap();
+    // A failed edit leaves the archive as it was.
+    assert_eq!(
+        edit_archive_file_in_place(&path, |editor| editor.delete(b"a.txt")),
+        Err(ZipError::FileNotFound)
+    );
+    // The edited archive keeps the permissions of the original.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+    let names: Vec<_> = archive.entries().iter().map(|e| e.name.clone()).collect();
+    assert_eq!(
+        names,
+        vec![b"data.bin".to_vec(), b"c.txt".to_vec(), b"d.txt".to_vec()]
+    );
+    assert!(archive.extract_to_vec(0).unwrap() == data);
+    assert_eq!(archive.extract_to_vec(1).unwrap(), b"b");
+    assert_eq!(archive.extract_to_vec(2).unwrap(), b"d");
+    drop(archive);
+    fs::remove_file(&path).unwrap();
+}
@@ -144,6 +144,7 @@ typedef enum {
     MZ_ZIP_ARCHIVE_TOO_LARGE,
     MZ_ZIP_VALIDATION_FAILED,
     MZ_ZIP_WRITE_CALLBACK_FAILED,
+    MZ_ZIP_WRONG_PASSWORD,
     MZ_ZIP_TOTAL_ERRORS
 } mz_zip_error;
 
@@ -174,25 +175,8 @@ typedef struct
 
 } mz_zip_archive;
 
-typedef struct
-{
-    mz_zip_archive *pZip;
-    mz_uint flags;
-
-    int status;
-#ifndef MINIZ_DISABLE_ZIP_READER_CRC32_CHECKS
-    mz_uint file_crc32;
-#endif
-    mz_uint64 read_buf_size, read_buf_ofs, read_buf_avail, comp_remaining, out_buf_ofs, cur_file_ofs;
-    mz_zip_archive_file_stat file_stat;
-    void *pRead_buf;
-    void *pWrite_buf;
-
-    size_t out_blk_remain;
-
-    tinfl_decompressor inflator;
-
-} mz_zip_reader_extract_iter_state;
+/* Opaque state of mz_zip_reader_extract_iter_*(). Unlike in miniz, its fields can't be accessed. */
+typedef struct mz_zip_reader_extract_iter_state_tag mz_zip_reader_extract_iter_state;
 
 /* -------- ZIP reading */
 
@@ -258,9 +242,13 @@ mz_bool mz_zip_reader_is_file_a_directory(mz_zip_archive *pZip, mz_uint file_ind
 /* MZ_TRUE if the file is encrypted/strong encrypted. */
 mz_bool mz_zip_reader_is_file_encrypted(mz_zip_archive *pZip, mz_uint file_index);
 
-/* MZ_TRUE if the compression method is supported, and the file is not encrypted, and the file is not a compressed patch file. */
+/* MZ_TRUE if the compression method is supported, and the file is not encrypted (or uses traditional PKWARE encryption and a password has been set), and the file is not a compressed patch file. */
 mz_bool mz_zip_reader_is_file_supported(mz_zip_archive *pZip, mz_uint file_index);
 
+/* Sets the password used to extract files with traditional PKWARE encryption, or clears it if pPassword is NULL. */
+/* Extracting an encrypted file with the wrong password fails with MZ_ZIP_WRONG_PASSWORD. */
+mz_bool mz_zip_reader_set_password(mz_zip_archive *pZip, const char *pPassword);
+
 /* Retrieves the filename of an archive file entry. */
 /* Returns the number of bytes written to pFilename, or if filename_buf_size is 0 this function returns the number of bytes needed to fully store the filename. */
 mz_uint mz_zip_reader_get_filename(mz_zip_archive *pZip, mz_uint file_index, char *pFilename, mz_uint filename_buf_size);
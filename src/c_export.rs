@@ -11,11 +11,13 @@ pub use tdef::{
     tdefl_allocate, tdefl_compress, tdefl_compress_buffer, tdefl_compress_mem_to_heap,
     tdefl_compress_mem_to_mem, tdefl_compress_mem_to_output,
     tdefl_create_comp_flags_from_zip_params, tdefl_deallocate, tdefl_get_adler32,
-    tdefl_get_prev_return_status, tdefl_init,
+    tdefl_get_prev_return_status, tdefl_init, tdefl_write_image_to_png_file_in_memory,
+    tdefl_write_image_to_png_file_in_memory_ex,
 };
 
 use lib_oxide::{InternalState, StateType, StateTypeEnum, StreamOxide, MZ_ADLER32_INIT};
 
+use miniz_oxide::checksum::Crc32;
 use miniz_oxide::{mz_adler32_oxide, MZError};
 
 #[allow(bad_style)]
@@ -61,9 +63,9 @@ pub enum CAPICompressionStrategy {
 pub const MZ_CRC32_INIT: c_ulong = 0;
 
 pub fn mz_crc32_oxide(crc32: c_uint, data: &[u8]) -> c_uint {
-    let mut digest = crc32fast::Hasher::new_with_initial(crc32);
-    digest.update(data);
-    digest.finalize()
+    let mut crc = Crc32::from_checksum(crc32);
+    crc.update(data);
+    crc.checksum()
 }
 
 /// Signature of function used to allocate the compressor/decompressor structs.
@@ -4,7 +4,6 @@
 //!
 //! The C API is in a bit of a rough shape currently.
 
-extern crate crc32fast;
 #[cfg(not(any(
     feature = "libc_stub",
     all(target_arch = "wasm32", not(target_os = "emscripten"))
@@ -79,11 +78,16 @@ mod tinfl;
 
 mod c_export;
 pub use c_export::*;
+#[cfg(feature = "miniz_zip")]
+mod zip;
+#[cfg(feature = "miniz_zip")]
+pub use zip::*;
 #[allow(bad_style)]
 pub use tdef::Compressor as tdefl_compressor;
 
 pub const MZ_DEFLATED: c_int = 8;
 pub const MZ_DEFAULT_WINDOW_BITS: c_int = 15;
+pub const MZ_DEFAULT_MEM_LEVEL: c_int = 8;
 
 fn as_c_return_code(r: MZResult) -> c_int {
     match r {
@@ -129,6 +133,8 @@ oxidize!(mz_deflate, mz_deflate_oxide;
          flush: c_int);
 oxidize!(mz_deflateEnd, mz_deflate_end_oxide;);
 oxidize!(mz_deflateReset, mz_deflate_reset_oxide;);
+oxidize!(mz_deflateTune, mz_deflate_tune_oxide;
+         good_length: c_int, max_lazy: c_int, nice_length: c_int, max_chain: c_int);
 
 oxidize!(mz_inflate, mz_inflate_oxide;
          flush: c_int);
@@ -141,7 +147,7 @@ unmangle!(
             level,
             MZ_DEFLATED,
             MZ_DEFAULT_WINDOW_BITS,
-            9,
+            MZ_DEFAULT_MEM_LEVEL,
             CompressionStrategy::Default as c_int,
         )
     }
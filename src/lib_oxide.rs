@@ -128,7 +128,7 @@ pub fn mz_deflate_init_oxide(stream_oxide: &mut StreamOxide<Compressor>, level:
         level,
         MZ_DEFLATED,
         MZ_DEFAULT_WINDOW_BITS,
-        9,
+        ::MZ_DEFAULT_MEM_LEVEL,
         CompressionStrategy::Default as i32,
     )
 }
@@ -143,7 +143,8 @@ pub fn mz_deflate_init_oxide(stream_oxide: &mut StreamOxide<Compressor>, level:
 ///              Only `MZ_DEFAULT_WINDOW_BITS` is currently supported.
 ///              A negative value, i.e `-MZ_DEFAULT_WINDOW_BITS` indicates that the stream
 ///              should be not be wrapped in a zlib wrapper.
-/// mem_level: Currently unused. Only values from 1 to and including 9 are accepted.
+/// mem_level: How much memory to use for finding matches (1-9). As in zlib, the hash table
+///            uses `mem_level + 7` bits. The default is `MZ_DEFAULT_MEM_LEVEL` (8).
 /// strategy: Compression strategy. See `deflate::CompressionStrategy` for accepted options.
 ///           The default, which is used in most cases, is 0.
 pub fn mz_deflate_init2_oxide(
@@ -166,8 +167,11 @@ pub fn mz_deflate_init2_oxide(
     stream_oxide.total_in = 0;
     stream_oxide.total_out = 0;
 
+    let mut inner = CompressorOxide::new(comp_flags);
+    inner.set_hash_bits(mem_level as u8 + 7);
+
     let mut compr: Box<Compressor> = Box::default();
-    compr.inner = Some(CompressorOxide::new(comp_flags));
+    compr.inner = Some(inner);
     stream_oxide.state = Some(Box::new(InternalState::Deflate(compr)));
 
     Ok(MZStatus::Ok)
@@ -206,6 +210,33 @@ pub fn mz_deflate_end_oxide(stream_oxide: &mut StreamOxide<Compressor>) -> MZRes
     Ok(MZStatus::Ok)
 }
 
+/// Fine-tune the match finder of the compressor, with the same meaning as zlib's `deflateTune`.
+///
+/// See `CompressorOxide::set_tuning` for what the parameters do.
+///
+/// Returns `MZError::Stream` if the inner stream is missing, `MZError::Param` if any of the
+/// parameters are negative, otherwise `MZStatus::Ok`.
+pub fn mz_deflate_tune_oxide(
+    stream_oxide: &mut StreamOxide<Compressor>,
+    good_length: i32,
+    max_lazy: i32,
+    nice_length: i32,
+    max_chain: i32,
+) -> MZResult {
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    let compressor = state.inner.as_mut().ok_or(MZError::Stream)?;
+    if good_length < 0 || max_lazy < 0 || nice_length < 0 || max_chain < 0 {
+        return Err(MZError::Param);
+    }
+    compressor.set_tuning(
+        good_length as u32,
+        max_lazy as u32,
+        nice_length as u32,
+        max_chain as u32,
+    );
+    Ok(MZStatus::Ok)
+}
+
 /// Reset the compressor, so it can be used to compress a new set of data.
 ///
 /// Returns `MZError::Stream` if the inner stream is missing, otherwise `MZStatus::Ok`.
@@ -6,6 +6,7 @@ use miniz_oxide::deflate::core::{
     compress, compress_to_output, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush,
     TDEFLStatus,
 };
+use miniz_oxide::png::{write_image_to_png_with_options, PngOptions};
 
 /// Compression callback function type.
 pub type PutBufFuncPtrNotNull = unsafe extern "C" fn(*const c_void, c_int, *mut c_void) -> bool;
@@ -416,12 +417,76 @@ unmangle!(
     ) -> c_uint {
         create_comp_flags_from_zip_params(level, window_bits, strategy)
     }
+
+    /// Write an image to a PNG file in memory, see `miniz_oxide::png`.
+    ///
+    /// Returns a buffer that has to be freed with `miniz_def_free_func`, or null on failure.
+    pub unsafe extern "C" fn tdefl_write_image_to_png_file_in_memory_ex(
+        image: *const c_void,
+        w: c_int,
+        h: c_int,
+        num_chans: c_int,
+        len_out: *mut usize,
+        level: c_uint,
+        flip: c_int,
+    ) -> *mut c_void {
+        let len_out = match len_out.as_mut() {
+            None => return ptr::null_mut(),
+            Some(len_out) => len_out,
+        };
+        *len_out = 0;
+        if image.is_null() || w <= 0 || h <= 0 || num_chans < 1 || num_chans > 4 {
+            return ptr::null_mut();
+        }
+
+        let image_len = match (w as usize)
+            .checked_mul(h as usize)
+            .and_then(|len| len.checked_mul(num_chans as usize))
+        {
+            Some(image_len) => image_len,
+            None => return ptr::null_mut(),
+        };
+        let image = slice::from_raw_parts(image as *const u8, image_len);
+        let options = PngOptions {
+            level: cmp::min(level, 10) as u8,
+            flip: flip != 0,
+            ..PngOptions::default()
+        };
+        let png = match write_image_to_png_with_options(
+            image,
+            w as u32,
+            h as u32,
+            num_chans as u8,
+            &options,
+        ) {
+            Ok(png) => png,
+            Err(_) => return ptr::null_mut(),
+        };
+
+        let buf = ::miniz_def_alloc_func(ptr::null_mut(), png.len(), 1) as *mut u8;
+        if buf.is_null() {
+            return ptr::null_mut();
+        }
+        ptr::copy_nonoverlapping(png.as_ptr(), buf, png.len());
+        *len_out = png.len();
+        buf as *mut c_void
+    }
+
+    pub unsafe extern "C" fn tdefl_write_image_to_png_file_in_memory(
+        image: *const c_void,
+        w: c_int,
+        h: c_int,
+        num_chans: c_int,
+        len_out: *mut usize,
+    ) -> *mut c_void {
+        tdefl_write_image_to_png_file_in_memory_ex(image, w, h, num_chans, len_out, 6, 0)
+    }
 );
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use miniz_oxide::inflate::decompress_to_vec;
+    use miniz_oxide::inflate::{decompress_to_vec, decompress_to_vec_zlib};
 
     #[test]
     fn mem_to_heap() {
@@ -444,4 +509,47 @@ mod test {
             assert!(dec.as_slice() == &data[..]);
         }
     }
+
+    #[test]
+    fn png_to_heap() {
+        let image: Vec<u8> = (0..4 * 3 * 3).map(|n| n as u8).collect();
+        let mut out_len = 0;
+        let png = unsafe {
+            let res = tdefl_write_image_to_png_file_in_memory(
+                image.as_ptr() as *const c_void,
+                4,
+                3,
+                3,
+                &mut out_len,
+            );
+            assert!(!res.is_null());
+            res
+        };
+        {
+            let png_slice = unsafe { slice::from_raw_parts(png as *const u8, out_len) };
+            assert_eq!(&png_slice[..8], b"\x89PNG\r\n\x1a\n");
+            // Single IDAT chunk following the IHDR chunk.
+            let idat_len =
+                u32::from_be_bytes([png_slice[33], png_slice[34], png_slice[35], png_slice[36]])
+                    as usize;
+            assert_eq!(&png_slice[37..41], b"IDAT");
+            let dec = decompress_to_vec_zlib(&png_slice[41..41 + idat_len]).unwrap();
+            // Each scanline is prefixed by the filter type.
+            assert_eq!(dec.len(), 3 * (1 + 4 * 3));
+            assert_eq!(&dec[1..13], &image[..12]);
+        }
+        unsafe { ::miniz_def_free_func(ptr::null_mut(), png) };
+
+        let res = unsafe {
+            tdefl_write_image_to_png_file_in_memory(
+                image.as_ptr() as *const c_void,
+                4,
+                3,
+                5,
+                &mut out_len,
+            )
+        };
+        assert!(res.is_null());
+        assert_eq!(out_len, 0);
+    }
 }
@@ -0,0 +1,2417 @@
+//! The `mz_zip_*` functions from `miniz_zip.h`, implemented on top of `miniz_oxide::zip`.
+
+use std::ffi::CStr;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::{cmp, mem, ptr, slice};
+
+use libc::*;
+
+use miniz_oxide::zip::zip_flags::ZIP_FLAG_COMPRESSED_DATA;
+use miniz_oxide::zip::{
+    DateTime, EntryDecoder, EntryOptions, ExtendedTimestamp, ValidationLimits, ZipArchive,
+    ZipEntry, ZipError, ZipWriter,
+};
+
+use c_export::{mz_alloc_func, mz_free_func};
+
+#[allow(bad_style)]
+pub type mz_realloc_func =
+    unsafe extern "C" fn(*mut c_void, *mut c_void, size_t, size_t) -> *mut c_void;
+#[allow(bad_style)]
+pub type mz_file_read_func = unsafe extern "C" fn(*mut c_void, u64, *mut c_void, size_t) -> size_t;
+#[allow(bad_style)]
+pub type mz_file_write_func =
+    unsafe extern "C" fn(*mut c_void, u64, *const c_void, size_t) -> size_t;
+#[allow(bad_style)]
+pub type mz_file_needs_keepalive = unsafe extern "C" fn(*mut c_void) -> c_int;
+
+/// The C enums are kept as plain integers, as C code can store any value in them.
+#[allow(bad_style)]
+pub type mz_zip_mode = c_int;
+#[allow(bad_style)]
+pub type mz_zip_type = c_int;
+#[allow(bad_style)]
+pub type mz_zip_error = c_int;
+
+pub const MZ_ZIP_MODE_INVALID: mz_zip_mode = 0;
+pub const MZ_ZIP_MODE_READING: mz_zip_mode = 1;
+pub const MZ_ZIP_MODE_WRITING: mz_zip_mode = 2;
+pub const MZ_ZIP_MODE_WRITING_HAS_BEEN_FINALIZED: mz_zip_mode = 3;
+
+pub const MZ_ZIP_TYPE_INVALID: mz_zip_type = 0;
+pub const MZ_ZIP_TYPE_USER: mz_zip_type = 1;
+pub const MZ_ZIP_TYPE_MEMORY: mz_zip_type = 2;
+pub const MZ_ZIP_TYPE_HEAP: mz_zip_type = 3;
+pub const MZ_ZIP_TYPE_FILE: mz_zip_type = 4;
+pub const MZ_ZIP_TYPE_CFILE: mz_zip_type = 5;
+
+pub const MZ_ZIP_NO_ERROR: mz_zip_error = 0;
+
+pub const MZ_ZIP_FLAG_VALIDATE_HEADERS_ONLY: c_uint = 0x2000;
+pub const MZ_ZIP_FLAG_WRITE_ZIP64: c_uint = 0x4000;
+pub const MZ_ZIP_FLAG_WRITE_ALLOW_READING: c_uint = 0x8000;
+pub const MZ_ZIP_FLAG_ASCII_FILENAME: c_uint = 0x10000;
+
+pub const MZ_ZIP_MAX_ARCHIVE_FILENAME_SIZE: usize = 512;
+pub const MZ_ZIP_MAX_ARCHIVE_FILE_COMMENT_SIZE: usize = 512;
+
+const MZ_TRUE: c_int = 1;
+const MZ_FALSE: c_int = 0;
+
+/// Compression level used for negative values of `level_and_flags`.
+const MZ_DEFAULT_LEVEL: c_uint = 6;
+
+/// Files at least this large are added with ZIP64 fields, leaving room for the compressed data
+/// to be slightly larger than the file.
+const LARGE_FILE_SIZE: u64 = 0xFFFF_FFFF - 0x0100_0000;
+
+/// Archive struct shared with C, see `miniz_zip.h`.
+#[repr(C)]
+#[allow(bad_style)]
+pub struct mz_zip_archive {
+    pub m_archive_size: u64,
+    pub m_central_directory_file_ofs: u64,
+    pub m_total_files: u32,
+    pub m_zip_mode: mz_zip_mode,
+    pub m_zip_type: mz_zip_type,
+    pub m_last_error: mz_zip_error,
+    pub m_file_offset_alignment: u64,
+
+    pub m_pAlloc: Option<mz_alloc_func>,
+    pub m_pFree: Option<mz_free_func>,
+    pub m_pRealloc: Option<mz_realloc_func>,
+    pub m_pAlloc_opaque: *mut c_void,
+
+    pub m_pRead: Option<mz_file_read_func>,
+    pub m_pWrite: Option<mz_file_write_func>,
+    pub m_pNeeds_keepalive: Option<mz_file_needs_keepalive>,
+    pub m_pIO_opaque: *mut c_void,
+
+    /// `ReaderState` when reading, `WriterState` when writing.
+    pub m_pState: *mut c_void,
+}
+
+/// Entry information returned by `mz_zip_reader_file_stat`.
+#[repr(C)]
+#[allow(bad_style)]
+pub struct mz_zip_archive_file_stat {
+    pub m_file_index: u32,
+    pub m_central_dir_ofs: u64,
+    pub m_version_made_by: u16,
+    pub m_version_needed: u16,
+    pub m_bit_flag: u16,
+    pub m_method: u16,
+    pub m_time: time_t,
+    pub m_crc32: u32,
+    pub m_comp_size: u64,
+    pub m_uncomp_size: u64,
+    pub m_internal_attr: u16,
+    pub m_external_attr: u32,
+    pub m_local_header_ofs: u64,
+    pub m_comment_size: u32,
+    pub m_is_directory: c_int,
+    pub m_is_encrypted: c_int,
+    pub m_is_supported: c_int,
+    pub m_filename: [c_char; MZ_ZIP_MAX_ARCHIVE_FILENAME_SIZE],
+    pub m_comment: [c_char; MZ_ZIP_MAX_ARCHIVE_FILE_COMMENT_SIZE],
+}
+
+/// Where the data of an archive opened by one of the `mz_zip_reader_init_*` or
+/// `mz_zip_writer_init_*` functions is stored.
+///
+/// Used as `m_pIO_opaque` with `stream_read_func` and `stream_write_func`.
+enum Stream {
+    Memory {
+        data: *const u8,
+        size: usize,
+    },
+    /// Memory allocated with the archive's allocation functions, grown while writing.
+    Heap {
+        mem: *mut u8,
+        size: usize,
+        capacity: usize,
+        realloc: mz_realloc_func,
+        free: mz_free_func,
+        alloc_opaque: *mut c_void,
+    },
+    CFile {
+        file: *mut FILE,
+        start: u64,
+        owned: bool,
+    },
+}
+
+impl Stream {
+    /// Close the file if it was opened by us, returning false if that failed.
+    fn close(&mut self) -> bool {
+        match *self {
+            Stream::CFile {
+                ref mut file,
+                owned: true,
+                ..
+            } if !file.is_null() => {
+                let res = unsafe { fclose(*file) };
+                *file = ptr::null_mut();
+                res == 0
+            }
+            Stream::Heap {
+                ref mut mem,
+                free,
+                alloc_opaque,
+                ..
+            } if !mem.is_null() => {
+                unsafe { free(alloc_opaque, *mem as *mut c_void) };
+                *mem = ptr::null_mut();
+                true
+            }
+            _ => true,
+        }
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+unsafe extern "C" fn stream_read_func(
+    opaque: *mut c_void,
+    file_ofs: u64,
+    buf: *mut c_void,
+    n: size_t,
+) -> size_t {
+    let (data, size) = match *(opaque as *mut Stream) {
+        Stream::Memory { data, size } => (data, size),
+        Stream::Heap { mem, size, .. } => (mem as *const u8, size),
+        Stream::CFile { file, start, .. } => {
+            let pos = start + file_ofs;
+            if ftell(file) as u64 != pos && fseek(file, pos as c_long, SEEK_SET) != 0 {
+                return 0;
+            }
+            return fread(buf, 1, n, file);
+        }
+    };
+    if file_ofs >= size as u64 {
+        return 0;
+    }
+    let n = cmp::min(n, size - file_ofs as usize);
+    ptr::copy_nonoverlapping(data.add(file_ofs as usize), buf as *mut u8, n);
+    n
+}
+
+unsafe extern "C" fn stream_write_func(
+    opaque: *mut c_void,
+    file_ofs: u64,
+    buf: *const c_void,
+    n: size_t,
+) -> size_t {
+    match *(opaque as *mut Stream) {
+        Stream::Memory { .. } => 0,
+        Stream::Heap {
+            ref mut mem,
+            ref mut size,
+            ref mut capacity,
+            realloc,
+            alloc_opaque,
+            ..
+        } => {
+            let end = match (file_ofs as usize).checked_add(n) {
+                Some(end) if file_ofs <= usize::max_value() as u64 => end,
+                _ => return 0,
+            };
+            if end > *capacity {
+                let mut new_capacity = cmp::max(*capacity, 64);
+                while new_capacity < end {
+                    new_capacity = match new_capacity.checked_mul(2) {
+                        Some(new_capacity) => new_capacity,
+                        None => end,
+                    };
+                }
+                let new_mem = realloc(alloc_opaque, *mem as *mut c_void, 1, new_capacity);
+                if new_mem.is_null() {
+                    return 0;
+                }
+                *mem = new_mem as *mut u8;
+                *capacity = new_capacity;
+            }
+            // Space skipped over, for example reserved with `mz_zip_writer_init_heap`, is zeroed.
+            if file_ofs as usize > *size {
+                ptr::write_bytes(mem.add(*size), 0, file_ofs as usize - *size);
+            }
+            ptr::copy_nonoverlapping(buf as *const u8, mem.add(file_ofs as usize), n);
+            *size = cmp::max(*size, end);
+            n
+        }
+        Stream::CFile { file, start, .. } => {
+            let pos = start + file_ofs;
+            if ftell(file) as u64 != pos && fseek(file, pos as c_long, SEEK_SET) != 0 {
+                return 0;
+            }
+            fwrite(buf, 1, n, file)
+        }
+    }
+}
+
+/// `Read` and `Seek` on top of the archive's read function.
+struct ArchiveIo {
+    read: mz_file_read_func,
+    opaque: *mut c_void,
+    pos: u64,
+    size: u64,
+}
+
+impl Read for ArchiveIo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = cmp::min(buf.len() as u64, self.size.saturating_sub(self.pos)) as usize;
+        if n == 0 {
+            return Ok(0);
+        }
+        let read =
+            unsafe { (self.read)(self.opaque, self.pos, buf.as_mut_ptr() as *mut c_void, n) };
+        if read > n {
+            return Err(io::Error::new(io::ErrorKind::Other, "read function failed"));
+        }
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for ArchiveIo {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(pos) => pos,
+            SeekFrom::End(offset) => (self.size as i64 + offset) as u64,
+            SeekFrom::Current(offset) => (self.pos as i64 + offset) as u64,
+        };
+        Ok(self.pos)
+    }
+}
+
+/// State of an archive opened for reading, stored in `m_pState`.
+struct ReaderState {
+    archive: ZipArchive<ArchiveIo>,
+    /// Kept alive for `stream_read_func`, `None` for user supplied read functions.
+    stream: Option<Box<Stream>>,
+}
+
+/// `Write` on top of the archive's write function.
+struct ArchiveWriter {
+    write: mz_file_write_func,
+    opaque: *mut c_void,
+    pos: u64,
+}
+
+impl Write for ArchiveWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = unsafe {
+            (self.write)(
+                self.opaque,
+                self.pos,
+                buf.as_ptr() as *const c_void,
+                buf.len(),
+            )
+        };
+        if written != buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "write function failed",
+            ));
+        }
+        self.pos += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// State of an archive opened for writing, stored in `m_pState`.
+struct WriterState {
+    writer: ZipWriter<ArchiveWriter>,
+    /// Kept alive for `stream_write_func`, `None` for user supplied write functions.
+    stream: Option<Box<Stream>>,
+}
+
+/// State returned by `mz_zip_reader_extract_iter_new`.
+///
+/// Unlike in miniz, this is opaque to C.
+struct IterState {
+    zip: *mut mz_zip_archive,
+    decoder: EntryDecoder,
+    failed: bool,
+}
+
+fn set_error(zip: &mut mz_zip_archive, error: ZipError) -> c_int {
+    zip.m_last_error = error as mz_zip_error;
+    MZ_FALSE
+}
+
+unsafe fn set_error_ptr(zip: *mut mz_zip_archive, error: ZipError) -> c_int {
+    if let Some(zip) = zip.as_mut() {
+        set_error(zip, error);
+    }
+    MZ_FALSE
+}
+
+/// Get the archive and its reader state, if it is open for reading.
+unsafe fn reader<'a>(
+    zip: *mut mz_zip_archive,
+) -> Option<(&'a mut mz_zip_archive, &'a mut ReaderState)> {
+    let zip = zip.as_mut()?;
+    if zip.m_zip_mode != MZ_ZIP_MODE_READING || zip.m_pState.is_null() {
+        set_error(zip, ZipError::InvalidParameter);
+        return None;
+    }
+    let state = &mut *(zip.m_pState as *mut ReaderState);
+    Some((zip, state))
+}
+
+/// Get the archive and its writer state, if it is open for writing and hasn't been finalized.
+unsafe fn writer<'a>(
+    zip: *mut mz_zip_archive,
+) -> Option<(&'a mut mz_zip_archive, &'a mut WriterState)> {
+    let zip = zip.as_mut()?;
+    if zip.m_zip_mode != MZ_ZIP_MODE_WRITING || zip.m_pState.is_null() {
+        set_error(zip, ZipError::InvalidParameter);
+        return None;
+    }
+    let state = &mut *(zip.m_pState as *mut WriterState);
+    Some((zip, state))
+}
+
+/// Get the writer state, if the archive is open for writing.
+unsafe fn writer_state<'a>(zip: &mz_zip_archive) -> Option<&'a WriterState> {
+    if zip.m_zip_mode == MZ_ZIP_MODE_WRITING
+        || zip.m_zip_mode == MZ_ZIP_MODE_WRITING_HAS_BEEN_FINALIZED
+    {
+        (zip.m_pState as *const WriterState).as_ref()
+    } else {
+        None
+    }
+}
+
+/// Get the stream of an archive opened for reading or writing.
+unsafe fn stream<'a>(zip: &mz_zip_archive) -> Option<&'a Stream> {
+    if let Some(state) = writer_state(zip) {
+        return state.stream.as_ref().map(|s| &**s);
+    }
+    if zip.m_zip_mode == MZ_ZIP_MODE_READING && !zip.m_pState.is_null() {
+        let state = &*(zip.m_pState as *const ReaderState);
+        return state.stream.as_ref().map(|s| &**s);
+    }
+    None
+}
+
+unsafe fn c_bytes<'a>(s: *const c_char) -> Option<&'a [u8]> {
+    if s.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(s).to_bytes())
+    }
+}
+
+/// Open the archive with the given read function, checking the central directory.
+unsafe fn reader_init(
+    zip: *mut mz_zip_archive,
+    size: u64,
+    read: Option<mz_file_read_func>,
+    stream: Option<Box<Stream>>,
+    zip_type: mz_zip_type,
+) -> c_int {
+    let zip = match zip.as_mut() {
+        Some(zip) => zip,
+        None => return MZ_FALSE,
+    };
+    if !zip.m_pState.is_null() || zip.m_zip_mode != MZ_ZIP_MODE_INVALID {
+        return set_error(zip, ZipError::InvalidParameter);
+    }
+    let read = match read {
+        Some(read) => read,
+        None => return set_error(zip, ZipError::InvalidParameter),
+    };
+    if let Some(ref stream) = stream {
+        zip.m_pIO_opaque = &**stream as *const Stream as *mut c_void;
+        zip.m_pRead = Some(read);
+    }
+
+    let io = ArchiveIo {
+        read,
+        opaque: zip.m_pIO_opaque,
+        pos: 0,
+        size,
+    };
+    // Drops and closes the stream on failure.
+    let archive = match ZipArchive::new(io) {
+        Ok(archive) => archive,
+        Err(error) => {
+            if stream.is_some() {
+                zip.m_pIO_opaque = ptr::null_mut();
+                zip.m_pRead = None;
+            }
+            return set_error(zip, error);
+        }
+    };
+
+    zip.m_archive_size = size;
+    zip.m_central_directory_file_ofs = archive.central_dir_offset();
+    zip.m_total_files = archive.len() as u32;
+    zip.m_zip_mode = MZ_ZIP_MODE_READING;
+    zip.m_zip_type = zip_type;
+    zip.m_last_error = MZ_ZIP_NO_ERROR;
+    zip.m_pState = Box::into_raw(Box::new(ReaderState { archive, stream })) as *mut c_void;
+    MZ_TRUE
+}
+
+/// Open the archive for writing with `m_pWrite`, or `stream` if given, continuing after
+/// `existing_size` bytes holding `entries`.
+unsafe fn writer_init(
+    zip: *mut mz_zip_archive,
+    existing_size: u64,
+    flags: c_uint,
+    stream: Option<Box<Stream>>,
+    entries: Vec<ZipEntry>,
+    zip_type: mz_zip_type,
+) -> c_int {
+    let zip = match zip.as_mut() {
+        Some(zip) => zip,
+        None => return MZ_FALSE,
+    };
+    if !zip.m_pState.is_null() || zip.m_zip_mode != MZ_ZIP_MODE_INVALID {
+        return set_error(zip, ZipError::InvalidParameter);
+    }
+    if let Some(ref stream) = stream {
+        zip.m_pIO_opaque = &**stream as *const Stream as *mut c_void;
+        zip.m_pWrite = Some(stream_write_func);
+        if flags & MZ_ZIP_FLAG_WRITE_ALLOW_READING != 0 {
+            zip.m_pRead = Some(stream_read_func);
+        }
+    }
+    let write = match zip.m_pWrite {
+        Some(write) => write,
+        None => return set_error(zip, ZipError::InvalidParameter),
+    };
+
+    let io = ArchiveWriter {
+        write,
+        opaque: zip.m_pIO_opaque,
+        pos: existing_size,
+    };
+    let mut writer = ZipWriter::with_entries(io, existing_size, entries);
+    writer.set_zip64(flags & MZ_ZIP_FLAG_WRITE_ZIP64 != 0);
+    if let Err(error) = writer.set_file_offset_alignment(zip.m_file_offset_alignment) {
+        if stream.is_some() {
+            zip.m_pIO_opaque = ptr::null_mut();
+            zip.m_pWrite = None;
+            zip.m_pRead = None;
+        }
+        return set_error(zip, error);
+    }
+
+    if zip.m_pAlloc.is_none() {
+        zip.m_pAlloc = Some(::miniz_def_alloc_func);
+    }
+    if zip.m_pFree.is_none() {
+        zip.m_pFree = Some(::miniz_def_free_func);
+    }
+    if zip.m_pRealloc.is_none() {
+        zip.m_pRealloc = Some(::miniz_def_realloc_func);
+    }
+    zip.m_archive_size = existing_size;
+    zip.m_central_directory_file_ofs = 0;
+    zip.m_total_files = writer.len() as u32;
+    zip.m_zip_mode = MZ_ZIP_MODE_WRITING;
+    zip.m_zip_type = zip_type;
+    zip.m_last_error = MZ_ZIP_NO_ERROR;
+    zip.m_pState = Box::into_raw(Box::new(WriterState { writer, stream })) as *mut c_void;
+    MZ_TRUE
+}
+
+/// Split miniz's `level_and_flags` into the compression level and the flags.
+fn split_level_and_flags(level_and_flags: c_uint) -> Result<(u8, c_uint), ZipError> {
+    let level_and_flags = if (level_and_flags as c_int) < 0 {
+        MZ_DEFAULT_LEVEL
+    } else {
+        level_and_flags
+    };
+    let level = level_and_flags & 0xF;
+    if level > 10 {
+        return Err(ZipError::InvalidParameter);
+    }
+    Ok((level as u8, level_and_flags & !0xF))
+}
+
+/// Build the options for a new entry from the arguments of the `mz_zip_writer_add_*` functions.
+#[allow(clippy::too_many_arguments)]
+unsafe fn entry_options(
+    level: u8,
+    flags: c_uint,
+    last_modified: DateTime,
+    comment: *const c_void,
+    comment_size: u16,
+    user_extra_data_local: *const c_char,
+    user_extra_data_local_len: c_uint,
+    user_extra_data_central: *const c_char,
+    user_extra_data_central_len: c_uint,
+) -> Result<EntryOptions, ZipError> {
+    Ok(EntryOptions {
+        level,
+        last_modified,
+        comment: c_slice(comment as *const u8, comment_size as usize)?.to_vec(),
+        local_extra_field: c_slice(
+            user_extra_data_local as *const u8,
+            user_extra_data_local_len as usize,
+        )?
+        .to_vec(),
+        central_extra_field: c_slice(
+            user_extra_data_central as *const u8,
+            user_extra_data_central_len as usize,
+        )?
+        .to_vec(),
+        external_attributes: 0,
+        unix_mode: None,
+        utf8: flags & MZ_ZIP_FLAG_ASCII_FILENAME == 0,
+        large_file: false,
+    })
+}
+
+/// A slice of `len` bytes at `data`, which may only be null if `len` is 0.
+unsafe fn c_slice<'a>(data: *const u8, len: usize) -> Result<&'a [u8], ZipError> {
+    if len == 0 {
+        Ok(&[])
+    } else if data.is_null() {
+        Err(ZipError::InvalidParameter)
+    } else {
+        Ok(slice::from_raw_parts(data, len))
+    }
+}
+
+/// Update the fields of the archive struct after an entry has been added.
+fn update_writer_fields(zip: &mut mz_zip_archive, writer: &ZipWriter<ArchiveWriter>) {
+    zip.m_archive_size = writer.archive_size();
+    zip.m_total_files = writer.len() as u32;
+}
+
+/// `Read` for the next `remaining` bytes of a C file.
+struct CFileReader {
+    file: *mut FILE,
+    remaining: u64,
+}
+
+impl Read for CFileReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = cmp::min(buf.len() as u64, self.remaining) as usize;
+        if n == 0 {
+            return Ok(0);
+        }
+        let read = unsafe { fread(buf.as_mut_ptr() as *mut c_void, 1, n, self.file) };
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file is too short",
+            ));
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Convert a time to an MS-DOS date and time in local time, the same way as miniz.
+#[cfg(unix)]
+fn time_t_to_dos(time: time_t) -> DateTime {
+    unsafe {
+        let mut tm: tm = mem::zeroed();
+        if localtime_r(&time, &mut tm).is_null() {
+            return DateTime::default();
+        }
+        DateTime {
+            year: (tm.tm_year + 1900) as u16,
+            month: (tm.tm_mon + 1) as u8,
+            day: tm.tm_mday as u8,
+            hour: tm.tm_hour as u8,
+            minute: tm.tm_min as u8,
+            second: tm.tm_sec as u8,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn time_t_to_dos(_time: time_t) -> DateTime {
+    DateTime::default()
+}
+
+#[cfg(unix)]
+unsafe fn file_modified_time(path: *const c_char) -> Option<time_t> {
+    let mut st: stat = mem::zeroed();
+    if stat(path, &mut st) != 0 {
+        None
+    } else {
+        Some(st.st_mtime)
+    }
+}
+
+#[cfg(not(unix))]
+unsafe fn file_modified_time(_path: *const c_char) -> Option<time_t> {
+    Some(time(ptr::null_mut()))
+}
+
+/// The modification time of an entry, from the extended timestamp if it has one as that is
+/// more precise and has a time zone.
+fn entry_modified_time(entry: &ZipEntry) -> time_t {
+    match entry
+        .extra_field::<ExtendedTimestamp>()
+        .and_then(|timestamp| timestamp.modified)
+    {
+        Some(modified) => modified as time_t,
+        None => dos_to_time_t(&entry.last_modified),
+    }
+}
+
+/// Convert an MS-DOS date and time to local time the same way as miniz.
+fn dos_to_time_t(date_time: &DateTime) -> time_t {
+    unsafe {
+        let mut tm: tm = mem::zeroed();
+        tm.tm_isdst = -1;
+        tm.tm_year = c_int::from(date_time.year) - 1900;
+        tm.tm_mon = c_int::from(date_time.month) - 1;
+        tm.tm_mday = c_int::from(date_time.day);
+        tm.tm_hour = c_int::from(date_time.hour);
+        tm.tm_min = c_int::from(date_time.minute);
+        tm.tm_sec = c_int::from(date_time.second);
+        mktime(&mut tm)
+    }
+}
+
+/// Copy `src` to `dst`, truncating it if needed, and add a terminating zero.
+fn copy_c_string(dst: &mut [c_char], src: &[u8]) -> usize {
+    let n = cmp::min(src.len(), dst.len() - 1);
+    for (d, &s) in dst.iter_mut().zip(&src[..n]) {
+        *d = s as c_char;
+    }
+    dst[n] = 0;
+    n
+}
+
+/// Create a decoder for entry `index`, returning the compressed data as is if
+/// `ZIP_FLAG_COMPRESSED_DATA` is set.
+fn entry_decoder(
+    archive: &mut ZipArchive<ArchiveIo>,
+    index: usize,
+    flags: c_uint,
+) -> Result<EntryDecoder, ZipError> {
+    let entry = archive.entry(index).ok_or(ZipError::InvalidParameter)?;
+    if flags & ZIP_FLAG_COMPRESSED_DATA != 0 {
+        // Like miniz, encrypted data isn't returned even as it is stored.
+        if entry.is_encrypted() {
+            return Err(ZipError::UnsupportedEncryption);
+        }
+        archive.raw_decoder(index)
+    } else {
+        archive.decoder(index)
+    }
+}
+
+/// The size of the data returned by `entry_decoder`.
+fn extracted_size(entry: &ZipEntry, flags: c_uint) -> u64 {
+    if flags & ZIP_FLAG_COMPRESSED_DATA != 0 {
+        entry.compressed_size
+    } else {
+        entry.uncompressed_size
+    }
+}
+
+/// Extract entry `index`, passing the data to `callback`.
+unsafe fn extract_to_callback<F>(
+    zip: *mut mz_zip_archive,
+    index: c_uint,
+    flags: c_uint,
+    callback: F,
+) -> c_int
+where
+    F: FnMut(&[u8]) -> bool,
+{
+    let (zip, state) = match reader(zip) {
+        Some(reader) => reader,
+        None => return MZ_FALSE,
+    };
+    let res = entry_decoder(&mut state.archive, index as usize, flags)
+        .and_then(|mut decoder| decoder.read_to_callback(state.archive.get_mut(), callback));
+    match res {
+        Ok(_) => MZ_TRUE,
+        Err(error) => set_error(zip, error),
+    }
+}
+
+unsafe extern "C" fn cfile_write_func(
+    opaque: *mut c_void,
+    _file_ofs: u64,
+    buf: *const c_void,
+    n: size_t,
+) -> size_t {
+    fwrite(buf, 1, n, opaque as *mut FILE)
+}
+
+#[cfg(unix)]
+unsafe fn set_file_times(path: *const c_char, modified: time_t) {
+    let times = utimbuf {
+        actime: modified,
+        modtime: modified,
+    };
+    utime(path, &times);
+}
+
+#[cfg(not(unix))]
+unsafe fn set_file_times(_path: *const c_char, _modified: time_t) {}
+
+/// Set the permissions of an extracted file to those of an entry made on Unix.
+///
+/// Like Info-ZIP, this drops the setuid, setgid and sticky bits, so that extracting an
+/// untrusted archive as root can't create setuid binaries.
+#[cfg(unix)]
+unsafe fn set_file_permissions(path: *const c_char, mode: u32) {
+    chmod(path, (mode & 0o777) as mode_t);
+}
+
+#[cfg(not(unix))]
+unsafe fn set_file_permissions(_path: *const c_char, _mode: u32) {}
+
+unmangle!(
+    pub unsafe extern "C" fn mz_zip_zero_struct(zip: *mut mz_zip_archive) {
+        if !zip.is_null() {
+            ptr::write_bytes(zip, 0, 1);
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_init(
+        zip: *mut mz_zip_archive,
+        size: u64,
+        _flags: c_uint,
+    ) -> c_int {
+        let read = match zip.as_ref() {
+            Some(zip) => zip.m_pRead,
+            None => return MZ_FALSE,
+        };
+        reader_init(zip, size, read, None, MZ_ZIP_TYPE_USER)
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_init_mem(
+        zip: *mut mz_zip_archive,
+        mem: *const c_void,
+        size: size_t,
+        _flags: c_uint,
+    ) -> c_int {
+        if mem.is_null() {
+            return set_error_ptr(zip, ZipError::InvalidParameter);
+        }
+        let stream = Box::new(Stream::Memory {
+            data: mem as *const u8,
+            size,
+        });
+        reader_init(
+            zip,
+            size as u64,
+            Some(stream_read_func),
+            Some(stream),
+            MZ_ZIP_TYPE_MEMORY,
+        )
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_init_file(
+        zip: *mut mz_zip_archive,
+        filename: *const c_char,
+        flags: u32,
+    ) -> c_int {
+        mz_zip_reader_init_file_v2(zip, filename, flags, 0, 0)
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_init_file_v2(
+        zip: *mut mz_zip_archive,
+        filename: *const c_char,
+        _flags: c_uint,
+        file_start_ofs: u64,
+        archive_size: u64,
+    ) -> c_int {
+        if zip.is_null() || filename.is_null() {
+            return set_error_ptr(zip, ZipError::InvalidParameter);
+        }
+        let file = fopen(filename, b"rb\0".as_ptr() as *const c_char);
+        if file.is_null() {
+            return set_error_ptr(zip, ZipError::FileOpenFailed);
+        }
+        let stream = Box::new(Stream::CFile {
+            file,
+            start: file_start_ofs,
+            owned: true,
+        });
+
+        let mut size = archive_size;
+        if size == 0 {
+            if fseek(file, 0, SEEK_END) != 0 {
+                return set_error_ptr(zip, ZipError::FileSeekFailed);
+            }
+            size = (ftell(file) as u64).saturating_sub(file_start_ofs);
+        }
+        reader_init(
+            zip,
+            size,
+            Some(stream_read_func),
+            Some(stream),
+            MZ_ZIP_TYPE_FILE,
+        )
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_init_cfile(
+        zip: *mut mz_zip_archive,
+        file: *mut FILE,
+        archive_size: u64,
+        _flags: c_uint,
+    ) -> c_int {
+        if zip.is_null() || file.is_null() {
+            return set_error_ptr(zip, ZipError::InvalidParameter);
+        }
+        let start = ftell(file);
+        if start < 0 {
+            return set_error_ptr(zip, ZipError::FileSeekFailed);
+        }
+        let mut size = archive_size;
+        if size == 0 {
+            if fseek(file, 0, SEEK_END) != 0 {
+                return set_error_ptr(zip, ZipError::FileSeekFailed);
+            }
+            size = (ftell(file) - start) as u64;
+        }
+        let stream = Box::new(Stream::CFile {
+            file,
+            start: start as u64,
+            owned: false,
+        });
+        reader_init(
+            zip,
+            size,
+            Some(stream_read_func),
+            Some(stream),
+            MZ_ZIP_TYPE_CFILE,
+        )
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_end(zip: *mut mz_zip_archive) -> c_int {
+        let zip = match reader(zip) {
+            Some((zip, _)) => zip,
+            None => return MZ_FALSE,
+        };
+        drop(Box::from_raw(zip.m_pState as *mut ReaderState));
+        zip.m_pState = ptr::null_mut();
+        zip.m_zip_mode = MZ_ZIP_MODE_INVALID;
+        MZ_TRUE
+    }
+
+    pub unsafe extern "C" fn mz_zip_end(zip: *mut mz_zip_archive) -> c_int {
+        match zip.as_ref().map(|zip| zip.m_zip_mode) {
+            Some(MZ_ZIP_MODE_READING) => mz_zip_reader_end(zip),
+            Some(MZ_ZIP_MODE_WRITING) | Some(MZ_ZIP_MODE_WRITING_HAS_BEEN_FINALIZED) => {
+                mz_zip_writer_end(zip)
+            }
+            _ => MZ_FALSE,
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_get_mode(zip: *mut mz_zip_archive) -> mz_zip_mode {
+        zip.as_ref()
+            .map_or(MZ_ZIP_MODE_INVALID, |zip| zip.m_zip_mode)
+    }
+
+    pub unsafe extern "C" fn mz_zip_get_type(zip: *mut mz_zip_archive) -> mz_zip_type {
+        zip.as_ref()
+            .map_or(MZ_ZIP_TYPE_INVALID, |zip| zip.m_zip_type)
+    }
+
+    pub unsafe extern "C" fn mz_zip_set_last_error(
+        zip: *mut mz_zip_archive,
+        error: mz_zip_error,
+    ) -> mz_zip_error {
+        match zip.as_mut() {
+            Some(zip) => mem::replace(&mut zip.m_last_error, error),
+            None => ZipError::InvalidParameter as mz_zip_error,
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_peek_last_error(zip: *mut mz_zip_archive) -> mz_zip_error {
+        zip.as_ref()
+            .map_or(ZipError::InvalidParameter as mz_zip_error, |zip| {
+                zip.m_last_error
+            })
+    }
+
+    pub unsafe extern "C" fn mz_zip_clear_last_error(zip: *mut mz_zip_archive) -> mz_zip_error {
+        mz_zip_set_last_error(zip, MZ_ZIP_NO_ERROR)
+    }
+
+    pub unsafe extern "C" fn mz_zip_get_last_error(zip: *mut mz_zip_archive) -> mz_zip_error {
+        mz_zip_set_last_error(zip, MZ_ZIP_NO_ERROR)
+    }
+
+    /// Get a description of `error` as a static C string.
+    pub extern "C" fn mz_zip_get_error_string(error: mz_zip_error) -> *const c_char {
+        let s: &[u8] = match error {
+            MZ_ZIP_NO_ERROR => b"no error\0",
+            1 => b"undefined error\0",
+            2 => b"too many files\0",
+            3 => b"file too large\0",
+            4 => b"unsupported method\0",
+            5 => b"unsupported encryption\0",
+            6 => b"unsupported feature\0",
+            7 => b"failed finding central directory\0",
+            8 => b"not a ZIP archive\0",
+            9 => b"invalid header or archive is corrupted\0",
+            10 => b"unsupported multidisk archive\0",
+            11 => b"decompression failed or archive is corrupted\0",
+            12 => b"compression failed\0",
+            13 => b"unexpected decompressed size\0",
+            14 => b"CRC-32 check failed\0",
+            15 => b"unsupported central directory size\0",
+            16 => b"allocation failed\0",
+            17 => b"file open failed\0",
+            18 => b"file create failed\0",
+            19 => b"file write failed\0",
+            20 => b"file read failed\0",
+            21 => b"file close failed\0",
+            22 => b"file seek failed\0",
+            23 => b"file stat failed\0",
+            24 => b"invalid parameter\0",
+            25 => b"invalid filename\0",
+            26 => b"buffer too small\0",
+            27 => b"internal error\0",
+            28 => b"file not found\0",
+            29 => b"archive is too large\0",
+            30 => b"validation failed\0",
+            31 => b"write callback failed\0",
+            32 => b"wrong password\0",
+            _ => b"unknown error\0",
+        };
+        s.as_ptr() as *const c_char
+    }
+
+    pub unsafe extern "C" fn mz_zip_is_zip64(zip: *mut mz_zip_archive) -> c_int {
+        let zip = match zip.as_ref() {
+            Some(zip) => zip,
+            None => return MZ_FALSE,
+        };
+        if let Some(state) = writer_state(zip) {
+            return state.writer.is_zip64() as c_int;
+        }
+        if zip.m_zip_mode == MZ_ZIP_MODE_READING && !zip.m_pState.is_null() {
+            let state = &*(zip.m_pState as *const ReaderState);
+            return state.archive.is_zip64() as c_int;
+        }
+        MZ_FALSE
+    }
+
+    pub unsafe extern "C" fn mz_zip_get_central_dir_size(zip: *mut mz_zip_archive) -> size_t {
+        let zip = match zip.as_ref() {
+            Some(zip) => zip,
+            None => return 0,
+        };
+        if let Some(state) = writer_state(zip) {
+            return state.writer.central_dir_size() as size_t;
+        }
+        if zip.m_zip_mode == MZ_ZIP_MODE_READING && !zip.m_pState.is_null() {
+            let state = &*(zip.m_pState as *const ReaderState);
+            return state.archive.central_dir_size() as size_t;
+        }
+        0
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_get_num_files(zip: *mut mz_zip_archive) -> c_uint {
+        zip.as_ref().map_or(0, |zip| zip.m_total_files)
+    }
+
+    pub unsafe extern "C" fn mz_zip_get_archive_size(zip: *mut mz_zip_archive) -> u64 {
+        zip.as_ref().map_or(0, |zip| zip.m_archive_size)
+    }
+
+    pub unsafe extern "C" fn mz_zip_get_archive_file_start_offset(zip: *mut mz_zip_archive) -> u64 {
+        match zip.as_ref().and_then(|zip| stream(zip)) {
+            Some(&Stream::CFile { start, .. }) => start,
+            _ => 0,
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_get_cfile(zip: *mut mz_zip_archive) -> *mut FILE {
+        match zip.as_ref().and_then(|zip| stream(zip)) {
+            Some(&Stream::CFile { file, .. }) => file,
+            _ => ptr::null_mut(),
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_read_archive_data(
+        zip: *mut mz_zip_archive,
+        file_ofs: u64,
+        buf: *mut c_void,
+        n: size_t,
+    ) -> size_t {
+        let zip = match zip.as_mut() {
+            Some(zip) => zip,
+            None => return 0,
+        };
+        match zip.m_pRead {
+            Some(read) if !zip.m_pState.is_null() && !buf.is_null() => {
+                read(zip.m_pIO_opaque, file_ofs, buf, n)
+            }
+            _ => set_error(zip, ZipError::InvalidParameter) as size_t,
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_is_file_a_directory(
+        zip: *mut mz_zip_archive,
+        file_index: c_uint,
+    ) -> c_int {
+        match reader(zip) {
+            Some((zip, state)) => match state.archive.entry(file_index as usize) {
+                Some(entry) => entry.is_dir() as c_int,
+                None => set_error(zip, ZipError::InvalidParameter),
+            },
+            None => MZ_FALSE,
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_is_file_encrypted(
+        zip: *mut mz_zip_archive,
+        file_index: c_uint,
+    ) -> c_int {
+        match reader(zip) {
+            Some((zip, state)) => match state.archive.entry(file_index as usize) {
+                Some(entry) => entry.is_encrypted() as c_int,
+                None => set_error(zip, ZipError::InvalidParameter),
+            },
+            None => MZ_FALSE,
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_is_file_supported(
+        zip: *mut mz_zip_archive,
+        file_index: c_uint,
+    ) -> c_int {
+        match reader(zip) {
+            Some((zip, state)) => match state.archive.entry(file_index as usize) {
+                Some(_) => state.archive.can_extract(file_index as usize) as c_int,
+                None => set_error(zip, ZipError::InvalidParameter),
+            },
+            None => MZ_FALSE,
+        }
+    }
+
+    /// Set the password for extracting entries with the traditional PKWARE encryption, or clear
+    /// it if `password` is null.
+    ///
+    /// Extracting an entry with the wrong password fails with `MZ_ZIP_WRONG_PASSWORD`.
+    pub unsafe extern "C" fn mz_zip_reader_set_password(
+        zip: *mut mz_zip_archive,
+        password: *const c_char,
+    ) -> c_int {
+        match reader(zip) {
+            Some((_, state)) => {
+                state.archive.set_password(c_bytes(password));
+                MZ_TRUE
+            }
+            None => MZ_FALSE,
+        }
+    }
+
+    /// Copy the name of entry `file_index` to `filename`.
+    ///
+    /// Returns the number of bytes written including the terminating zero, or if
+    /// `filename_buf_size` is 0 the size needed to store the whole name.
+    pub unsafe extern "C" fn mz_zip_reader_get_filename(
+        zip: *mut mz_zip_archive,
+        file_index: c_uint,
+        filename: *mut c_char,
+        filename_buf_size: c_uint,
+    ) -> c_uint {
+        let buf = if filename.is_null() || filename_buf_size == 0 {
+            None
+        } else {
+            Some(slice::from_raw_parts_mut(
+                filename,
+                filename_buf_size as usize,
+            ))
+        };
+        let entry = reader(zip).and_then(|(_, state)| state.archive.entry(file_index as usize));
+        match (entry, buf) {
+            (Some(entry), Some(buf)) => copy_c_string(buf, &entry.name) as c_uint + 1,
+            (Some(entry), None) => entry.name.len() as c_uint + 1,
+            (None, buf) => {
+                if let Some(buf) = buf {
+                    buf[0] = 0;
+                }
+                set_error_ptr(zip, ZipError::InvalidParameter);
+                0
+            }
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_file_stat(
+        zip: *mut mz_zip_archive,
+        file_index: c_uint,
+        stat: *mut mz_zip_archive_file_stat,
+    ) -> c_int {
+        let (zip, state) = match reader(zip) {
+            Some(reader) => reader,
+            None => return MZ_FALSE,
+        };
+        let (entry, stat) = match (state.archive.entry(file_index as usize), stat.as_mut()) {
+            (Some(entry), Some(stat)) => (entry, stat),
+            _ => return set_error(zip, ZipError::InvalidParameter),
+        };
+
+        stat.m_file_index = file_index;
+        stat.m_central_dir_ofs = state
+            .archive
+            .central_header_offset(file_index as usize)
+            .unwrap_or(0);
+        stat.m_version_made_by = entry.version_made_by;
+        stat.m_version_needed = entry.version_needed;
+        stat.m_bit_flag = entry.flags;
+        stat.m_method = entry.method;
+        stat.m_time = entry_modified_time(entry);
+        stat.m_crc32 = entry.crc32;
+        stat.m_comp_size = entry.compressed_size;
+        stat.m_uncomp_size = entry.uncompressed_size;
+        stat.m_internal_attr = entry.internal_attributes;
+        stat.m_external_attr = entry.external_attributes;
+        stat.m_local_header_ofs = entry.header_offset;
+        copy_c_string(&mut stat.m_filename, &entry.name);
+        stat.m_comment_size = copy_c_string(&mut stat.m_comment, &entry.comment) as u32;
+        stat.m_is_directory = entry.is_dir() as c_int;
+        stat.m_is_encrypted = entry.is_encrypted() as c_int;
+        stat.m_is_supported = state.archive.can_extract(file_index as usize) as c_int;
+        MZ_TRUE
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_locate_file(
+        zip: *mut mz_zip_archive,
+        name: *const c_char,
+        comment: *const c_char,
+        flags: c_uint,
+    ) -> c_int {
+        let mut index = 0;
+        if mz_zip_reader_locate_file_v2(zip, name, comment, flags, &mut index) != 0 {
+            index as c_int
+        } else {
+            -1
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_locate_file_v2(
+        zip: *mut mz_zip_archive,
+        name: *const c_char,
+        comment: *const c_char,
+        flags: c_uint,
+        file_index: *mut u32,
+    ) -> c_int {
+        if let Some(file_index) = file_index.as_mut() {
+            *file_index = 0;
+        }
+        let (zip, state) = match reader(zip) {
+            Some(reader) => reader,
+            None => return MZ_FALSE,
+        };
+        let name = match c_bytes(name) {
+            Some(name) => name,
+            None => return set_error(zip, ZipError::InvalidParameter),
+        };
+        // An empty comment matches every entry, like in miniz.
+        let comment = c_bytes(comment).filter(|comment| !comment.is_empty());
+        match state.archive.locate(name, comment, flags) {
+            Some(index) => {
+                if let Some(file_index) = file_index.as_mut() {
+                    *file_index = index as u32;
+                }
+                MZ_TRUE
+            }
+            None => set_error(zip, ZipError::FileNotFound),
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_locate_file(
+        zip: *mut mz_zip_archive,
+        name: *const c_char,
+        comment: *const c_char,
+        flags: c_uint,
+    ) -> c_int {
+        mz_zip_reader_locate_file(zip, name, comment, flags)
+    }
+
+    pub unsafe extern "C" fn mz_zip_locate_file_v2(
+        zip: *mut mz_zip_archive,
+        name: *const c_char,
+        comment: *const c_char,
+        flags: c_uint,
+        file_index: *mut u32,
+    ) -> c_int {
+        mz_zip_reader_locate_file_v2(zip, name, comment, flags, file_index)
+    }
+
+    /// Extract entry `file_index` to `buf`. The read buffer is not needed by this
+    /// implementation and is ignored.
+    pub unsafe extern "C" fn mz_zip_reader_extract_to_mem_no_alloc(
+        zip: *mut mz_zip_archive,
+        file_index: c_uint,
+        buf: *mut c_void,
+        buf_size: size_t,
+        flags: c_uint,
+        _user_read_buf: *mut c_void,
+        _user_read_buf_size: size_t,
+    ) -> c_int {
+        let (zip, state) = match reader(zip) {
+            Some(reader) => reader,
+            None => return MZ_FALSE,
+        };
+        if buf.is_null() && buf_size != 0 {
+            return set_error(zip, ZipError::InvalidParameter);
+        }
+        let size = match state.archive.entry(file_index as usize) {
+            // Like miniz, don't try to extract directories.
+            Some(entry) if entry.is_dir() || entry.compressed_size == 0 => return MZ_TRUE,
+            Some(entry) => extracted_size(entry, flags),
+            None => return set_error(zip, ZipError::InvalidParameter),
+        };
+
+        let res = entry_decoder(&mut state.archive, file_index as usize, flags).and_then(
+            |mut decoder| {
+                if (buf_size as u64) < size {
+                    return Err(ZipError::BufTooSmall);
+                }
+                let out = slice::from_raw_parts_mut(buf as *mut u8, size as usize);
+                decoder.read_to_slice(state.archive.get_mut(), out)
+            },
+        );
+        match res {
+            Ok(()) => MZ_TRUE,
+            Err(error) => set_error(zip, error),
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_extract_file_to_mem_no_alloc(
+        zip: *mut mz_zip_archive,
+        filename: *const c_char,
+        buf: *mut c_void,
+        buf_size: size_t,
+        flags: c_uint,
+        user_read_buf: *mut c_void,
+        user_read_buf_size: size_t,
+    ) -> c_int {
+        let mut index = 0;
+        if mz_zip_reader_locate_file_v2(zip, filename, ptr::null(), flags, &mut index) == 0 {
+            return MZ_FALSE;
+        }
+        mz_zip_reader_extract_to_mem_no_alloc(
+            zip,
+            index,
+            buf,
+            buf_size,
+            flags,
+            user_read_buf,
+            user_read_buf_size,
+        )
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_extract_to_mem(
+        zip: *mut mz_zip_archive,
+        file_index: c_uint,
+        buf: *mut c_void,
+        buf_size: size_t,
+        flags: c_uint,
+    ) -> c_int {
+        mz_zip_reader_extract_to_mem_no_alloc(
+            zip,
+            file_index,
+            buf,
+            buf_size,
+            flags,
+            ptr::null_mut(),
+            0,
+        )
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_extract_file_to_mem(
+        zip: *mut mz_zip_archive,
+        filename: *const c_char,
+        buf: *mut c_void,
+        buf_size: size_t,
+        flags: c_uint,
+    ) -> c_int {
+        mz_zip_reader_extract_file_to_mem_no_alloc(
+            zip,
+            filename,
+            buf,
+            buf_size,
+            flags,
+            ptr::null_mut(),
+            0,
+        )
+    }
+
+    /// Extract entry `file_index` to a buffer allocated with the archive's allocation function.
+    pub unsafe extern "C" fn mz_zip_reader_extract_to_heap(
+        zip: *mut mz_zip_archive,
+        file_index: c_uint,
+        size: *mut size_t,
+        flags: c_uint,
+    ) -> *mut c_void {
+        if let Some(size) = size.as_mut() {
+            *size = 0;
+        }
+        let (zip, state) = match reader(zip) {
+            Some(reader) => reader,
+            None => return ptr::null_mut(),
+        };
+        let alloc_size = match state.archive.entry(file_index as usize) {
+            Some(entry) => extracted_size(entry, flags),
+            None => {
+                set_error(zip, ZipError::InvalidParameter);
+                return ptr::null_mut();
+            }
+        };
+        if alloc_size > isize::max_value() as u64 {
+            set_error(zip, ZipError::InternalError);
+            return ptr::null_mut();
+        }
+
+        let alloc = zip.m_pAlloc.unwrap_or(::miniz_def_alloc_func);
+        let buf = alloc(zip.m_pAlloc_opaque, 1, alloc_size as size_t);
+        if buf.is_null() {
+            set_error(zip, ZipError::AllocFailed);
+            return ptr::null_mut();
+        }
+        if mz_zip_reader_extract_to_mem(zip, file_index, buf, alloc_size as size_t, flags) == 0 {
+            zip.m_pFree.unwrap_or(::miniz_def_free_func)(zip.m_pAlloc_opaque, buf);
+            return ptr::null_mut();
+        }
+        if let Some(size) = size.as_mut() {
+            *size = alloc_size as size_t;
+        }
+        buf
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_extract_file_to_heap(
+        zip: *mut mz_zip_archive,
+        filename: *const c_char,
+        size: *mut size_t,
+        flags: c_uint,
+    ) -> *mut c_void {
+        if let Some(size) = size.as_mut() {
+            *size = 0;
+        }
+        let mut index = 0;
+        if mz_zip_reader_locate_file_v2(zip, filename, ptr::null(), flags, &mut index) == 0 {
+            return ptr::null_mut();
+        }
+        mz_zip_reader_extract_to_heap(zip, index, size, flags)
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_extract_to_callback(
+        zip: *mut mz_zip_archive,
+        file_index: c_uint,
+        callback: Option<mz_file_write_func>,
+        opaque: *mut c_void,
+        flags: c_uint,
+    ) -> c_int {
+        let callback = match callback {
+            Some(callback) => callback,
+            None => return set_error_ptr(zip, ZipError::InvalidParameter),
+        };
+        let mut ofs = 0;
+        extract_to_callback(zip, file_index, flags, |data| {
+            let written = callback(opaque, ofs, data.as_ptr() as *const c_void, data.len());
+            ofs += data.len() as u64;
+            written == data.len()
+        })
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_extract_file_to_callback(
+        zip: *mut mz_zip_archive,
+        filename: *const c_char,
+        callback: Option<mz_file_write_func>,
+        opaque: *mut c_void,
+        flags: c_uint,
+    ) -> c_int {
+        let mut index = 0;
+        if mz_zip_reader_locate_file_v2(zip, filename, ptr::null(), flags, &mut index) == 0 {
+            return MZ_FALSE;
+        }
+        mz_zip_reader_extract_to_callback(zip, index, callback, opaque, flags)
+    }
+
+    /// Start extracting entry `file_index` piece by piece.
+    ///
+    /// The returned state is opaque and must be freed with `mz_zip_reader_extract_iter_free`.
+    pub unsafe extern "C" fn mz_zip_reader_extract_iter_new(
+        zip: *mut mz_zip_archive,
+        file_index: c_uint,
+        flags: c_uint,
+    ) -> *mut c_void {
+        let (zip, state) = match reader(zip) {
+            Some(reader) => reader,
+            None => return ptr::null_mut(),
+        };
+        match entry_decoder(&mut state.archive, file_index as usize, flags) {
+            Ok(decoder) => Box::into_raw(Box::new(IterState {
+                zip,
+                decoder,
+                failed: false,
+            })) as *mut c_void,
+            Err(error) => {
+                set_error(zip, error);
+                ptr::null_mut()
+            }
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_extract_file_iter_new(
+        zip: *mut mz_zip_archive,
+        filename: *const c_char,
+        flags: c_uint,
+    ) -> *mut c_void {
+        let mut index = 0;
+        if mz_zip_reader_locate_file_v2(zip, filename, ptr::null(), flags, &mut index) == 0 {
+            return ptr::null_mut();
+        }
+        mz_zip_reader_extract_iter_new(zip, index, flags)
+    }
+
+    /// Read the next piece of data, returning the number of bytes read, or 0 at the end or
+    /// on failure.
+    pub unsafe extern "C" fn mz_zip_reader_extract_iter_read(
+        iter: *mut c_void,
+        buf: *mut c_void,
+        buf_size: size_t,
+    ) -> size_t {
+        let iter = match (iter as *mut IterState).as_mut() {
+            Some(iter) if !iter.failed && !buf.is_null() => iter,
+            _ => return 0,
+        };
+        let (zip, state) = match reader(iter.zip) {
+            Some(reader) => reader,
+            None => return 0,
+        };
+        let buf = slice::from_raw_parts_mut(buf as *mut u8, buf_size);
+        match iter.decoder.read(state.archive.get_mut(), buf) {
+            Ok(n) => n,
+            Err(error) => {
+                iter.failed = true;
+                set_error(zip, error);
+                0
+            }
+        }
+    }
+
+    /// Free the state, returning `MZ_TRUE` if the whole entry was extracted successfully.
+    pub unsafe extern "C" fn mz_zip_reader_extract_iter_free(iter: *mut c_void) -> c_int {
+        if iter.is_null() {
+            return MZ_FALSE;
+        }
+        let mut iter = Box::from_raw(iter as *mut IterState);
+        if iter.failed {
+            return MZ_FALSE;
+        }
+        // Check that the end was reached, which also checks the CRC-32.
+        let mut scratch = [0; 1];
+        let n = mz_zip_reader_extract_iter_read(
+            &mut *iter as *mut IterState as *mut c_void,
+            scratch.as_mut_ptr() as *mut c_void,
+            1,
+        );
+        (n == 0 && !iter.failed) as c_int
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_extract_to_cfile(
+        zip: *mut mz_zip_archive,
+        file_index: c_uint,
+        file: *mut FILE,
+        flags: c_uint,
+    ) -> c_int {
+        if file.is_null() {
+            return set_error_ptr(zip, ZipError::InvalidParameter);
+        }
+        match reader(zip).and_then(|(_, state)| state.archive.entry(file_index as usize)) {
+            Some(entry) if entry.is_dir() => return set_error_ptr(zip, ZipError::InvalidParameter),
+            _ => (),
+        }
+        mz_zip_reader_extract_to_callback(
+            zip,
+            file_index,
+            Some(cfile_write_func),
+            file as *mut c_void,
+            flags,
+        )
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_extract_file_to_cfile(
+        zip: *mut mz_zip_archive,
+        archive_filename: *const c_char,
+        file: *mut FILE,
+        flags: c_uint,
+    ) -> c_int {
+        let mut index = 0;
+        if mz_zip_reader_locate_file_v2(zip, archive_filename, ptr::null(), flags, &mut index) == 0
+        {
+            return MZ_FALSE;
+        }
+        mz_zip_reader_extract_to_cfile(zip, index, file, flags)
+    }
+
+    /// Extract entry `file_index` to a new file, setting its modification time to that of the
+    /// entry, and its permissions too if the entry was made on Unix.
+    pub unsafe extern "C" fn mz_zip_reader_extract_to_file(
+        zip: *mut mz_zip_archive,
+        file_index: c_uint,
+        dst_filename: *const c_char,
+        flags: c_uint,
+    ) -> c_int {
+        let archive = match reader(zip) {
+            Some((_, state)) => &state.archive,
+            None => return MZ_FALSE,
+        };
+        let (modified, mode) = match archive.entry(file_index as usize) {
+            // Directories and other special entries can't be extracted to a file.
+            Some(entry) if entry.is_dir() || !archive.can_extract(file_index as usize) => {
+                return set_error_ptr(zip, ZipError::UnsupportedFeature)
+            }
+            Some(entry) => (entry_modified_time(entry), entry.unix_mode()),
+            None => return set_error_ptr(zip, ZipError::InvalidParameter),
+        };
+        if dst_filename.is_null() {
+            return set_error_ptr(zip, ZipError::InvalidParameter);
+        }
+
+        let file = fopen(dst_filename, b"wb\0".as_ptr() as *const c_char);
+        if file.is_null() {
+            return set_error_ptr(zip, ZipError::FileOpenFailed);
+        }
+        let mut status = mz_zip_reader_extract_to_cfile(zip, file_index, file, flags);
+        if fclose(file) != 0 {
+            if status != 0 {
+                set_error_ptr(zip, ZipError::FileCloseFailed);
+            }
+            status = MZ_FALSE;
+        }
+        if status != 0 {
+            set_file_times(dst_filename, modified);
+            if let Some(mode) = mode {
+                set_file_permissions(dst_filename, mode);
+            }
+        }
+        status
+    }
+
+    pub unsafe extern "C" fn mz_zip_reader_extract_file_to_file(
+        zip: *mut mz_zip_archive,
+        archive_filename: *const c_char,
+        dst_filename: *const c_char,
+        flags: c_uint,
+    ) -> c_int {
+        let mut index = 0;
+        if mz_zip_reader_locate_file_v2(zip, archive_filename, ptr::null(), flags, &mut index) == 0
+        {
+            return MZ_FALSE;
+        }
+        mz_zip_reader_extract_to_file(zip, index, dst_filename, flags)
+    }
+
+    pub unsafe extern "C" fn mz_zip_validate_file(
+        zip: *mut mz_zip_archive,
+        file_index: c_uint,
+        flags: c_uint,
+    ) -> c_int {
+        let (zip, state) = match reader(zip) {
+            Some(reader) => reader,
+            None => return MZ_FALSE,
+        };
+        let index = file_index as usize;
+        let result = if flags & MZ_ZIP_FLAG_VALIDATE_HEADERS_ONLY != 0 {
+            state.archive.validate_entry_header(index).map(|_| ())
+        } else {
+            state.archive.validate_entry(index)
+        };
+        match result {
+            Ok(()) => MZ_TRUE,
+            Err(error) => set_error(zip, error),
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_validate_archive(
+        zip: *mut mz_zip_archive,
+        flags: c_uint,
+    ) -> c_int {
+        let (zip, state) = match reader(zip) {
+            Some(reader) => reader,
+            None => return MZ_FALSE,
+        };
+        // Also rejects entries whose data overlaps, which miniz doesn't check.
+        let limits = ValidationLimits::default();
+        let result = if flags & MZ_ZIP_FLAG_VALIDATE_HEADERS_ONLY != 0 {
+            state.archive.validate_headers(&limits)
+        } else {
+            state.archive.validate(&limits)
+        };
+        match result {
+            Ok(()) => MZ_TRUE,
+            Err(error) => set_error(zip, error),
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_validate_mem_archive(
+        mem: *const c_void,
+        size: size_t,
+        flags: c_uint,
+        err: *mut mz_zip_error,
+    ) -> c_int {
+        let mut zip: mz_zip_archive = mem::zeroed();
+        let mut status = mz_zip_reader_init_mem(&mut zip, mem, size, flags) != 0
+            && mz_zip_validate_archive(&mut zip, flags) != 0;
+        if zip.m_zip_mode == MZ_ZIP_MODE_READING && mz_zip_reader_end(&mut zip) == 0 {
+            status = false;
+        }
+        if let Some(err) = err.as_mut() {
+            *err = zip.m_last_error;
+        }
+        status as c_int
+    }
+
+    pub unsafe extern "C" fn mz_zip_validate_file_archive(
+        filename: *const c_char,
+        flags: c_uint,
+        err: *mut mz_zip_error,
+    ) -> c_int {
+        let mut zip: mz_zip_archive = mem::zeroed();
+        let mut status = mz_zip_reader_init_file_v2(&mut zip, filename, flags, 0, 0) != 0
+            && mz_zip_validate_archive(&mut zip, flags) != 0;
+        if zip.m_zip_mode == MZ_ZIP_MODE_READING && mz_zip_reader_end(&mut zip) == 0 {
+            status = false;
+        }
+        if let Some(err) = err.as_mut() {
+            *err = zip.m_last_error;
+        }
+        status as c_int
+    }
+
+    pub unsafe extern "C" fn mz_zip_writer_init(
+        zip: *mut mz_zip_archive,
+        existing_size: u64,
+    ) -> c_int {
+        mz_zip_writer_init_v2(zip, existing_size, 0)
+    }
+
+    /// Open an archive for writing with the user supplied `m_pWrite` function.
+    pub unsafe extern "C" fn mz_zip_writer_init_v2(
+        zip: *mut mz_zip_archive,
+        existing_size: u64,
+        flags: c_uint,
+    ) -> c_int {
+        let read = match zip.as_ref() {
+            Some(zip) => zip.m_pRead,
+            None => return MZ_FALSE,
+        };
+        if flags & MZ_ZIP_FLAG_WRITE_ALLOW_READING != 0 && read.is_none() {
+            return set_error_ptr(zip, ZipError::InvalidParameter);
+        }
+        writer_init(
+            zip,
+            existing_size,
+            flags,
+            None,
+            Vec::new(),
+            MZ_ZIP_TYPE_USER,
+        )
+    }
+
+    pub unsafe extern "C" fn mz_zip_writer_init_heap(
+        zip: *mut mz_zip_archive,
+        size_to_reserve_at_beginning: size_t,
+        initial_allocation_size: size_t,
+    ) -> c_int {
+        mz_zip_writer_init_heap_v2(
+            zip,
+            size_to_reserve_at_beginning,
+            initial_allocation_size,
+            0,
+        )
+    }
+
+    /// Open an archive for writing to memory, which can be taken with
+    /// `mz_zip_writer_finalize_heap_archive`.
+    pub unsafe extern "C" fn mz_zip_writer_init_heap_v2(
+        zip: *mut mz_zip_archive,
+        size_to_reserve_at_beginning: size_t,
+        initial_allocation_size: size_t,
+        flags: c_uint,
+    ) -> c_int {
+        let zip_ref = match zip.as_mut() {
+            Some(zip) => zip,
+            None => return MZ_FALSE,
+        };
+        let alloc = zip_ref.m_pAlloc.unwrap_or(::miniz_def_alloc_func);
+        let free = zip_ref.m_pFree.unwrap_or(::miniz_def_free_func);
+        let realloc = zip_ref.m_pRealloc.unwrap_or(::miniz_def_realloc_func);
+        let alloc_opaque = zip_ref.m_pAlloc_opaque;
+
+        let capacity = cmp::max(initial_allocation_size, size_to_reserve_at_beginning);
+        let mut mem = ptr::null_mut();
+        if capacity != 0 {
+            mem = alloc(alloc_opaque, 1, capacity) as *mut u8;
+            if mem.is_null() {
+                return set_error(zip_ref, ZipError::AllocFailed);
+            }
+        }
+        let stream = Box::new(Stream::Heap {
+            mem,
+            size: 0,
+            capacity,
+            realloc,
+            free,
+            alloc_opaque,
+        });
+        writer_init(
+            zip,
+            size_to_reserve_at_beginning as u64,
+            flags,
+            Some(stream),
+            Vec::new(),
+            MZ_ZIP_TYPE_HEAP,
+        )
+    }
+
+    pub unsafe extern "C" fn mz_zip_writer_init_file(
+        zip: *mut mz_zip_archive,
+        filename: *const c_char,
+        size_to_reserve_at_beginning: u64,
+    ) -> c_int {
+        mz_zip_writer_init_file_v2(zip, filename, size_to_reserve_at_beginning, 0)
+    }
+
+    /// Create a new file and open it for writing an archive, writing zeros to the first
+    /// `size_to_reserve_at_beginning` bytes.
+    pub unsafe extern "C" fn mz_zip_writer_init_file_v2(
+        zip: *mut mz_zip_archive,
+        filename: *const c_char,
+        size_to_reserve_at_beginning: u64,
+        flags: c_uint,
+    ) -> c_int {
+        if zip.is_null() || filename.is_null() {
+            return set_error_ptr(zip, ZipError::InvalidParameter);
+        }
+        let mode: &[u8] = if flags & MZ_ZIP_FLAG_WRITE_ALLOW_READING != 0 {
+            b"w+b\0"
+        } else {
+            b"wb\0"
+        };
+        let file = fopen(filename, mode.as_ptr() as *const c_char);
+        if file.is_null() {
+            return set_error_ptr(zip, ZipError::FileOpenFailed);
+        }
+        let mut stream = Box::new(Stream::CFile {
+            file,
+            start: 0,
+            owned: true,
+        });
+
+        let zeros = [0u8; 4096];
+        let mut pos = 0;
+        while pos < size_to_reserve_at_beginning {
+            let n = cmp::min(zeros.len() as u64, size_to_reserve_at_beginning - pos) as usize;
+            let opaque = &mut *stream as *mut Stream as *mut c_void;
+            if stream_write_func(opaque, pos, zeros.as_ptr() as *const c_void, n) != n {
+                return set_error_ptr(zip, ZipError::FileWriteFailed);
+            }
+            pos += n as u64;
+        }
+        writer_init(
+            zip,
+            size_to_reserve_at_beginning,
+            flags,
+            Some(stream),
+            Vec::new(),
+            MZ_ZIP_TYPE_FILE,
+        )
+    }
+
+    /// Open an archive for writing to `file`, starting at its current position.
+    pub unsafe extern "C" fn mz_zip_writer_init_cfile(
+        zip: *mut mz_zip_archive,
+        file: *mut FILE,
+        flags: c_uint,
+    ) -> c_int {
+        if zip.is_null() || file.is_null() {
+            return set_error_ptr(zip, ZipError::InvalidParameter);
+        }
+        let start = ftell(file);
+        if start < 0 {
+            return set_error_ptr(zip, ZipError::FileSeekFailed);
+        }
+        let stream = Box::new(Stream::CFile {
+            file,
+            start: start as u64,
+            owned: false,
+        });
+        writer_init(zip, 0, flags, Some(stream), Vec::new(), MZ_ZIP_TYPE_CFILE)
+    }
+
+    pub unsafe extern "C" fn mz_zip_writer_init_from_reader(
+        zip: *mut mz_zip_archive,
+        filename: *const c_char,
+    ) -> c_int {
+        mz_zip_writer_init_from_reader_v2(zip, filename, 0)
+    }
+
+    /// Turn an archive opened for reading into one opened for writing, so entries can be
+    /// appended to it.
+    ///
+    /// New entries overwrite the old central directory. Archives opened from a file are reopened
+    /// as `filename` for writing. Archives in memory have to be allocated with the archive's
+    /// allocation functions, as they are grown with `m_pRealloc` and freed by
+    /// `mz_zip_writer_end`.
+    pub unsafe extern "C" fn mz_zip_writer_init_from_reader_v2(
+        zip: *mut mz_zip_archive,
+        filename: *const c_char,
+        flags: c_uint,
+    ) -> c_int {
+        let zip = match reader(zip) {
+            Some((zip, _)) => zip,
+            None => return MZ_FALSE,
+        };
+        let mut zip_type = zip.m_zip_type;
+        if (zip_type == MZ_ZIP_TYPE_FILE && filename.is_null())
+            || (zip_type == MZ_ZIP_TYPE_USER && zip.m_pWrite.is_none())
+        {
+            return set_error(zip, ZipError::InvalidParameter);
+        }
+
+        let ReaderState {
+            archive,
+            mut stream,
+        } = *Box::from_raw(zip.m_pState as *mut ReaderState);
+        zip.m_pState = ptr::null_mut();
+        zip.m_zip_mode = MZ_ZIP_MODE_INVALID;
+        let existing_size = archive.central_dir_offset();
+        let zip64 = archive.is_zip64();
+        let comment = archive.comment().to_vec();
+        let entries = archive.entries().to_vec();
+        drop(archive);
+
+        if let Some(stream) = stream.as_mut() {
+            match **stream {
+                Stream::CFile {
+                    ref mut file,
+                    owned: true,
+                    ..
+                } => {
+                    // `freopen` closes the old file even if it fails.
+                    *file = freopen(filename, b"r+b\0".as_ptr() as *const c_char, *file);
+                    if file.is_null() {
+                        return set_error(zip, ZipError::FileOpenFailed);
+                    }
+                }
+                Stream::Memory { data, size } => {
+                    **stream = Stream::Heap {
+                        mem: data as *mut u8,
+                        size,
+                        capacity: size,
+                        realloc: zip.m_pRealloc.unwrap_or(::miniz_def_realloc_func),
+                        free: zip.m_pFree.unwrap_or(::miniz_def_free_func),
+                        alloc_opaque: zip.m_pAlloc_opaque,
+                    };
+                    zip_type = MZ_ZIP_TYPE_HEAP;
+                }
+                _ => (),
+            }
+        }
+
+        if writer_init(zip, existing_size, flags, stream, entries, zip_type) == 0 {
+            return MZ_FALSE;
+        }
+        let state = &mut *(zip.m_pState as *mut WriterState);
+        if zip64 {
+            state.writer.set_zip64(true);
+        }
+        match state.writer.set_comment(&comment) {
+            Ok(()) => MZ_TRUE,
+            Err(error) => set_error(zip, error),
+        }
+    }
+
+    pub unsafe extern "C" fn mz_zip_writer_add_mem(
+        zip: *mut mz_zip_archive,
+        archive_name: *const c_char,
+        buf: *const c_void,
+        buf_size: size_t,
+        level_and_flags: c_uint,
+    ) -> c_int {
+        mz_zip_writer_add_mem_ex(
+            zip,
+            archive_name,
+            buf,
+            buf_size,
+            ptr::null(),
+            0,
+            level_and_flags,
+            0,
+            0,
+        )
+    }
+
+    pub unsafe extern "C" fn mz_zip_writer_add_mem_ex(
+        zip: *mut mz_zip_archive,
+        archive_name: *const c_char,
+        buf: *const c_void,
+        buf_size: size_t,
+        comment: *const c_void,
+        comment_size: u16,
+        level_and_flags: c_uint,
+        uncomp_size: u64,
+        uncomp_crc32: u32,
+    ) -> c_int {
+        mz_zip_writer_add_mem_ex_v2(
+            zip,
+            archive_name,
+            buf,
+            buf_size,
+            comment,
+            comment_size,
+            level_and_flags,
+            uncomp_size,
+            uncomp_crc32,
+            ptr::null_mut(),
+            ptr::null(),
+            0,
+            ptr::null(),
+            0,
+        )
+    }
+
+    /// Add an entry with the contents of `buf`.
+    ///
+    /// With `MZ_ZIP_FLAG_COMPRESSED_DATA`, `buf` holds deflate compressed data and
+    /// `uncomp_size` and `uncomp_crc32` describe the uncompressed data. If `last_modified` is
+    /// null the current time is used.
+    pub unsafe extern "C" fn mz_zip_writer_add_mem_ex_v2(
+        zip: *mut mz_zip_archive,
+        archive_name: *const c_char,
+        buf: *const c_void,
+        buf_size: size_t,
+        comment: *const c_void,
+        comment_size: u16,
+        level_and_flags: c_uint,
+        uncomp_size: u64,
+        uncomp_crc32: u32,
+        last_modified: *mut time_t,
+        user_extra_data_local: *const c_char,
+        user_extra_data_local_len: c_uint,
+        user_extra_data_central: *const c_char,
+        user_extra_data_central_len: c_uint,
+    ) -> c_int {
+        let (zip, state) = match writer(zip) {
+            Some(writer) => writer,
+            None => return MZ_FALSE,
+        };
+        let (level, flags) = match split_level_and_flags(level_and_flags) {
+            Ok(level_and_flags) => level_and_flags,
+            Err(error) => return set_error(zip, error),
+        };
+        let compressed = flags & ZIP_FLAG_COMPRESSED_DATA != 0;
+        if !compressed && uncomp_size != 0 {
+            return set_error(zip, ZipError::InvalidParameter);
+        }
+        let name = match c_bytes(archive_name) {
+            Some(name) => name,
+            None => return set_error(zip, ZipError::InvalidParameter),
+        };
+        let data = match c_slice(buf as *const u8, buf_size) {
+            Ok(data) => data,
+            Err(error) => return set_error(zip, error),
+        };
+        let modified = match last_modified.as_ref() {
+            Some(&modified) => modified,
+            None => time(ptr::null_mut()),
+        };
+        let options = match entry_options(
+            level,
+            flags,
+            time_t_to_dos(modified),
+            comment,
+            comment_size,
+            user_extra_data_local,
+            user_extra_data_local_len,
+            user_extra_data_central,
+            user_extra_data_central_len,
+        ) {
+            Ok(options) => options,
+            Err(error) => return set_error(zip, error),
+        };
+
+        let res = if compressed && !data.is_empty() {
+            state
+                .writer
+                .add_compressed_entry(name, data, uncomp_size, uncomp_crc32, &options)
+        } else {
+            state.writer.add_entry(name, data, &options)
+        };
+        update_writer_fields(zip, &state.writer);
+        match res {
+            Ok(()) => MZ_TRUE,
+            Err(error) => set_error(zip, error),
+        }
+    }
+
+    /// Add an entry with the next `size_to_add` bytes of `file`.
+    ///
+    /// If `file_time` is null the modification time is left at zero like in miniz.
+    pub unsafe extern "C" fn mz_zip_writer_add_cfile(
+        zip: *mut mz_zip_archive,
+        archive_name: *const c_char,
+        file: *mut FILE,
+        size_to_add: u64,
+        file_time: *const time_t,
+        comment: *const c_void,
+        comment_size: u16,
+        level_and_flags: c_uint,
+        user_extra_data_local: *const c_char,
+        user_extra_data_local_len: c_uint,
+        user_extra_data_central: *const c_char,
+        user_extra_data_central_len: c_uint,
+    ) -> c_int {
+        let (zip, state) = match writer(zip) {
+            Some(writer) => writer,
+            None => return MZ_FALSE,
+        };
+        let (level, flags) = match split_level_and_flags(level_and_flags) {
+            Ok(level_and_flags) => level_and_flags,
+            Err(error) => return set_error(zip, error),
+        };
+        if flags & ZIP_FLAG_COMPRESSED_DATA != 0 || file.is_null() {
+            return set_error(zip, ZipError::InvalidParameter);
+        }
+        let name = match c_bytes(archive_name) {
+            Some(name) => name,
+            None => return set_error(zip, ZipError::InvalidParameter),
+        };
+        let last_modified = match file_time.as_ref() {
+            Some(&modified) => time_t_to_dos(modified),
+            None => DateTime::from_dos(0, 0),
+        };
+        let mut options = match entry_options(
+            level,
+            flags,
+            last_modified,
+            comment,
+            comment_size,
+            user_extra_data_local,
+            user_extra_data_local_len,
+            user_extra_data_central,
+            user_extra_data_central_len,
+        ) {
+            Ok(options) => options,
+            Err(error) => return set_error(zip, error),
+        };
+
+        options.large_file = size_to_add >= LARGE_FILE_SIZE;
+        let mut reader = CFileReader {
+            file,
+            remaining: size_to_add,
+        };
+        let res = state
+            .writer
+            .add_entry_from_reader(name, &mut reader, &options);
+        update_writer_fields(zip, &state.writer);
+        match res {
+            Ok(_) => MZ_TRUE,
+            Err(error) => set_error(zip, error),
+        }
+    }
+
+    /// Add an entry with the contents of the file `src_filename`, using its modification time.
+    pub unsafe extern "C" fn mz_zip_writer_add_file(
+        zip: *mut mz_zip_archive,
+        archive_name: *const c_char,
+        src_filename: *const c_char,
+        comment: *const c_void,
+        comment_size: u16,
+        level_and_flags: c_uint,
+    ) -> c_int {
+        if src_filename.is_null() {
+            return set_error_ptr(zip, ZipError::InvalidParameter);
+        }
+        let modified = match file_modified_time(src_filename) {
+            Some(modified) => modified,
+            None => return set_error_ptr(zip, ZipError::FileStatFailed),
+        };
+        let file = fopen(src_filename, b"rb\0".as_ptr() as *const c_char);
+        if file.is_null() {
+            return set_error_ptr(zip, ZipError::FileOpenFailed);
+        }
+        let size = if fseek(file, 0, SEEK_END) == 0 {
+            ftell(file)
+        } else {
+            -1
+        };
+        let status = if size < 0 || fseek(file, 0, SEEK_SET) != 0 {
+            set_error_ptr(zip, ZipError::FileSeekFailed)
+        } else {
+            mz_zip_writer_add_cfile(
+                zip,
+                archive_name,
+                file,
+                size as u64,
+                &modified,
+                comment,
+                comment_size,
+                level_and_flags,
+                ptr::null(),
+                0,
+                ptr::null(),
+                0,
+            )
+        };
+        fclose(file);
+        status
+    }
+
+    /// Copy entry `src_file_index` of `source_zip` without decompressing and compressing it
+    /// again.
+    pub unsafe extern "C" fn mz_zip_writer_add_from_zip_reader(
+        zip: *mut mz_zip_archive,
+        source_zip: *mut mz_zip_archive,
+        src_file_index: c_uint,
+    ) -> c_int {
+        let (zip, state) = match writer(zip) {
+            Some(writer) => writer,
+            None => return MZ_FALSE,
+        };
+        let (_, source) = match reader(source_zip) {
+            Some(reader) => reader,
+            None => return set_error(zip, ZipError::InvalidParameter),
+        };
+        let res = state.writer.add_entry_from_archive(
+            &mut source.archive,
+            src_file_index as usize,
+            false,
+        );
+        update_writer_fields(zip, &state.writer);
+        match res {
+            Ok(()) => MZ_TRUE,
+            Err(error) => set_error(zip, error),
+        }
+    }
+
+    /// Write the central directory, after which no more entries can be added.
+    pub unsafe extern "C" fn mz_zip_writer_finalize_archive(zip: *mut mz_zip_archive) -> c_int {
+        let (zip, state) = match writer(zip) {
+            Some(writer) => writer,
+            None => return MZ_FALSE,
+        };
+        if let Err(error) = state.writer.finish() {
+            return set_error(zip, error);
+        }
+        if let Some(&Stream::CFile { file, .. }) = state.stream.as_ref().map(|s| &**s) {
+            if fflush(file) != 0 {
+                return set_error(zip, ZipError::FileCloseFailed);
+            }
+        }
+        zip.m_archive_size = state.writer.archive_size();
+        zip.m_central_directory_file_ofs = state.writer.central_dir_offset().unwrap_or(0);
+        zip.m_zip_mode = MZ_ZIP_MODE_WRITING_HAS_BEEN_FINALIZED;
+        MZ_TRUE
+    }
+
+    /// Finalize an archive opened with `mz_zip_writer_init_heap`, taking its memory.
+    ///
+    /// The memory is allocated with the archive's allocation functions.
+    pub unsafe extern "C" fn mz_zip_writer_finalize_heap_archive(
+        zip: *mut mz_zip_archive,
+        buf: *mut *mut c_void,
+        size: *mut size_t,
+    ) -> c_int {
+        let (buf, size) = match (buf.as_mut(), size.as_mut()) {
+            (Some(buf), Some(size)) => (buf, size),
+            _ => return set_error_ptr(zip, ZipError::InvalidParameter),
+        };
+        *buf = ptr::null_mut();
+        *size = 0;
+        let is_heap = match writer(zip) {
+            Some((_, state)) => match state.stream.as_ref().map(|s| &**s) {
+                Some(&Stream::Heap { .. }) => true,
+                _ => false,
+            },
+            None => return MZ_FALSE,
+        };
+        if !is_heap {
+            return set_error_ptr(zip, ZipError::InvalidParameter);
+        }
+        if mz_zip_writer_finalize_archive(zip) == 0 {
+            return MZ_FALSE;
+        }
+
+        let state = &mut *((*zip).m_pState as *mut WriterState);
+        if let Some(stream) = state.stream.as_mut() {
+            if let Stream::Heap {
+                ref mut mem,
+                size: ref mut mem_size,
+                ref mut capacity,
+                ..
+            } = **stream
+            {
+                *buf = mem::replace(mem, ptr::null_mut()) as *mut c_void;
+                *size = mem::replace(mem_size, 0);
+                *capacity = 0;
+            }
+        }
+        MZ_TRUE
+    }
+
+    /// Close an archive opened for writing, freeing its memory or closing its file.
+    pub unsafe extern "C" fn mz_zip_writer_end(zip: *mut mz_zip_archive) -> c_int {
+        let zip = match zip.as_mut() {
+            Some(zip) => zip,
+            None => return MZ_FALSE,
+        };
+        if writer_state(zip).is_none() {
+            return set_error(zip, ZipError::InvalidParameter);
+        }
+        let WriterState { writer, stream } = *Box::from_raw(zip.m_pState as *mut WriterState);
+        zip.m_pState = ptr::null_mut();
+        zip.m_zip_mode = MZ_ZIP_MODE_INVALID;
+        drop(writer);
+        if let Some(mut stream) = stream {
+            if !stream.close() {
+                return set_error(zip, ZipError::FileCloseFailed);
+            }
+        }
+        MZ_TRUE
+    }
+
+    pub unsafe extern "C" fn mz_zip_add_mem_to_archive_file_in_place(
+        zip_filename: *const c_char,
+        archive_name: *const c_char,
+        buf: *const c_void,
+        buf_size: size_t,
+        comment: *const c_void,
+        comment_size: u16,
+        level_and_flags: c_uint,
+    ) -> c_int {
+        mz_zip_add_mem_to_archive_file_in_place_v2(
+            zip_filename,
+            archive_name,
+            buf,
+            buf_size,
+            comment,
+            comment_size,
+            level_and_flags,
+            ptr::null_mut(),
+        )
+    }
+
+    /// Add an entry to the archive `zip_filename`, creating the archive if it doesn't exist.
+    ///
+    /// The archive is finalized even if adding the entry fails, so the existing entries stay
+    /// readable. A newly created archive is deleted on failure.
+    pub unsafe extern "C" fn mz_zip_add_mem_to_archive_file_in_place_v2(
+        zip_filename: *const c_char,
+        archive_name: *const c_char,
+        buf: *const c_void,
+        buf_size: size_t,
+        comment: *const c_void,
+        comment_size: u16,
+        level_and_flags: c_uint,
+        err: *mut mz_zip_error,
+    ) -> c_int {
+        let mut zip: mz_zip_archive = mem::zeroed();
+        let mut status = false;
+        let mut created = false;
+        match split_level_and_flags(level_and_flags) {
+            Ok((_, flags)) if !zip_filename.is_null() => {
+                let file = fopen(zip_filename, b"rb\0".as_ptr() as *const c_char);
+                let opened = if file.is_null() {
+                    created = true;
+                    mz_zip_writer_init_file_v2(&mut zip, zip_filename, 0, flags) != 0
+                } else {
+                    fclose(file);
+                    mz_zip_reader_init_file_v2(&mut zip, zip_filename, flags, 0, 0) != 0
+                        && mz_zip_writer_init_from_reader_v2(&mut zip, zip_filename, flags) != 0
+                };
+                if opened {
+                    status = mz_zip_writer_add_mem_ex(
+                        &mut zip,
+                        archive_name,
+                        buf,
+                        buf_size,
+                        comment,
+                        comment_size,
+                        level_and_flags,
+                        0,
+                        0,
+                    ) != 0;
+                    if mz_zip_writer_finalize_archive(&mut zip) == 0 {
+                        status = false;
+                    }
+                }
+                if zip.m_zip_mode != MZ_ZIP_MODE_INVALID && mz_zip_end(&mut zip) == 0 {
+                    status = false;
+                }
+            }
+            _ => zip.m_last_error = ZipError::InvalidParameter as mz_zip_error,
+        }
+        if !status && created {
+            remove(zip_filename);
+        }
+        if let Some(err) = err.as_mut() {
+            *err = zip.m_last_error;
+        }
+        status as c_int
+    }
+
+    pub unsafe extern "C" fn mz_zip_extract_archive_file_to_heap(
+        zip_filename: *const c_char,
+        archive_name: *const c_char,
+        size: *mut size_t,
+        flags: c_uint,
+    ) -> *mut c_void {
+        mz_zip_extract_archive_file_to_heap_v2(
+            zip_filename,
+            archive_name,
+            ptr::null(),
+            size,
+            flags,
+            ptr::null_mut(),
+        )
+    }
+
+    /// Open an archive, extract a single entry to the heap and close it again.
+    pub unsafe extern "C" fn mz_zip_extract_archive_file_to_heap_v2(
+        zip_filename: *const c_char,
+        archive_name: *const c_char,
+        comment: *const c_char,
+        size: *mut size_t,
+        flags: c_uint,
+        err: *mut mz_zip_error,
+    ) -> *mut c_void {
+        if let Some(size) = size.as_mut() {
+            *size = 0;
+        }
+        let mut zip: mz_zip_archive = mem::zeroed();
+        let mut buf = ptr::null_mut();
+        if zip_filename.is_null() || archive_name.is_null() {
+            zip.m_last_error = ZipError::InvalidParameter as mz_zip_error;
+        } else if mz_zip_reader_init_file_v2(&mut zip, zip_filename, flags, 0, 0) != 0 {
+            let mut index = 0;
+            if mz_zip_reader_locate_file_v2(&mut zip, archive_name, comment, flags, &mut index) != 0
+            {
+                buf = mz_zip_reader_extract_to_heap(&mut zip, index, size, flags);
+            }
+            mz_zip_reader_end(&mut zip);
+        }
+        if let Some(err) = err.as_mut() {
+            *err = zip.m_last_error;
+        }
+        buf
+    }
+);
@@ -123,3 +123,380 @@ fn c_api() {
 
     assert_eq!(data[..], decompressed[0..decompressed_size as usize]);
 }
+
+/// Compress with non-default match finder settings through the C API.
+#[test]
+fn c_api_tune() {
+    use miniz_oxide::inflate::decompress_to_vec_zlib;
+    use miniz_oxide::{MZError, MZStatus};
+    use miniz_oxide_c_api::{
+        mz_deflate, mz_deflateEnd, mz_deflateInit2, mz_deflateTune, mz_stream,
+        MZ_DEFAULT_WINDOW_BITS, MZ_DEFLATED,
+    };
+    let mut data = get_test_data();
+    let mut compressed = vec![0; data.len() + 10];
+    unsafe {
+        let mut stream = mz_stream {
+            next_in: data.as_mut_ptr(),
+            avail_in: data.len() as u32,
+            next_out: compressed.as_mut_ptr(),
+            avail_out: compressed.len() as u32,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            mz_deflateInit2(&mut stream, 6, MZ_DEFLATED, MZ_DEFAULT_WINDOW_BITS, 1, 0),
+            MZStatus::Ok as i32
+        );
+        assert_eq!(
+            mz_deflateTune(&mut stream, -1, 4, 8, 4),
+            MZError::Param as i32
+        );
+        assert_eq!(mz_deflateTune(&mut stream, 4, 4, 8, 4), MZStatus::Ok as i32);
+        assert_eq!(mz_deflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+        compressed.truncate(stream.total_out as usize);
+        assert_eq!(mz_deflateEnd(&mut stream), MZStatus::Ok as i32);
+    }
+
+    assert!(decompress_to_vec_zlib(&compressed).unwrap() == data);
+}
+
+/// Read an archive through the `mz_zip_reader_*` functions.
+#[cfg(feature = "miniz_zip")]
+#[test]
+fn c_api_zip_reader() {
+    use miniz_oxide::zip::ZipError;
+    use miniz_oxide_c_api::*;
+    use std::ffi::CStr;
+    use std::{mem, ptr};
+
+    let data = get_test_file_data("miniz_oxide/tests/test_data/archive.zip");
+    let lines: String = (0..2000).map(|n| format!("line {}\n", n)).collect();
+    unsafe {
+        let mut zip: mz_zip_archive = mem::zeroed();
+        assert_eq!(
+            mz_zip_reader_init_mem(&mut zip, data.as_ptr() as *const _, data.len(), 0),
+            1
+        );
+        assert_eq!(mz_zip_get_mode(&mut zip), MZ_ZIP_MODE_READING);
+        assert_eq!(mz_zip_reader_get_num_files(&mut zip), 3);
+        assert_eq!(mz_zip_reader_is_file_a_directory(&mut zip, 1), 1);
+
+        let name = b"DIR/LINES.TXT\0".as_ptr() as *const _;
+        let index = mz_zip_reader_locate_file(&mut zip, name, ptr::null(), 0);
+        assert_eq!(index, 2);
+        let mut stat: mz_zip_archive_file_stat = mem::zeroed();
+        assert_eq!(mz_zip_reader_file_stat(&mut zip, 2, &mut stat), 1);
+        assert_eq!(stat.m_uncomp_size, lines.len() as u64);
+        assert_eq!(
+            CStr::from_ptr(stat.m_filename.as_ptr()).to_bytes(),
+            b"dir/lines.txt"
+        );
+
+        let mut size = 0;
+        let buf = mz_zip_reader_extract_to_heap(&mut zip, 2, &mut size, 0);
+        assert!(!buf.is_null());
+        assert!(std::slice::from_raw_parts(buf as *const u8, size) == lines.as_bytes());
+        miniz_def_free_func(ptr::null_mut(), buf);
+
+        let mut buf = [0u8; 5];
+        assert_eq!(
+            mz_zip_reader_extract_to_mem(&mut zip, 0, buf.as_mut_ptr() as *mut _, 5, 0),
+            0
+        );
+        assert_eq!(
+            mz_zip_get_last_error(&mut zip),
+            ZipError::BufTooSmall as mz_zip_error
+        );
+
+        let iter = mz_zip_reader_extract_iter_new(&mut zip, 0, 0);
+        assert!(!iter.is_null());
+        let mut buf = [0u8; 32];
+        let n = mz_zip_reader_extract_iter_read(iter, buf.as_mut_ptr() as *mut _, buf.len());
+        assert_eq!(&buf[..n], b"Hello, zip!\n");
+        assert_eq!(mz_zip_reader_extract_iter_free(iter), 1);
+
+        assert_eq!(mz_zip_validate_archive(&mut zip, 0), 1);
+        assert_eq!(mz_zip_end(&mut zip), 1);
+        assert_eq!(mz_zip_get_mode(&mut zip), MZ_ZIP_MODE_INVALID);
+    }
+}
+
+/// Extract entries compressed with Shrink, Reduce and Implode through the C API.
+#[cfg(feature = "miniz_zip")]
+#[test]
+fn c_api_zip_reader_legacy_methods() {
+    use miniz_oxide_c_api::*;
+    use std::{mem, ptr};
+
+    let data = get_test_file_data("miniz_oxide/tests/test_data/legacy.zip");
+    unsafe {
+        let mut zip: mz_zip_archive = mem::zeroed();
+        assert_eq!(
+            mz_zip_reader_init_mem(&mut zip, data.as_ptr() as *const _, data.len(), 0),
+            1
+        );
+        assert_eq!(mz_zip_reader_get_num_files(&mut zip), 9);
+        for index in 0..9 {
+            assert_eq!(mz_zip_reader_is_file_supported(&mut zip, index), 1);
+            let mut stat: mz_zip_archive_file_stat = mem::zeroed();
+            assert_eq!(mz_zip_reader_file_stat(&mut zip, index, &mut stat), 1);
+            let mut size = 0;
+            let buf = mz_zip_reader_extract_to_heap(&mut zip, index, &mut size, 0);
+            assert!(!buf.is_null());
+            assert_eq!(size as u64, stat.m_uncomp_size);
+            miniz_def_free_func(ptr::null_mut(), buf);
+        }
+        assert_eq!(mz_zip_validate_archive(&mut zip, 0), 1);
+        assert_eq!(mz_zip_end(&mut zip), 1);
+    }
+}
+
+/// Extract entries with the traditional PKWARE encryption through the C API.
+#[cfg(feature = "miniz_zip")]
+#[test]
+fn c_api_zip_reader_encrypted() {
+    use miniz_oxide::zip::ZipError;
+    use miniz_oxide_c_api::*;
+    use std::ffi::CStr;
+    use std::{mem, ptr};
+
+    let data = get_test_file_data("miniz_oxide/tests/test_data/encrypted.zip");
+    unsafe {
+        let mut zip: mz_zip_archive = mem::zeroed();
+        assert_eq!(
+            mz_zip_reader_init_mem(&mut zip, data.as_ptr() as *const _, data.len(), 0),
+            1
+        );
+        assert_eq!(mz_zip_reader_is_file_encrypted(&mut zip, 0), 1);
+        assert_eq!(mz_zip_reader_is_file_supported(&mut zip, 0), 0);
+        let mut buf = [0u8; 64];
+        let extract = |zip: &mut mz_zip_archive, buf: &mut [u8]| {
+            mz_zip_reader_extract_to_mem(zip, 0, buf.as_mut_ptr() as *mut _, buf.len(), 0)
+        };
+        assert_eq!(extract(&mut zip, &mut buf), 0);
+        assert_eq!(
+            mz_zip_get_last_error(&mut zip),
+            ZipError::UnsupportedEncryption as mz_zip_error
+        );
+
+        let wrong = b"wrong\0".as_ptr() as *const _;
+        assert_eq!(mz_zip_reader_set_password(&mut zip, wrong), 1);
+        assert_eq!(mz_zip_reader_is_file_supported(&mut zip, 0), 1);
+        assert_eq!(extract(&mut zip, &mut buf), 0);
+        assert_eq!(
+            mz_zip_get_last_error(&mut zip),
+            ZipError::WrongPassword as mz_zip_error
+        );
+        let error = CStr::from_ptr(mz_zip_get_error_string(ZipError::WrongPassword as _));
+        assert_eq!(error.to_bytes(), b"wrong password");
+
+        let password = b"secret\0".as_ptr() as *const _;
+        assert_eq!(mz_zip_reader_set_password(&mut zip, password), 1);
+        assert_eq!(extract(&mut zip, &mut buf), 1);
+        assert_eq!(&buf[..22], b"Hello, encrypted zip!\n");
+        for index in 0..mz_zip_reader_get_num_files(&mut zip) {
+            let mut stat: mz_zip_archive_file_stat = mem::zeroed();
+            assert_eq!(mz_zip_reader_file_stat(&mut zip, index, &mut stat), 1);
+            assert_eq!(stat.m_is_supported, 1);
+            let mut size = 0;
+            let buf = mz_zip_reader_extract_to_heap(&mut zip, index, &mut size, 0);
+            assert!(!buf.is_null());
+            assert_eq!(size as u64, stat.m_uncomp_size);
+            miniz_def_free_func(ptr::null_mut(), buf);
+        }
+        assert_eq!(mz_zip_validate_archive(&mut zip, 0), 1);
+
+        // A null password clears it.
+        assert_eq!(mz_zip_reader_set_password(&mut zip, ptr::null()), 1);
+        assert_eq!(mz_zip_reader_is_file_supported(&mut zip, 0), 0);
+        assert_eq!(mz_zip_end(&mut zip), 1);
+    }
+}
+
+/// Write archives through the `mz_zip_writer_*` functions and read them back.
+#[cfg(feature = "miniz_zip")]
+#[test]
+fn c_api_zip_writer() {
+    use miniz_oxide_c_api::*;
+    use std::ffi::CString;
+    use std::{fs, mem, ptr, slice};
+
+    let data = get_test_data();
+    unsafe {
+        let mut zip: mz_zip_archive = mem::zeroed();
+        assert_eq!(mz_zip_writer_init_heap(&mut zip, 0, 0), 1);
+        assert_eq!(mz_zip_get_mode(&mut zip), MZ_ZIP_MODE_WRITING);
+        let name = b"data.bin\0".as_ptr() as *const _;
+        assert_eq!(
+            mz_zip_writer_add_mem(&mut zip, name, data.as_ptr() as *const _, data.len(), 9),
+            1
+        );
+        let dir = b"dir/\0".as_ptr() as *const _;
+        assert_eq!(mz_zip_writer_add_mem(&mut zip, dir, ptr::null(), 0, 0), 1);
+        assert_eq!(mz_zip_reader_get_num_files(&mut zip), 2);
+
+        let mut buf = ptr::null_mut();
+        let mut size = 0;
+        assert_eq!(
+            mz_zip_writer_finalize_heap_archive(&mut zip, &mut buf, &mut size),
+            1
+        );
+        assert_eq!(mz_zip_get_archive_size(&mut zip), size as u64);
+        assert_eq!(mz_zip_writer_end(&mut zip), 1);
+
+        // Append to the archive in memory, which takes over the buffer.
+        assert_eq!(mz_zip_reader_init_mem(&mut zip, buf, size, 0), 1);
+        assert_eq!(mz_zip_writer_init_from_reader(&mut zip, ptr::null()), 1);
+        let hello = b"hello.txt\0".as_ptr() as *const _;
+        assert_eq!(
+            mz_zip_writer_add_mem(&mut zip, hello, b"Hello!".as_ptr() as *const _, 6, 6),
+            1
+        );
+        assert_eq!(
+            mz_zip_writer_finalize_heap_archive(&mut zip, &mut buf, &mut size),
+            1
+        );
+        assert_eq!(mz_zip_end(&mut zip), 1);
+
+        assert_eq!(mz_zip_reader_init_mem(&mut zip, buf, size, 0), 1);
+        assert_eq!(mz_zip_reader_get_num_files(&mut zip), 3);
+        assert_eq!(mz_zip_reader_is_file_a_directory(&mut zip, 1), 1);
+        assert_eq!(mz_zip_validate_archive(&mut zip, 0), 1);
+        let mut out_size = 0;
+        let out = mz_zip_reader_extract_file_to_heap(&mut zip, name, &mut out_size, 0);
+        assert!(slice::from_raw_parts(out as *const u8, out_size) == &data[..]);
+        miniz_def_free_func(ptr::null_mut(), out);
+        assert_eq!(mz_zip_end(&mut zip), 1);
+
+        // Copy entries to a new archive without recompressing them.
+        let mut source: mz_zip_archive = mem::zeroed();
+        assert_eq!(mz_zip_reader_init_mem(&mut source, buf, size, 0), 1);
+        assert_eq!(mz_zip_writer_init_heap(&mut zip, 0, 0), 1);
+        assert_eq!(
+            mz_zip_writer_add_from_zip_reader(&mut zip, &mut source, 2),
+            1
+        );
+        assert_eq!(
+            mz_zip_writer_add_from_zip_reader(&mut zip, &mut source, 0),
+            1
+        );
+        assert_eq!(
+            mz_zip_writer_add_from_zip_reader(&mut zip, &mut source, 3),
+            0
+        );
+        assert_eq!(
+            mz_zip_get_last_error(&mut zip),
+            miniz_oxide::zip::ZipError::InvalidParameter as mz_zip_error
+        );
+        assert_eq!(mz_zip_end(&mut source), 1);
+        let mut copy = ptr::null_mut();
+        let mut copy_size = 0;
+        assert_eq!(
+            mz_zip_writer_finalize_heap_archive(&mut zip, &mut copy, &mut copy_size),
+            1
+        );
+        assert_eq!(mz_zip_end(&mut zip), 1);
+        assert_eq!(mz_zip_reader_init_mem(&mut zip, copy, copy_size, 0), 1);
+        assert_eq!(mz_zip_reader_get_num_files(&mut zip), 2);
+        assert_eq!(mz_zip_validate_archive(&mut zip, 0), 1);
+        let out = mz_zip_reader_extract_to_heap(&mut zip, 1, &mut out_size, 0);
+        assert!(slice::from_raw_parts(out as *const u8, out_size) == &data[..]);
+        miniz_def_free_func(ptr::null_mut(), out);
+        assert_eq!(mz_zip_end(&mut zip), 1);
+        miniz_def_free_func(ptr::null_mut(), copy);
+        miniz_def_free_func(ptr::null_mut(), buf);
+
+        // Create an archive file and add to it.
+        let path = std::env::temp_dir().join("miniz_oxide_c_api_zip_writer.zip");
+        let _ = fs::remove_file(&path);
+        let filename = CString::new(path.to_str().unwrap()).unwrap();
+        for &entry in &[b"a.txt\0", b"b.txt\0"] {
+            assert_eq!(
+                mz_zip_add_mem_to_archive_file_in_place(
+                    filename.as_ptr(),
+                    entry.as_ptr() as *const _,
+                    b"contents".as_ptr() as *const _,
+                    8,
+                    ptr::null(),
+                    0,
+                    6,
+                ),
+                1
+            );
+        }
+        let mut err = 0;
+        assert_eq!(
+            mz_zip_validate_file_archive(filename.as_ptr(), 0, &mut err),
+            1
+        );
+        let out = mz_zip_extract_archive_file_to_heap(
+            filename.as_ptr(),
+            b"b.txt\0".as_ptr() as *const _,
+            &mut out_size,
+            0,
+        );
+        assert_eq!(
+            slice::from_raw_parts(out as *const u8, out_size),
+            b"contents"
+        );
+        miniz_def_free_func(ptr::null_mut(), out);
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+/// Extracting an entry made on Unix keeps its permissions, except for the setuid, setgid and
+/// sticky bits, and its modification time.
+#[cfg(all(feature = "miniz_zip", unix))]
+#[test]
+fn c_api_zip_extract_metadata() {
+    use miniz_oxide::zip::{EntryOptions, ExtendedTimestamp, ZipWriter};
+    use miniz_oxide_c_api::*;
+    use std::ffi::CString;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::{fs, mem};
+
+    let mut options = EntryOptions::default();
+    options.unix_mode = Some(0o100_600);
+    options
+        .set_extra_field(&ExtendedTimestamp::new(1_500_000_000))
+        .unwrap();
+    let mut zip = ZipWriter::new(Vec::new());
+    zip.add_entry(b"file.txt", b"contents", &options).unwrap();
+    let mut options = EntryOptions::default();
+    options.unix_mode = Some(0o104_755);
+    zip.add_entry(b"setuid", b"contents", &options).unwrap();
+    zip.finish().unwrap();
+    let data = zip.into_inner();
+
+    let path = std::env::temp_dir().join("miniz_oxide_c_api_zip_extract_metadata.txt");
+    let filename = CString::new(path.to_str().unwrap()).unwrap();
+    let setuid_path = std::env::temp_dir().join("miniz_oxide_c_api_zip_extract_metadata_setuid");
+    let setuid_filename = CString::new(setuid_path.to_str().unwrap()).unwrap();
+    unsafe {
+        let mut zip: mz_zip_archive = mem::zeroed();
+        assert_eq!(
+            mz_zip_reader_init_mem(&mut zip, data.as_ptr() as *const _, data.len(), 0),
+            1
+        );
+        let mut stat: mz_zip_archive_file_stat = mem::zeroed();
+        assert_eq!(mz_zip_reader_file_stat(&mut zip, 0, &mut stat), 1);
+        assert_eq!(stat.m_time, 1_500_000_000);
+        assert_eq!(
+            mz_zip_reader_extract_to_file(&mut zip, 0, filename.as_ptr(), 0),
+            1
+        );
+        assert_eq!(
+            mz_zip_reader_extract_to_file(&mut zip, 1, setuid_filename.as_ptr(), 0),
+            1
+        );
+        assert_eq!(mz_zip_end(&mut zip), 1);
+    }
+    let metadata = fs::metadata(&path).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o600);
+    assert_eq!(metadata.mtime(), 1_500_000_000);
+    fs::remove_file(&path).unwrap();
+    let metadata = fs::metadata(&setuid_path).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o755);
+    fs::remove_file(&setuid_path).unwrap();
+}